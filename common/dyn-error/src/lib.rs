@@ -27,7 +27,7 @@
 extern crate alloc;
 
 use {
-	core::{any::Any, fmt::{self, Display, Debug, Formatter}},
+	core::{any::{Any, TypeId}, fmt::{self, Display, Debug, Formatter}},
 	alloc::{boxed::Box, string::ToString}
 };
 
@@ -35,7 +35,13 @@ pub type Result<T> = core::result::Result<T, Error>;
 
 pub struct Error(pub Box<dyn AnyDisplay>);
 
-pub trait AnyDisplay: Any + Display + Send + Sync {}
+pub trait AnyDisplay: Any + Display + Send + Sync {
+	/// The id of the concrete type. Unlike `Any::type_id`, which `dyn AnyDisplay` also implements
+	/// itself, this is always dispatched dynamically.
+	fn concrete_type_id(&self) -> TypeId {
+		TypeId::of::<Self>()
+	}
+}
 
 impl<T: Any + Display + Send + Sync> AnyDisplay for T {}
 
@@ -53,12 +59,13 @@ impl Error {
 	}
 	
 	pub fn is<T: Any>(&self) -> bool {
-		core::any::TypeId::of::<T>() == self.0.type_id()
+		// `self.0.type_id()` would be the id of the box
+		TypeId::of::<T>() == (*self.0).concrete_type_id()
 	}
 	
 	pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
 		if self.is::<T>() {
-			unsafe { Some(&*(self as *const dyn Any as *const T)) }
+			unsafe { Some(&*(&*self.0 as *const dyn AnyDisplay as *const T)) }
 		} else {
 			None
 		}
@@ -66,7 +73,7 @@ impl Error {
 	
 	pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
 		if self.is::<T>() {
-			unsafe { Some(&mut *(self as *mut dyn Any as *mut T)) }
+			unsafe { Some(&mut *(&mut *self.0 as *mut dyn AnyDisplay as *mut T)) }
 		} else {
 			None
 		}
@@ -103,4 +110,18 @@ impl<T: AnyDisplay> From<T> for Error {
 	fn from(v: T) -> Self {
 		Error::new(v)
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn downcast() {
+		let mut e = Error::new(alloc::string::String::from("message"));
+		assert!(e.is::<alloc::string::String>());
+		assert!(!e.is::<&str>());
+		assert_eq!(e.downcast_ref::<alloc::string::String>().map(|v| v.as_str()), Some("message"));
+		e.downcast_mut::<alloc::string::String>().unwrap().push('!');
+		assert_eq!(e.downcast_ref::<alloc::string::String>().map(|v| v.as_str()), Some("message!"));
+	}
+}
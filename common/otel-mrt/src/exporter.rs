@@ -117,34 +117,67 @@ pub(crate) async fn run(mut runtime: Arc<RuntimeInner>) {
 			}
 		};
 		
-		{
-			let data = runtime.sync.lock().expect("failed to lock runtime");
-			
-			// TODO prepare data
-		}
+		let (spans, logs) = {
+			let mut data = runtime.sync.lock().expect("failed to lock runtime");
+			(std::mem::take(&mut data.spans), std::mem::take(&mut data.logs))
+		};
+		
+		let (metric_count, metrics) = encode_metrics(&runtime);
+		let requests = [
+			(net::otlp::metrics::HTTP_PATH, metric_count, metrics),
+			(net::otlp::tracing::HTTP_PATH, spans.len(), encode_traces(&runtime.config, &spans)),
+			(net::otlp::logging::HTTP_PATH, logs.len(), encode_logs(&runtime.config, &logs))
+		];
+		let mut sent = [false; 3];
 		
 		let r = (async {
-			let len = 0;
-			
-			let stream_metrics = conn.open().await?;
-			conn.write_headers(stream_metrics, &http_headers(net::otlp::metrics::HTTP_PATH, len)).await?;
-			conn.write_body(stream_metrics, &buf[..len]).await?;
-			
-			let stream_tracing = conn.open().await?;
-			conn.write_headers(stream_tracing, &http_headers(net::otlp::tracing::HTTP_PATH, len)).await?;
-			conn.write_body(stream_tracing, &buf[..len]).await?;
-			
-			let stream_logging = conn.open().await?;
-			conn.write_headers(stream_logging, &http_headers(net::otlp::logging::HTTP_PATH, len)).await?;
-			conn.write_body(stream_logging, &buf[..len]).await?;
-			
-			while let Some(v) = conn.read(&mut buf).await? {
-				match v {
-					(_,  http::Read::Opened)      => return Err(std::io::Error::new(std::io::ErrorKind::Other, "server opened push stream")),
-					(id, http::Read::Headers(v))  => todo!(),
-					(id, http::Read::HeadersDone) => todo!(),
-					(id, http::Read::Body(v))     => todo!(),
-					(id, http::Read::Closed)      => todo!()
+			for (i, (path, count, body)) in requests.iter().enumerate() {
+				if *count == 0 {
+					sent[i] = true;
+					continue;
+				}
+				
+				buf.clear();
+				serde_json::to_writer(&mut buf, body)?;
+				
+				let stream = conn.open().await?;
+				conn.write_headers(stream, &http_headers(path, buf.len())).await?;
+				conn.write_body(stream, &buf).await?;
+				conn.flush(stream).await?;
+				
+				let mut status = None;
+				let mut len = None;
+				let mut read = 0;
+				
+				buf.resize(IO_BUF_LEN, 0);
+				while len.map_or(true, |len| read < len) {
+					match conn.read(&mut buf).await? {
+						None                                => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+						Some((_, http::Read::Opened))       => return Err(std::io::Error::new(std::io::ErrorKind::Other, "server opened push stream")),
+						Some((_, http::Read::Headers(v)))   => {
+							status = v.iter().find_map(http::Header::as_status).copied();
+							len = Some(v.iter().find_map(http::Header::as_content_length).copied().unwrap_or(0));
+						}
+						Some((_, http::Read::HeadersDone))  => (),
+						Some((_, http::Read::Body(v)))      => read += v.len(),
+						Some((_, http::Read::Closed))       => break
+					}
+				}
+				
+				// the server may be able to process the records later, see the OTLP specification
+				match status {
+					Some(http::Status::Ok) => {
+						log::trace!("[OpenTelemetry Exporter] exported {} records to {}", count, path);
+						sent[i] = true;
+					}
+					Some(status @ (http::Status::TooManyRequests | http::Status::BadGateway
+						| http::Status::ServiceUnavailable | http::Status::GatewayTimeout)) =>
+						log::warn!("[OpenTelemetry Exporter] failed to export {} records to {}: {}, retrying", count, path, status),
+					Some(status) => {
+						log::warn!("[OpenTelemetry Exporter] failed to export {} records to {}: {}, dropping them", count, path, status);
+						sent[i] = true;
+					}
+					None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "response without status"))
 				}
 			}
 			
@@ -155,7 +188,33 @@ pub(crate) async fn run(mut runtime: Arc<RuntimeInner>) {
 			log::warn!("[OpenTelemetry Exporter] failed to send data: {}, terminating connection", e);
 			connection.take();
 		}
+		
+		// metrics are sent with their current values on the next attempt
+		if !sent[1] || !sent[2] {
+			let mut data = runtime.sync.lock().expect("failed to lock runtime");
+			
+			if !sent[1] {
+				requeue("spans", &mut data.spans, spans, DEFAULT_SPAN_QUEUE_LEN);
+			}
+			
+			if !sent[2] {
+				requeue("log records", &mut data.logs, logs, DEFAULT_LOG_QUEUE_LEN);
+			}
+		}
+	}
+}
+
+/// Puts records, that could not be exported, in front of the queue. Records exceeding the queue
+/// length are dropped, like new records are dropped, if the exporter does not keep up.
+fn requeue<T>(kind: &str, queue: &mut Vec<T>, mut records: Vec<T>, limit: usize) {
+	records.append(queue);
+	
+	if records.len() > limit {
+		log::warn!("[OpenTelemetry Exporter] queue full, dropping {} {}", records.len() - limit, kind);
+		records.truncate(limit);
 	}
+	
+	*queue = records;
 }
 
 fn http_headers(path: &str, length: usize) -> [http::Header; 5] {
@@ -168,6 +227,321 @@ fn http_headers(path: &str, length: usize) -> [http::Header; 5] {
 	]
 }
 
+// OTLP/JSON, see https://github.com/open-telemetry/opentelemetry-proto/blob/main/docs/specification.md#json-protobuf-encoding
+
+fn encode_metrics(runtime: &RuntimeInner) -> (usize, serde_json::Value) {
+	let instruments = runtime.sync.lock().expect("failed to lock runtime")
+		.instruments.iter()
+		.cloned()
+		.collect::<Vec<_>>();
+	
+	// callbacks record values and lock the runtime themselves
+	for instrument in &instruments {
+		if let Some(call) = &instrument.call {
+			call(&**instrument);
+		}
+	}
+	
+	let sync = runtime.sync.lock().expect("failed to lock runtime");
+	let now = (Instant::now(), tracing::now_unix_nano());
+	let metrics = instruments.iter()
+		.filter_map(|v| encode_metric(v, now))
+		.collect::<Vec<_>>();
+	std::mem::drop(sync);
+	
+	(metrics.len(), serde_json::json!({
+		"resourceMetrics": [{
+			"resource": encode_resource(&runtime.config),
+			"scopeMetrics": [{
+				"scope":   encode_scope(&runtime.config),
+				"metrics": metrics
+			}]
+		}]
+	}))
+}
+
+/// Encodes the data points of an instrument, the data points of instruments with delta temporality
+/// are reset. Must be called while the runtime is locked.
+fn encode_metric(instrument: &metrics::InstrumentInner, (now, now_unix_nano): (Instant, u64)) -> Option<serde_json::Value> {
+	let time = |v: Instant| now_unix_nano.saturating_sub(now.saturating_duration_since(v).as_nanos() as u64).to_string();
+	let number = |attributes: &[KeyValue], start_time: Instant, time_: Instant, key: &str, value: serde_json::Value| {
+		let mut v = serde_json::json!({
+			"attributes":        encode_attributes(attributes),
+			"startTimeUnixNano": time(start_time),
+			"timeUnixNano":      time(time_)
+		});
+		v[key] = value;
+		v
+	};
+	let histogram = |attributes: &[KeyValue], start_time: Instant, time_: Instant, count: u64, sum: f64, bucket_counts: &[u64], explicit_bounds: Vec<f64>| serde_json::json!({
+		"attributes":        encode_attributes(attributes),
+		"startTimeUnixNano": time(start_time),
+		"timeUnixNano":      time(time_),
+		"count":             count.to_string(),
+		"sum":               sum,
+		"bucketCounts":      bucket_counts.iter().map(ToString::to_string).collect::<Vec<_>>(),
+		"explicitBounds":    explicit_bounds
+	});
+	
+	// data points are never reset, unless delta temporality was requested explicitly
+	let temporality = |v: &AggregationTemporality| match v {
+		AggregationTemporality::Delta => AggregationTemporality::Delta as u32,
+		_ => AggregationTemporality::Cumulative as u32
+	};
+	
+	let data = unsafe { &mut*instrument.data.get() };
+	let (key, data_points, aggregation) = match &*data {
+		InstrumentData::Drop => return None,
+		InstrumentData::SumI64 { data_points, temporality: t, monotonic } => ("sum", data_points.values()
+			.map(|v| number(&v.attributes, v.start_time, v.time, "asInt", v.value.to_string().into()))
+			.collect::<Vec<_>>(), Some((temporality(t), Some(*monotonic)))),
+		InstrumentData::SumF64 { data_points, temporality: t, monotonic } => ("sum", data_points.values()
+			.map(|v| number(&v.attributes, v.start_time, v.time, "asDouble", serde_json::Value::from(v.value)))
+			.collect::<Vec<_>>(), Some((temporality(t), Some(*monotonic)))),
+		InstrumentData::LastValueI64 { data_points } => ("gauge", data_points.values()
+			.map(|v| number(&v.attributes, v.start_time, v.time, "asInt", v.value.to_string().into()))
+			.collect::<Vec<_>>(), None),
+		InstrumentData::LastValueF64 { data_points } => ("gauge", data_points.values()
+			.map(|v| number(&v.attributes, v.start_time, v.time, "asDouble", serde_json::Value::from(v.value)))
+			.collect::<Vec<_>>(), None),
+		InstrumentData::HistogramI64 { data_points, temporality: t } => ("histogram", data_points.values()
+			.map(|v| histogram(&v.attributes, v.start_time, v.time, v.count, v.sum as f64, &v.bucket_counts,
+				v.explicit_bounds.iter().map(|v| *v as f64).collect()))
+			.collect::<Vec<_>>(), Some((temporality(t), None))),
+		InstrumentData::HistogramF64 { data_points, temporality: t } => ("histogram", data_points.values()
+			.map(|v| histogram(&v.attributes, v.start_time, v.time, v.count, v.sum, &v.bucket_counts, v.explicit_bounds.clone()))
+			.collect::<Vec<_>>(), Some((temporality(t), None)))
+	};
+	
+	if data_points.is_empty() {
+		return None;
+	}
+	
+	let mut value = serde_json::json!({ "dataPoints": data_points });
+	
+	if let Some((temporality, monotonic)) = aggregation {
+		value["aggregationTemporality"] = serde_json::Value::from(temporality);
+		
+		if let Some(monotonic) = monotonic {
+			value["isMonotonic"] = monotonic.into();
+		}
+		
+		if temporality == AggregationTemporality::Delta as u32 {
+			data.reset();
+		}
+	}
+	
+	let mut metric = serde_json::json!({ "name": instrument.name });
+	metric[key] = value;
+	
+	if let Some(unit) = &instrument.unit {
+		metric["unit"] = unit.as_ref().into();
+	}
+	
+	if let Some(desc) = &instrument.desc {
+		metric["description"] = desc.as_ref().into();
+	}
+	
+	Some(metric)
+}
+
+fn encode_traces(config: &Config, spans: &[net::otlp::tracing::Span]) -> serde_json::Value {
+	serde_json::json!({
+		"resourceSpans": [{
+			"resource": encode_resource(config),
+			"scopeSpans": [{
+				"scope": encode_scope(config),
+				"spans": spans.iter().map(|span| serde_json::json!({
+					"traceId":                span.trace_id.iter().map(|v| format!("{:02x}", v)).collect::<String>(),
+					"spanId":                 span.span_id.iter().map(|v| format!("{:02x}", v)).collect::<String>(),
+					"traceState":             span.trace_state,
+					"parentSpanId":           span.parent_span_id.iter().map(|v| format!("{:02x}", v)).collect::<String>(),
+					"name":                   span.name,
+					"kind":                   span.kind as u32,
+					"startTimeUnixNano":      span.start_time_unix_nano.to_string(),
+					"endTimeUnixNano":        span.end_time_unix_nano.to_string(),
+					"attributes":             encode_attributes(&span.attributes),
+					"droppedAttributesCount": span.dropped_attributes_count,
+					"events":                 span.events.iter().map(|event| serde_json::json!({
+						"timeUnixNano":           event.time_unix_nano.to_string(),
+						"name":                   event.name,
+						"attributes":             encode_attributes(&event.attributes),
+						"droppedAttributesCount": event.dropped_attributes_count
+					})).collect::<Vec<_>>(),
+					"droppedEventsCount":     span.dropped_events_count,
+					"links":                  span.links.iter().map(|link| serde_json::json!({
+						"traceId":                link.trace_id.iter().map(|v| format!("{:02x}", v)).collect::<String>(),
+						"spanId":                 link.span_id.iter().map(|v| format!("{:02x}", v)).collect::<String>(),
+						"traceState":             link.trace_state,
+						"attributes":             encode_attributes(&link.attributes),
+						"droppedAttributesCount": link.dropped_attributes_count
+					})).collect::<Vec<_>>(),
+					"droppedLinksCount":      span.dropped_links_count,
+					"status":                 {
+						"message": span.status.message,
+						"code":    span.status.code as u32
+					}
+				})).collect::<Vec<_>>()
+			}]
+		}]
+	})
+}
+
+fn encode_logs(config: &Config, logs: &[LogRecord]) -> serde_json::Value {
+	serde_json::json!({
+		"resourceLogs": [{
+			"resource": encode_resource(config),
+			"scopeLogs": [{
+				"scope": encode_scope(config),
+				"logRecords": logs.iter().map(|log| {
+					let mut record = serde_json::json!({
+						"timeUnixNano":           log.time_unix_nano.to_string(),
+						"severityNumber":         log.severity_number.map_or(0, |v| v as u32),
+						"attributes":             encode_attributes(log.attributes.as_deref().unwrap_or_default()),
+						"droppedAttributesCount": log.dropped_attributes_count.unwrap_or(0),
+						"flags":                  log.flags.unwrap_or(0)
+					});
+					
+					let fields = [
+						("severityText", log.severity_text.clone().map(serde_json::Value::String)),
+						("name", log.name.clone().map(serde_json::Value::String)),
+						("body", log.body.as_ref().map(encode_any_value)),
+						("traceId", log.trace_id.as_ref().map(|v| v.iter().map(|v| format!("{:02x}", v)).collect::<String>().into())),
+						("spanId", log.span_id.as_ref().map(|v| v.iter().map(|v| format!("{:02x}", v)).collect::<String>().into()))
+					];
+					
+					for (key, val) in fields {
+						if let Some(val) = val {
+							record[key] = val;
+						}
+					}
+					
+					record
+				}).collect::<Vec<_>>()
+			}]
+		}]
+	})
+}
+
+fn encode_resource(config: &Config) -> serde_json::Value {
+	serde_json::json!({ "attributes": encode_attributes(&config.resource) })
+}
+
+fn encode_scope(config: &Config) -> serde_json::Value {
+	match &config.instrumentation_library {
+		Some(v) => serde_json::json!({ "name": v.name, "version": v.version }),
+		None    => serde_json::json!({ "name": "otel-mrt", "version": VERSION })
+	}
+}
+
+fn encode_attributes(attributes: &[KeyValue]) -> serde_json::Value {
+	attributes.iter()
+		.map(|v| serde_json::json!({ "key": v.key, "value": encode_any_value(&v.value) }))
+		.collect()
+}
+
+fn encode_any_value(value: &AnyValue) -> serde_json::Value {
+	match value {
+		AnyValue::String(v)       => serde_json::json!({ "stringValue": v }),
+		AnyValue::Bool(v)         => serde_json::json!({ "boolValue": v }),
+		AnyValue::Int(v)          => serde_json::json!({ "intValue": v.to_string() }),
+		AnyValue::Double(v)       => serde_json::json!({ "doubleValue": v }),
+		AnyValue::Array(v)        => serde_json::json!({ "arrayValue": {
+			"values": v.values.iter().map(encode_any_value).collect::<Vec<_>>()
+		} }),
+		AnyValue::KeyValueList(v) => serde_json::json!({ "kvlistValue": { "values": encode_attributes(&v.values) } }),
+		AnyValue::Bytes(v)        => serde_json::json!({ "bytesValue": encode_base64(v) })
+	}
+}
+
+fn encode_base64(v: &[u8]) -> String {
+	const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+	
+	let mut buf = String::with_capacity((v.len() + 2) / 3 * 4);
+	for chunk in v.chunks(3) {
+		let n = chunk.iter().enumerate().fold(0u32, |n, (i, v)| n | (*v as u32) << (16 - i * 8));
+		for i in 0..4 {
+			match i <= chunk.len() {
+				true  => buf.push(ALPHABET[(n >> (18 - i * 6) & 0x3f) as usize] as char),
+				false => buf.push('=')
+			}
+		}
+	}
+	
+	buf
+}
+
 pub fn get_default_executor() -> Box<Executor> {
 	Box::new(|task| { std::thread::spawn(move || smol::block_on(task)); })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn metrics() {
+		let rt = Runtime::new(Config::disabled(), None);
+		let labels = |v: &str| vec![(Cow::Borrowed("branch"), AnyValue::String(v.to_string()))];
+		let a = rt.instrument::<usize>(InstrumentParameters::new()
+			.name_str("requests")
+			.aggregation_sum(AggregationTemporality::Unspecified, true))
+			.bind(labels("a"));
+		let b = rt.instrument::<usize>(InstrumentParameters::new()
+			.name_str("requests")
+			.aggregation_sum(AggregationTemporality::Unspecified, true))
+			.bind(labels("b"));
+		let state = rt.instrument::<f64>(InstrumentParameters::new()
+			.name_str("state")
+			.aggregation_last_value());
+		let delta = rt.instrument::<usize>(InstrumentParameters::new()
+			.name_str("delta")
+			.aggregation_histogram(AggregationTemporality::Delta))
+			.bind(labels("a"));
+		rt.instrument::<usize>(InstrumentParameters::new()
+			.name_str("empty")
+			.aggregation_sum(AggregationTemporality::Unspecified, true));
+		
+		a.record(1);
+		a.record(2);
+		b.record(5);
+		state.record(0.5, labels("a"));
+		state.record(1.5, labels("a"));
+		delta.record(3);
+		delta.record(4);
+		
+		let (count, data) = encode_metrics(&rt.0);
+		assert_eq!(count, 3);
+		
+		let metrics = data["resourceMetrics"][0]["scopeMetrics"][0]["metrics"].as_array().unwrap();
+		let metric = |name: &str| metrics.iter().find(|v| v["name"] == name).unwrap().clone();
+		
+		let requests = metric("requests");
+		assert_eq!(requests["sum"]["aggregationTemporality"], AggregationTemporality::Cumulative as u32);
+		assert_eq!(requests["sum"]["isMonotonic"], true);
+		let mut values = requests["sum"]["dataPoints"].as_array().unwrap().iter()
+			.map(|v| (v["attributes"][0]["value"]["stringValue"].as_str().unwrap().to_string(), v["asInt"].as_str().unwrap().to_string()))
+			.collect::<Vec<_>>();
+		values.sort();
+		assert_eq!(values, [("a".to_string(), "3".to_string()), ("b".to_string(), "5".to_string())]);
+		
+		assert_eq!(metric("state")["gauge"]["dataPoints"][0]["asDouble"], 1.5);
+		
+		let delta = metric("delta");
+		assert_eq!(delta["histogram"]["aggregationTemporality"], AggregationTemporality::Delta as u32);
+		assert_eq!(delta["histogram"]["dataPoints"][0]["count"], "2");
+		assert_eq!(delta["histogram"]["dataPoints"][0]["sum"], 7.0);
+		
+		// data points with delta temporality are reset after the export
+		let (count, data) = encode_metrics(&rt.0);
+		assert_eq!(count, 2);
+		assert!(data["resourceMetrics"][0]["scopeMetrics"][0]["metrics"].as_array().unwrap().iter().all(|v| v["name"] != "delta"));
+	}
+	
+	#[test]
+	fn requeue_records() {
+		let mut queue = vec![3, 4];
+		requeue("records", &mut queue, vec![1, 2], 3);
+		assert_eq!(queue, [1, 2, 3]);
+	}
+}
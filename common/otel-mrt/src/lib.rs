@@ -42,7 +42,7 @@ pub use {
 	tracing::*,
 	logging::*,
 	exporter::{Protocol, Compression},
-	net::otlp::{common::*, metrics::AggregationTemporality}
+	net::otlp::{common::*, metrics::AggregationTemporality, tracing::{SpanKind, StatusCode}}
 };
 
 pub mod metrics;
@@ -61,6 +61,8 @@ pub const DEFAULT_EXEMPLAR_FILTER: ExemplarFilter = ExemplarFilter::WithSampledT
 pub const DEFAULT_LENGTH_LIMIT:    usize          = usize::MAX;
 pub const DEFAULT_COUNT_LIMIT:     usize          = 128;
 pub const DEFAULT_BUF_LEN:         usize          = 256;
+pub const DEFAULT_LOG_QUEUE_LEN:   usize          = 0x1000;
+pub const DEFAULT_SPAN_QUEUE_LEN:  usize          = 0x1000;
 
 static RUNTIME: AtomicPtr<RuntimeInner> = AtomicPtr::new(ptr::null_mut());
 
@@ -89,7 +91,11 @@ pub unsafe fn set_global(runtime: Runtime) {
 
 pub fn get_global() -> Option<Runtime> {
 	let ptr = RUNTIME.load(Ordering::SeqCst);
-	(!ptr.is_null()).then(|| unsafe { Runtime(Arc::from_raw(ptr)) })
+	// the global reference is kept, the returned one is a new one
+	(!ptr.is_null()).then(|| unsafe {
+		Arc::increment_strong_count(ptr);
+		Runtime(Arc::from_raw(ptr))
+	})
 }
 
 #[derive(Clone)]
@@ -206,7 +212,7 @@ struct RuntimeInner {
 
 struct RuntimeSync {
 	instruments:    HashSet<Arc<InstrumentInner>>,
-	spans:          Vec<tracing::Span>,
+	spans:          Vec<net::otlp::tracing::Span>,
 	logs:           Vec<LogRecord>,
 	attribute_keys: HashMap<u64, Cow<'static, str>>,
 	attributes_buf: Vec<(u64, AnyValue)>,
@@ -263,12 +269,35 @@ impl Runtime {
 		Instrument::new_observable(self, params, callback)
 	}
 
-	pub fn span(&self, attributes: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>, links: impl IntoIterator<Item = Link>) -> Span {
-		unimplemented!()
+	/// Starts a span, it is recorded if the sampler decides so, given the parent.
+	pub fn span(&self, params: SpanParams) -> tracing::Span {
+		tracing::Span::new(self, params)
 	}
 
 	pub fn log(&self, params: LogParams) {
-		unimplemented!()
+		if self.0.config.disabled {
+			return;
+		}
+
+		let record = LogRecord {
+			time_unix_nano:           tracing::now_unix_nano(),
+			severity_number:          params.severity_number,
+			severity_text:            params.severity_text.map(Cow::into_owned),
+			name:                     params.name.map(Cow::into_owned),
+			body:                     params.body,
+			attributes:               params.attributes,
+			dropped_attributes_count: params.dropped_attributes_count,
+			flags:                    params.flags,
+			trace_id:                 params.span.map(|v| v.trace_id().to_be_bytes().to_vec()),
+			span_id:                  params.span.map(|v| v.span_id().to_be_bytes().to_vec())
+		};
+
+		let mut sync = self.0.sync.lock().expect("failed to lock runtime");
+
+		// records are dropped if the exporter does not keep up
+		if sync.logs.len() < DEFAULT_LOG_QUEUE_LEN {
+			sync.logs.push(record);
+		}
 	}
 
 	pub fn logger<T: log::Log>(&self, inner: T) -> Logger<T> {
@@ -31,7 +31,7 @@ pub struct LogParams<'a> {
 	pub attributes:               Option<Vec<KeyValue>>,
 	pub dropped_attributes_count: Option<u32>,
 	pub flags:                    Option<u32>,
-	pub span:                     Option<&'a Span>
+	pub span:                     Option<&'a tracing::Span>
 }
 
 impl<'a> LogParams<'a> {
@@ -79,7 +79,7 @@ impl<'a> LogParams<'a> {
 		self
 	}
 	
-	pub fn span(mut self, v: &'a Span) -> Self {
+	pub fn span(mut self, v: &'a tracing::Span) -> Self {
 		self.span = Some(v);
 		self
 	}
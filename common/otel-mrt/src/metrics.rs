@@ -244,7 +244,7 @@ pub struct BoundInstrument<T: DataPointValue> {
 impl<T: DataPointValue> BoundInstrument<T> {
 	pub fn record(&self, value: T) {
 		let sync = self.inner.inner.rt.0.sync.lock().expect("failed to lock runtime");
-		unsafe { &mut*self.inner.inner.data.get() }.record(self.hash, value.into());
+		unsafe { &mut*self.inner.inner.data.get() }.record(self.hash, &self.attrs, value.into());
 		std::mem::drop(sync);
 	}
 }
@@ -256,7 +256,15 @@ pub struct Instrument<T: DataPointValue> {
 }
 
 impl<T: DataPointValue> Instrument<T> {
+	/// Instruments with the same name share their data points, e.g. if an instrument is created
+	/// for each instance of a module, which are then distinguished by their attributes.
 	pub(crate) fn new(runtime: &Runtime, params: InstrumentParameters) -> Self {
+		let mut sync = runtime.0.sync.lock().expect("failed to lock runtime");
+		
+		if let Some(inner) = sync.instruments.iter().find(|v| v.name == params.name && v.call.is_none()) {
+			return Instrument { inner: inner.clone(), _marker: PhantomData };
+		}
+		
 		let inner = Arc::new(InstrumentInner {
 			name: params.name,
 			unit: params.unit,
@@ -266,11 +274,7 @@ impl<T: DataPointValue> Instrument<T> {
 			rt:   runtime.clone()
 		});
 		
-		runtime.0.sync.lock()
-			.expect("failed to lock runtime")
-			.instruments
-			.insert(inner.clone());
-		
+		sync.instruments.insert(inner.clone());
 		Instrument { inner, _marker: PhantomData }
 	}
 	
@@ -298,13 +302,15 @@ impl<T: DataPointValue> Instrument<T> {
 	}
 	
 	pub fn bind(self, attributes: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>) -> BoundInstrument<T> {
-		BoundInstrument { inner: self, hash: 0, attrs: attributes.into_iter().collect() }
+		let attrs = attributes.into_iter().collect::<Vec<_>>();
+		BoundInstrument { inner: self, hash: hash_attributes(&attrs), attrs }
 	}
 	
 	pub fn record(&self, value: T, attributes: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>) {
+		let attrs = attributes.into_iter().collect::<Vec<_>>();
+		let hash = hash_attributes(&attrs);
 		let sync = self.inner.rt.0.sync.lock().expect("failed to lock runtime");
-		let hash = 0; // TODO attr hash
-		unsafe { &mut*self.inner.data.get() }.record(hash, value.into());
+		unsafe { &mut*self.inner.data.get() }.record(hash, &attrs, value.into());
 		std::mem::drop(sync);
 	}
 }
@@ -320,13 +326,25 @@ impl<T: DataPointValue> std::fmt::Debug for Instrument<T> {
 	}
 }
 
+/// Data points are keyed by this hash of their attributes.
+fn hash_attributes(attrs: Attributes) -> u64 {
+	let mut hasher = std::collections::hash_map::DefaultHasher::new();
+	
+	for (key, value) in attrs {
+		key.hash(&mut hasher);
+		serde_json::to_string(value).unwrap_or_default().hash(&mut hasher);
+	}
+	
+	hasher.finish()
+}
+
 pub(crate) struct InstrumentInner {
-	name: Cow<'static, str>,
-	unit: Option<Cow<'static, str>>,
-	desc: Option<Cow<'static, str>>,
-	data: UnsafeCell<InstrumentData>,
-	call: Option<Box<dyn Fn(&Self) + Send + Sync + 'static>>,
-	rt:   Runtime
+	pub(crate) name: Cow<'static, str>,
+	pub(crate) unit: Option<Cow<'static, str>>,
+	pub(crate) desc: Option<Cow<'static, str>>,
+	pub(crate) data: UnsafeCell<InstrumentData>,
+	pub(crate) call: Option<Box<dyn Fn(&Self) + Send + Sync + 'static>>,
+	rt:              Runtime
 }
 
 unsafe impl Send for InstrumentInner {}
@@ -416,36 +434,51 @@ impl InstrumentData {
 		}
 	}
 	
-	fn record(&mut self, hash: u64, value: NumberDataPointValue) {
+	/// Removes all data points.
+	pub(crate) fn reset(&mut self) {
+		match self {
+			Self::Drop => (),
+			Self::SumI64 { data_points, .. } | Self::LastValueI64 { data_points } => data_points.clear(),
+			Self::SumF64 { data_points, .. } | Self::LastValueF64 { data_points } => data_points.clear(),
+			Self::HistogramI64 { data_points, .. } => data_points.clear(),
+			Self::HistogramF64 { data_points, .. } => data_points.clear()
+		}
+	}
+	
+		fn record(&mut self, hash: u64, attrs: Attributes, value: NumberDataPointValue) {
+		let new = || attrs.iter()
+			.map(|(key, value)| KeyValue { key: key.to_string(), value: value.clone() })
+			.collect::<Vec<_>>();
+		
 		match (self, value) {
 			(Self::SumI64 { data_points, .. }, NumberDataPointValue::I64(v)) => {
-				let data_point = data_points.entry(hash).or_insert_with(Default::default);
+				let data_point = data_points.entry(hash).or_insert_with(|| NumberDataPoint::new(new()));
 				data_point.time = Instant::now();
 				data_point.value += v;
 			}
 			(Self::SumF64 { data_points, .. }, NumberDataPointValue::F64(v)) => {
-				let data_point = data_points.entry(hash).or_insert_with(Default::default);
+				let data_point = data_points.entry(hash).or_insert_with(|| NumberDataPoint::new(new()));
 				data_point.time = Instant::now();
 				data_point.value += v;
 			}
 			(Self::LastValueI64 { data_points, .. }, NumberDataPointValue::I64(v)) => {
-				let data_point = data_points.entry(hash).or_insert_with(Default::default);
+				let data_point = data_points.entry(hash).or_insert_with(|| NumberDataPoint::new(new()));
 				data_point.time = Instant::now();
 				data_point.value = v;
 			}
 			(Self::LastValueF64 { data_points, .. }, NumberDataPointValue::F64(v)) => {
-				let data_point = data_points.entry(hash).or_insert_with(Default::default);
+				let data_point = data_points.entry(hash).or_insert_with(|| NumberDataPoint::new(new()));
 				data_point.time = Instant::now();
 				data_point.value = v;
 			}
 			(Self::HistogramI64 { data_points, .. }, NumberDataPointValue::I64(v)) => {
-				let data_point = data_points.entry(hash).or_insert_with(Default::default);
+				let data_point = data_points.entry(hash).or_insert_with(|| HistogramDataPoint::new(new()));
 				data_point.time = Instant::now();
 				data_point.count += 1;
 				data_point.sum += v;
 			}
 			(Self::HistogramF64 { data_points, .. }, NumberDataPointValue::F64(v)) => {
-				let data_point = data_points.entry(hash).or_insert_with(Default::default);
+				let data_point = data_points.entry(hash).or_insert_with(|| HistogramDataPoint::new(new()));
 				data_point.time = Instant::now();
 				data_point.count += 1;
 				data_point.sum += v;
@@ -472,6 +505,7 @@ impl std::fmt::Debug for InstrumentData {
 
 #[derive(Clone, Debug)]
 pub struct NumberDataPoint<T> {
+	pub attributes: Vec<KeyValue>,
 	pub start_time: Instant,
 	pub time:       Instant,
 	pub value:      T,
@@ -479,9 +513,16 @@ pub struct NumberDataPoint<T> {
 	pub flags:      u32
 }
 
+impl<T: Default> NumberDataPoint<T> {
+	pub fn new(attributes: Vec<KeyValue>) -> Self {
+		Self { attributes, ..Self::default() }
+	}
+}
+
 impl<T: Default> Default for NumberDataPoint<T> {
 	fn default() -> Self {
 		Self {
+			attributes: Vec::new(),
 			start_time: Instant::now(),
 			time:       Instant::now(),
 			value:      T::default(),
@@ -492,6 +533,7 @@ impl<T: Default> Default for NumberDataPoint<T> {
 }
 
 pub struct HistogramDataPoint<T> {
+	pub attributes:           Vec<KeyValue>,
 	pub start_time:           Instant,
 	pub time:                 Instant,
 	pub count:                u64,
@@ -502,9 +544,16 @@ pub struct HistogramDataPoint<T> {
 	pub flags:                u32
 }
 
+impl<T: Default> HistogramDataPoint<T> {
+	pub fn new(attributes: Vec<KeyValue>) -> Self {
+		Self { attributes, ..Self::default() }
+	}
+}
+
 impl<T: Default> Default for HistogramDataPoint<T> {
 	fn default() -> Self {
 		Self {
+			attributes:      Vec::new(),
 			start_time:      Instant::now(),
 			time:            Instant::now(),
 			count:           0,
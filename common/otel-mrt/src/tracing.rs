@@ -58,64 +58,494 @@ impl Sampler {
 	}
 }
 
+impl Sampler {
+	/// Decides whether a new span with this parent and trace id is recorded.
+	pub fn should_sample(&self, parent: Option<&SpanContext>, trace_id: u128) -> bool {
+		let ratio = |probability: f32| (trace_id as u64) < (probability as f64 * u64::MAX as f64) as u64;
+		
+		match (self, parent) {
+			(Self::AlwaysOn, _)                                          => true,
+			(Self::AlwaysOff, _)                                         => false,
+			(Self::TraceIdRatio { probability }, _)                      => ratio(*probability),
+			(Self::ParentBasedAlwaysOn
+			| Self::ParentBasedAlwaysOff
+			| Self::ParentBasedTraceIdRatio { .. }, Some(parent))        => parent.sampled,
+			(Self::ParentBasedAlwaysOn, None)                            => true,
+			(Self::ParentBasedAlwaysOff, None)                           => false,
+			(Self::ParentBasedTraceIdRatio { probability }, None)        => ratio(*probability)
+		}
+	}
+}
+
+pub const HEADER_TRACEPARENT:   &str = "traceparent";
+pub const HEADER_TRACESTATE:    &str = "tracestate";
+pub const HEADER_B3:            &str = "b3";
+pub const HEADER_B3_TRACE_ID:   &str = "x-b3-traceid";
+pub const HEADER_B3_SPAN_ID:    &str = "x-b3-spanid";
+pub const HEADER_B3_PARENT_ID:  &str = "x-b3-parentspanid";
+pub const HEADER_B3_SAMPLED:    &str = "x-b3-sampled";
+pub const HEADER_B3_FLAGS:      &str = "x-b3-flags";
+
+const PROPAGATION_HEADERS: [&str; 8] = [
+	HEADER_TRACEPARENT,
+	HEADER_TRACESTATE,
+	HEADER_B3,
+	HEADER_B3_TRACE_ID,
+	HEADER_B3_SPAN_ID,
+	HEADER_B3_PARENT_ID,
+	HEADER_B3_SAMPLED,
+	HEADER_B3_FLAGS
+];
+
+/// The part of a span that is propagated to other processes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpanContext {
+	pub trace_id:    u128,
+	pub span_id:     u64,
+	pub sampled:     bool,
+	pub trace_state: String,
+	/// Whether the context was extracted from a request.
+	pub remote:      bool
+}
+
+impl SpanContext {
+	pub fn is_valid(&self) -> bool {
+		self.trace_id != 0 && self.span_id != 0
+	}
+	
+	/// Parses a W3C `traceparent` header, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+	pub fn from_traceparent(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+		let mut parts = traceparent.trim().split('-');
+		let version = parts.next().filter(|v| v.len() == 2 && *v != "ff")?;
+		let trace_id = parts.next().filter(|v| v.len() == 32)?;
+		let span_id = parts.next().filter(|v| v.len() == 16)?;
+		let flags = parts.next().filter(|v| v.len() == 2)?;
+		
+		// future versions may append fields
+		if version == "00" && parts.next().is_some() {
+			return None;
+		}
+		
+		Some(Self {
+			trace_id:    u128::from_str_radix(trace_id, 16).ok()?,
+			span_id:     u64::from_str_radix(span_id, 16).ok()?,
+			sampled:     u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+			trace_state: tracestate.unwrap_or_default().trim().to_string(),
+			remote:      true
+		}).filter(Self::is_valid)
+	}
+	
+	pub fn to_traceparent(&self) -> String {
+		format!("00-{:032x}-{:016x}-{:02x}", self.trace_id, self.span_id, self.sampled as u8)
+	}
+	
+	/// Parses a single B3 header, e.g. `80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1`.
+	pub fn from_b3(b3: &str) -> Option<Self> {
+		let mut parts = b3.trim().split('-');
+		let trace_id = parts.next()?;
+		let span_id = parts.next()?;
+		Self::from_b3_parts(trace_id, span_id, parts.next(), None)
+	}
+	
+	/// Parses the `X-B3-*` headers.
+	pub fn from_b3_parts(trace_id: &str, span_id: &str, sampled: Option<&str>, flags: Option<&str>) -> Option<Self> {
+		if trace_id.len() != 16 && trace_id.len() != 32 || span_id.len() != 16 {
+			return None;
+		}
+		
+		Some(Self {
+			trace_id:    u128::from_str_radix(trace_id, 16).ok()?,
+			span_id:     u64::from_str_radix(span_id, 16).ok()?,
+			sampled:     matches!(sampled, Some("1" | "d" | "true")) || flags == Some("1"),
+			trace_state: String::new(),
+			remote:      true
+		}).filter(Self::is_valid)
+	}
+	
+	pub fn to_b3(&self) -> String {
+		format!("{:032x}-{:016x}-{}", self.trace_id, self.span_id, self.sampled as u8)
+	}
+	
+	/// Extracts the context from W3C trace context headers, or B3 headers if they are absent.
+	pub fn extract<'a>(headers: impl IntoIterator<Item = &'a net::http::Header>) -> Option<Self> {
+		let mut values: [Option<&str>; 8] = Default::default();
+		
+		for header in headers {
+			if let net::http::Header::Custom(k, v) = header {
+				if let Some(i) = PROPAGATION_HEADERS.iter().position(|h| h.eq_ignore_ascii_case(k)) {
+					values[i].get_or_insert(v);
+				}
+			}
+		}
+		
+		let [traceparent, tracestate, b3, b3_trace_id, b3_span_id, _, b3_sampled, b3_flags] = values;
+		traceparent.and_then(|v| Self::from_traceparent(v, tracestate))
+			.or_else(|| b3.and_then(Self::from_b3))
+			.or_else(|| Self::from_b3_parts(b3_trace_id?, b3_span_id?, b3_sampled, b3_flags))
+	}
+	
+	/// Replaces all propagation headers with the W3C trace context headers of this context.
+	/// If the request carried B3 headers, a single B3 header is added as well.
+	pub fn inject(&self, headers: &mut Vec<net::http::Header>) {
+		let mut b3 = false;
+		headers.retain(|header| match header {
+			net::http::Header::Custom(k, _) if PROPAGATION_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(k)) => {
+				let k = k.to_ascii_lowercase();
+				b3 |= k == HEADER_B3 || k.starts_with("x-b3-");
+				false
+			}
+			_ => true
+		});
+		
+		headers.push(net::http::Header::Custom(HEADER_TRACEPARENT.to_string(), self.to_traceparent()));
+		
+		if !self.trace_state.is_empty() {
+			headers.push(net::http::Header::Custom(HEADER_TRACESTATE.to_string(), self.trace_state.clone()));
+		}
+		
+		if b3 {
+			headers.push(net::http::Header::Custom(HEADER_B3.to_string(), self.to_b3()));
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct SpanParams<'a> {
+	pub name:       Cow<'static, str>,
+	pub kind:       SpanKind,
+	pub parent:     Option<&'a SpanContext>,
+	pub attributes: Vec<(Cow<'static, str>, AnyValue)>,
+	pub links:      Vec<Link>
+}
+
+impl<'a> SpanParams<'a> {
+	pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+		Self {
+			name:       name.into(),
+			kind:       SpanKind::Internal,
+			parent:     None,
+			attributes: Vec::new(),
+			links:      Vec::new()
+		}
+	}
+	
+	pub fn kind(mut self, v: SpanKind) -> Self {
+		self.kind = v;
+		self
+	}
+	
+	pub fn parent(mut self, v: Option<&'a SpanContext>) -> Self {
+		self.parent = v;
+		self
+	}
+	
+	pub fn attribute(mut self, key: impl Into<Cow<'static, str>>, val: AnyValue) -> Self {
+		self.attributes.push((key.into(), val));
+		self
+	}
+	
+	pub fn attributes(mut self, v: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>) -> Self {
+		self.attributes.extend(v);
+		self
+	}
+	
+	pub fn link(mut self, v: Link) -> Self {
+		self.links.push(v);
+		self
+	}
+}
+
 #[derive(Debug)]
 pub struct Span {
-	trace_id:       u128,
-	span_id:        u128,
+	context:        SpanContext,
+	parent_span_id: Option<u64>,
 	span_kind:      SpanKind,
-	parent:         Option<Arc<Self>>,
+	start:          u64,
+	links:          Vec<Link>,
+	data:           Mutex<SpanData>,
+	runtime:        Runtime
+}
+
+#[derive(Debug)]
+struct SpanData {
 	name:           Cow<'static, str>,
-	start:          u128,
-	end:            u128,
 	attributes:     Vec<(Cow<'static, str>, AnyValue)>,
-	links:          Vec<Link>,
 	events:         Vec<Event>,
 	status_code:    Option<StatusCode>,
-	status_message: Option<Cow<'static, str>>,
-	runtime:        Runtime
+	status_message: Option<Cow<'static, str>>
 }
 
 impl Span {
+	pub(crate) fn new(runtime: &Runtime, params: SpanParams) -> Self {
+		let parent = params.parent.filter(|v| v.is_valid());
+		let trace_id = parent.map_or_else(random_trace_id, |v| v.trace_id);
+		let config = runtime.config();
+		
+		Self {
+			context:        SpanContext {
+				trace_id,
+				span_id:     random_span_id(),
+				sampled:     !config.disabled && config.traces_sampler.should_sample(parent, trace_id),
+				trace_state: parent.map(|v| v.trace_state.clone()).unwrap_or_default(),
+				remote:      false
+			},
+			parent_span_id: parent.map(|v| v.span_id),
+			span_kind:      params.kind,
+			start:          now_unix_nano(),
+			links:          params.links,
+			data:           Mutex::new(SpanData {
+				name:           params.name,
+				attributes:     params.attributes,
+				events:         Vec::new(),
+				status_code:    None,
+				status_message: None
+			}),
+			runtime:        runtime.clone()
+		}
+	}
+	
+	pub fn context(&self) -> &SpanContext {
+		&self.context
+	}
+	
+	pub fn trace_id(&self) -> u128 {
+		self.context.trace_id
+	}
+	
+	pub fn span_id(&self) -> u64 {
+		self.context.span_id
+	}
+	
 	pub fn is_recording(&self) -> bool {
-		unimplemented!()
+		self.context.sampled
 	}
 	
-	pub fn set_attribute(&self, key: Cow<'static, str>, val: AnyValue) {
-		unimplemented!()
+	pub fn set_attribute(&self, key: impl Into<Cow<'static, str>>, val: AnyValue) {
+		if self.is_recording() {
+			self.data().attributes.push((key.into(), val));
+		}
 	}
 	
-	pub fn set_attributes(&self, key: Cow<'static, str>, val: AnyValue) {
-		unimplemented!()
+	pub fn set_attributes(&self, attributes: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>) {
+		if self.is_recording() {
+			self.data().attributes.extend(attributes);
+		}
 	}
 	
-	pub fn add_event(&self, name: Cow<'static, str>, attributes: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>, timestamp: Option<u128>) {
-		unimplemented!()
+	/// Adds an event, `timestamp` is in nanoseconds since the unix epoch and defaults to now.
+	pub fn add_event(&self, name: impl Into<Cow<'static, str>>, attributes: impl IntoIterator<Item = (Cow<'static, str>, AnyValue)>, timestamp: Option<u64>) {
+		if self.is_recording() {
+			self.data().events.push(Event {
+				name:       name.into(),
+				attributes: attributes.into_iter().collect(),
+				timestamp:  timestamp.unwrap_or_else(now_unix_nano)
+			});
+		}
 	}
 	
-	pub fn set_status(&self) {
-		unimplemented!()
+	pub fn set_status(&self, code: StatusCode, message: Option<Cow<'static, str>>) {
+		let mut data = self.data();
+		
+		// `Ok` is final
+		if data.status_code != Some(StatusCode::Ok) {
+			data.status_code = Some(code);
+			data.status_message = message;
+		}
 	}
 	
-	pub fn update_name(&self, name: Cow<'static, str>) {
-		unimplemented!()
+	pub fn update_name(&self, name: impl Into<Cow<'static, str>>) {
+		self.data().name = name.into();
 	}
 	
+	/// Ends the span and queues it for export, if it is recorded.
 	pub fn end(self) {
-		self.runtime.0.clone()
-			.sync
-			.lock().unwrap()
-			.spans
-			.push(self);
-		unimplemented!()
+		if !self.is_recording() {
+			return;
+		}
+		
+		let end = now_unix_nano();
+		let config = self.runtime.config();
+		let data = self.data.into_inner().unwrap_or_else(|e| e.into_inner());
+		let (attributes, dropped_attributes_count) = limit_attributes(
+			data.attributes, config.span_attribute_count_limit, config.span_attribute_value_length_limit);
+		let dropped_events_count = data.events.len().saturating_sub(config.span_event_count_limit) as u32;
+		let dropped_links_count = self.links.len().saturating_sub(config.span_link_count_limit) as u32;
+		
+		let span = net::otlp::tracing::Span {
+			trace_id:                 self.context.trace_id.to_be_bytes().to_vec(),
+			span_id:                  self.context.span_id.to_be_bytes().to_vec(),
+			trace_state:              self.context.trace_state,
+			parent_span_id:           self.parent_span_id.map_or_else(Vec::new, |v| v.to_be_bytes().to_vec()),
+			name:                     data.name.into_owned(),
+			kind:                     self.span_kind,
+			start_time_unix_nano:     self.start,
+			end_time_unix_nano:       end,
+			attributes,
+			dropped_attributes_count,
+			events:                   data.events.into_iter()
+				.take(config.span_event_count_limit)
+				.map(|v| {
+					let (attributes, dropped_attributes_count) = limit_attributes(
+						v.attributes, config.span_event_attribute_count_limit, config.span_attribute_value_length_limit);
+					net::otlp::tracing::Event {
+						time_unix_nano: v.timestamp,
+						name:           v.name.into_owned(),
+						attributes,
+						dropped_attributes_count
+					}
+				})
+				.collect(),
+			dropped_events_count,
+			links:                    self.links.into_iter()
+				.take(config.span_link_count_limit)
+				.map(|v| {
+					let (attributes, dropped_attributes_count) = limit_attributes(
+						v.attributes, config.span_link_attribute_count_limit, config.span_attribute_value_length_limit);
+					net::otlp::tracing::Link {
+						trace_id:       v.context.trace_id.to_be_bytes().to_vec(),
+						span_id:        v.context.span_id.to_be_bytes().to_vec(),
+						trace_state:    v.context.trace_state,
+						attributes,
+						dropped_attributes_count
+					}
+				})
+				.collect(),
+			dropped_links_count,
+			status:                   net::otlp::tracing::Status {
+				message: data.status_message.map(Cow::into_owned).unwrap_or_default(),
+				code:    data.status_code.unwrap_or(StatusCode::Unset)
+			}
+		};
+		
+		let mut sync = self.runtime.0.sync.lock().expect("failed to lock runtime");
+		
+		// spans are dropped if the exporter does not keep up
+		if sync.spans.len() < DEFAULT_SPAN_QUEUE_LEN {
+			sync.spans.push(span);
+		}
+	}
+	
+	fn data(&self) -> std::sync::MutexGuard<SpanData> {
+		self.data.lock().unwrap_or_else(|e| e.into_inner())
 	}
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Link {
-	
+	pub context:    SpanContext,
+	pub attributes: Vec<(Cow<'static, str>, AnyValue)>
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Event {
+	name:       Cow<'static, str>,
+	attributes: Vec<(Cow<'static, str>, AnyValue)>,
+	timestamp:  u64
+}
+
+fn limit_attributes(attributes: Vec<(Cow<'static, str>, AnyValue)>, count_limit: usize, len_limit: usize) -> (Vec<KeyValue>, u32) {
+	let dropped = attributes.len().saturating_sub(count_limit) as u32;
+	let attributes = attributes.into_iter()
+		.take(count_limit)
+		.map(|(key, value)| KeyValue {
+			key:   key.into_owned(),
+			value: match value {
+				AnyValue::String(mut v) if v.len() > len_limit => {
+					let mut len = len_limit;
+					while !v.is_char_boundary(len) {
+						len -= 1;
+					}
+					
+					v.truncate(len);
+					AnyValue::String(v)
+				}
+				v => v
+			}
+		})
+		.collect();
 	
-}
\ No newline at end of file
+	(attributes, dropped)
+}
+
+pub(crate) fn now_unix_nano() -> u64 {
+	std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map_or(0, |v| v.as_nanos() as _)
+}
+
+/// A random, non-zero value. The keys of `RandomState` are random and hashing a counter ensures
+/// that consecutive values differ.
+fn random_u64() -> u64 {
+	use std::{hash::BuildHasher, sync::atomic::AtomicU64};
+	
+	static COUNTER: AtomicU64 = AtomicU64::new(0);
+	
+	loop {
+		let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+		hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+		hasher.write_u64(now_unix_nano());
+		
+		match hasher.finish() {
+			0 => continue,
+			v => return v
+		}
+	}
+}
+
+fn random_trace_id() -> u128 {
+	(random_u64() as u128) << 64 | random_u64() as u128
+}
+
+fn random_span_id() -> u64 {
+	random_u64()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn traceparent() {
+		let ctx = SpanContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", Some("congo=t61rcWkgMzE")).unwrap();
+		assert_eq!(ctx.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+		assert_eq!(ctx.span_id, 0x00f067aa0ba902b7);
+		assert!(ctx.sampled);
+		assert_eq!(ctx.trace_state, "congo=t61rcWkgMzE");
+		assert_eq!(ctx.to_traceparent(), "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+		
+		assert!(SpanContext::from_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01", None).is_none());
+		assert!(SpanContext::from_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", None).is_none());
+		assert!(SpanContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7", None).is_none());
+	}
+	
+	#[test]
+	fn b3() {
+		let ctx = SpanContext::from_b3("80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1-05e3ac9a4f6e3b90").unwrap();
+		assert_eq!(ctx.trace_id, 0x80f198ee56343ba864fe8b2a57d3eff7);
+		assert_eq!(ctx.span_id, 0xe457b5a2e4d86bd1);
+		assert!(ctx.sampled);
+		
+		let ctx = SpanContext::from_b3_parts("a3ce929d0e0e4736", "00f067aa0ba902b7", Some("0"), None).unwrap();
+		assert_eq!(ctx.trace_id, 0xa3ce929d0e0e4736);
+		assert!(!ctx.sampled);
+	}
+	
+	#[test]
+	fn extract_inject() {
+		let mut headers = vec![
+			net::http::Header::Custom("X-B3-TraceId".to_string(), "80f198ee56343ba864fe8b2a57d3eff7".to_string()),
+			net::http::Header::Custom("X-B3-SpanId".to_string(), "e457b5a2e4d86bd1".to_string()),
+			net::http::Header::Custom("X-B3-Sampled".to_string(), "1".to_string())
+		];
+		
+		let mut ctx = SpanContext::extract(&headers).unwrap();
+		assert_eq!(ctx.trace_id, 0x80f198ee56343ba864fe8b2a57d3eff7);
+		
+		ctx.span_id = 1;
+		ctx.inject(&mut headers);
+		assert_eq!(headers, vec![
+			net::http::Header::Custom(HEADER_TRACEPARENT.to_string(), "00-80f198ee56343ba864fe8b2a57d3eff7-0000000000000001-01".to_string()),
+			net::http::Header::Custom(HEADER_B3.to_string(), "80f198ee56343ba864fe8b2a57d3eff7-0000000000000001-1".to_string())
+		]);
+	}
+}
@@ -151,6 +151,12 @@ impl<T: futures_lite::io::AsyncRead + futures_lite::io::AsyncWrite> AsyncBufStre
 		Self(futures_lite::io::BufReader::with_capacity(
 			capacity, InternalAsyncBufWriter(futures_lite::io::BufWriter::with_capacity(capacity, inner))))
 	}
+	
+	/// Returns the stream, discarding data that was read ahead, e.g. before a TLS handshake.
+	/// Written data has to be flushed before.
+	pub fn into_inner(self) -> T {
+		self.0.into_inner().0.into_inner()
+	}
 }
 
 impl<T: futures_lite::io::AsyncRead + futures_lite::io::AsyncWrite> futures_lite::io::AsyncRead for AsyncBufStream<T> {
@@ -22,6 +22,7 @@
 
 use {
 	crate::dns::DomainName,
+	super::wire::{WireReader, WireWriter},
 	std::{io, str::FromStr, fmt}
 };
 use crate::dns::{ZoneParseError, ZoneParseErrorType};
@@ -37,8 +38,11 @@ pub struct ResourceRecord {
 impl FromStr for ResourceRecord {
 	type Err = ZoneParseError;
 	
+	/// Parses a single record line with an absolute owner name and explicit TTL.
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+		let zone = format!("$ORIGIN .\n{}\n", s).parse::<super::Zone>()?;
+		zone.records.into_iter().next()
+			.ok_or_else(|| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -64,7 +68,7 @@ impl TryFrom<u16> for Class {
 	
 	fn try_from(value: u16) -> Result<Self, Self::Error> {
 		match value {
-			1..=4 | 245..=255 => Ok(unsafe { std::mem::transmute(value) }),
+			1..=4 | 254..=255 => Ok(unsafe { std::mem::transmute(value) }),
 			_ => Err(())
 		}
 	}
@@ -194,7 +198,7 @@ impl TryFrom<u16> for Type {
 	
 	fn try_from(value: u16) -> Result<Self, Self::Error> {
 		match value {
-			1..=53 | 55..=65 | 99..=109 | 249..=260 => Ok(unsafe { std::mem::transmute(value) }),
+			1..=53 | 55..=65 | 99..=109 | 249..=250 | 252..=260 => Ok(unsafe { std::mem::transmute(value) }),
 			_ => Err(())
 		}
 	}
@@ -222,11 +226,12 @@ impl FromStr for Type {
 			"MX"         => Self::MX,
 			"TXT"        => Self::TXT,
 			"RP"         => Self::RP,
+			"AFSDB"      => Self::AFSB,
 			"X25"        => Self::X25,
 			"ISDN"       => Self::ISDN,
 			"RT"         => Self::RT,
 			"NSAP"       => Self::Nsap,
-			"NSAP_PTR"   => Self::Ptr,
+			"NSAP-PTR"   => Self::NsapPtr,
 			"SIG"        => Self::Sig,
 			"KEY"        => Self::Key,
 			"PX"         => Self::PX,
@@ -238,7 +243,7 @@ impl FromStr for Type {
 			"NIMLOC"     => Self::NimLoc,
 			"SRV"        => Self::Srv,
 			"ATMA"       => Self::ATMA,
-			"NAP_PTR"    => Self::Ptr,
+			"NAPTR"      => Self::NaPtr,
 			"KX"         => Self::KX,
 			"CERT"       => Self::Cert,
 			"A6"         => Self::A6,
@@ -403,13 +408,25 @@ impl FromStr for SoaRecord {
 	type Err = ZoneParseError;
 	
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+		let mut s = s.split_ascii_whitespace();
+		let mut next = || s.next().ok_or_else(|| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0));
+		
+		Ok(Self {
+			name_server: next()?.to_string(),
+			mailbox:     next()?.to_string(),
+			serial:      next()?.parse().map_err(|_| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))?,
+			refresh:     super::zone::parse_ttl(next()?)?,
+			retry:       super::zone::parse_ttl(next()?)?,
+			expire:      super::zone::parse_ttl(next()?)?,
+			minimum:     super::zone::parse_ttl(next()?)?
+		})
 	}
 }
 
 impl fmt::Display for SoaRecord {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		unimplemented!()
+		write!(f, "{} {} {} {} {} {} {}", &self.name_server, &self.mailbox,
+			self.serial, self.refresh, self.retry, self.expire, self.minimum)
 	}
 }
 
@@ -423,8 +440,8 @@ pub struct WksRecord {
 impl FromStr for WksRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -452,8 +469,8 @@ impl HardwareInfoRecord {
 impl FromStr for HardwareInfoRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -472,8 +489,8 @@ pub struct MailInfoRecord {
 impl FromStr for MailInfoRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -493,13 +510,19 @@ impl FromStr for MailExchangeRecord {
 	type Err = ZoneParseError;
 	
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+		match s.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+			[preference, exchange] => Ok(Self {
+				preference: preference.parse().map_err(|_| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))?,
+				exchange:   exchange.to_string()
+			}),
+			_ => Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
+		}
 	}
 }
 
 impl fmt::Display for MailExchangeRecord {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		unimplemented!()
+		write!(f, "{} {}", self.preference, &self.exchange)
 	}
 }
 
@@ -512,8 +535,8 @@ pub struct ResponsiblePerson {
 impl FromStr for ResponsiblePerson {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -532,8 +555,8 @@ pub struct IsdnRecord {
 impl FromStr for IsdnRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -552,8 +575,8 @@ pub struct RouteThroughRecord {
 impl FromStr for RouteThroughRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -569,8 +592,8 @@ pub struct NsapRecord(pub Vec<u8>);
 impl FromStr for NsapRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -595,8 +618,8 @@ pub struct AFSDataBaseRecord {
 impl FromStr for AFSDataBaseRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -622,8 +645,8 @@ pub struct SignatureRecord {
 impl FromStr for SignatureRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -644,8 +667,8 @@ pub struct KeyRecord {
 impl FromStr for KeyRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -665,8 +688,8 @@ pub struct PxRecord {
 impl FromStr for PxRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -686,8 +709,8 @@ pub struct GPosRecord {
 impl FromStr for GPosRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -711,8 +734,8 @@ pub struct LocationRecord {
 impl FromStr for LocationRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -731,8 +754,8 @@ pub struct NxtRecord {
 impl FromStr for NxtRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -754,13 +777,23 @@ impl FromStr for SrvRecord {
 	type Err = ZoneParseError;
 	
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+		let err = |_| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0);
+		
+		match s.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+			[priority, weight, port, target] => Ok(Self {
+				priority: priority.parse().map_err(err)?,
+				weight:   weight.parse().map_err(err)?,
+				port:     port.parse().map_err(err)?,
+				target:   target.to_string()
+			}),
+			_ => Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
+		}
 	}
 }
 
 impl fmt::Display for SrvRecord {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		unimplemented!()
+		write!(f, "{} {} {} {}", self.priority, self.weight, self.port, &self.target)
 	}
 }
 
@@ -777,8 +810,8 @@ pub struct NaPtr {
 impl FromStr for NaPtr {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -797,8 +830,8 @@ pub struct KeyExchangeRecord {
 impl FromStr for KeyExchangeRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -819,8 +852,8 @@ pub struct CertRecord {
 impl FromStr for CertRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -840,8 +873,8 @@ pub struct A6Record {
 impl FromStr for A6Record {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -861,8 +894,8 @@ pub struct SinkRecord {
 impl FromStr for SinkRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -883,8 +916,8 @@ pub struct AplRecord {
 impl FromStr for AplRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -905,8 +938,8 @@ pub struct DsRecord {
 impl FromStr for DsRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -926,8 +959,8 @@ pub struct SshFpRecord {
 impl FromStr for SshFpRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -955,8 +988,8 @@ pub enum IpSecKeyRecordGateway {
 impl FromStr for IpSecKeyRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -982,8 +1015,8 @@ pub struct RRSigRecord {
 impl FromStr for RRSigRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1002,8 +1035,8 @@ pub struct NSecRecord {
 impl FromStr for NSecRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1024,8 +1057,8 @@ pub struct DnsKeyRecord {
 impl FromStr for DnsKeyRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1045,8 +1078,8 @@ pub struct DHCIDRecord {
 impl FromStr for DHCIDRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1064,8 +1097,8 @@ pub struct NSec3Record {
 impl FromStr for NSec3Record {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1083,8 +1116,8 @@ pub struct NSec3ParamRecord {
 impl FromStr for NSec3ParamRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1105,8 +1138,8 @@ pub struct TlsaRecord {
 impl FromStr for TlsaRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1124,8 +1157,8 @@ pub struct HipRecord {
 impl FromStr for HipRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1146,8 +1179,8 @@ pub struct RKeyRecord {
 impl FromStr for RKeyRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1166,8 +1199,8 @@ pub struct TALinkRecord {
 impl FromStr for TALinkRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1187,8 +1220,8 @@ pub struct CSyncRecord {
 impl FromStr for CSyncRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1209,8 +1242,8 @@ pub struct ZoneMDRecord {
 impl FromStr for ZoneMDRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1229,8 +1262,8 @@ pub struct NIDRecord {
 impl FromStr for NIDRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1249,8 +1282,8 @@ pub struct L32Record {
 impl FromStr for L32Record {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1269,8 +1302,8 @@ pub struct L64Record {
 impl FromStr for L64Record {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1289,8 +1322,8 @@ pub struct LPRecord {
 impl FromStr for LPRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1308,8 +1341,8 @@ pub struct TSigRecord {
 impl FromStr for TSigRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1325,8 +1358,8 @@ pub struct EUI48Record([u8; 6]);
 impl FromStr for EUI48Record {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1342,8 +1375,8 @@ pub struct EUI64Record([u8; 8]);
 impl FromStr for EUI64Record {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1361,8 +1394,8 @@ pub struct TKeyRecord {
 impl FromStr for TKeyRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1382,8 +1415,8 @@ pub struct UriRecord {
 impl FromStr for UriRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1404,13 +1437,21 @@ impl FromStr for CaaRecord {
 	type Err = ZoneParseError;
 	
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+		let mut s = s.trim().splitn(3, |c: char| c.is_ascii_whitespace());
+		let err   = || ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0);
+		
+		Ok(Self {
+			flags: s.next().ok_or_else(err)?.parse().map_err(|_| err())?,
+			tag:   s.next().filter(|v| !v.is_empty() && v.bytes().all(|c| c.is_ascii_alphanumeric()))
+				.ok_or_else(err)?.to_string(),
+			value: unquote(s.next().ok_or_else(err)?)?.into_bytes()
+		})
 	}
 }
 
 impl fmt::Display for CaaRecord {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		unimplemented!()
+		write!(f, "{} {} {}", self.flags, &self.tag, quote(&String::from_utf8_lossy(&self.value)))
 	}
 }
 
@@ -1423,8 +1464,8 @@ pub struct AmtRelayRecord {
 impl FromStr for AmtRelayRecord {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1440,8 +1481,8 @@ pub struct NimrodLocator(pub Vec<u8>);
 impl FromStr for NimrodLocator {
 	type Err = ZoneParseError;
 	
-	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(_s: &str) -> Result<Self, Self::Err> {
+		Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0))
 	}
 }
 
@@ -1459,6 +1500,47 @@ pub enum AmtRelayRecordRelay {
 	Domain(DomainName)
 }
 
+/// EDNS(0) pseudo record ([RFC 6891](https://datatracker.ietf.org/doc/html/rfc6891)),
+/// whose fields are carried in the class and TTL of the record.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OptRecord {
+	pub udp_payload_size: u16,
+	pub extended_rcode:   u8,
+	pub version:          u8,
+	pub dnssec_ok:        bool,
+	pub options:          Vec<(u16, Vec<u8>)>
+}
+
+impl OptRecord {
+	pub(super) fn read(class: u16, ttl: u32, mut data: &[u8]) -> io::Result<Self> {
+		let mut options = Vec::new();
+		
+		while !data.is_empty() {
+			let len = match data {
+				[_, _, a, b, ..] => u16::from_be_bytes([*a, *b]) as usize,
+				_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid EDNS option"))
+			};
+			let option = data.get(4..4 + len).ok_or_else(|| io::Error::new(
+				io::ErrorKind::InvalidData, "invalid EDNS option"))?;
+			options.push((u16::from_be_bytes([data[0], data[1]]), option.to_vec()));
+			data = &data[4 + len..];
+		}
+		
+		Ok(Self {
+			udp_payload_size: class,
+			extended_rcode:   (ttl >> 24) as u8,
+			version:          (ttl >> 16) as u8,
+			dnssec_ok:        ttl & 0x8000 != 0,
+			options
+		})
+	}
+	
+	pub(super) fn class_and_ttl(&self) -> (u16, u32) {
+		(self.udp_payload_size, (self.extended_rcode as u32) << 24
+			| (self.version as u32) << 16 | if self.dnssec_ok { 0x8000 } else { 0 })
+	}
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum UnimplementedRecord {
 	Wire { id: u16, data: Box<[u8]> },
@@ -1507,7 +1589,7 @@ pub enum ResourceRecordData {
 	A6(A6Record),
 	DName(DomainName),
 	Sink(SinkRecord),
-	OPT,
+	OPT(OptRecord),
 	APL(AplRecord),
 	DS(DsRecord),
 	SSHFP(SshFpRecord),
@@ -1599,7 +1681,7 @@ impl ResourceRecordData {
 			Self::A6(_)         => Type::A6,
 			Self::DName(_)      => Type::DName,
 			Self::Sink(_)       => Type::SINK,
-			Self::OPT           => Type::OPT,
+			Self::OPT(_)        => Type::OPT,
 			Self::APL(_)        => Type::APL,
 			Self::DS(_)         => Type::DS,
 			Self::SSHFP(_)      => Type::SSHFP,
@@ -1652,12 +1734,153 @@ impl ResourceRecordData {
 		})
 	}
 	
-	pub(super) fn read(ty: u16, data: &[u8]) -> io::Result<Self> {
-		unimplemented!()
+	/// Qualifies relative domain names in the record data with the zone origin.
+	pub fn qualify(&mut self, origin: &str) {
+		match self {
+			Self::NS(v) | Self::MD(v) | Self::MF(v) | Self::CName(v) | Self::MB(v) | Self::MG(v)
+			| Self::MR(v) | Self::Ptr(v) | Self::NsapPtr(v) | Self::DName(v) => *v = super::qualify_name(v, origin),
+			Self::SOA(v) => {
+				v.name_server = super::qualify_name(&v.name_server, origin);
+				v.mailbox     = super::qualify_name(&v.mailbox, origin);
+			}
+			Self::MX(v)  => v.exchange = super::qualify_name(&v.exchange, origin),
+			Self::Srv(v) => v.target = super::qualify_name(&v.target, origin),
+			_ => ()
+		}
+	}
+	
+	pub(super) fn read(ty: u16, reader: &mut WireReader, len: usize) -> io::Result<Self> {
+		let ty = match Type::try_from(ty) {
+			Ok(ty) => ty,
+			Err(_) => return Ok(Self::Other(UnimplementedRecord::Wire { id: ty, data: reader.bytes(len)?.into() }))
+		};
+		
+		Ok(match ty {
+			Type::A          => {
+				let v = reader.bytes(4)?;
+				Self::A(std::net::Ipv4Addr::new(v[0], v[1], v[2], v[3]))
+			}
+			Type::AAAA       => {
+				let mut v = [0u8; 16];
+				v.copy_from_slice(reader.bytes(16)?);
+				Self::AAAA(v.into())
+			}
+			Type::NS         => Self::NS(reader.name()?),
+			Type::MD         => Self::MD(reader.name()?),
+			Type::MF         => Self::MF(reader.name()?),
+			Type::CName      => Self::CName(reader.name()?),
+			Type::MB         => Self::MB(reader.name()?),
+			Type::MG         => Self::MG(reader.name()?),
+			Type::MR         => Self::MR(reader.name()?),
+			Type::Ptr        => Self::Ptr(reader.name()?),
+			Type::NsapPtr    => Self::NsapPtr(reader.name()?),
+			Type::DName      => Self::DName(reader.name()?),
+			Type::SOA        => Self::SOA(SoaRecord {
+				name_server: reader.name()?,
+				mailbox:     reader.name()?,
+				serial:      reader.u32()?,
+				refresh:     reader.u32()?,
+				retry:       reader.u32()?,
+				expire:      reader.u32()?,
+				minimum:     reader.u32()?
+			}),
+			Type::MX         => Self::MX(MailExchangeRecord {
+				preference: reader.u16()?,
+				exchange:   reader.name()?
+			}),
+			Type::Srv        => Self::Srv(SrvRecord {
+				priority: reader.u16()?,
+				weight:   reader.u16()?,
+				port:     reader.u16()?,
+				target:   reader.name()?
+			}),
+			Type::Null       => Self::Null(reader.bytes(len)?.to_vec()),
+			Type::TXT | Type::SPF => {
+				let data    = reader.bytes(len)?;
+				let mut buf = Vec::with_capacity(len);
+				let mut off = 0;
+				
+				while off < data.len() {
+					let end = off + 1 + data[off] as usize;
+					buf.extend_from_slice(data.get(off + 1..end).ok_or_else(|| io::Error::new(
+						io::ErrorKind::InvalidData, "invalid character string"))?);
+					off = end;
+				}
+				
+				match String::from_utf8(buf) {
+					Ok(v) if ty == Type::TXT => Self::TXT(v),
+					Ok(v)                    => Self::SPF(v),
+					Err(_) => Self::Other(UnimplementedRecord::Wire { id: ty as u16, data: data.into() })
+				}
+			}
+			Type::CAA        => {
+				let data = reader.bytes(len)?;
+				let err  = || io::Error::new(io::ErrorKind::InvalidData, "invalid CAA record");
+				let tag  = data.get(2..2 + *data.get(1).ok_or_else(err)? as usize).ok_or_else(err)?;
+				
+				Self::CAA(CaaRecord {
+					flags: data[0],
+					tag:   String::from_utf8(tag.to_vec()).map_err(|_| err())?,
+					value: data[2 + tag.len()..].to_vec()
+				})
+			}
+			ty               => Self::Other(UnimplementedRecord::Wire { id: ty as u16, data: reader.bytes(len)?.into() })
+		})
 	}
 	
-	pub(super) fn write(&self, buf: &mut Vec<u8>) -> u16 {
-		unimplemented!();
+	pub(super) fn write(&self, writer: &mut WireWriter) -> io::Result<()> {
+		match self {
+			Self::A(v)          => writer.bytes(&v.octets()),
+			Self::AAAA(v)       => writer.bytes(&v.octets()),
+			Self::NS(v) | Self::MD(v) | Self::MF(v) | Self::CName(v) | Self::MB(v) | Self::MG(v)
+			| Self::MR(v) | Self::Ptr(v) => writer.name(v, true)?,
+			Self::NsapPtr(v) | Self::DName(v) => writer.name(v, false)?,
+			Self::SOA(v)        => {
+				writer.name(&v.name_server, true)?;
+				writer.name(&v.mailbox, true)?;
+				
+				for v in [v.serial, v.refresh, v.retry, v.expire, v.minimum] {
+					writer.u32(v);
+				}
+			}
+			Self::MX(v)         => {
+				writer.u16(v.preference);
+				writer.name(&v.exchange, true)?;
+			}
+			Self::Srv(v)        => {
+				writer.u16(v.priority);
+				writer.u16(v.weight);
+				writer.u16(v.port);
+				writer.name(&v.target, false)?;
+			}
+			Self::Null(v)       => writer.bytes(v),
+			Self::TXT(v) | Self::SPF(v) => {
+				if v.is_empty() {
+					writer.u8(0);
+				}
+				
+				for chunk in v.as_bytes().chunks(255) {
+					writer.u8(chunk.len() as u8);
+					writer.bytes(chunk);
+				}
+			}
+			Self::CAA(v)        => {
+				writer.u8(v.flags);
+				writer.u8(u8::try_from(v.tag.len()).map_err(|_| io::Error::new(
+					io::ErrorKind::InvalidInput, "CAA tag too long"))?);
+				writer.bytes(v.tag.as_bytes());
+				writer.bytes(&v.value);
+			}
+			Self::OPT(v)        => for (code, data) in &v.options {
+				writer.u16(*code);
+				writer.u16(data.len() as u16);
+				writer.bytes(data);
+			}
+			Self::Other(UnimplementedRecord::Wire { data, .. }) => writer.bytes(data),
+			_ => return Err(io::Error::new(io::ErrorKind::Unsupported, "record type not supported on the wire"))
+		}
+		
+		Ok(())
 	}
 }
 
@@ -1684,7 +1907,7 @@ impl FromStr for ResourceRecordData {
 			Type::HInfo      => Self::HInfo(s.parse()?),
 			Type::MInfo      => Self::MInfo(s.parse()?),
 			Type::MX         => Self::MX(s.parse()?),
-			Type::TXT        => Self::TXT(s.to_string()),
+			Type::TXT        => Self::TXT(unquote(s)?),
 			Type::RP         => Self::RP(s.parse()?),
 			Type::X25        => Self::X25(s.to_string()),
 			Type::ISDN       => Self::ISDN(s.parse()?),
@@ -1734,7 +1957,7 @@ impl FromStr for ResourceRecordData {
 				.map_err(|_| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, row))?),
 			Type::HTTPS      => Self::HTTPS(base64::decode(s)
 				.map_err(|_| ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, row))?),
-			Type::SPF        => Self::SPF(s.to_string()),
+			Type::SPF        => Self::SPF(unquote(s)?),
 			Type::NID        => Self::NID(s.parse()?),
 			Type::L32        => Self::L32(s.parse()?),
 			Type::L64        => Self::L64(s.parse()?),
@@ -1772,7 +1995,7 @@ impl fmt::Display for ResourceRecordData {
 			ResourceRecordData::HInfo(v)        => write!(f, "HINFO {}", v),
 			ResourceRecordData::MInfo(v)        => write!(f, "MINFO {}", v),
 			ResourceRecordData::MX(v)           => write!(f, "MX {}", v),
-			ResourceRecordData::TXT(v)          => write!(f, "TXT {}", v),
+			ResourceRecordData::TXT(v)          => write!(f, "TXT {}", quote(v)),
 			ResourceRecordData::RP(v)           => write!(f, "RP {}", v),
 			ResourceRecordData::AFSDB(v)        => write!(f, "AFSDB {}", v),
 			ResourceRecordData::X25(v)          => write!(f, "X25 {}", v),
@@ -1796,6 +2019,7 @@ impl fmt::Display for ResourceRecordData {
 			ResourceRecordData::A6(v)           => write!(f, "A6 {}", v),
 			ResourceRecordData::DName(v)        => write!(f, "DNAME {}", v),
 			ResourceRecordData::Sink(v)         => write!(f, "SINK {}", v),
+			ResourceRecordData::OPT(v)          => write!(f, "OPT {}", v.udp_payload_size),
 			ResourceRecordData::APL(v)          => write!(f, "APL {}", v),
 			ResourceRecordData::DS(v)           => write!(f, "DS {}", v),
 			ResourceRecordData::SSHFP(v)        => write!(f, "SSHFP {}", v),
@@ -1819,7 +2043,7 @@ impl fmt::Display for ResourceRecordData {
 			ResourceRecordData::ZoneMD(v)       => write!(f, "ZONEMD {}", v),
 			ResourceRecordData::SVCB(v)         => write!(f, "SVCB {}", base64::encode(v)),
 			ResourceRecordData::HTTPS(v)        => write!(f, "HTTPS {}", base64::encode(v)),
-			ResourceRecordData::SPF(v)          => write!(f, "SPF {}", v),
+			ResourceRecordData::SPF(v)          => write!(f, "SPF {}", quote(v)),
 			ResourceRecordData::NID(v)          => write!(f, "NID {}", v),
 			ResourceRecordData::L32(v)          => write!(f, "L32 {}", v),
 			ResourceRecordData::L64(v)          => write!(f, "L64 {}", v),
@@ -1836,4 +2060,44 @@ impl fmt::Display for ResourceRecordData {
 			_                                   => unimplemented!("non-standardized RR")
 		}
 	}
+}
+
+/// Parses a sequence of quoted or unquoted character strings into one string.
+fn unquote(s: &str) -> Result<String, ZoneParseError> {
+	let err       = || ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0);
+	let mut out   = String::with_capacity(s.len());
+	let mut chars = s.trim().chars();
+	
+	while let Some(c) = chars.next() {
+		match c {
+			'"' => loop {
+				match chars.next().ok_or_else(err)? {
+					'"'  => break,
+					'\\' => out.push(chars.next().ok_or_else(err)?),
+					c    => out.push(c)
+				}
+			},
+			c if c.is_ascii_whitespace() => (),
+			'\\' => out.push(chars.next().ok_or_else(err)?),
+			c    => out.push(c)
+		}
+	}
+	
+	Ok(out)
+}
+
+fn quote(s: &str) -> String {
+	let mut out = String::with_capacity(s.len() + 2);
+	out.push('"');
+	
+	for c in s.chars() {
+		if c == '"' || c == '\\' {
+			out.push('\\');
+		}
+		
+		out.push(c);
+	}
+	
+	out.push('"');
+	out
 }
\ No newline at end of file
@@ -20,26 +20,32 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use {super::*, std::io::{self, Write}};
+use {super::*, std::{io::{self, Write}, collections::HashMap}};
 
-pub const QR_BIT:                u16 = 1 << 0;
+pub const QR_BIT:                u16 = 1 << 15;
 pub const OPCODE_MASK:           u16 = 0b0111100000000000;
-pub const OPCODE_SHIFT:          u16 = 1;
+pub const OPCODE_SHIFT:          u16 = 11;
 pub const OPCODE_QUERY:          u16 = 0;
 pub const OPCODE_IQUERY:         u16 = 1;
 pub const OPCODE_STATUS:         u16 = 2;
 pub const OPCODE_NOTIFY:         u16 = 4;
 pub const OPCODE_UPDATE:         u16 = 5;
-pub const AA_BIT:                u16 = 1 << 5;
-pub const TC_BIT:                u16 = 1 << 6;
-pub const RD_BIT:                u16 = 1 << 7;
-pub const RA_BIT:                u16 = 1 << 8;
-pub const Z_BIT:                 u16 = 1 << 9;
-pub const AD_BIT:                u16 = 1 << 10;
-pub const CD_BIT:                u16 = 1 << 11;
+pub const AA_BIT:                u16 = 1 << 10;
+pub const TC_BIT:                u16 = 1 << 9;
+pub const RD_BIT:                u16 = 1 << 8;
+pub const RA_BIT:                u16 = 1 << 7;
+pub const Z_BIT:                 u16 = 1 << 6;
+pub const AD_BIT:                u16 = 1 << 5;
+pub const CD_BIT:                u16 = 1 << 4;
 pub const RCODE_MASK:            u16 = 0b0000000000001111;
-pub const RCODE_SHIFT:           u16 = 12;
+pub const RCODE_SHIFT:           u16 = 0;
 
+/// Maximum size of a message sent over UDP without EDNS.
+pub const MAX_UDP_LEN:           usize = 512;
+const MAX_NAME_LEN:              usize = 255;
+const MAX_LABEL_LEN:             usize = 63;
+const MAX_POINTERS:              usize = 64;
+const MAX_POINTER_OFFSET:        usize = 0x3FFF;
 const DEFAULT_BUF_LEN: usize = 1024;
 
 #[derive(Copy, Clone, Debug , Eq, PartialEq)]
@@ -63,118 +69,109 @@ pub struct Message {
 }
 
 impl Message {
-	pub fn read(mut reader: impl io::Read) -> io::Result<Self> {
-		let mut buf = [0u8; 12];
-		reader.read_exact(&mut buf)?;
+	/// Parses a complete message, e.g. a single UDP datagram.
+	pub fn parse(buf: &[u8]) -> io::Result<Self> {
+		let mut reader = WireReader::new(buf);
+		let id     = reader.u16()?;
+		let flags  = Flags(reader.u16()?);
+		let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
 		
-		Ok(Self {
-			id:                 u16::from_le_bytes([buf[0], buf[1]]),
-			flags:              Flags(u16::from_le_bytes([buf[2], buf[3]])),
-			questions:          (0..u16::from_le_bytes([buf[4], buf[5]])).map(|_| Question::read(&mut reader)).collect::<io::Result<_>>()?,
-			answers:            (0..u16::from_le_bytes([buf[6], buf[7]])).map(|_| ResourceRecord::read(&mut reader)).collect::<io::Result<_>>()?,
-			authority_records:  (0..u16::from_le_bytes([buf[8], buf[9]])).map(|_| ResourceRecord::read(&mut reader)).collect::<io::Result<_>>()?,
-			additional_records: (0..u16::from_le_bytes([buf[10], buf[11]])).map(|_| ResourceRecord::read(&mut reader)).collect::<io::Result<_>>()?
-		})
-	}
-	
-	pub async fn read_async(reader: impl futures_lite::AsyncReadExt + Unpin) -> io::Result<Self> {
-		unimplemented!()
-	}
-	
-	pub fn write(&self, mut writer: impl io::Write) -> io::Result<()> {
-		let mut buf = [0u8; 12];
-		buf[0..2].copy_from_slice(&self.id.to_le_bytes());
-		buf[2..4].copy_from_slice(&self.flags.0.to_le_bytes());
-		buf[4..6].copy_from_slice(&(self.questions.len() as u16).to_le_bytes());
-		buf[6..8].copy_from_slice(&(self.answers.len() as u16).to_le_bytes());
-		buf[8..10].copy_from_slice(&(self.authority_records.len() as u16).to_le_bytes());
-		buf[10..12].copy_from_slice(&(self.additional_records.len() as u16).to_le_bytes());
-		writer.write_all(&buf)?;
+		let self_ = Self {
+			id,
+			flags,
+			questions:          (0..counts[0]).map(|_| Question::read(&mut reader)).collect::<io::Result<_>>()?,
+			answers:            (0..counts[1]).map(|_| ResourceRecord::read(&mut reader)).collect::<io::Result<_>>()?,
+			authority_records:  (0..counts[2]).map(|_| ResourceRecord::read(&mut reader)).collect::<io::Result<_>>()?,
+			additional_records: (0..counts[3]).map(|_| ResourceRecord::read(&mut reader)).collect::<io::Result<_>>()?
+		};
 		
-		for question in &self.questions {
-			question.write(&mut writer)?;
+		match reader.remaining() {
+			0 => Ok(self_),
+			_ => Err(io::Error::new(io::ErrorKind::InvalidData, "trailing data after message"))
 		}
+	}
+	
+	/// Encodes the message, compressing domain names where permitted.
+	pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+		let mut writer = WireWriter::new(Vec::with_capacity(DEFAULT_BUF_LEN));
+		writer.u16(self.id);
+		writer.u16(self.flags.0);
 		
-		for rr in &self.answers {
-			rr.write(&mut writer)?;
+		for len in [self.questions.len(), self.answers.len(), self.authority_records.len(), self.additional_records.len()] {
+			writer.u16(u16::try_from(len).map_err(|_| io::Error::new(
+				io::ErrorKind::InvalidInput, "too many records"))?);
 		}
 		
-		for rr in &self.authority_records {
-			rr.write(&mut writer)?;
+		for question in &self.questions {
+			question.write(&mut writer)?;
 		}
 		
-		for rr in &self.additional_records {
+		for rr in self.answers.iter().chain(&self.authority_records).chain(&self.additional_records) {
 			rr.write(&mut writer)?;
 		}
 		
-		Ok(())
+		Ok(writer.buf)
+	}
+	
+	/// Returns the EDNS pseudo record of the message, if any.
+	pub fn edns(&self) -> Option<&OptRecord> {
+		self.additional_records.iter().find_map(|rr| match &rr.data {
+			ResourceRecordData::OPT(v) => Some(v),
+			_ => None
+		})
+	}
+	
+	pub fn read(mut reader: impl io::Read) -> io::Result<Self> {
+		let mut buf = Vec::with_capacity(DEFAULT_BUF_LEN);
+		reader.read_to_end(&mut buf)?;
+		Self::parse(&buf)
+	}
+	
+	pub async fn read_async(mut reader: impl futures_lite::AsyncReadExt + Unpin) -> io::Result<Self> {
+		let mut buf = Vec::with_capacity(DEFAULT_BUF_LEN);
+		reader.read_to_end(&mut buf).await?;
+		Self::parse(&buf)
+	}
+	
+	pub fn write(&self, mut writer: impl io::Write) -> io::Result<()> {
+		writer.write_all(&self.to_bytes()?)
 	}
 	
 	pub async fn write_async(&self, mut writer: impl futures_lite::AsyncWriteExt + Unpin) -> io::Result<()> {
-		let mut buf = [0u8; 12];
-		buf[0..2].copy_from_slice(&self.id.to_le_bytes());
-		buf[2..4].copy_from_slice(&self.flags.0.to_le_bytes());
-		buf[4..6].copy_from_slice(&(self.questions.len() as u16).to_le_bytes());
-		buf[6..8].copy_from_slice(&(self.answers.len() as u16).to_le_bytes());
-		buf[8..10].copy_from_slice(&(self.authority_records.len() as u16).to_le_bytes());
-		buf[10..12].copy_from_slice(&(self.additional_records.len() as u16).to_le_bytes());
-		writer.write_all(&buf).await?;
-		
-		for question in &self.questions {
-			question.write_async(&mut writer).await?;
-		}
-		
-		for rr in &self.answers {
-			rr.write_async(&mut writer).await?;
-		}
-		
-		for rr in &self.authority_records {
-			rr.write_async(&mut writer).await?;
-		}
-		
-		for rr in &self.additional_records {
-			rr.write_async(&mut writer).await?;
-		}
-		
-		Ok(())
+		writer.write_all(&self.to_bytes()?).await
 	}
 	
 	pub fn read_with_len(mut reader: impl io::Read) -> io::Result<Self> {
 		let mut len = [0u8; 2];
 		reader.read_exact(&mut len)?;
-		let len = u16::from_be_bytes(len) as usize;
-		let mut buf = Vec::with_capacity(len);
-		unsafe { buf.set_len(len); }
+		let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
 		reader.read_exact(&mut buf)?;
-		Self::read(&mut &*buf)
+		Self::parse(&buf)
 	}
 	
 	pub async fn read_async_with_len(mut reader: impl futures_lite::AsyncReadExt + Unpin) -> io::Result<Self> {
 		let mut len = [0u8; 2];
 		reader.read_exact(&mut len).await?;
-		let len = u16::from_be_bytes(len) as usize;
-		let mut buf = Vec::with_capacity(len);
-		unsafe { buf.set_len(len); }
+		let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
 		reader.read_exact(&mut buf).await?;
-		Self::read_async(&mut &*buf).await
+		Self::parse(&buf)
 	}
 	
 	pub fn write_with_len(&self, mut writer: impl io::Write) -> io::Result<()> {
-		let mut buf = Vec::with_capacity(DEFAULT_BUF_LEN);
-		buf.write_all(&[0u8; 2])?;
-		self.write(&mut buf)?;
-		let __buf_len__ = buf.len();
-		buf[..2].copy_from_slice(&(__buf_len__ as u16 - 2).to_be_bytes());
-		writer.write_all(&buf)
+		writer.write_all(&Self::prepend_len(self.to_bytes()?)?)
 	}
 	
 	pub async fn write_async_with_len(&self, mut writer: impl futures_lite::AsyncWriteExt + Unpin) -> io::Result<()> {
-		let mut buf = Vec::with_capacity(DEFAULT_BUF_LEN);
-		buf.write_all(&[0u8; 2])?;
-		self.write(&mut buf)?;
-		let __buf_len__ = buf.len();
-		buf[..2].copy_from_slice(&(__buf_len__ as u16 - 2).to_be_bytes());
-		writer.write_all(&buf).await
+		writer.write_all(&Self::prepend_len(self.to_bytes()?)?).await
+	}
+	
+	fn prepend_len(buf: Vec<u8>) -> io::Result<Vec<u8>> {
+		let len = u16::try_from(buf.len()).map_err(|_| io::Error::new(
+			io::ErrorKind::InvalidInput, "message too long"))?;
+		let mut out = Vec::with_capacity(buf.len() + 2);
+		out.write_all(&len.to_be_bytes())?;
+		out.write_all(&buf)?;
+		Ok(out)
 	}
 }
 
@@ -195,11 +192,11 @@ impl Flags {
 	}
 	
 	pub fn op_code(&self) -> u16 {
-		self.0 & OPCODE_MASK << OPCODE_SHIFT
+		(self.0 & OPCODE_MASK) >> OPCODE_SHIFT
 	}
 	
 	pub fn set_op_code(&mut self, v: u16) {
-		self.0 |= v >> OPCODE_SHIFT;
+		self.0 = self.0 & !OPCODE_MASK | (v << OPCODE_SHIFT) & OPCODE_MASK;
 	}
 	
 	pub fn aa(&self) -> bool {
@@ -227,14 +224,14 @@ impl Flags {
 	}
 	
 	pub fn rd(&self) -> bool {
-		self.0 & QR_BIT == QR_BIT
+		self.0 & RD_BIT == RD_BIT
 	}
 	
 	pub fn set_rd(&mut self, v: bool) {
 		if v {
-			self.0 |= QR_BIT;
+			self.0 |= RD_BIT;
 		} else {
-			self.0 &= !QR_BIT;
+			self.0 &= !RD_BIT;
 		}
 	}
 	
@@ -287,12 +284,13 @@ impl Flags {
 	}
 	
 	pub fn rcode(&self) -> Result<RCode, u16> {
-		let v = self.0 & RCODE_MASK << RCODE_SHIFT;
+		let v = (self.0 & RCODE_MASK) >> RCODE_SHIFT;
 		RCode::try_from(v).map_err(|_| v)
 	}
 	
 	pub fn set_rcode(&mut self, v: Result<RCode, u16>) {
-		self.0 |= crate::utils::unstable::_82223_into_ok_or_err(v.map(|v| v as u16)) >> RCODE_SHIFT;
+		let v = crate::utils::unstable::_82223_into_ok_or_err(v.map(|v| v as u16));
+		self.0 = self.0 & !RCODE_MASK | (v << RCODE_SHIFT) & RCODE_MASK;
 	}
 }
 
@@ -340,179 +338,314 @@ pub struct Question {
 }
 
 impl Question {
-	fn read(mut reader: impl io::Read) -> io::Result<Self> {
-		let name = read_domain_name(&mut reader)?;
-		let mut buf = [0u8; 4];
-		reader.read_exact(&mut buf)?;
-		
+	fn read(reader: &mut WireReader) -> io::Result<Self> {
 		Ok(Self {
-			name,
-			r#type: Type::try_from(u16::from_le_bytes([buf[0], buf[1]]))
-				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid type"))?,
-			class:  Class::try_from(u16::from_le_bytes([buf[2], buf[3]]))
-				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid class"))?
-		})
-	}
-	
-	async fn read_async(mut reader: impl futures_lite::AsyncReadExt + Unpin) -> io::Result<Self> {
-		let name = read_domain_name_async(&mut reader).await?;
-		let mut buf = [0u8; 4];
-		reader.read_exact(&mut buf).await?;
-		
-		Ok(Self {
-			name,
-			r#type: Type::try_from(u16::from_le_bytes([buf[0], buf[1]]))
+			name:   reader.name()?,
+			r#type: Type::try_from(reader.u16()?)
 				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid type"))?,
-			class:  Class::try_from(u16::from_le_bytes([buf[2], buf[3]]))
+			class:  Class::try_from(reader.u16()?)
 				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid class"))?
 		})
 	}
 	
-	fn write(&self, mut writer: impl io::Write) -> io::Result<()> {
-		write_domain_name(&self.name, &mut writer)?;
-		let mut buf = [0u8; 4];
-		buf[0..2].copy_from_slice(&(self.r#type as u16).to_le_bytes());
-		buf[2..4].copy_from_slice(&(self.class as u16).to_le_bytes());
-		writer.write_all(&buf)
-	}
-	
-	async fn write_async(&self, mut writer: impl futures_lite::AsyncWriteExt + Unpin) -> io::Result<()> {
-		write_domain_name_async(&self.name, &mut writer).await?;
-		let mut buf = [0u8; 4];
-		buf[0..2].copy_from_slice(&(self.r#type as u16).to_le_bytes());
-		buf[2..4].copy_from_slice(&(self.class as u16).to_le_bytes());
-		writer.write_all(&buf).await
+	fn write(&self, writer: &mut WireWriter) -> io::Result<()> {
+		writer.name(&self.name, true)?;
+		writer.u16(self.r#type as u16);
+		writer.u16(self.class as u16);
+		Ok(())
 	}
 }
 
 impl ResourceRecord {
-	fn read(mut reader: impl io::Read) -> io::Result<Self> {
-		let name = read_domain_name(&mut reader)?;
-		let mut buf = [0u8; 10];
-		reader.read_exact(&mut buf)?;
+	fn read(reader: &mut WireReader) -> io::Result<Self> {
+		let name  = reader.name()?;
+		let ty    = reader.u16()?;
+		let class = reader.u16()?;
+		let ttl   = reader.u32()?;
+		let len   = reader.u16()? as usize;
 		
-		Ok(Self {
-			name,
-			class:  Class::try_from(u16::from_le_bytes([buf[2], buf[3]]))
-				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid class"))?,
-			ttl:    u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
-			data:   {
-				let len = u16::from_le_bytes([buf[8], buf[9]]) as usize;
-				let mut buf = Vec::with_capacity(len);
-				unsafe { buf.set_len(len); }
-				reader.read_exact(&mut buf)?;
-				ResourceRecordData::read(u16::from_le_bytes([buf[0], buf[1]]), &buf)?
-			}
-		})
-	}
-	
-	async fn read_async(mut reader: impl futures_lite::AsyncReadExt + Unpin) -> io::Result<Self> {
-		let name = read_domain_name_async(&mut reader).await?;
-		let mut buf = [0u8; 10];
-		reader.read_exact(&mut buf).await?;
+		if ty == Type::OPT as u16 {
+			return Ok(Self {
+				name,
+				ttl:   0,
+				class: Class::IN,
+				data:  ResourceRecordData::OPT(OptRecord::read(class, ttl, reader.bytes(len)?)?)
+			});
+		}
 		
 		Ok(Self {
 			name,
-			class:  Class::try_from(u16::from_le_bytes([buf[2], buf[3]]))
+			class: Class::try_from(class)
 				.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid class"))?,
-			ttl:    u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
-			data:   {
-				let len = u16::from_le_bytes([buf[8], buf[9]]) as usize;
-				let mut buf = Vec::with_capacity(len);
-				unsafe { buf.set_len(len); }
-				reader.read_exact(&mut buf).await?;
-				ResourceRecordData::read(u16::from_le_bytes([buf[0], buf[1]]), &buf)?
+			ttl,
+			data:  {
+				let end  = reader.pos + len;
+				let data = ResourceRecordData::read(ty, reader, len)?;
+				
+				if reader.pos != end {
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid record length"));
+				}
+				
+				data
 			}
 		})
 	}
 	
-	fn write(&self, mut writer: impl io::Write) -> io::Result<()> {
-		write_domain_name(&self.name, &mut writer)?;
-		let mut buf = [0u8; 10];
-		let mut data = Vec::with_capacity(1024);
-		let ty = self.data.write(&mut data);
-		buf[0..2].copy_from_slice(&ty.to_le_bytes());
-		buf[2..4].copy_from_slice(&(self.class as u16).to_le_bytes());
-		buf[4..8].copy_from_slice(&self.ttl.to_le_bytes());
-		buf[8..10].copy_from_slice(&(data.len() as u16).to_le_bytes());
-		writer.write_all(&buf)?;
-		writer.write_all(&data)
-	}
-	
-	async fn write_async(&self, mut writer: impl futures_lite::AsyncWriteExt + Unpin) -> io::Result<()> {
-		write_domain_name_async(&self.name, &mut writer).await?;
-		let mut buf = [0u8; 10];
-		let mut data = Vec::with_capacity(1024);
-		let ty = self.data.write(&mut data);
-		buf[0..2].copy_from_slice(&ty.to_le_bytes());
-		buf[2..4].copy_from_slice(&(self.class as u16).to_le_bytes());
-		buf[4..8].copy_from_slice(&self.ttl.to_le_bytes());
-		buf[8..10].copy_from_slice(&(data.len() as u16).to_le_bytes());
-		writer.write_all(&buf).await?;
-		writer.write_all(&data).await
+	fn write(&self, writer: &mut WireWriter) -> io::Result<()> {
+		writer.name(&self.name, true)?;
+		
+		let (class, ttl) = match &self.data {
+			ResourceRecordData::OPT(v) => v.class_and_ttl(),
+			_ => (self.class as u16, self.ttl)
+		};
+		
+		writer.u16(match self.data.to_type() {
+			Ok(ty)       => ty as u16,
+			Err(Some(v)) => v,
+			Err(None)    => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown record type"))
+		});
+		writer.u16(class);
+		writer.u32(ttl);
+		
+		let len_off = writer.buf.len();
+		writer.u16(0);
+		self.data.write(writer)?;
+		let len = u16::try_from(writer.buf.len() - len_off - 2).map_err(|_| io::Error::new(
+			io::ErrorKind::InvalidInput, "record data too long"))?;
+		writer.buf[len_off..len_off + 2].copy_from_slice(&len.to_be_bytes());
+		Ok(())
 	}
 }
 
-fn read_domain_name(mut reader: impl io::Read) -> io::Result<String> {
-	let mut len  = [0];
-	let mut name = Vec::new();
+/// Cursor over a complete message, required to follow compression pointers.
+pub(super) struct WireReader<'a> {
+	buf: &'a [u8],
+	pos: usize
+}
+
+impl<'a> WireReader<'a> {
+	fn new(buf: &'a [u8]) -> Self {
+		Self { buf, pos: 0 }
+	}
 	
-	loop {
-		reader.read_exact(&mut len[..1])?;
-		let len = len[0] as usize;
-		
-		if len == 0 {
-			break;
+	fn remaining(&self) -> usize {
+		self.buf.len() - self.pos
+	}
+	
+	pub(super) fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+		if self.remaining() < len {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "message truncated"));
 		}
 		
-		let off = name.len();
-		name.reserve(len + 1);
-		unsafe { name.set_len(off + len + 1); }
-		reader.read_exact(&mut name[off..off + len])?;
-		name[off + len] = b'.';
+		self.pos += len;
+		Ok(&self.buf[self.pos - len..self.pos])
 	}
 	
-	String::from_utf8(name).map_err(
-		|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8"))
-}
-
-async fn read_domain_name_async(mut reader: impl futures_lite::AsyncReadExt + Unpin) -> io::Result<String> {
-	let mut len  = [0];
-	let mut name = Vec::new();
+	pub(super) fn u8(&mut self) -> io::Result<u8> {
+		Ok(self.bytes(1)?[0])
+	}
+	
+	pub(super) fn u16(&mut self) -> io::Result<u16> {
+		let v = self.bytes(2)?;
+		Ok(u16::from_be_bytes([v[0], v[1]]))
+	}
+	
+	pub(super) fn u32(&mut self) -> io::Result<u32> {
+		let v = self.bytes(4)?;
+		Ok(u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+	}
 	
-	loop {
-		reader.read_exact(&mut len[..1]).await?;
-		let len = len[0] as usize;
+	/// Reads a possibly compressed domain name as an absolute name with a trailing dot.
+	pub(super) fn name(&mut self) -> io::Result<String> {
+		let mut name     = String::new();
+		let mut pos      = self.pos;
+		let mut end      = None;
+		let mut pointers = 0;
 		
-		if len == 0 {
-			break;
+		loop {
+			let len = *self.buf.get(pos).ok_or_else(|| io::Error::new(
+				io::ErrorKind::UnexpectedEof, "message truncated"))? as usize;
+			
+			match len & 0xC0 {
+				0x00 if len == 0 => {
+					pos += 1;
+					break;
+				}
+				0x00 => {
+					let label = self.buf.get(pos + 1..pos + 1 + len).ok_or_else(|| io::Error::new(
+						io::ErrorKind::UnexpectedEof, "message truncated"))?;
+					
+					if label.contains(&b'.') {
+						return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid label"));
+					}
+					
+					name.push_str(std::str::from_utf8(label).map_err(|_| io::Error::new(
+						io::ErrorKind::InvalidData, "invalid UTF-8"))?);
+					name.push('.');
+					pos += 1 + len;
+					
+					if name.len() > MAX_NAME_LEN {
+						return Err(io::Error::new(io::ErrorKind::InvalidData, "name too long"));
+					}
+				}
+				0xC0 => {
+					let lo = *self.buf.get(pos + 1).ok_or_else(|| io::Error::new(
+						io::ErrorKind::UnexpectedEof, "message truncated"))? as usize;
+					pointers += 1;
+					
+					if pointers > MAX_POINTERS {
+						return Err(io::Error::new(io::ErrorKind::InvalidData, "compression loop"));
+					}
+					
+					end.get_or_insert(pos + 2);
+					pos = (len & 0x3F) << 8 | lo;
+				}
+				_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid label type"))
+			}
+		}
+		
+		self.pos = end.unwrap_or(pos);
+		
+		if name.is_empty() {
+			name.push('.');
 		}
 		
-		let off = name.len();
-		name.reserve(len + 1);
-		unsafe { name.set_len(off + len + 1); }
-		reader.read_exact(&mut name[off..off + len]).await?;
-		name[off + len] = b'.';
+		Ok(name)
 	}
-	
-	String::from_utf8(name).map_err(
-		|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8"))
 }
 
-fn write_domain_name(name: &str, mut writer: impl io::Write) -> io::Result<()> {
-	for name in name.split('.') {
-		writer.write_all(&[name.len() as u8])?;
-		writer.write_all(name.as_bytes())?;
+/// Message encoder that remembers where names were written for compression.
+pub(super) struct WireWriter {
+	buf:   Vec<u8>,
+	names: HashMap<String, u16>
+}
+
+impl WireWriter {
+	fn new(buf: Vec<u8>) -> Self {
+		Self { buf, names: HashMap::new() }
+	}
+	
+	pub(super) fn bytes(&mut self, v: &[u8]) {
+		self.buf.extend_from_slice(v);
+	}
+	
+	pub(super) fn u8(&mut self, v: u8) {
+		self.buf.push(v);
+	}
+	
+	pub(super) fn u16(&mut self, v: u16) {
+		self.buf.extend_from_slice(&v.to_be_bytes());
+	}
+	
+	pub(super) fn u32(&mut self, v: u32) {
+		self.buf.extend_from_slice(&v.to_be_bytes());
 	}
 	
-	Ok(())
+	/// Writes a domain name, replacing a known suffix by a pointer if `compress` is set.
+	pub(super) fn name(&mut self, name: &str, compress: bool) -> io::Result<()> {
+		let name = name.strip_suffix('.').unwrap_or(name);
+		
+		if name.len() + 1 > MAX_NAME_LEN {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "name too long"));
+		}
+		
+		let mut suffix = name;
+		
+		while !suffix.is_empty() {
+			let key = suffix.to_ascii_lowercase();
+			
+			if compress {
+				if let Some(off) = self.names.get(&key) {
+					self.u16(0xC000 | *off);
+					return Ok(());
+				}
+			}
+			
+			if self.buf.len() <= MAX_POINTER_OFFSET {
+				self.names.entry(key).or_insert(self.buf.len() as u16);
+			}
+			
+			let (label, rest) = suffix.split_once('.').unwrap_or((suffix, ""));
+			
+			if label.is_empty() || label.len() > MAX_LABEL_LEN {
+				return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid label"));
+			}
+			
+			self.u8(label.len() as u8);
+			self.bytes(label.as_bytes());
+			suffix = rest;
+		}
+		
+		self.u8(0);
+		Ok(())
+	}
 }
 
-async fn write_domain_name_async(name: &str, mut writer: impl futures_lite::AsyncWriteExt + Unpin) -> io::Result<()> {
-	for name in name.split('.') {
-		writer.write_all(&[name.len() as u8]).await?;
-		writer.write_all(name.as_bytes()).await?;
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn rr(name: &str, data: ResourceRecordData) -> ResourceRecord {
+		ResourceRecord { name: name.to_string(), ttl: 300, class: Class::IN, data }
+	}
+	
+	#[test]
+	fn flags() {
+		let mut flags = Flags(0x0100);
+		assert!(flags.rd() && !flags.qr());
+		flags.set_qr(true);
+		flags.set_aa(true);
+		flags.set_op_code(OPCODE_NOTIFY);
+		flags.set_rcode(Ok(RCode::NonExistentDomain));
+		assert_eq!(flags.0, 0xA503);
+		assert_eq!(flags.op_code(), OPCODE_NOTIFY);
+		assert_eq!(flags.rcode(), Ok(RCode::NonExistentDomain));
+	}
+	
+	#[test]
+	fn round_trip() {
+		let msg = Message {
+			id:                 0x1234,
+			flags:              Flags(QR_BIT | AA_BIT | RD_BIT),
+			questions:          vec![Question { name: "www.example.com.".to_string(), r#type: Type::A, class: Class::IN }],
+			answers:            vec![
+				rr("www.example.com.", ResourceRecordData::CName("web.example.com.".to_string())),
+				rr("web.example.com.", ResourceRecordData::A([192, 0, 2, 1].into())),
+				rr("example.com.", ResourceRecordData::MX(MailExchangeRecord { preference: 10, exchange: "mail.example.com.".to_string() })),
+				rr("example.com.", ResourceRecordData::TXT("v=spf1 -all".to_string()))
+			],
+			authority_records:  vec![rr("example.com.", ResourceRecordData::SOA(SoaRecord {
+				name_server: "ns.example.com.".to_string(),
+				mailbox:     "hostmaster.example.com.".to_string(),
+				serial:      1,
+				refresh:     7200,
+				retry:       3600,
+				expire:      1209600,
+				minimum:     300
+			}))],
+			additional_records: vec![ResourceRecord {
+				name:  ".".to_string(),
+				ttl:   0,
+				class: Class::IN,
+				data:  ResourceRecordData::OPT(OptRecord { udp_payload_size: 1232, ..OptRecord::default() })
+			}]
+		};
+		
+		let buf = msg.to_bytes().unwrap();
+		assert_eq!(&buf[..4], &[0x12, 0x34, 0x85, 0x00]);
+		// "example.com" is written once and referenced by pointers afterwards
+		assert_eq!(buf.windows(7).filter(|v| v == b"example").count(), 1);
+		
+		let parsed = Message::parse(&buf).unwrap();
+		assert_eq!(parsed.questions, msg.questions);
+		assert_eq!(parsed.answers, msg.answers);
+		assert_eq!(parsed.authority_records, msg.authority_records);
+		assert_eq!(parsed.edns().map(|v| v.udp_payload_size), Some(1232));
 	}
 	
-	Ok(())
-}
\ No newline at end of file
+	#[test]
+	fn compression_loop() {
+		let buf = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12, 0, 1, 0, 1];
+		assert!(Message::parse(&buf).is_err());
+	}
+}
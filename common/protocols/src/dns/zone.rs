@@ -55,25 +55,64 @@ impl FromStr for Zone {
 	type Err = ZoneParseError;
 	
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		let lines = s.lines().enumerate();
-		let mut self_ = Self::default();
+		let mut self_    = Self::default();
+		let mut has_ttl  = false;
+		let mut last_ttl = None;
+		let mut owner    = None::<String>;
 		
-		for (line_idx, line) in lines {
-			let end   = line.find(';').unwrap_or(line.len());
-			let mut s = line[..end].split_ascii_whitespace();
+		for Entry { line, indented, tokens } in entries(s)? {
+			let err = |ty| ZoneParseError::new(ty, line, 0);
 			
-			match s.next() {
-				Some("$ORIGIN") => self_.origin = s.next()
-					.ok_or_else(|| ZoneParseError::new(ZoneParseErrorType::DirectiveValue, line_idx, 0))?
-					.to_string(),
-				Some("$TTL")    => self_.ttl    = s.next()
-					.ok_or_else(|| ZoneParseError::new(ZoneParseErrorType::DirectiveValue, line_idx, 0))?
-					.parse()
-					.map_err(|_| ZoneParseError::new(ZoneParseErrorType::DirectiveValue, line_idx, 0))?,
-				Some(v) if v.starts_with('$') => return Err(ZoneParseError::new(ZoneParseErrorType::Directive, line_idx, 0)),
-				Some(_) => self_.records.push(line[..end].parse()?),
-				None    => continue
+			match tokens[0].as_str() {
+				"$ORIGIN" => {
+					self_.origin = qualify_name(tokens.get(1).ok_or_else(|| err(ZoneParseErrorType::DirectiveValue))?, &self_.origin);
+					continue;
+				}
+				"$TTL" => {
+					self_.ttl = parse_ttl(tokens.get(1).ok_or_else(|| err(ZoneParseErrorType::DirectiveValue))?)
+						.map_err(|_| err(ZoneParseErrorType::DirectiveValue))? as usize;
+					has_ttl = true;
+					continue;
+				}
+				v if v.starts_with('$') => return Err(err(ZoneParseErrorType::Directive)),
+				_ => ()
 			}
+			
+			let mut tokens = tokens.iter().map(String::as_str);
+			
+			if !indented {
+				owner = Some(qualify_name(tokens.next().unwrap_or_default(), &self_.origin));
+			}
+			
+			let name      = owner.clone().ok_or_else(|| err(ZoneParseErrorType::RecordValue))?;
+			let mut ttl   = None;
+			let mut class = None;
+			let ty = loop {
+				match tokens.next() {
+					Some(v) if ttl.is_none() && v.starts_with(|c: char| c.is_ascii_digit()) =>
+						ttl = Some(parse_ttl(v).map_err(|_| err(ZoneParseErrorType::RecordValue))?),
+					Some(v) if class.is_none() && v != "*" && v.parse::<Class>().is_ok() =>
+						class = v.parse::<Class>().ok(),
+					Some(v) => break v.to_ascii_uppercase(),
+					None    => return Err(err(ZoneParseErrorType::RecordType))
+				}
+			};
+			
+			let mut data = format!("{} {}", ty, tokens.collect::<Vec<_>>().join(" "))
+				.parse::<ResourceRecordData>()
+				.map_err(|e| ZoneParseError::new(e.ty, line, e.row))?;
+			data.qualify(&self_.origin);
+			
+			if ttl.is_some() {
+				last_ttl = ttl;
+			}
+			
+			self_.records.push(ResourceRecord {
+				name,
+				ttl:   ttl.or(if has_ttl { None } else { last_ttl }).unwrap_or(self_.ttl as u32),
+				class: class.unwrap_or(Class::IN),
+				data
+			});
 		}
 		
 		Ok(self_)
@@ -85,9 +124,182 @@ impl fmt::Display for Zone {
 		write!(f, "$ORIGIN {}\n$TTL {}\n", &self.origin, self.ttl)?;
 		
 		for record in &self.records {
-			fmt::Display::fmt(record, f)?;
+			writeln!(f, "{}", record)?;
 		}
 		
 		Ok(())
 	}
 }
+
+/// Resolves `@` and names relative to `origin` into absolute names.
+pub fn qualify_name(name: &str, origin: &str) -> DomainName {
+	match name {
+		"@" if origin.is_empty()          => ".".to_string(),
+		"@"                               => origin.to_string(),
+		_ if name.ends_with('.')          => name.to_string(),
+		_ if origin.is_empty() || origin == "." => format!("{}.", name),
+		_                                 => format!("{}.{}", name, origin)
+	}
+}
+
+/// Parses a TTL given in seconds or with units, e.g. `1h30m`.
+pub(super) fn parse_ttl(s: &str) -> Result<u32, ZoneParseError> {
+	let err = || ZoneParseError::new(ZoneParseErrorType::RecordValue, 0, 0);
+	
+	if let Ok(v) = s.parse() {
+		return Ok(v);
+	}
+	
+	let mut total = 0u32;
+	let mut num   = None::<u32>;
+	
+	for c in s.chars() {
+		let unit = match c.to_ascii_lowercase() {
+			c @ '0'..='9' => {
+				num = Some(num.unwrap_or(0).checked_mul(10)
+					.and_then(|v| v.checked_add(c as u32 - '0' as u32))
+					.ok_or_else(err)?);
+				continue;
+			}
+			's' => 1,
+			'm' => 60,
+			'h' => 3600,
+			'd' => 86400,
+			'w' => 604800,
+			_   => return Err(err())
+		};
+		
+		total = num.take().ok_or_else(err)?.checked_mul(unit)
+			.and_then(|v| total.checked_add(v))
+			.ok_or_else(err)?;
+	}
+	
+	match num {
+		None if !s.is_empty() => Ok(total),
+		_ => Err(err())
+	}
+}
+
+struct Entry {
+	line:     usize,
+	indented: bool,
+	tokens:   Vec<String>
+}
+
+/// Splits a zone file into entries, joining lines within parentheses and
+/// dropping comments outside of quoted strings.
+fn entries(s: &str) -> Result<Vec<Entry>, ZoneParseError> {
+	let mut entries = Vec::<Entry>::new();
+	let mut depth   = 0usize;
+	
+	fn flush(tokens: &mut Vec<String>, token: &mut String) {
+		if !token.is_empty() {
+			tokens.push(std::mem::take(token));
+		}
+	}
+	
+	for (line, s) in s.lines().enumerate() {
+		if depth == 0 {
+			entries.push(Entry {
+				line,
+				indented: s.starts_with(|c: char| c.is_ascii_whitespace()),
+				tokens:   Vec::new()
+			});
+		}
+		
+		let tokens     = &mut entries.last_mut().unwrap().tokens;
+		let mut token  = String::new();
+		let mut quoted = false;
+		let mut chars  = s.char_indices();
+		
+		while let Some((row, c)) = chars.next() {
+			match c {
+				'\\' => {
+					token.push(c);
+					token.extend(chars.next().map(|(_, c)| c));
+				}
+				'"' => {
+					quoted = !quoted;
+					token.push(c);
+				}
+				_ if quoted => token.push(c),
+				';' => break,
+				'(' => {
+					flush(tokens, &mut token);
+					depth += 1;
+				}
+				')' => {
+					flush(tokens, &mut token);
+					depth = depth.checked_sub(1)
+						.ok_or_else(|| ZoneParseError::new(ZoneParseErrorType::RecordValue, line, row))?;
+				}
+				c if c.is_ascii_whitespace() => flush(tokens, &mut token),
+				c => token.push(c)
+			}
+		}
+		
+		if quoted {
+			return Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, line, s.len()));
+		}
+		
+		flush(tokens, &mut token);
+	}
+	
+	if depth != 0 {
+		return Err(ZoneParseError::new(ZoneParseErrorType::RecordValue, s.lines().count(), 0));
+	}
+	
+	entries.retain(|v| !v.tokens.is_empty());
+	Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const ZONE: &str = r#"$ORIGIN example.com.
+$TTL 1h
+@       IN  SOA ns hostmaster (
+                2023010101 ; serial
+                2h 1h 2w 300 )
+        IN  NS  ns
+        IN  MX  10 mail.example.net.
+ns      300 A   192.0.2.1
+        AAAA    2001:db8::1
+www     CNAME   @
+*.dyn   TXT     "a \"quoted\" ; string" "and more"
+"#;
+	
+	#[test]
+	fn parse() {
+		let zone = ZONE.parse::<Zone>().unwrap();
+		assert_eq!(zone.origin, "example.com.");
+		assert_eq!(zone.ttl, 3600);
+		assert_eq!(zone.records.len(), 7);
+		assert_eq!(zone.records[0].data, ResourceRecordData::SOA(SoaRecord {
+			name_server: "ns.example.com.".to_string(),
+			mailbox:     "hostmaster.example.com.".to_string(),
+			serial:      2023010101,
+			refresh:     7200,
+			retry:       3600,
+			expire:      1209600,
+			minimum:     300
+		}));
+		assert_eq!(zone.records[1].name, "example.com.");
+		assert_eq!(zone.records[1].data, ResourceRecordData::NS("ns.example.com.".to_string()));
+		assert_eq!(zone.records[2].data.to_string(), "MX 10 mail.example.net.");
+		assert_eq!((zone.records[3].name.as_str(), zone.records[3].ttl), ("ns.example.com.", 300));
+		assert_eq!((zone.records[4].name.as_str(), zone.records[4].ttl), ("ns.example.com.", 3600));
+		assert_eq!(zone.records[5].data, ResourceRecordData::CName("example.com.".to_string()));
+		assert_eq!(zone.records[6].name, "*.dyn.example.com.");
+		assert_eq!(zone.records[6].data, ResourceRecordData::TXT("a \"quoted\" ; stringand more".to_string()));
+	}
+	
+	#[test]
+	fn errors() {
+		assert_eq!("$INCLUDE other".parse::<Zone>().unwrap_err().ty, ZoneParseErrorType::Directive);
+		assert_eq!("a.  3600  IN  BOGUS 1".parse::<Zone>().unwrap_err().ty, ZoneParseErrorType::RecordType);
+		assert_eq!("a.  3600  IN  A  (1.2.3.4".parse::<Zone>().unwrap_err().ty, ZoneParseErrorType::RecordValue);
+		assert_eq!("a. 3600 IN A 1.2.3.4".parse::<ResourceRecord>().unwrap().ttl, 3600);
+	}
+}
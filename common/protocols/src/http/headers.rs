@@ -42,10 +42,10 @@ static NAMES_HTTP1: [&str; 62] = [
 	"Access-Control-Allow-Headers",
 	"Access-Control-Allow-Methods",
 	"Access-Control-Allow-Origin",
-	"Access-Control-Allow-Expose-Headers",
-	"Access-Control-Allow-Max-Age",
-	"Access-Control-Allow-Request-Headers",
-	"Access-Control-Allow-Method",
+	"Access-Control-Expose-Headers",
+	"Access-Control-Max-Age",
+	"Access-Control-Request-Headers",
+	"Access-Control-Request-Method",
 	"Allow",
 	":Authority",
 	"Authorization",
@@ -82,11 +82,11 @@ static NAMES_HTTP1: [&str; 62] = [
 	"Retry-After",
 	"Save-Data",
 	":Scheme",
-	"Sec-Web-Socket-Protocol",
-	"Sec-Web-Socket-Extensions",
-	"Sec-Web-Socket-Version",
-	"Sec-Web-Socket-Accept",
-	"Sec-Web-Socket-Key",
+	"Sec-WebSocket-Protocol",
+	"Sec-WebSocket-Extensions",
+	"Sec-WebSocket-Version",
+	"Sec-WebSocket-Accept",
+	"Sec-WebSocket-Key",
 	"Server",
 	"Set-Cookie",
 	"Status",
@@ -107,10 +107,10 @@ static NAMES_HTTP2: [&str; 62] = [
 	"access-control-allow-headers",
 	"access-control-allow-methods",
 	"access-control-allow-origin",
-	"access-control-allow-expose-headers",
-	"access-control-allow-max-age",
-	"access-control-allow-request-headers",
-	"access-control-allow-method",
+	"access-control-expose-headers",
+	"access-control-max-age",
+	"access-control-request-headers",
+	"access-control-request-method",
 	"allow",
 	":authority",
 	"authorization",
@@ -147,11 +147,11 @@ static NAMES_HTTP2: [&str; 62] = [
 	"retry-after",
 	"save-data",
 	":scheme",
-	"sec-web-socket-protocol",
-	"sec-web-socket-extensions",
-	"sec-web-socket-version",
-	"sec-web-socket-accept",
-	"sec-web-socket-key",
+	"sec-websocket-protocol",
+	"sec-websocket-extensions",
+	"sec-websocket-version",
+	"sec-websocket-accept",
+	"sec-websocket-key",
 	"server",
 	"set-cookie",
 	"status",
@@ -258,10 +258,10 @@ impl FromStr for HeaderId {
 			"Access-Control-Allow-Headers"           | "access-control-allow-headers"         => HeaderId::AccessControlAllowHeaders,
 			"Access-Control-Allow-Methods"           | "access-control-allow-methods"         => HeaderId::AccessControlAllowMethods,
 			"Access-Control-Allow-Origin"            | "access-control-allow-origin"          => HeaderId::AccessControlAllowOrigin,
-			"Access-Control-Allow-Expose-Headers"    | "access-control-allow-expose-headers"  => HeaderId::AccessControlAllowExposeHeaders,
-			"Access-Control-Allow-Max-Age"           | "access-control-allow-max-age"         => HeaderId::AccessControlAllowMaxAge,
-			"Access-Control-Allow-Request-Headers"   | "access-control-allow-request-headers" => HeaderId::AccessControlAllowRequestHeaders,
-			"Access-Control-Allow-Method"            | "access-control-allow-method"          => HeaderId::AccessControlAllowRequestMethod,
+			"Access-Control-Expose-Headers"          | "access-control-expose-headers"        => HeaderId::AccessControlAllowExposeHeaders,
+			"Access-Control-Max-Age"                 | "access-control-max-age"               => HeaderId::AccessControlAllowMaxAge,
+			"Access-Control-Request-Headers"         | "access-control-request-headers"       => HeaderId::AccessControlAllowRequestHeaders,
+			"Access-Control-Request-Method"          | "access-control-request-method"        => HeaderId::AccessControlAllowRequestMethod,
 			"Allow"                                  | "allow"                                => HeaderId::Allow,
 			                                           ":authority"                           => HeaderId::Authority,
 			"Authorization"                          | "authorization"                        => HeaderId::Authorization,
@@ -298,11 +298,11 @@ impl FromStr for HeaderId {
 			"Retry-After"                            | "retry-after"                          => HeaderId::RetryAfter,
 			"Save-Data"                              | "save-data"                            => HeaderId::SaveData,
 			                                           ":scheme"                              => HeaderId::Scheme,
-			"sec-web-socket-protocol"                | "Sec-Web-Socket-Protocol"              => HeaderId::SecWebSocketProtocol,
-			"sec-web-socket-extensions"              | "Sec-Web-Socket-Extensions"            => HeaderId::SecWebSocketExtensions,
-			"sec-web-socket-version"                 | "Sec-Web-Socket-Version"               => HeaderId::SecWebSocketVersion,
-			"sec-web-socket-accept"                  | "Sec-Web-Socket-Accept"                => HeaderId::SecWebSocketAccept,
-			"sec-web-socket-key"                     | "Sec-Web-Socket-Key"                   => HeaderId::SecWebSocketKey,
+			"sec-websocket-protocol"                | "Sec-WebSocket-Protocol"              => HeaderId::SecWebSocketProtocol,
+			"sec-websocket-extensions"              | "Sec-WebSocket-Extensions"            => HeaderId::SecWebSocketExtensions,
+			"sec-websocket-version"                 | "Sec-WebSocket-Version"               => HeaderId::SecWebSocketVersion,
+			"sec-websocket-accept"                  | "Sec-WebSocket-Accept"                => HeaderId::SecWebSocketAccept,
+			"sec-websocket-key"                     | "Sec-WebSocket-Key"                   => HeaderId::SecWebSocketKey,
 			"Server"                                 | "server"                               => HeaderId::Server,
 			"Set-Cookie"                             | "set-cookie"                           => HeaderId::SetCookie,
 			"Status"                                 | "status"                               => HeaderId::Status,
@@ -324,12 +324,12 @@ pub enum Header {
 	AcceptRanges(AcceptRanges),
 	AcceptTypes(Vec<MediaType>),
 	AccessControlAllowCredentials(bool),
-	AccessControlAllowHeaders(Vec<HeaderId>),
+	AccessControlAllowHeaders(Vec<String>),
 	AccessControlAllowMethods(Vec<Method>),
 	AccessControlAllowOrigin(String),
-	AccessControlAllowExposeHeaders(Vec<HeaderId>),
+	AccessControlAllowExposeHeaders(Vec<String>),
 	AccessControlAllowMaxAge(usize),
-	AccessControlAllowRequestHeaders(Vec<HeaderId>),
+	AccessControlAllowRequestHeaders(Vec<String>),
 	AccessControlAllowRequestMethod(Method),
 	Allow(Vec<Method>),
 	Authority(String),
@@ -378,7 +378,7 @@ pub enum Header {
 	Upgrade(String),
 	UpgradeInsecureRequests(bool),
 	UserAgent(String),
-	Vary(Vec<HeaderId>),
+	Vary(Vec<String>),
 	WwwAuthenticate(String),
 	Custom(String, String)
 }
@@ -437,12 +437,12 @@ impl Header {
 			HeaderId::AcceptRanges                     => Self::AcceptRanges(v.parse()?),
 			HeaderId::AcceptTypes                      => Self::AcceptTypes(parse_list::<MediaType>(v)?),
 			HeaderId::AccessControlAllowCredentials    => Self::AccessControlAllowCredentials(true),
-			HeaderId::AccessControlAllowHeaders        => Self::AccessControlAllowHeaders(parse_list::<HeaderId>(v)?),
+			HeaderId::AccessControlAllowHeaders        => Self::AccessControlAllowHeaders(parse_list::<String>(v)?),
 			HeaderId::AccessControlAllowMethods        => Self::AccessControlAllowMethods(parse_list::<Method>(v)?),
 			HeaderId::AccessControlAllowOrigin         => Self::AccessControlAllowOrigin(v.to_string()),
-			HeaderId::AccessControlAllowExposeHeaders  => Self::AccessControlAllowExposeHeaders(parse_list::<HeaderId>(v)?),
+			HeaderId::AccessControlAllowExposeHeaders  => Self::AccessControlAllowExposeHeaders(parse_list::<String>(v)?),
 			HeaderId::AccessControlAllowMaxAge         => Self::AccessControlAllowMaxAge(v.parse().map_err(|_| ())?),
-			HeaderId::AccessControlAllowRequestHeaders => Self::AccessControlAllowRequestHeaders(parse_list::<HeaderId>(v)?),
+			HeaderId::AccessControlAllowRequestHeaders => Self::AccessControlAllowRequestHeaders(parse_list::<String>(v)?),
 			HeaderId::AccessControlAllowRequestMethod  => Self::AccessControlAllowRequestMethod(v.parse()?),
 			HeaderId::Allow                            => Self::Allow(parse_list::<Method>(v)?),
 			HeaderId::Authority                        => Self::Authority(v.to_string()),
@@ -493,7 +493,7 @@ impl Header {
 			HeaderId::Upgrade                          => Self::Upgrade(v.to_string()),
 			HeaderId::UpgradeInsecureRequests          => Self::UpgradeInsecureRequests(v.parse().map_err(|_| ())?),
 			HeaderId::UserAgent                        => Self::UserAgent(v.to_string()),
-			HeaderId::Vary                             => Self::Vary(parse_list::<HeaderId>(v)?),
+			HeaderId::Vary                             => Self::Vary(parse_list::<String>(v)?),
 			HeaderId::WwwAuthenticate                  => Self::WwwAuthenticate(v.to_string()),
 		})
 	}
@@ -700,21 +700,21 @@ impl Header {
 		}
 	}
 
-	pub fn as_access_control_allow_headers(&self) -> Option<&Vec<HeaderId>> {
+	pub fn as_access_control_allow_headers(&self) -> Option<&Vec<String>> {
 		match self {
 			Self::AccessControlAllowHeaders(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn as_mut_access_control_allow_headers(&mut self) -> Option<&mut Vec<HeaderId>> {
+	pub fn as_mut_access_control_allow_headers(&mut self) -> Option<&mut Vec<String>> {
 		match self {
 			Self::AccessControlAllowHeaders(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn into_access_control_allow_headers(self) -> Option<Vec<HeaderId>> {
+	pub fn into_access_control_allow_headers(self) -> Option<Vec<String>> {
 		match self {
 			Self::AccessControlAllowHeaders(v) => Some(v),
 			_ => None
@@ -763,21 +763,21 @@ impl Header {
 		}
 	}
 
-	pub fn as_access_control_allow_expose_headers(&self) -> Option<&Vec<HeaderId>> {
+	pub fn as_access_control_allow_expose_headers(&self) -> Option<&Vec<String>> {
 		match self {
 			Self::AccessControlAllowExposeHeaders(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn as_mut_access_control_allow_expose_headers(&mut self) -> Option<&mut Vec<HeaderId>> {
+	pub fn as_mut_access_control_allow_expose_headers(&mut self) -> Option<&mut Vec<String>> {
 		match self {
 			Self::AccessControlAllowExposeHeaders(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn into_access_control_allow_expose_headers(self) -> Option<Vec<HeaderId>> {
+	pub fn into_access_control_allow_expose_headers(self) -> Option<Vec<String>> {
 		match self {
 			Self::AccessControlAllowExposeHeaders(v) => Some(v),
 			_ => None
@@ -805,21 +805,21 @@ impl Header {
 		}
 	}
 
-	pub fn as_access_control_allow_request_headers(&self) -> Option<&Vec<HeaderId>> {
+	pub fn as_access_control_allow_request_headers(&self) -> Option<&Vec<String>> {
 		match self {
 			Self::AccessControlAllowRequestHeaders(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn as_mut_access_control_allow_request_headers(&mut self) -> Option<&mut Vec<HeaderId>> {
+	pub fn as_mut_access_control_allow_request_headers(&mut self) -> Option<&mut Vec<String>> {
 		match self {
 			Self::AccessControlAllowRequestHeaders(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn into_access_control_allow_request_headers(self) -> Option<Vec<HeaderId>> {
+	pub fn into_access_control_allow_request_headers(self) -> Option<Vec<String>> {
 		match self {
 			Self::AccessControlAllowRequestHeaders(v) => Some(v),
 			_ => None
@@ -1687,21 +1687,21 @@ impl Header {
 		}
 	}
 
-	pub fn as_vary(&self) -> Option<&Vec<HeaderId>> {
+	pub fn as_vary(&self) -> Option<&Vec<String>> {
 		match self {
 			Self::Vary(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn as_mut_vary(&mut self) -> Option<&mut Vec<HeaderId>> {
+	pub fn as_mut_vary(&mut self) -> Option<&mut Vec<String>> {
 		match self {
 			Self::Vary(v) => Some(v),
 			_ => None
 		}
 	}
 
-	pub fn into_vary(self) -> Option<Vec<HeaderId>> {
+	pub fn into_vary(self) -> Option<Vec<String>> {
 		match self {
 			Self::Vary(v) => Some(v),
 			_ => None
@@ -1739,12 +1739,12 @@ impl Display for Header {
 			Self::AcceptRanges(v)                     => Display::fmt(v, f),
 			Self::AcceptTypes(v)                      => fmt_list(f, v),
 			Self::AccessControlAllowCredentials(v)    => Display::fmt(v, f),
-			Self::AccessControlAllowHeaders(v)        => fmt_list(f, v.iter().map(|v| v.name_v1())),
+			Self::AccessControlAllowHeaders(v)        => fmt_list(f, v),
 			Self::AccessControlAllowMethods(v)        => fmt_list(f, v),
 			Self::AccessControlAllowOrigin(v)         => Display::fmt(v, f),
-			Self::AccessControlAllowExposeHeaders(v)  => fmt_list(f, v.iter().map(|v| v.name_v1())),
+			Self::AccessControlAllowExposeHeaders(v)  => fmt_list(f, v),
 			Self::AccessControlAllowMaxAge(v)         => Display::fmt(v, f),
-			Self::AccessControlAllowRequestHeaders(v) => fmt_list(f, v.iter().map(|v| v.name_v1())),
+			Self::AccessControlAllowRequestHeaders(v) => fmt_list(f, v),
 			Self::AccessControlAllowRequestMethod(v)  => Display::fmt(v, f),
 			Self::Allow(v)                            => fmt_list(f, v),
 			Self::Authority(v)                        => Display::fmt(v, f),
@@ -1793,7 +1793,7 @@ impl Display for Header {
 			Self::Upgrade(v)                          => Display::fmt(v, f),
 			Self::UpgradeInsecureRequests(v)          => Display::fmt(v, f),
 			Self::UserAgent(v)                        => Display::fmt(v, f),
-			Self::Vary(v)                             => fmt_list(f, v.iter().map(|v| v.name_v1())),
+			Self::Vary(v)                             => fmt_list(f, v),
 			Self::WwwAuthenticate(v)                  => Display::fmt(v, f),
 			Self::Custom(_, v)                        => Display::fmt(v, f),
 
@@ -2133,10 +2133,10 @@ impl FromStr for Connection {
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
 		Ok(match s {
-			"keep-alive" => Self::KeepAlive,
-			"close"      => Self::Close,
-			"Upgrade"    => Self::Upgrade,
-			v            => Self::Other(v.to_string().into_boxed_str())
+			v if v.eq_ignore_ascii_case("keep-alive") => Self::KeepAlive,
+			v if v.eq_ignore_ascii_case("close")      => Self::Close,
+			v if v.eq_ignore_ascii_case("upgrade")    => Self::Upgrade,
+			v => Self::Other(v.to_string().into_boxed_str())
 		})
 	}
 }
@@ -2536,8 +2536,18 @@ pub struct KeepAlive {
 impl FromStr for KeepAlive {
 	type Err = ();
 
-	fn from_str(_s: &str) -> Result<Self, Self::Err> {
-		unimplemented!()
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut v = Self { timeout: 0, max: 0 };
+
+		for param in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+			match param.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+				Some((k, n)) if k.eq_ignore_ascii_case("timeout") => v.timeout = n.parse().map_err(|_| ())?,
+				Some((k, n)) if k.eq_ignore_ascii_case("max") => v.max = n.parse().map_err(|_| ())?,
+				_ => ()
+			}
+		}
+
+		Ok(v)
 	}
 }
 
@@ -2782,7 +2792,7 @@ REGEX:
 		self
 	}
 
-	pub fn access_control_allow_headers(mut self, v: Vec<HeaderId>) -> Self {
+	pub fn access_control_allow_headers(mut self, v: Vec<String>) -> Self {
 		self.0.extend(std::iter::once(Header::AccessControlAllowHeaders(v)));
 		self
 	}
@@ -2797,7 +2807,7 @@ REGEX:
 		self
 	}
 
-	pub fn access_control_allow_expose_headers(mut self, v: Vec<HeaderId>) -> Self {
+	pub fn access_control_allow_expose_headers(mut self, v: Vec<String>) -> Self {
 		self.0.extend(std::iter::once(Header::AccessControlAllowExposeHeaders(v)));
 		self
 	}
@@ -2807,7 +2817,7 @@ REGEX:
 		self
 	}
 
-	pub fn access_control_allow_request_headers(mut self, v: Vec<HeaderId>) -> Self {
+	pub fn access_control_allow_request_headers(mut self, v: Vec<String>) -> Self {
 		self.0.extend(std::iter::once(Header::AccessControlAllowRequestHeaders(v)));
 		self
 	}
@@ -3016,7 +3026,7 @@ REGEX:
 		self
 	}
 
-	pub fn vary(mut self, v: Vec<HeaderId>) -> Self {
+	pub fn vary(mut self, v: Vec<String>) -> Self {
 		self.0.extend(std::iter::once(Header::Vary(v)));
 		self
 	}
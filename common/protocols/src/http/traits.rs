@@ -373,28 +373,28 @@ impl<'a, T: AsyncConnection + ?Sized> Future for AsyncConnectionClose<'a, T> {
 pub type BoxedAsyncConnection = Pin<Box<dyn AsyncConnection>>;
 
 impl AsyncConnection for BoxedAsyncConnection {
-	fn poll_open(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<StreamId>> {
-		self.as_mut().poll_open(cx)
+	fn poll_open(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<StreamId>> {
+		self.get_mut().as_mut().poll_open(cx)
 	}
 	
-	fn poll_read<'a>(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &'a mut [u8]) -> Poll<io::Result<Option<(usize, Read<'a>)>>> {
-		self.as_mut().poll_read(cx, buf)
+	fn poll_read<'a>(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &'a mut [u8]) -> Poll<io::Result<Option<(usize, Read<'a>)>>> {
+		self.get_mut().as_mut().poll_read(cx, buf)
 	}
 	
-	fn poll_write_headers(mut self: Pin<&mut Self>, cx: &mut Context<'_>, id: usize, headers: &[Header]) -> Poll<io::Result<()>> {
-		self.as_mut().poll_write_headers(cx, id, headers)
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, id: usize, headers: &[Header]) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_write_headers(cx, id, headers)
 	}
 	
-	fn poll_write_body(mut self: Pin<&mut Self>, cx: &mut Context<'_>, id: usize, buf: &[u8]) -> Poll<io::Result<()>> {
-		self.as_mut().poll_write_body(cx, id, buf)
+	fn poll_write_body(self: Pin<&mut Self>, cx: &mut Context<'_>, id: usize, buf: &[u8]) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_write_body(cx, id, buf)
 	}
 	
-	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<io::Result<()>> {
-		self.as_mut().poll_flush(cx, id)
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_flush(cx, id)
 	}
 	
-	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<io::Result<()>> {
-		self.as_mut().poll_close(cx, id)
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_close(cx, id)
 	}
 }
 
@@ -581,35 +581,35 @@ pub type BoxedAsyncSharedConnection = Pin<Box<dyn AsyncSharedConnection>>;
 
 impl AsyncSharedConnection for BoxedAsyncSharedConnection {
 	fn poll_open(self: Pin<&Self>, cx: &mut Context<'_>) -> Poll<io::Result<StreamId>> {
-		self.as_ref().poll_open(cx)
+		self.get_ref().as_ref().poll_open(cx)
 	}
 	
 	fn poll_opened(self: Pin<&Self>, cx: &mut Context<'_>) -> Poll<io::Result<Option<StreamId>>> {
-		self.as_ref().poll_opened(cx)
+		self.get_ref().as_ref().poll_opened(cx)
 	}
 	
 	fn poll_read<'a>(self: Pin<&Self>, cx: &mut Context<'_>, id: StreamId, buf: &'a mut [u8]) -> Poll<io::Result<Read<'a>>> {
-		self.as_ref().poll_read(cx, id, buf)
+		self.get_ref().as_ref().poll_read(cx, id, buf)
 	}
 	
 	fn poll_read_vectored<'a>(self: Pin<&Self>, cx: &mut Context<'_>, id: StreamId, buf: &'a mut [&'a mut [u8]]) -> Poll<io::Result<Read<'a>>> {
-		self.as_ref().poll_read_vectored(cx, id, buf)
+		self.get_ref().as_ref().poll_read_vectored(cx, id, buf)
 	}
 	
 	fn poll_write_headers(self: Pin<&Self>, cx: &mut Context<'_>, id: StreamId, headers: &[Header]) -> Poll<io::Result<()>> {
-		self.as_ref().poll_write_headers(cx, id, headers)
+		self.get_ref().as_ref().poll_write_headers(cx, id, headers)
 	}
 	
 	fn poll_write_body(self: Pin<&Self>, cx: &mut Context<'_>, id: StreamId, buf: &[u8]) -> Poll<io::Result<()>> {
-		self.as_ref().poll_write_body(cx, id, buf)
+		self.get_ref().as_ref().poll_write_body(cx, id, buf)
 	}
 	
 	fn poll_flush(self: Pin<&Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<io::Result<()>> {
-		self.as_ref().poll_flush(cx, id)
+		self.get_ref().as_ref().poll_flush(cx, id)
 	}
 	
 	fn poll_close(self: Pin<&Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<io::Result<()>> {
-		self.as_ref().poll_close(cx, id)
+		self.get_ref().as_ref().poll_close(cx, id)
 	}
 }
 
@@ -658,11 +658,11 @@ impl<'a, T: AsyncStream + ?Sized> Future for AsyncStreamWriteHeaders<'a, T> {
 pub type BoxedAsyncStream = Pin<Box<dyn AsyncStream>>;
 
 impl AsyncStream for BoxedAsyncStream {
-	fn poll_read_headers<'a>(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<Header>>> {
-		self.as_mut().poll_read_headers(cx)
+	fn poll_read_headers<'a>(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<Header>>> {
+		self.get_mut().as_mut().poll_read_headers(cx)
 	}
 	
-	fn poll_write_headers(mut self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[Header]) -> Poll<io::Result<()>> {
-		self.as_mut().poll_write_headers(cx, headers)
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[Header]) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_write_headers(cx, headers)
 	}
 }
\ No newline at end of file
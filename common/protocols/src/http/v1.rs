@@ -36,6 +36,10 @@ pub const DEFAULT_PORT_TLS: u16 = 443;
 
 const DEFAULT_BUF_LEN: usize = 256;
 
+/// A pseudo header with the version of a request line, e.g. `HTTP/1.0`. It is added to the
+/// headers of requests that are read, but never written.
+pub const HEADER_VERSION: &str = ":version";
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum State {
 	Ready,
@@ -63,6 +67,9 @@ pub enum AsyncState {
 	WriteResponseHeaders { rem: usize, len: Option<usize> },
 	WriteResponseNewLine { rem: usize, len: usize },
 	WriteResponseBody(usize),
+	/// After a `101 Switching Protocols` response, or a `2xx` response to `CONNECT`, the
+	/// connection carries raw bytes in both directions until either side closes it.
+	Upgraded { written: usize },
 	Closed
 }
 
@@ -183,6 +190,7 @@ impl<T: io::BufRead + io::Write> traits::Connection for Connection<T> {
 							return Err(io::Error::new(io::ErrorKind::Other, "Invalid protocol"));
 						}
 						
+						headers.push(Header::Custom(HEADER_VERSION.to_string(), proto.to_string()));
 						*pseudo = true;
 					}
 					(State::ReadResponseHeaders { pseudo: pseudo @ false, .. }, false) => {
@@ -196,7 +204,7 @@ impl<T: io::BufRead + io::Write> traits::Connection for Connection<T> {
 						let (status, _) = s.split_once(' ').ok_or_else(|| Error::new(
 							ErrorKind::InvalidData, "failed to parse HTTP header"))?;
 						
-						headers.push(Header::Status(s.trim()
+						headers.push(Header::Status(status.trim()
 							.parse::<usize>()
 							.map_err(header_error)?
 							.try_into()
@@ -267,6 +275,7 @@ impl<T: io::BufRead + io::Write> traits::Connection for Connection<T> {
 						Header::Method(v) => write!(&mut self.inner, "{}", v),
 						Header::Path(v)   => write!(&mut self.inner, " {} HTTP/1.1\r\n", v),
 						Header::Status(v) => write!(&mut self.inner, "HTTP/1.1 {} {}\r\n", *v as u32, v),
+						Header::Custom(k, _) if k == HEADER_VERSION => Ok(()),
 						header            => write!(&mut self.inner, "{}: {}\r\n", header.name_v1(), header),
 					}?;
 				}
@@ -323,12 +332,12 @@ impl<T: io::BufRead + io::Write> traits::Connection for Connection<T> {
 }
 
 #[cfg(feature = "smol")]
-pub fn connector_async<T: smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T) -> AsyncConnector<buffered::AsyncConnector<tcp::AsyncConnector<T>>> {
+pub fn connector_async<T: 'static + smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T) -> AsyncConnector<buffered::AsyncConnector<tcp::AsyncConnector<T>>> {
 	AsyncConnector::new(buffered::AsyncConnector::new(tcp::AsyncConnector::new(addr)))
 }
 
 #[cfg(feature = "smol")]
-pub fn connector_tls_async<T: smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T, host: tls::r#async::webpki::DNSName, tls: Arc<tls::r#async::rustls::ClientConfig>) -> AsyncConnector<buffered::AsyncConnector<tls::AsyncConnector<tcp::AsyncConnector<T>>>> {
+pub fn connector_tls_async<T: 'static + smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T, host: tls::r#async::webpki::DNSName, tls: Arc<tls::r#async::rustls::ClientConfig>) -> AsyncConnector<buffered::AsyncConnector<tls::AsyncConnector<tcp::AsyncConnector<T>>>> {
 	AsyncConnector::new(buffered::AsyncConnector::new(tls::AsyncConnector::new(tcp::AsyncConnector::new(addr), host, tls)))
 }
 
@@ -384,15 +393,21 @@ impl<T: utils::AsyncAcceptor> utils::AsyncAcceptor for AsyncAcceptor<T> where T:
 }
 
 pub struct AsyncConnection<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite> {
-	inner:  T,
-	stream: usize,
-	state:  AsyncState,
-	buf:    Vec<u8>
+	inner:   T,
+	stream:  usize,
+	state:   AsyncState,
+	buf:     Vec<u8>,
+	/// The bytes of the body part passed to `poll_write_body` that were written already.
+	written: usize,
+	/// Whether the response was sent before the request body was read completely.
+	unread:  bool,
+	/// Whether the current request is a `CONNECT`, which becomes a tunnel if it succeeds.
+	connect: bool
 }
 
 impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite> AsyncConnection<T> {
 	pub fn new(inner: T) -> Self {
-		Self { inner, stream: 0, state: AsyncState::Ready, buf: Vec::with_capacity(DEFAULT_BUF_LEN) }
+		Self { inner, stream: 0, state: AsyncState::Ready, buf: Vec::with_capacity(DEFAULT_BUF_LEN), written: 0, unread: false, connect: false }
 	}
 }
 
@@ -414,6 +429,16 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 		let __buf_len__ = buf.len();
 		let mut headers = Vec::new();
 		
+		// a request without a body is complete once the empty line after its headers was written
+		if let AsyncState::WriteRequestHeaders { rem: 0, len: Some(0) } = self_.state {
+			let id = self_.stream;
+			match traits::AsyncConnection::poll_write_body(unsafe { Pin::new_unchecked(&mut*self_) }, cx, id, &[]) {
+				Poll::Ready(Ok(())) => (),
+				Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+				Poll::Pending       => return Poll::Pending
+			}
+		}
+		
 		match &mut self_.state {
 			AsyncState::Ready => match unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_fill_buf(cx) {
 				Poll::Ready(Ok([])) => Poll::Ready(Ok(None)),
@@ -443,10 +468,11 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 						let method = method.parse()
 							.map_err(header_error)?;
 						
-						if method == Method::Get || method == Method::Post {
+						if method == Method::Get || method == Method::Post || method == Method::Connect {
 							*len = Some(0);
 						}
 						
+						self_.connect = method == Method::Connect;
 						headers.push(Header::Method(method));
 						
 						let (path, proto) = s.split_once(' ').ok_or_else(|| Error::new(
@@ -458,6 +484,7 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 							return Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "Invalid protocol")))
 						}
 						
+						headers.push(Header::Custom(HEADER_VERSION.to_string(), proto.to_string()));
 						*pseudo = true;
 					}
 					(AsyncState::ReadResponseHeaders { pseudo: pseudo @ false, .. }, false) => {
@@ -471,7 +498,7 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 						let (status, _) = s.split_once(' ').ok_or_else(|| Error::new(
 							ErrorKind::InvalidData, "failed to parse HTTP header"))?;
 						
-						headers.push(Header::Status(s.trim()
+						headers.push(Header::Status(status.trim()
 							.parse::<usize>()
 							.map_err(header_error)?
 							.try_into()
@@ -499,8 +526,11 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 						return Poll::Ready(Ok(Some((self_.stream, Read::Headers(headers)))));
 					}
 					(AsyncState::ReadResponseHeaders { pseudo: true, len }, true) => {
-						self_.state = AsyncState::ReadResponseBody(len.ok_or_else(|| io::Error::new(
-							io::ErrorKind::Other, "Content-Length header not present"))?);
+						self_.state = match headers.iter().any(is_switching_protocols) {
+							true  => AsyncState::Upgraded { written: 0 },
+							false => AsyncState::ReadResponseBody(len.ok_or_else(|| io::Error::new(
+								io::ErrorKind::Other, "Content-Length header not present"))?)
+						};
 						return Poll::Ready(Ok(Some((self_.stream, Read::Headers(headers)))));
 					}
 					_ => unreachable!()
@@ -525,6 +555,16 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 					Poll::Pending         => Poll::Pending
 				}
 			}
+			AsyncState::Upgraded { .. } => match unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_read(cx, buf) {
+				Poll::Ready(Ok(0)) => {
+					self_.state = AsyncState::Closed;
+					Poll::Ready(Ok(Some((self_.stream, Read::Closed))))
+				}
+				Poll::Ready(Ok(read)) => Poll::Ready(Ok(Some((self_.stream, Read::Body(&buf[..read]))))),
+				Poll::Ready(Err(e))   => Poll::Ready(Err(e)),
+				Poll::Pending         => Poll::Pending
+			}
+			AsyncState::Closed => Poll::Ready(Ok(Some((self_.stream, Read::Closed)))),
 			_ => panic!("invalid state")
 		}
 	}
@@ -538,6 +578,17 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 			panic!("invalid stream id");
 		}
 		
+		// the response may be sent once the request body was read, without reading its end, or
+		// before, e.g. if reading it timed out, then the rest of the body cannot be told apart
+		// from the next request and the connection is closed after the response
+		if let AsyncState::ReadRequestBody(rem) = self_.state {
+			self_.state = AsyncState::WriteResponseHeaders { rem: 0, len: None };
+			self_.unread = rem > 0;
+		}
+		
+		let upgrade = matches!(self_.state, AsyncState::WriteResponseHeaders { .. })
+			&& headers.iter().any(|v| is_switching_protocols(v) || (self_.connect && is_success(v)));
+		
 		loop {
 			match &mut self_.state {
 				AsyncState::WriteRequestHeaders { rem, len } | AsyncState::WriteResponseHeaders { rem, len } if *rem == 0 => {
@@ -554,10 +605,16 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 							Header::Method(v) => write!(&mut self_.buf, "{}", v),
 							Header::Path(v)   => write!(&mut self_.buf, " {} HTTP/1.1\r\n", v),
 							Header::Status(v) => write!(&mut self_.buf, "HTTP/1.1 {} {}\r\n", *v as u32, v),
+							Header::Custom(k, _) if k == HEADER_VERSION => Ok(()),
 							header            => write!(&mut self_.buf, "{}: {}\r\n", header.name_v1(), header),
 						}?;
 					}
 					
+					// there is no body, the protocol changes right after the headers
+					if upgrade {
+						self_.buf.extend_from_slice(b"\r\n");
+					}
+					
 					*rem = self_.buf.len();
 				}
 				/*AsyncState::WriteRequestHeaders { rem: 0, len, .. }  => {
@@ -574,6 +631,11 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 					match unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_write(cx, &self_.buf[self_.buf.len() - *rem..]) {
 						Poll::Ready(Ok(n)) if n == *rem => {
 							*rem = 0;
+							
+							if upgrade {
+								self_.state = AsyncState::Upgraded { written: 0 };
+							}
+							
 							return Poll::Ready(Ok(()));
 						}
 						Poll::Ready(Ok(n))  => *rem -= n,
@@ -622,16 +684,38 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 					return Poll::Ready(Ok(()));
 				}
 				AsyncState::WriteResponseBody(0) => {
-					self_.state = AsyncState::Ready;
+					self_.state = match self_.unread {
+						true  => AsyncState::Closed,
+						false => AsyncState::Ready
+					};
 					return Poll::Ready(Ok(()));
 				}
+				AsyncState::Upgraded { written } => {
+					let r = write_all_internal(unsafe { Pin::new_unchecked(&mut self_.inner) }, cx, written, buf);
+					
+					if r.is_ready() {
+						*written = 0;
+					}
+					
+					return r;
+				}
+				AsyncState::Closed => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+				// the body may be written in several parts, each one is written completely
 				AsyncState::WriteRequestBody(rem) | AsyncState::WriteResponseBody(rem) => {
-					match unsafe { Pin::new_unchecked(&mut self_.inner) }
-						.poll_write(cx, &buf[buf.len() - *rem..])
-					{
-						Poll::Ready(Ok(n))  => *rem -= n,
-						Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
-						Poll::Pending       => return Poll::Pending
+					if buf.len() > *rem {
+						return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidInput, "body exceeds Content-Length")));
+					}
+					
+					match write_all_internal(unsafe { Pin::new_unchecked(&mut self_.inner) }, cx, &mut self_.written, buf) {
+						Poll::Ready(Ok(())) => {
+							self_.written = 0;
+							*rem -= buf.len();
+							
+							if *rem > 0 {
+								return Poll::Ready(Ok(()));
+							}
+						}
+						v => return v
 					}
 				}
 				_ => panic!("invalid state")
@@ -646,12 +730,24 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + '
 			panic!("invalid stream id")
 		}
 		
+		// the empty line after the headers of a request without a body must be sent too
+		if let AsyncState::WriteRequestHeaders { rem: 0, len: Some(0) } = self_.state {
+			match traits::AsyncConnection::poll_write_body(unsafe { Pin::new_unchecked(&mut*self_) }, cx, id, &[]) {
+				Poll::Ready(Ok(())) => (),
+				v => return v
+			}
+		}
+		
 		unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_flush(cx)
 	}
 	
 	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>, id: StreamId) -> Poll<Result<()>> {
 		let self_ = unsafe { Pin::into_inner_unchecked(self) };
 		
+		if let AsyncState::Upgraded { .. } | AsyncState::Closed = self_.state {
+			return unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_close(cx);
+		}
+		
 		if self_.state != AsyncState::Ready {
 			match unsafe { Pin::new_unchecked(&mut*self_) }.poll_write_body(cx, id, &[]) {
 				Poll::Ready(Ok(())) => (),
@@ -732,7 +828,10 @@ impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send + S
 		let Self { inner, wait } = unsafe { Pin::into_inner_unchecked(self) };
 		let mut inner = inner.try_lock().expect("synchronization error");
 		
-		if inner.state != AsyncState::Ready {
+		if let AsyncState::Upgraded { .. } | AsyncState::Closed = inner.state {
+			// an upgraded connection does not carry any more HTTP requests
+			Poll::Ready(Ok(None))
+		} else if inner.state != AsyncState::Ready {
 			std::mem::drop(wait.push(cx.waker().clone()));
 			Poll::Pending
 		} else {
@@ -871,8 +970,16 @@ fn read_until_internal<R: AsyncBufRead + ?Sized>(
 	}
 }
 
+fn is_switching_protocols(header: &Header) -> bool {
+	matches!(header, Header::Status(super::Status::SwitchingProtocols))
+}
+
+fn is_success(header: &Header) -> bool {
+	matches!(header, Header::Status(v) if (200..300).contains(&(*v as u32)))
+}
+
 fn write_all_internal(
-	mut write: Pin<&mut (impl AsyncWrite + Unpin + ?Sized)>,
+	mut write: Pin<&mut (impl AsyncWrite + ?Sized)>,
 	cx:        &mut Context<'_>,
 	written:   &mut usize,
 	buf:       &[u8]
@@ -256,13 +256,15 @@ impl FromStr for Received {
 	type Err = ();
 	
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		todo!()
+		let (token, date) = s.rsplit_once(';').ok_or(())?;
+		let date = chrono::DateTime::parse_from_rfc2822(date.trim()).map_err(|_| ())?;
+		Ok(Self { token: token.trim().to_string(), date: date.with_timezone(&chrono::Utc) })
 	}
 }
 
 impl Display for Received {
 	fn fmt(&self, f: &mut Formatter) -> FmtResult {
-		todo!()
+		write!(f, "{}; {}", self.token, self.date.format("%a, %d %b %Y %T +0000"))
 	}
 }
 
@@ -115,12 +115,12 @@ impl<T: io::BufRead + io::Write> traits::Connection for Connection<T> {
 }
 
 #[cfg(feature = "smol")]
-pub fn connector_async<T: smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T) -> AsyncConnector<buffered::AsyncConnector<tcp::AsyncConnector<T>>> {
+pub fn connector_async<T: 'static + smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T) -> AsyncConnector<buffered::AsyncConnector<tcp::AsyncConnector<T>>> {
 	AsyncConnector::new(buffered::AsyncConnector::new(tcp::AsyncConnector::new(addr)))
 }
 
 #[cfg(feature = "smol")]
-pub fn connector_tls_async<T: smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T, host: tls::r#async::webpki::DNSName, tls: Arc<tls::r#async::rustls::ClientConfig>) -> AsyncConnector<buffered::AsyncConnector<tls::AsyncConnector<tcp::AsyncConnector<T>>>> {
+pub fn connector_tls_async<T: 'static + smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T, host: tls::r#async::webpki::DNSName, tls: Arc<tls::r#async::rustls::ClientConfig>) -> AsyncConnector<buffered::AsyncConnector<tls::AsyncConnector<tcp::AsyncConnector<T>>>> {
 	AsyncConnector::new(buffered::AsyncConnector::new(tls::AsyncConnector::new(tcp::AsyncConnector::new(addr), host, tls)))
 }
 
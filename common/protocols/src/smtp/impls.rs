@@ -21,7 +21,7 @@
 // SOFTWARE.
 
 use {
-	super::{traits, Command},
+	super::{traits, Command, MAX_COMMAND_LEN, MAX_LINE_LEN},
 	crate::*,
 	std::{io, net, pin::Pin, task::{Poll, Context}, sync::Arc, future::Future}
 };
@@ -68,15 +68,37 @@ impl<T: io::BufRead + io::Write> ClientConnection<T> {
 	pub fn new(inner: T) -> Self {
 		Self { inner }
 	}
+	
+	pub fn into_inner(self) -> T {
+		self.inner
+	}
 }
 
 impl<T: io::BufRead + io::Write> traits::ClientConnection for ClientConnection<T> {
 	fn write_command(&mut self, command: &Command) -> io::Result<()> {
-		todo!()
+		write!(&mut self.inner, "{}\r\n", command)?;
+		self.inner.flush()
 	}
 	
 	fn read_response(&mut self) -> io::Result<String> {
-		todo!()
+		let mut buf = Vec::new();
+		
+		loop {
+			let start = buf.len();
+			
+			if !read_line(&mut self.inner, &mut buf, start + MAX_LINE_LEN)? {
+				return Err(line_too_long());
+			}
+			
+			if !is_continuation(&buf[start..]) {
+				return String::from_utf8(buf).map_err(|_| invalid_utf8());
+			}
+		}
+	}
+	
+	fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+		self.inner.write_all(&encode_data(data))?;
+		self.inner.flush()
 	}
 }
 
@@ -106,25 +128,59 @@ impl<T: io::BufRead + io::Write> ServerConnection<T> {
 	pub fn new(inner: T) -> Self {
 		Self { inner }
 	}
+	
+	pub fn into_inner(self) -> T {
+		self.inner
+	}
 }
 
 impl<T: io::BufRead + io::Write> traits::ServerConnection for ServerConnection<T> {
 	fn read_command(&mut self) -> io::Result<Command> {
-		todo!()
+		let mut buf = Vec::new();
+		
+		match read_line(&mut self.inner, &mut buf, MAX_COMMAND_LEN)? {
+			true  => parse_command(&buf).map(Command::into_owned),
+			false => Err(line_too_long())
+		}
 	}
 	
 	fn write_response(&mut self, msg: &str) -> io::Result<()> {
-		todo!()
+		self.inner.write_all(msg.as_bytes())?;
+		self.inner.flush()
+	}
+	
+	fn read_data(&mut self, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+		let mut data = DataDecoder::default();
+		let mut line = Vec::new();
+		
+		loop {
+			read_line(&mut self.inner, &mut line, MAX_LINE_LEN)?;
+			
+			if data.push(&line, max_len) {
+				return Ok(data.finish());
+			}
+			
+			line.clear();
+		}
+	}
+	
+	fn read_line(&mut self) -> io::Result<Vec<u8>> {
+		let mut buf = Vec::new();
+		
+		match read_line(&mut self.inner, &mut buf, MAX_COMMAND_LEN)? {
+			true  => Ok(trim_line_end(buf)),
+			false => Err(line_too_long())
+		}
 	}
 }
 
 #[cfg(feature = "smol")]
-pub fn connector_async<T: smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T) -> AsyncConnector<buffered::AsyncConnector<tcp::AsyncConnector<T>>> {
+pub fn connector_async<T: 'static + smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T) -> AsyncConnector<buffered::AsyncConnector<tcp::AsyncConnector<T>>> {
 	AsyncConnector::new(buffered::AsyncConnector::new(tcp::AsyncConnector::new(addr)))
 }
 
 #[cfg(all(feature = "smol"))]
-pub fn connector_tls_async<T: smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T, host: tls::r#async::webpki::DNSName, tls: Arc<tls::r#async::rustls::ClientConfig>) -> AsyncConnector<buffered::AsyncConnector<tls::AsyncConnector<tcp::AsyncConnector<T>>>> {
+pub fn connector_tls_async<T: 'static + smol::net::AsyncToSocketAddrs<Iter: Send> + Send + Sync>(addr: T, host: tls::r#async::webpki::DNSName, tls: Arc<tls::r#async::rustls::ClientConfig>) -> AsyncConnector<buffered::AsyncConnector<tls::AsyncConnector<tcp::AsyncConnector<T>>>> {
 	AsyncConnector::new(buffered::AsyncConnector::new(tls::AsyncConnector::new(tcp::AsyncConnector::new(addr), host, tls)))
 }
 
@@ -159,22 +215,49 @@ impl<T: utils::AsyncConnector> utils::AsyncConnector for AsyncConnector<T> where
 }
 
 pub struct AsyncClientConnection<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite> {
-	inner: T
+	inner: T,
+	read:  Vec<u8>,
+	write: AsyncWriteState
 }
 
 impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite> AsyncClientConnection<T> {
 	pub fn new(inner: T) -> Self {
-		Self { inner }
+		Self { inner, read: Vec::new(), write: AsyncWriteState::default() }
+	}
+	
+	/// Returns the stream, e.g. to start TLS after `STARTTLS`.
+	pub fn into_inner(self) -> T {
+		self.inner
 	}
 }
 
 impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send> traits::AsyncClientConnection for AsyncClientConnection<T> {
 	fn poll_write_command(self: Pin<&mut Self>, cx: &mut Context<'_>, command: &Command) -> Poll<io::Result<()>> {
-		todo!()
+		let Self { inner, write, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		write.poll(unsafe { Pin::new_unchecked(inner) }, cx, || format!("{}\r\n", command).into_bytes())
 	}
 	
 	fn poll_read_response(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<String>> {
-		todo!()
+		let Self { inner, read, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		let mut inner = unsafe { Pin::new_unchecked(inner) };
+		
+		loop {
+			let start = read.len() - read.iter().rev().position(|b| *b == b'\n').unwrap_or(read.len());
+			
+			match poll_read_line(inner.as_mut(), cx, read, start + MAX_LINE_LEN) {
+				Poll::Pending          => return Poll::Pending,
+				Poll::Ready(Err(e))    => return Poll::Ready(Err(e)),
+				Poll::Ready(Ok(false)) => return Poll::Ready(Err(line_too_long())),
+				Poll::Ready(Ok(true))  => if !is_continuation(&read[start..]) {
+					return Poll::Ready(String::from_utf8(std::mem::take(read)).map_err(|_| invalid_utf8()));
+				}
+			}
+		}
+	}
+	
+	fn poll_write_data(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<()>> {
+		let Self { inner, write, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		write.poll(unsafe { Pin::new_unchecked(inner) }, cx, || encode_data(data))
 	}
 }
 
@@ -200,21 +283,419 @@ impl<T: utils::AsyncAcceptor> utils::AsyncAcceptor for AsyncAcceptor<T> where T:
 }
 
 pub struct AsyncServerConnection<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite> {
-	inner: T
+	inner: T,
+	line:  Vec<u8>,
+	data:  DataDecoder,
+	write: AsyncWriteState
 }
 
 impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite> AsyncServerConnection<T> {
 	pub fn new(inner: T) -> Self {
-		Self { inner }
+		Self { inner, line: Vec::new(), data: DataDecoder::default(), write: AsyncWriteState::default() }
+	}
+	
+	/// Returns the stream, e.g. to start TLS after `STARTTLS`.
+	pub fn into_inner(self) -> T {
+		self.inner
 	}
 }
 
 impl<T: futures_lite::io::AsyncBufRead + futures_lite::io::AsyncWrite + Send> traits::AsyncServerConnection for AsyncServerConnection<T> {
 	fn poll_read_command(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Command<'static>>> {
-		todo!()
+		let Self { inner, line, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		
+		let r = match poll_read_line(unsafe { Pin::new_unchecked(inner) }, cx, line, MAX_COMMAND_LEN) {
+			Poll::Pending          => return Poll::Pending,
+			Poll::Ready(Ok(true))  => parse_command(line).map(Command::into_owned),
+			Poll::Ready(Ok(false)) => Err(line_too_long()),
+			Poll::Ready(Err(e))    => Err(e)
+		};
+		
+		line.clear();
+		Poll::Ready(r)
 	}
 	
 	fn poll_write_response(self: Pin<&mut Self>, cx: &mut Context<'_>, msg: &str) -> Poll<io::Result<()>> {
-		todo!()
+		let Self { inner, write, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		write.poll(unsafe { Pin::new_unchecked(inner) }, cx, || msg.as_bytes().to_vec())
+	}
+	
+	fn poll_read_data(self: Pin<&mut Self>, cx: &mut Context<'_>, max_len: usize) -> Poll<io::Result<Option<Vec<u8>>>> {
+		let Self { inner, line, data, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		let mut inner = unsafe { Pin::new_unchecked(inner) };
+		
+		loop {
+			match poll_read_line(inner.as_mut(), cx, line, MAX_LINE_LEN) {
+				Poll::Pending       => return Poll::Pending,
+				Poll::Ready(Err(e)) => {
+					line.clear();
+					*data = DataDecoder::default();
+					return Poll::Ready(Err(e));
+				}
+				Poll::Ready(Ok(_))  => ()
+			}
+			
+			let done = data.push(line, max_len);
+			line.clear();
+			
+			if done {
+				return Poll::Ready(Ok(data.finish()));
+			}
+		}
 	}
-}
\ No newline at end of file
+	
+	fn poll_read_line(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<u8>>> {
+		let Self { inner, line, .. } = unsafe { Pin::into_inner_unchecked(self) };
+		
+		let r = match poll_read_line(unsafe { Pin::new_unchecked(inner) }, cx, line, MAX_COMMAND_LEN) {
+			Poll::Pending          => return Poll::Pending,
+			Poll::Ready(Ok(true))  => Ok(trim_line_end(std::mem::take(line))),
+			Poll::Ready(Ok(false)) => Err(line_too_long()),
+			Poll::Ready(Err(e))    => Err(e)
+		};
+		
+		line.clear();
+		Poll::Ready(r)
+	}
+}
+
+/// Decodes mail data line by line, lines longer than `MAX_LINE_LEN` are pushed in parts.
+#[derive(Debug)]
+struct DataDecoder {
+	buf:        Vec<u8>,
+	exceeded:   bool,
+	line_start: bool,
+	cr:         bool
+}
+
+impl Default for DataDecoder {
+	fn default() -> Self {
+		Self { buf: Vec::new(), exceeded: false, line_start: true, cr: false }
+	}
+}
+
+impl DataDecoder {
+	/// Returns `true` at the terminating `.` line. Only CRLF ends a line, a bare LF does not
+	/// start a new one, so `\n.\n` can not be used to smuggle a second message past a relay.
+	fn push(&mut self, line: &[u8], max_len: usize) -> bool {
+		// a CRLF may be split between two parts of a long line
+		let crlf = line.ends_with(b"\r\n") || (self.cr && line == b"\n");
+		let line_start = std::mem::replace(&mut self.line_start, crlf);
+		self.cr = line.ends_with(b"\r");
+		
+		let line = match line {
+			b".\r\n" if line_start => return true,
+			[b'.', rest @ ..] if line_start  => rest,
+			line => line
+		};
+		
+		self.exceeded |= self.buf.len() + line.len() > max_len;
+		
+		if !self.exceeded {
+			self.buf.extend_from_slice(line);
+		}
+		
+		false
+	}
+	
+	fn finish(&mut self) -> Option<Vec<u8>> {
+		let Self { buf, exceeded, .. } = std::mem::take(self);
+		if exceeded { None } else { Some(buf) }
+	}
+}
+
+/// A pending write of a command, reply or mail data, which is flushed afterwards.
+#[derive(Debug, Default)]
+struct AsyncWriteState {
+	buf:     Vec<u8>,
+	written: usize
+}
+
+impl AsyncWriteState {
+	fn poll(
+		&mut self,
+		mut inner: Pin<&mut impl futures_lite::io::AsyncWrite>,
+		cx:        &mut Context<'_>,
+		encode:    impl FnOnce() -> Vec<u8>
+	) -> Poll<io::Result<()>> {
+		// the buffer is empty until the first poll of a write
+		if self.buf.is_empty() {
+			self.buf = encode();
+		}
+		
+		while self.written < self.buf.len() {
+			match inner.as_mut().poll_write(cx, &self.buf[self.written..]) {
+				Poll::Pending       => return Poll::Pending,
+				Poll::Ready(Ok(0))  => return self.finish(Err(io::ErrorKind::WriteZero.into())),
+				Poll::Ready(Ok(n))  => self.written += n,
+				Poll::Ready(Err(e)) => return self.finish(Err(e))
+			}
+		}
+		
+		match inner.poll_flush(cx) {
+			Poll::Pending => Poll::Pending,
+			Poll::Ready(r) => self.finish(r)
+		}
+	}
+	
+	fn finish(&mut self, r: io::Result<()>) -> Poll<io::Result<()>> {
+		self.buf.clear();
+		self.written = 0;
+		Poll::Ready(r)
+	}
+}
+
+/// Reads until and including the next LF, at most until `buf` is `limit` bytes long. Returns
+/// `false` if the limit was reached before the LF.
+fn read_line(reader: &mut impl io::BufRead, buf: &mut Vec<u8>, limit: usize) -> io::Result<bool> {
+	loop {
+		let available = reader.fill_buf()?;
+		
+		if available.is_empty() {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		
+		let (done, used) = take_line(available, buf, limit);
+		reader.consume(used);
+		
+		if let Some(done) = done {
+			return Ok(done);
+		}
+	}
+}
+
+/// Async version of `read_line`.
+fn poll_read_line(
+	mut reader: Pin<&mut impl futures_lite::io::AsyncBufRead>,
+	cx:         &mut Context<'_>,
+	buf:        &mut Vec<u8>,
+	limit:      usize
+) -> Poll<io::Result<bool>> {
+	loop {
+		let available = match reader.as_mut().poll_fill_buf(cx) {
+			Poll::Pending              => return Poll::Pending,
+			Poll::Ready(Err(e))        => return Poll::Ready(Err(e)),
+			Poll::Ready(Ok([]))        => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+			Poll::Ready(Ok(available)) => available
+		};
+		
+		let (done, used) = take_line(available, buf, limit);
+		reader.as_mut().consume(used);
+		
+		if let Some(done) = done {
+			return Poll::Ready(Ok(done));
+		}
+	}
+}
+
+/// Moves bytes until the next LF from `available` to `buf`, returns whether the line is
+/// complete, if `buf` is complete, and the number of bytes used.
+fn take_line(available: &[u8], buf: &mut Vec<u8>, limit: usize) -> (Option<bool>, usize) {
+	let len = available.len().min(limit.saturating_sub(buf.len()));
+	
+	match memchr::memchr(b'\n', &available[..len]) {
+		Some(i) => {
+			buf.extend_from_slice(&available[..=i]);
+			(Some(true), i + 1)
+		}
+		None => {
+			buf.extend_from_slice(&available[..len]);
+			(if buf.len() >= limit { Some(false) } else { None }, len)
+		}
+	}
+}
+
+fn parse_command(line: &[u8]) -> io::Result<Command> {
+	let line = std::str::from_utf8(line).map_err(|_| invalid_utf8())?;
+	Command::parse(line.trim_end_matches(['\r', '\n']))
+}
+
+/// Removes the line ending, i.e. CRLF or a bare LF.
+fn trim_line_end(mut line: Vec<u8>) -> Vec<u8> {
+	while let Some(b'\r' | b'\n') = line.last() {
+		line.pop();
+	}
+	
+	line
+}
+
+/// Returns whether a reply line is followed by more lines, i.e. has a `-` after the code.
+fn is_continuation(line: &[u8]) -> bool {
+	line.get(3) == Some(&b'-')
+}
+
+/// Adds the dot-stuffing and the terminating `.` line to mail data.
+fn encode_data(data: &[u8]) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(data.len() + 5);
+	let mut line_start = true;
+	
+	for b in data {
+		if line_start && *b == b'.' {
+			buf.push(b'.');
+		}
+		
+		buf.push(*b);
+		line_start = *b == b'\n';
+	}
+	
+	if !data.is_empty() && !line_start {
+		buf.extend_from_slice(b"\r\n");
+	}
+	
+	buf.extend_from_slice(b".\r\n");
+	buf
+}
+
+fn line_too_long() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "line too long")
+}
+
+fn invalid_utf8() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8")
+}
+#[cfg(test)]
+mod tests {
+	use {super::*, traits::{ClientConnection as _, ServerConnection as _, AsyncServerConnectionExt}, io::{Read, BufRead, Write}};
+	
+	/// Reads from `input` and writes to `output`.
+	struct Duplex {
+		input:  io::Cursor<Vec<u8>>,
+		output: Vec<u8>
+	}
+	
+	impl Duplex {
+		fn new(input: impl Into<Vec<u8>>) -> Self {
+			Self { input: io::Cursor::new(input.into()), output: Vec::new() }
+		}
+	}
+	
+	impl Read for Duplex {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			self.input.read(buf)
+		}
+	}
+	
+	impl BufRead for Duplex {
+		fn fill_buf(&mut self) -> io::Result<&[u8]> {
+			self.input.fill_buf()
+		}
+		
+		fn consume(&mut self, amt: usize) {
+			self.input.consume(amt)
+		}
+	}
+	
+	impl Write for Duplex {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.output.write(buf)
+		}
+		
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+	
+	impl futures_lite::io::AsyncRead for Duplex {
+		fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+			Poll::Ready(self.get_mut().read(buf))
+		}
+	}
+	
+	impl futures_lite::io::AsyncBufRead for Duplex {
+		fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+			Poll::Ready(self.get_mut().fill_buf())
+		}
+		
+		fn consume(self: Pin<&mut Self>, amt: usize) {
+			BufRead::consume(self.get_mut(), amt)
+		}
+	}
+	
+	impl futures_lite::io::AsyncWrite for Duplex {
+		fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+			Poll::Ready(self.get_mut().write(buf))
+		}
+		
+		fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+		
+		fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+	
+	const MESSAGE: &[u8] = b"Subject: test\r\n\r\n.leading dot\r\n..two dots\r\n.\r\nlast line";
+	
+	#[test]
+	fn transaction() {
+		let mut client = ClientConnection::new(Duplex::new("220 mx.example\r\n250-mx.example\r\n250 8BITMIME\r\n"));
+		assert_eq!(client.read_response().unwrap(), "220 mx.example\r\n");
+		client.write_command(&Command::Ehlo("client.example".into())).unwrap();
+		assert_eq!(client.read_response().unwrap(), "250-mx.example\r\n250 8BITMIME\r\n");
+		client.write_command(&Command::Mail("<a@example.com>".into())).unwrap();
+		client.write_command(&Command::Data).unwrap();
+		client.write_data(MESSAGE).unwrap();
+		client.write_command(&Command::Quit).unwrap();
+		
+		let mut server = ServerConnection::new(Duplex::new(client.into_inner().output));
+		assert_eq!(server.read_command().unwrap(), Command::Ehlo("client.example".into()));
+		assert_eq!(server.read_command().unwrap(), Command::Mail("<a@example.com>".into()));
+		assert_eq!(server.read_command().unwrap(), Command::Data);
+		assert_eq!(server.read_data(1024).unwrap().as_deref(), Some(&[MESSAGE, b"\r\n"].concat()[..]));
+		assert_eq!(server.read_command().unwrap(), Command::Quit);
+		assert!(server.read_command().is_err());
+		
+		server.write_response(&super::super::reply(221, "bye")).unwrap();
+		assert_eq!(server.into_inner().output, b"221 bye\r\n");
+	}
+	
+	#[test]
+	fn transaction_async() {
+		futures_lite::future::block_on(async {
+			let mut input = b"DATA\r\n".to_vec();
+			input.extend_from_slice(&encode_data(MESSAGE));
+			input.extend_from_slice(b"DATA\r\n");
+			input.extend_from_slice(&encode_data(MESSAGE));
+			input.extend_from_slice(b"NOOP\r\n");
+			input.extend_from_slice(&[b'x'; MAX_COMMAND_LEN + 1]);
+			
+			let mut server = AsyncServerConnection::new(Duplex::new(input));
+			assert_eq!(server.read_command().await.unwrap(), Command::Data);
+			assert_eq!(server.read_data(1024).await.unwrap().as_deref(), Some(&[MESSAGE, b"\r\n"].concat()[..]));
+			assert_eq!(server.read_command().await.unwrap(), Command::Data);
+			assert_eq!(server.read_data(16).await.unwrap(), None);
+			assert_eq!(server.read_command().await.unwrap(), Command::Noop);
+			assert_eq!(server.read_command().await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+			
+			server.write_response("250 OK\r\n").await.unwrap();
+			assert_eq!(server.into_inner().output, b"250 OK\r\n");
+		})
+	}
+	
+	#[test]
+	fn raw_line() {
+		let mut server = ServerConnection::new(Duplex::new("RGF0YQ==\r\nquit\n*\r\n"));
+		assert_eq!(server.read_line().unwrap(), b"RGF0YQ==");
+		assert_eq!(server.read_line().unwrap(), b"quit");
+		assert_eq!(server.read_line().unwrap(), b"*");
+		assert_eq!(server.read_line().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+		
+		futures_lite::future::block_on(async {
+			let mut input = b"\xFF not a command\r\nNOOP\r\n".to_vec();
+			input.extend_from_slice(&[b'x'; MAX_COMMAND_LEN + 1]);
+			
+			let mut server = AsyncServerConnection::new(Duplex::new(input));
+			assert_eq!(server.read_line().await.unwrap(), b"\xFF not a command");
+			assert_eq!(server.read_command().await.unwrap(), Command::Noop);
+			assert_eq!(server.read_line().await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+		})
+	}
+	
+	#[test]
+	fn data_bare_lf() {
+		let mut server = ServerConnection::new(Duplex::new("a\n.\nMAIL FROM:<b@example.com>\r\n.\r\nQUIT\r\n"));
+		assert_eq!(server.read_data(1024).unwrap().as_deref(), Some(&b"a\n.\nMAIL FROM:<b@example.com>\r\n"[..]));
+		assert_eq!(server.read_command().unwrap(), Command::Quit);
+	}
+}
@@ -54,7 +54,12 @@ impl<T: Connector> Connector for DynConnector<T>  where T::Connection: 'static +
 pub trait ClientConnection {
 	fn write_command(&mut self, command: &Command) -> io::Result<()>;
 	
+	/// Reads a reply, including the codes and the CRLF of each line.
 	fn read_response(&mut self) -> io::Result<String>;
+	
+	/// Writes the mail data after `DATA` was accepted, with dot-stuffing and the terminating
+	/// `.` line.
+	fn write_data(&mut self, data: &[u8]) -> io::Result<()>;
 }
 
 pub type BoxedClientConnection = Box<dyn ClientConnection>;
@@ -67,6 +72,10 @@ impl ClientConnection for BoxedClientConnection {
 	fn read_response(&mut self) -> io::Result<String> {
 		self.as_mut().read_response()
 	}
+	
+	fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+		self.as_mut().write_data(data)
+	}
 }
 
 pub type BoxedAcceptor = Box<dyn Acceptor<Connection = BoxedServerConnection>>;
@@ -95,7 +104,15 @@ impl<T: Acceptor> Acceptor for DynAcceptor<T> where T::Connection: 'static + Ser
 pub trait ServerConnection {
 	fn read_command(&mut self) -> io::Result<Command>;
 	
+	/// Writes a reply, e.g. one formatted with `wire::reply`.
 	fn write_response(&mut self, msg: &str) -> io::Result<()>;
+	
+	/// Reads the mail data up to the terminating `.` line and removes the dot-stuffing. Returns
+	/// `None` if the data is longer than `max_len`, in which case it is still read completely.
+	fn read_data(&mut self, max_len: usize) -> io::Result<Option<Vec<u8>>>;
+	
+	/// Reads a line that is not a command, e.g. a SASL response, without its line ending.
+	fn read_line(&mut self) -> io::Result<Vec<u8>>;
 }
 
 pub type BoxedServerConnection = Box<dyn ServerConnection>;
@@ -108,6 +125,14 @@ impl ServerConnection for BoxedServerConnection {
 	fn write_response(&mut self, msg: &str) -> io::Result<()> {
 		self.as_mut().write_response(msg)
 	}
+	
+	fn read_data(&mut self, max_len: usize) -> io::Result<Option<Vec<u8>>> {
+		self.as_mut().read_data(max_len)
+	}
+	
+	fn read_line(&mut self) -> io::Result<Vec<u8>> {
+		self.as_mut().read_line()
+	}
 }
 
 // ASYNC
@@ -136,6 +161,8 @@ pub trait AsyncClientConnection: Send {
 	fn poll_write_command(self: Pin<&mut Self>, cx: &mut Context<'_>, command: &Command) -> Poll<io::Result<()>>;
 	
 	fn poll_read_response(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<String>>;
+	
+	fn poll_write_data(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<()>>;
 }
 
 pub trait AsyncClientConnectionExt: AsyncClientConnection {
@@ -146,6 +173,10 @@ pub trait AsyncClientConnectionExt: AsyncClientConnection {
 	fn read_response(&mut self) -> AsyncClientConnectionReadResponse<Self> {
 		AsyncClientConnectionReadResponse(self)
 	}
+	
+	fn write_data<'a>(&'a mut self, data: &'a [u8]) -> AsyncClientConnectionWriteData<'a, Self> {
+		AsyncClientConnectionWriteData(self, data)
+	}
 }
 
 impl<T: AsyncClientConnection + ?Sized> AsyncClientConnectionExt for T {}
@@ -172,15 +203,30 @@ impl<'a, T: AsyncClientConnection + ?Sized> Future for AsyncClientConnectionRead
 	}
 }
 
+pub struct AsyncClientConnectionWriteData<'a, T: AsyncClientConnection + ?Sized>(&'a mut T, &'a [u8]);
+
+impl<'a, T: AsyncClientConnection + ?Sized> Future for AsyncClientConnectionWriteData<'a, T> {
+	type Output = io::Result<()>;
+	
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let Self(inner, data) = unsafe { Pin::into_inner_unchecked(self) };
+		unsafe { Pin::new_unchecked(&mut**inner) }.poll_write_data(cx, data)
+	}
+}
+
 pub type BoxedAsyncClientConnection = Pin<Box<dyn AsyncClientConnection>>;
 
 impl AsyncClientConnection for BoxedAsyncClientConnection {
-	fn poll_write_command(mut self: Pin<&mut Self>, cx: &mut Context<'_>, command: &Command) -> Poll<io::Result<()>> {
-		self.as_mut().poll_write_command(cx, command)
+	fn poll_write_command(self: Pin<&mut Self>, cx: &mut Context<'_>, command: &Command) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_write_command(cx, command)
 	}
 	
-	fn poll_read_response(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<String>> {
-		self.as_mut().poll_read_response(cx)
+	fn poll_read_response(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<String>> {
+		self.get_mut().as_mut().poll_read_response(cx)
+	}
+	
+	fn poll_write_data(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_write_data(cx, data)
 	}
 }
 
@@ -203,6 +249,10 @@ pub trait AsyncServerConnection: Send {
 	fn poll_read_command(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Command<'static>>>;
 	
 	fn poll_write_response(self: Pin<&mut Self>, cx: &mut Context<'_>, msg: &str) -> Poll<io::Result<()>>;
+	
+	fn poll_read_data(self: Pin<&mut Self>, cx: &mut Context<'_>, max_len: usize) -> Poll<io::Result<Option<Vec<u8>>>>;
+	
+	fn poll_read_line(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<u8>>>;
 }
 
 pub trait AsyncServerConnectionExt: AsyncServerConnection {
@@ -213,6 +263,14 @@ pub trait AsyncServerConnectionExt: AsyncServerConnection {
 	fn write_response<'a>(&'a mut self, msg: &'a str) -> AsyncServerConnectionWriteResponse<'a, Self> {
 		AsyncServerConnectionWriteResponse(self, msg)
 	}
+	
+	fn read_data(&mut self, max_len: usize) -> AsyncServerConnectionReadData<Self> {
+		AsyncServerConnectionReadData(self, max_len)
+	}
+	
+	fn read_line(&mut self) -> AsyncServerConnectionReadLine<Self> {
+		AsyncServerConnectionReadLine(self)
+	}
 }
 
 impl<T: AsyncServerConnection + ?Sized> AsyncServerConnectionExt for T {}
@@ -239,14 +297,44 @@ impl<'a, T: AsyncServerConnection + ?Sized> Future for AsyncServerConnectionWrit
 	}
 }
 
+pub struct AsyncServerConnectionReadData<'a, T: AsyncServerConnection + ?Sized>(&'a mut T, usize);
+
+impl<'a, T: AsyncServerConnection + ?Sized> Future for AsyncServerConnectionReadData<'a, T> {
+	type Output = io::Result<Option<Vec<u8>>>;
+	
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let Self(inner, max_len) = unsafe { Pin::into_inner_unchecked(self) };
+		unsafe { Pin::new_unchecked(&mut**inner) }.poll_read_data(cx, *max_len)
+	}
+}
+
+pub struct AsyncServerConnectionReadLine<'a, T: AsyncServerConnection + ?Sized>(&'a mut T);
+
+impl<'a, T: AsyncServerConnection + ?Sized> Future for AsyncServerConnectionReadLine<'a, T> {
+	type Output = io::Result<Vec<u8>>;
+	
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let Self(inner) = unsafe { Pin::into_inner_unchecked(self) };
+		unsafe { Pin::new_unchecked(&mut**inner) }.poll_read_line(cx)
+	}
+}
+
 pub type BoxedAsyncServerConnection = Pin<Box<dyn AsyncServerConnection>>;
 
 impl AsyncServerConnection for BoxedAsyncServerConnection {
-	fn poll_read_command(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Command<'static>>> {
-		self.as_mut().poll_read_command(cx)
+	fn poll_read_command(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Command<'static>>> {
+		self.get_mut().as_mut().poll_read_command(cx)
+	}
+	
+	fn poll_write_response(self: Pin<&mut Self>, cx: &mut Context<'_>, msg: &str) -> Poll<io::Result<()>> {
+		self.get_mut().as_mut().poll_write_response(cx, msg)
+	}
+	
+	fn poll_read_data(self: Pin<&mut Self>, cx: &mut Context<'_>, max_len: usize) -> Poll<io::Result<Option<Vec<u8>>>> {
+		self.get_mut().as_mut().poll_read_data(cx, max_len)
 	}
 	
-	fn poll_write_response(mut self: Pin<&mut Self>, cx: &mut Context<'_>, msg: &str) -> Poll<io::Result<()>> {
-		self.as_mut().poll_write_response(cx, msg)
+	fn poll_read_line(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<u8>>> {
+		self.get_mut().as_mut().poll_read_line(cx)
 	}
 }
\ No newline at end of file
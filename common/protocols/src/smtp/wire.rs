@@ -20,8 +20,15 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::{io, borrow::Cow};
+use std::{io, borrow::Cow, fmt};
 
+/// The maximum length of a command line, including the CRLF. This is the limit for lines of
+/// `AUTH` (RFC 4954), other commands are limited to 512 bytes by RFC 5321.
+pub const MAX_COMMAND_LEN: usize = 12288;
+/// The maximum length of a reply line or a line of mail data, including the CRLF.
+pub const MAX_LINE_LEN:    usize = 1000;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Command<'a> {
 	/// This command is used to identify the SMTP client to the SMTP
 	/// server.
@@ -61,6 +68,134 @@ pub enum Command<'a> {
 	Other(Cow<'a, str>, Option<Cow<'a, str>>)
 }
 
+impl<'a> Command<'a> {
+	/// Parses a command line without the CRLF. The verb is case-insensitive, the arguments of
+	/// `MAIL` and `RCPT` are what follows `FROM:` and `TO:`.
+	pub fn parse(line: &'a str) -> io::Result<Self> {
+		let (verb, arg) = match line.split_once(' ') {
+			Some((verb, arg)) => (verb, Some(arg.trim()).filter(|v| !v.is_empty())),
+			None              => (line, None)
+		};
+		
+		let required = |arg: Option<&'a str>| arg
+			.map(Cow::Borrowed)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing argument"));
+		let path = |arg: Option<&'a str>, prefix: &str| arg
+			.filter(|v| v.len() >= prefix.len() && v.is_char_boundary(prefix.len()) && v[..prefix.len()].eq_ignore_ascii_case(prefix))
+			.map(|v| Cow::Borrowed(v[prefix.len()..].trim_start()))
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid path"));
+		
+		Ok(match verb.to_ascii_uppercase().as_str() {
+			"HELO" => Self::Helo(required(arg)?),
+			"EHLO" => Self::Ehlo(required(arg)?),
+			"MAIL" => Self::Mail(path(arg, "FROM:")?),
+			"RCPT" => Self::Rcpt(path(arg, "TO:")?),
+			"DATA" => Self::Data,
+			"RSET" => Self::Rset,
+			"VRFY" => Self::Vrfy(required(arg)?),
+			"EXPN" => Self::Expn(required(arg)?),
+			"HELP" => Self::Help(arg.map(Cow::Borrowed)),
+			"NOOP" => Self::Noop,
+			"QUIT" => Self::Quit,
+			_      => Self::Other(Cow::Borrowed(verb), arg.map(Cow::Borrowed))
+		})
+	}
+	
+	pub fn into_owned(self) -> Command<'static> {
+		fn owned(v: Cow<str>) -> Cow<'static, str> {
+			Cow::Owned(v.into_owned())
+		}
+		
+		match self {
+			Self::Helo(v)         => Command::Helo(owned(v)),
+			Self::Ehlo(v)         => Command::Ehlo(owned(v)),
+			Self::Mail(v)         => Command::Mail(owned(v)),
+			Self::Rcpt(v)         => Command::Rcpt(owned(v)),
+			Self::Data            => Command::Data,
+			Self::Rset            => Command::Rset,
+			Self::Vrfy(v)         => Command::Vrfy(owned(v)),
+			Self::Expn(v)         => Command::Expn(owned(v)),
+			Self::Help(v)         => Command::Help(v.map(owned)),
+			Self::Noop            => Command::Noop,
+			Self::Quit            => Command::Quit,
+			Self::Other(cmd, arg) => Command::Other(owned(cmd), arg.map(owned))
+		}
+	}
+}
+
+/// Formats the command line without the CRLF.
+impl fmt::Display for Command<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Helo(v)               => write!(f, "HELO {}", v),
+			Self::Ehlo(v)               => write!(f, "EHLO {}", v),
+			Self::Mail(v)               => write!(f, "MAIL FROM:{}", v),
+			Self::Rcpt(v)               => write!(f, "RCPT TO:{}", v),
+			Self::Data                  => f.write_str("DATA"),
+			Self::Rset                  => f.write_str("RSET"),
+			Self::Vrfy(v)               => write!(f, "VRFY {}", v),
+			Self::Expn(v)               => write!(f, "EXPN {}", v),
+			Self::Help(None)            => f.write_str("HELP"),
+			Self::Help(Some(v))         => write!(f, "HELP {}", v),
+			Self::Noop                  => f.write_str("NOOP"),
+			Self::Quit                  => f.write_str("QUIT"),
+			Self::Other(cmd, None)      => f.write_str(cmd),
+			Self::Other(cmd, Some(arg)) => write!(f, "{} {}", cmd, arg)
+		}
+	}
+}
+
+/// Splits the argument of `MAIL` or `RCPT` into the address without angle brackets and the
+/// parameters, e.g. `<a@example.com> SIZE=1024` into `a@example.com` and `SIZE=1024`.
+pub fn split_path(arg: &str) -> Option<(&str, &str)> {
+	let arg = arg.trim_start().strip_prefix('<')?;
+	let (path, params) = arg.split_once('>')?;
+	// a source route, which is obsolete
+	let path = match path.split_once(':') {
+		Some((route, path)) if route.starts_with('@') => path,
+		_ => path
+	};
+	Some((path, params.trim()))
+}
+
+/// Formats a reply, every line of `text` becomes a line of the reply.
+pub fn reply(code: u16, text: &str) -> String {
+	let mut lines = text.lines().peekable();
+	let mut buf = String::new();
+	
+	if lines.peek().is_none() {
+		return format!("{}\r\n", code);
+	}
+	
+	while let Some(line) = lines.next() {
+		let sep = if lines.peek().is_some() { '-' } else { ' ' };
+		buf.push_str(&format!("{}{}{}\r\n", code, sep, line));
+	}
+	
+	buf
+}
+
+/// Returns the code of a reply and its text, with the lines separated by `\n`.
+pub fn parse_reply(reply: &str) -> io::Result<(u16, String)> {
+	let mut code = None;
+	let mut text = Vec::new();
+	
+	for line in reply.lines() {
+		let (c, line) = match (line.get(..3).map(str::parse::<u16>), line.get(3..4)) {
+			(Some(Ok(c)), Some(" " | "-") | None) if (200..600).contains(&c) => (c, line.get(4..).unwrap_or("")),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid reply"))
+		};
+		
+		if *code.get_or_insert(c) != c {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "inconsistent reply codes"));
+		}
+		
+		text.push(line);
+	}
+	
+	code.map(|code| (code, text.join("\n"))).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty reply"))
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u32)]
 pub enum ReplyCode {
@@ -122,4 +257,41 @@ impl TryFrom<[u8; 3]> for ReplyCode {
 			_      => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid SMTP reply code"))
 		})
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn command() {
+		assert_eq!(Command::parse("ehlo client.example").unwrap(), Command::Ehlo(Cow::Borrowed("client.example")));
+		assert_eq!(Command::parse("MAIL FROM:<a@example.com> SIZE=10").unwrap(), Command::Mail(Cow::Borrowed("<a@example.com> SIZE=10")));
+		assert_eq!(Command::parse("RCPT to: <b@example.com>").unwrap(), Command::Rcpt(Cow::Borrowed("<b@example.com>")));
+		assert_eq!(Command::parse("DATA").unwrap(), Command::Data);
+		assert_eq!(Command::parse("AUTH PLAIN").unwrap(), Command::Other(Cow::Borrowed("AUTH"), Some(Cow::Borrowed("PLAIN"))));
+		assert!(Command::parse("HELO").is_err());
+		assert!(Command::parse("MAIL <a@example.com>").is_err());
+		
+		for line in ["EHLO client.example", "MAIL FROM:<>", "RCPT TO:<b@example.com>", "HELP", "STARTTLS"] {
+			assert_eq!(Command::parse(line).unwrap().to_string(), line);
+		}
+	}
+	
+	#[test]
+	fn path() {
+		assert_eq!(split_path("<a@example.com> SIZE=10 BODY=8BITMIME"), Some(("a@example.com", "SIZE=10 BODY=8BITMIME")));
+		assert_eq!(split_path("<>"), Some(("", "")));
+		assert_eq!(split_path("<@relay.example:a@example.com>"), Some(("a@example.com", "")));
+		assert_eq!(split_path("a@example.com"), None);
+	}
+	
+	#[test]
+	fn replies() {
+		assert_eq!(reply(250, "mx.example\nSIZE 1024\nPIPELINING"), "250-mx.example\r\n250-SIZE 1024\r\n250 PIPELINING\r\n");
+		assert_eq!(reply(354, ""), "354\r\n");
+		assert_eq!(parse_reply("250-mx.example\r\n250 SIZE 1024\r\n").unwrap(), (250, "mx.example\nSIZE 1024".to_string()));
+		assert_eq!(parse_reply("221\r\n").unwrap(), (221, String::new()));
+		assert!(parse_reply("250-a\r\n550 b\r\n").is_err());
+		assert!(parse_reply("hello\r\n").is_err());
+	}
+}
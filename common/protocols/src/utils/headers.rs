@@ -36,9 +36,31 @@ pub enum Encoding {
 	Compress,
 	Identity,
 	Br,
+	Zstd,
 	Other(Box<str>)
 }
 
+impl Encoding {
+	/// Splits the quality value of an `Accept-Encoding` entry, e.g. `gzip;q=0.5`, from the coding.
+	///
+	/// Entries with parameters are parsed as [`Encoding::Other`], the quality defaults to `1.0`.
+	pub fn quality(&self) -> (Self, f32) {
+		let v = match self {
+			Self::Other(v) => v,
+			v => return (v.clone(), 1.0)
+		};
+		
+		let mut params = v.split(';').map(str::trim);
+		let coding = params.next().unwrap_or_default().parse().unwrap_or(Self::Identity);
+		let q = params
+			.find_map(|v| v.strip_prefix("q="))
+			.map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+			.unwrap_or(0.0);
+		
+		(coding, q)
+	}
+}
+
 impl FromStr for Encoding {
 	type Err = ();
 
@@ -49,6 +71,7 @@ impl FromStr for Encoding {
 			"compress" => Self::Compress,
 			"identity" => Self::Identity,
 			"br"       => Self::Br,
+			"zstd"     => Self::Zstd,
 			v          => Self::Other(v.to_string().into_boxed_str())
 		})
 	}
@@ -62,6 +85,7 @@ impl fmt::Display for Encoding {
 			Self::Compress => "compress",
 			Self::Identity => "identity",
 			Self::Br       => "br",
+			Self::Zstd     => "zstd",
 			Self::Other(v) => v
 		})
 	}
@@ -217,8 +241,12 @@ pub fn parse_map(value: &str) -> Vec<(String, String)> {
 }
 
 pub fn fmt_list<I: IntoIterator>(f: &mut fmt::Formatter, iter: I) -> fmt::Result where I::Item: fmt::Display {
-	for e in iter {
-		write!(f, "{}, ", e)?;
+	for (i, e) in iter.into_iter().enumerate() {
+		if i > 0 {
+			f.write_str(", ")?;
+		}
+		
+		write!(f, "{}", e)?;
 	}
 	Ok(())
 }
@@ -63,8 +63,52 @@ fn parse_attr(attr: &Attribute) -> Option<Group> {
 	Some(group)
 }
 
-fn parse_route(tokens: TokenStream) -> Option<TokenStream> {
-	let (mut method, mut path) = (TokenStream::from_str("_").unwrap(), TokenStream::from_str("_").unwrap());
+/// A route of the `controller`, with the variant of the method and the path as written in the
+/// attribute.
+struct Route {
+	method: Option<String>,
+	path:   Option<String>
+}
+
+impl Route {
+	/// The statement that adds the route to the `Routes` trie.
+	fn insert(&self, index: usize) -> TokenStream {
+		let method = match &self.method {
+			Some(v) => TokenStream::from_str(&format!("Some(::net::http::Method::{})", v)).unwrap(),
+			None    => quote! { None }
+		};
+		
+		let path = match &self.path {
+			Some(v) => quote! { Some(#v) },
+			None    => quote! { None }
+		};
+		
+		quote! { routes.insert(#method, #path, #index); }
+	}
+	
+	/// Binds the parameters of a path like `/items/{id}/{rest...}` to variables.
+	fn bindings(&self) -> TokenStream {
+		let mut buf = String::new();
+		let mut params = 0;
+		
+		for s in self.path.iter().flat_map(|v| v.split('/')) {
+			if let Some(s) = s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+				match s.strip_suffix("...") {
+					Some(s) => write!(&mut buf, "let {} = __rest;", s).unwrap(),
+					None    => {
+						write!(&mut buf, "let {} = &__params[{}];", s, params).unwrap();
+						params += 1;
+					}
+				}
+			}
+		}
+		
+		TokenStream::from_str(&buf).unwrap()
+	}
+}
+
+fn parse_route(tokens: TokenStream) -> Option<Route> {
+	let (mut method_name, mut path_name) = (None, None);
 	let mut tokens = tokens.into_iter();
 	
 	let group = match tokens.next() {
@@ -73,7 +117,7 @@ fn parse_route(tokens: TokenStream) -> Option<TokenStream> {
 			tt.span().unwrap().error("failed to parse attribute: expected (...)".to_string()).emit();
 			return None;
 		}
-		None => return Some(quote! { (_, _) })
+		None => return Some(Route { method: None, path: None })
 	};
 	
 	if let Some(tt) = tokens.next() {
@@ -112,8 +156,7 @@ fn parse_route(tokens: TokenStream) -> Option<TokenStream> {
 					Some(TokenTree::Literal(v)) => {
 						let v = v.to_string();
 						let v = v.strip_prefix('"').unwrap().strip_suffix('"').unwrap();
-						let v = v[..1].to_ascii_uppercase() + &v[1..].to_ascii_lowercase();
-						method = TokenStream::from_str(&format!("::net::http::Method::{}", v)).unwrap();
+						method_name = Some(v[..1].to_ascii_uppercase() + &v[1..].to_ascii_lowercase());
 					}
 					Some(tt) => {
 						tt.span().unwrap().error("failed to parse attribute: expected string literal".to_string()).emit();
@@ -141,21 +184,7 @@ fn parse_route(tokens: TokenStream) -> Option<TokenStream> {
 				match tokens.next() {
 					Some(TokenTree::Literal(v)) => {
 						let v = v.to_string();
-						let input = v.strip_prefix('"').unwrap().strip_suffix('"').unwrap();
-						let mut buf = "[".to_string();
-						
-						for s in input.split('/') {
-							match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
-								Some(s) => match s.strip_suffix("...") {
-									Some(s) => write!(&mut buf, "{} @ .., ", s).unwrap(),
-									None    => write!(&mut buf, "{}, ", s).unwrap()
-								},
-								None    => write!(&mut buf, "\"{}\", ", s).unwrap()
-							}
-						}
-						
-						buf.push(']');
-						path = TokenStream::from_str(&buf).unwrap();
+						path_name = Some(v.strip_prefix('"').unwrap().strip_suffix('"').unwrap().to_string());
 					}
 					Some(tt) => {
 						tt.span().unwrap().error("failed to parse attribute: expected string literal".to_string()).emit();
@@ -183,7 +212,7 @@ fn parse_route(tokens: TokenStream) -> Option<TokenStream> {
 		}
 	}
 	
-	Some(quote! { (#method, #path) })
+	Some(Route { method: method_name, path: path_name })
 }
 
 fn parse_error(tokens: TokenStream) -> Option<TokenStream> {
@@ -214,12 +243,51 @@ fn parse_error(tokens: TokenStream) -> Option<TokenStream> {
 	}
 }
 
-fn parse_input(ty: &mut PatType) -> Option<TokenStream> {
+/// Whether `ty` is `&str` or `&[&str]`, which path parameters are passed as without parsing.
+fn is_str_ref(ty: &Type) -> bool {
+	match ty {
+		Type::Reference(ty) => match &*ty.elem {
+			Type::Path(path) => path.path.is_ident("str"),
+			Type::Slice(slice) => is_str_ref(&slice.elem),
+			_ => false
+		},
+		_ => false
+	}
+}
+
+/// The type `T` of an `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+	let segment = match ty {
+		Type::Path(path) => path.path.segments.last()?,
+		_ => return None
+	};
+	
+	match &segment.arguments {
+		PathArguments::AngleBracketed(args) if segment.ident == "Option" => match args.args.first()? {
+			GenericArgument::Type(ty) => Some(ty),
+			_ => None
+		},
+		_ => None
+	}
+}
+
+/// Parses an argument, returns the expression passed to the handler and a statement that adds the
+/// argument to the OpenAPI operation.
+fn parse_input(ty: &mut PatType) -> Option<(TokenStream, TokenStream)> {
 	for (i, attr) in ty.attrs.iter_mut().enumerate() {
 		let v = match &attr.path.get_ident().map(Ident::to_string).as_deref() {
 			Some("path")  => {
 				let ident = &ty.pat;
-				quote! { std::convert::TryInto::try_into(PathArg(#ident).get())? }
+				let name = ident.to_token_stream().to_string();
+				
+				if is_str_ref(&ty.ty) {
+					(quote! { PathArg(#ident).get() },
+					 quote! { parameters.push(doc.parameter::<String>(#name, "path", true)); })
+				} else {
+					let schema_ty = &ty.ty;
+					(quote! { ::net_services::controller::parse_param(PathArg(#ident).get(), #name)? },
+					 quote! { parameters.push(doc.parameter::<#schema_ty>(#name, "path", true)); })
+				}
 			}
 			Some("query") => {
 				let group = parse_attr(attr)?;
@@ -227,17 +295,27 @@ fn parse_input(ty: &mut PatType) -> Option<TokenStream> {
 				
 				match tokens.next() {
 					Some(TokenTree::Literal(v)) => {
-						if ty.ty.to_token_stream().to_string().starts_with("Option") {
-							quote! {
-								{ query.get(#v)
-									.map(|v| std::convert::TryInto::try_into(*v)).transpose()? }
-							}
+						let (inner, optional) = match option_inner(&ty.ty) {
+							Some(inner) => (inner, true),
+							None        => (&*ty.ty, false)
+						};
+						
+						let (value, schema_ty) = match is_str_ref(inner) {
+							true  => (quote! { query.get(#v).map(|v| &**v) }, quote! { String }),
+							false => (quote! {
+								query.get(#v).map(|v| ::net_services::controller::parse_param(v, #v)).transpose()?
+							}, inner.to_token_stream())
+						};
+						
+						let doc = quote! { parameters.push(doc.parameter::<#schema_ty>(#v, "query", !#optional)); };
+						
+						if optional {
+							(value, doc)
 						} else {
-							quote! {
-								{ query.get(#v)
-									.map(|v| std::convert::TryInto::try_into(*v)).transpose()?
-									.ok_or_else(|| ::net_services::Error::new("required query parameter not present"))? }
-							}
+							(quote! {
+								#value.ok_or_else(|| ::net_services::controller::HttpError::bad_request(
+									concat!("required query parameter `", #v, "` not present")))?
+							}, doc)
 						}
 					}
 					Some(tt) => {
@@ -256,16 +334,22 @@ fn parse_input(ty: &mut PatType) -> Option<TokenStream> {
 				
 				match tokens.next() {
 					Some(TokenTree::Ident(v)) => {
-						if ty.ty.to_token_stream().to_string().starts_with("Option") {
-							quote! { headers.iter().find_map(|v| match v {
+						let optional = ty.ty.to_token_stream().to_string().starts_with("Option");
+						let doc = quote! {
+							parameters.push(doc.parameter::<String>(::net::http::HeaderId::#v.name_v1(), "header", !#optional));
+						};
+						
+						if optional {
+							(quote! { headers.iter().find_map(|v| match v {
 								::net::http::Header::#v(v) => Some(v),
 								_ => None
-							}) }
+							}) }, doc)
 						} else {
-							quote! { headers.iter().find_map(|v| match v {
+							let msg = format!("required header `{}` not present", v);
+							(quote! { headers.iter().find_map(|v| match v {
 								::net::http::Header::#v(v) => Some(v),
 								_ => None
-							}).ok_or_else(|| ::net_services::Error::new("required header not present"))? }
+							}).ok_or_else(|| ::net_services::controller::HttpError::bad_request(#msg))? }, doc)
 						}
 					}
 					Some(tt) => {
@@ -278,12 +362,20 @@ fn parse_input(ty: &mut PatType) -> Option<TokenStream> {
 					}
 				}
 			}
-			Some("body") => quote! { {
-				let mut buf = Vec::with_capacity(0x1000);
-				stream.read_to_end(&mut buf).await?;
-				std::convert::TryInto::try_into(buf)?
-			} },
-			Some("stream") => quote! { (&mut*stream) },
+			Some("body") => (quote! {
+				std::convert::TryInto::try_into(::net_services::controller::read_body(&mut*stream).await?)?
+			}, TokenStream::new()),
+			Some("json") => {
+				let schema_ty = &ty.ty;
+				(quote! { ::net_services::controller::read_json(&mut*stream).await? },
+				 quote! { request_body = Some(doc.request_body::<#schema_ty>("application/json")); })
+			}
+			Some("form") => {
+				let schema_ty = &ty.ty;
+				(quote! { ::net_services::controller::read_form(&mut*stream).await? },
+				 quote! { request_body = Some(doc.request_body::<#schema_ty>("application/x-www-form-urlencoded")); })
+			}
+			Some("stream") => (quote! { (&mut*stream) }, TokenStream::new()),
 			_ => continue
 		};
 		
@@ -295,13 +387,75 @@ fn parse_input(ty: &mut PatType) -> Option<TokenStream> {
 	None
 }
 
-//fn parse_output(_ty: &mut ReturnType) -> Option<TokenStream> {
-//	unimplemented!()
-//}
+/// The attribute of the `controller` macro, i.e. `Name` or `Name, openapi = "/openapi.json"`.
+struct ControllerAttr {
+	wrapper_name: Ident,
+	openapi:      Option<LitStr>
+}
+
+impl syn::parse::Parse for ControllerAttr {
+	fn parse(input: syn::parse::ParseStream) -> Result<Self> {
+		let wrapper_name = input.parse()?;
+		
+		if input.is_empty() {
+			return Ok(Self { wrapper_name, openapi: None });
+		}
+		
+		input.parse::<Token![,]>()?;
+		let key = input.parse::<Ident>()?;
+		
+		if key != "openapi" {
+			return Err(Error::new(key.span(), "expected `openapi`"));
+		}
+		
+		input.parse::<Token![=]>()?;
+		Ok(Self { wrapper_name, openapi: Some(input.parse()?) })
+	}
+}
 
+/// The first line of the doc comment of a method.
+fn summary(attrs: &[Attribute]) -> Option<String> {
+	attrs.iter()
+		.filter(|attr| attr.path.is_ident("doc"))
+		.find_map(|attr| match attr.parse_meta() {
+			Ok(Meta::NameValue(MetaNameValue { lit: Lit::Str(v), .. })) => Some(v.value().trim().to_string()),
+			_ => None
+		})
+}
+
+/// Generates a `HttpStreamHandler` named by the attribute, that routes requests to the methods of
+/// the impl-block annotated with `#[route(method = "GET", path = "/items/{id}")]`.
+///
+/// Arguments must be annotated with one of:
+/// - `#[path]`, a path segment with the name of the argument, parsed with `FromStr` unless it is
+///   `&str`. `{rest...}` matches the remaining segments as `&[&str]`.
+/// - `#[query("name")]`, a query parameter, parsed with `FromStr` unless it is `&str`. Optional if
+///   the argument is an `Option`.
+/// - `#[header(Variant)]`, the value of a header.
+/// - `#[body]`, the body converted from a `Vec<u8>` with `TryInto`.
+/// - `#[json]` or `#[form]`, the body deserialized with serde.
+/// - `#[stream]`, the stream itself.
+///
+/// Routes are compiled into a segment trie, where literal segments take precedence over parameters
+/// and parameters over wildcards. A route without a path matches any path, without a method any
+/// method. Unmatched paths are answered with `404`, other methods with `405` and an `Allow` header.
+/// `OPTIONS` is answered with the allowed methods and `HEAD` with the response of `GET` without
+/// body, unless there are routes for them. Bodies written to the `#[stream]` of a `HEAD` request
+/// are discarded.
+///
+/// Path and query are percent-decoded, invalid parameters are answered with `400`. Methods may
+/// return anything that implements `IntoResponse`, or `()` if they sent the response themselves.
+/// Errors of returned `Result`s are passed to the first matching `#[error(Type)]` method, which
+/// returns a response the same way, `#[error(panic)]` matches any error.
+///
+/// The wrapper gets a `fn openapi() -> serde_json::Value` with an OpenAPI 3.1 document of all routes
+/// with a method and path. Schemas are generated with `schemars`, so types of `#[json]` and `#[form]`
+/// bodies, `Json` responses and parsed parameters must implement `JsonSchema`, and return types
+/// `DescribeResponse`. With
+/// `#[controller(Name, openapi = "/openapi.json")]`, the document is served on `GET /openapi.json`.
 #[proc_macro_attribute]
 pub fn controller(attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
-	let wrapper_name = match syn::parse::<syn::Ident>(attr) {
+	let ControllerAttr { wrapper_name, openapi } = match syn::parse::<ControllerAttr>(attr) {
 		Ok(v) => v,
 		Err(e) => {
 			Span::call_site().error(format!("failed to parse attribute: {}", e)).emit();
@@ -317,21 +471,68 @@ pub fn controller(attr: proc_macro::TokenStream, item: proc_macro::TokenStream)
 		}
 	};
 	
+	let (mut operations, mut inserts) = (Vec::new(), Vec::new());
 	let routes = item_impl.items.iter_mut().filter_map(|item| {
-		let (attr, sig) = match item {
-			ImplItem::Method(method) => (get_attr(&mut method.attrs, "route")?, &mut method.sig),
+		let (attr, attrs, sig) = match item {
+			ImplItem::Method(method) => (get_attr(&mut method.attrs, "route")?, &method.attrs, &mut method.sig),
 			_ => return None
 		};
 		
 		let fn_name = &sig.ident;
-		let pattern = parse_route(attr.tokens)?;
-		let input   = sig.inputs.iter_mut().filter_map(|input| match input {
-			FnArg::Typed(input) => Some(parse_input(input)?),
-			FnArg::Receiver(_) => None
-		});
-		//let output  = parse_output(&mut sig.output)?;
+		let route   = parse_route(attr.tokens)?;
+		let (input, docs) = sig.inputs.iter_mut()
+			.filter_map(|input| match input {
+				FnArg::Typed(input) => Some(parse_input(input)?),
+				FnArg::Receiver(_) => None
+			})
+			.unzip::<_, _, Vec<_>, Vec<_>>();
+		
+		let index = inserts.len();
+		inserts.push(route.insert(index));
+		
+		if let (Some(method), Some(path)) = (&route.method, &route.path) {
+			let (method, path) = (method.to_ascii_lowercase(), path.replace("...}", "}"));
+			let output = match &sig.output {
+				ReturnType::Default => quote! { () },
+				ReturnType::Type(_, ty) => match &**ty {
+					Type::ImplTrait(_) => quote! { ::net_services::controller::Response },
+					ty => ty.to_token_stream()
+				}
+			};
+			
+			let operation_id = fn_name.to_string();
+			let summary = summary(attrs).map(|v| quote! { operation["summary"] = #v.into(); });
+			
+			operations.push(quote! {
+				{
+					let mut parameters = ::std::vec::Vec::<::net_services::controller::serde_json::Value>::new();
+					let mut request_body = ::std::option::Option::<::net_services::controller::serde_json::Value>::None;
+					#(#docs)*
+					
+					let mut operation = ::net_services::controller::serde_json::json!({
+						"operationId": #operation_id,
+						"parameters":  parameters,
+						"responses":   doc.responses::<#output>()
+					});
+					
+					#summary
+					
+					if let ::std::option::Option::Some(v) = request_body {
+						operation["requestBody"] = v;
+					}
+					
+					doc.operation(#method, #path, operation);
+				}
+			});
+		}
 		
-		Some(quote! { #pattern => self_.0.#fn_name(#(#input),*).await })
+		let bindings = route.bindings();
+		Some(quote! {
+			#index => {
+				#bindings
+				::net_services::controller::HandlerOutput::into_output(self_.0.#fn_name(#(#input),*).await)
+			}
+		})
 	}).collect::<Vec<_>>();
 	
 	let errors = item_impl.items.iter_mut().filter_map(|item| {
@@ -342,10 +543,21 @@ pub fn controller(attr: proc_macro::TokenStream, item: proc_macro::TokenStream)
 		
 		let fn_name = &sig.ident;
 		let pattern = parse_error(attr.tokens)?;
-		Some(quote! { #pattern => self.0.#fn_name(&headers, stream, e).await })
+		Some(quote! {
+			#pattern => ::net_services::controller::HandlerOutput::into_output(self.0.#fn_name(&headers, stream, e).await)
+		})
 	}).collect::<Vec<_>>();
 	
 	let context_ty = &*item_impl.self_ty;
+	let title = context_ty.to_token_stream().to_string();
+	let openapi_route = openapi.map(|path| {
+		let index = inserts.len();
+		inserts.insert(0, Route { method: Some("Get".to_string()), path: Some(path.value()) }.insert(index));
+		quote! {
+			#index => ::net_services::controller::HandlerOutput::into_output(
+				::net_services::controller::Json(#wrapper_name::openapi())),
+		}
+	});
 	
 	proc_macro::TokenStream::from(quote! {
 		#item_impl
@@ -353,12 +565,21 @@ pub fn controller(attr: proc_macro::TokenStream, item: proc_macro::TokenStream)
 		#[allow(non_camel_case_types)]
 		struct #wrapper_name(::std::sync::Arc<#context_ty>);
 		
+		impl #wrapper_name {
+			/// The OpenAPI document of the routes of this controller.
+			#[allow(unused_mut)]
+			pub fn openapi() -> ::net_services::controller::serde_json::Value {
+				let mut doc = ::net_services::controller::OpenApi::new();
+				#(#operations)*
+				doc.finish(#title, env!("CARGO_PKG_VERSION"))
+			}
+		}
+		
 		impl ::net_services::interfaces::StreamHandler<dyn ::net::http::traits::AsyncStream> for #wrapper_name {
 			fn accept<'a>(&'a self, stream: &'static mut dyn ::net::http::traits::AsyncStream) ->
 				::std::pin::Pin<::std::boxed::Box<dyn ::std::future::Future<Output = ::net_services::Result<()>> + Send + 'a>>
 			{
 				::std::boxed::Box::pin(async move {
-					use ::net::http::Method::*;
 					let mut headers = ::net::http::traits::AsyncStreamExt::read_headers(stream).await?;
 					smol::io::AsyncReadExt::read(stream, &mut []).await?;
 				
@@ -373,21 +594,15 @@ pub fn controller(attr: proc_macro::TokenStream, item: proc_macro::TokenStream)
 					let (path, query) = path.split_once('?')
 						.unwrap_or((path, ""));
 				
-					let query = query.split('&')
-						.map(|v| v.split_once('=').unwrap_or((v, "")))
-						.collect::<::std::collections::HashMap<_, _>>();
-				
-					let path = str::split(path, '/')
-						.collect::<::std::vec::Vec<_>>();
-					
+					#[allow(unused_variables)]
 					async fn __try(
 						self_:   &#wrapper_name,
 						stream:  &mut dyn ::net::http::traits::AsyncStream,
 						headers: &[::net::http::Header],
 						method:  &::net::http::Method,
-						path:    ::std::vec::Vec<&str>,
-						query:   ::std::collections::HashMap<&str, &str>
-					) -> ::net_services::Result<()> {
+						path:    &str,
+						query:   &str
+					) -> ::net_services::Result<::std::option::Option<::net_services::controller::Response>> {
 						pub struct PathArg<T>(T);
 					
 						impl<'a> PathArg<&'a &'a str> {
@@ -401,16 +616,53 @@ pub fn controller(attr: proc_macro::TokenStream, item: proc_macro::TokenStream)
 								self.0
 							}
 						}
-					
-						match (method, &*path) {
-							#(#routes),*
+						
+						let query = ::net_services::controller::parse_query(query)?;
+						let path = str::split(path, '/')
+							.map(|v| ::net_services::controller::percent_decode(v, false))
+							.collect::<::std::option::Option<::std::vec::Vec<_>>>()
+							.ok_or_else(|| ::net_services::controller::HttpError::bad_request("invalid path"))?;
+						let path = path.iter()
+							.map(|v| &**v)
+							.collect::<::std::vec::Vec<&str>>();
+						
+						static ROUTES: ::net_services::controller::LazyRoutes = ::net_services::controller::LazyRoutes::new();
+						let routes = ROUTES.get_or_init(|| {
+							let mut routes = ::net_services::controller::Routes::default();
+							#(#inserts)*
+							routes
+						});
+						
+						let (__route, __params, __rest) = match routes.find(method, &path) {
+							Ok(v) => v,
+							Err(response) => return Ok(Some(response))
+						};
+						
+						match __route {
+							#openapi_route
+							#(#routes)*
+							_ => unreachable!()
 						}
 					}
 				
-					match __try(self, stream, &headers, method, path, query).await {
-						#(#errors, )*
-						r => r
-					}
+					let output = {
+						// handlers that send the response themselves must not send a body to `HEAD`
+						let mut head_stream;
+						let stream: &mut dyn ::net::http::traits::AsyncStream = match method {
+							::net::http::Method::Head => {
+								head_stream = ::net_services::controller::HeadStream::new(&mut*stream);
+								&mut head_stream
+							}
+							_ => &mut*stream
+						};
+						
+						match __try(self, stream, &headers, method, path, query).await {
+							#(#errors, )*
+							r => r
+						}
+					};
+					
+					::net_services::controller::send_output(stream, output, *method == ::net::http::Method::Head).await
 				})
 			}
 		}
@@ -0,0 +1,714 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Writes an access log entry for each request forwarded to another module.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*},
+	std::{
+		io::{self, Write},
+		fs,
+		path::{Path, PathBuf},
+		pin::Pin,
+		sync::{Arc, mpsc, atomic::{AtomicU64, Ordering}},
+		task::{Context, Poll},
+		time::{Duration, Instant}
+	},
+	net::{http::{self, traits::AsyncStreamExt}, otlp::logging::SeverityNumber}
+};
+
+const CLF_DATE_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+/// The number of entries waiting for the writer thread, further entries are dropped.
+const QUEUE_LEN: usize = 0x1000;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// The module requests are forwarded to.
+	pub next:          String,
+	#[serde(default)]
+	pub format:        ConfigFormat,
+	pub sink:          ConfigSink,
+	/// Request headers added to each entry, only used by the `Json` format and the `Otlp` sink.
+	#[serde(default)]
+	pub headers:       Vec<String>,
+	/// The fraction of requests that are logged, between `0` and `1`.
+	#[serde(default = "default_sample")]
+	pub sample:        f64,
+	/// Logs all responses with a status of `500` or higher and failed requests, regardless of `sample`.
+	#[serde(default = "default_true")]
+	pub sample_errors: bool
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub enum ConfigFormat {
+	/// The Common Log Format.
+	Common,
+	/// The Combined Log Format, i.e. the Common Log Format with referer and user agent.
+	Combined,
+	/// One JSON object per line.
+	Json
+}
+
+impl Default for ConfigFormat {
+	fn default() -> Self {
+		Self::Combined
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub enum ConfigSink {
+	Stdout,
+	File(ConfigSinkFile),
+	/// Sends entries as OpenTelemetry log records, with the fields as attributes.
+	Otlp
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSinkFile {
+	pub path:      PathBuf,
+	/// The file is rotated once it would exceed this length.
+	#[serde(default = "default_max_len")]
+	pub max_len:   u64,
+	/// The number of rotated files that are kept, e.g. `access.log.1` to `access.log.10`.
+	#[serde(default = "default_max_files")]
+	pub max_files: usize
+}
+
+fn default_sample() -> f64 {
+	1.0
+}
+
+fn default_max_len() -> u64 {
+	0x640_0000
+}
+
+fn default_max_files() -> usize {
+	10
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	if !(0.0..=1.0).contains(&cfg.sample) {
+		return Err("sample must be between 0 and 1".into());
+	}
+	
+	let dropped = Arc::new(AtomicU64::new(0));
+	let sink = match &cfg.sink {
+		ConfigSink::Stdout    => Sink::Writer(spawn_writer(name, Output::Stdout, dropped.clone())?),
+		ConfigSink::File(cfg) => Sink::Writer(spawn_writer(name, Output::File(RotatingFile::open(cfg)
+			.map_err(|e| Error::new(format!("failed to open `{}`: {}", cfg.path.display(), e)))?), dropped.clone())?),
+		ConfigSink::Otlp      => Sink::Otlp(otel_mrt::runtime())
+	};
+	
+	let module: HttpStreamHandler = Box::new(Module {
+		next:     crate::get_component::<HttpStreamHandler>(crate::component_id(&cfg.next)),
+		name:     name.to_string(),
+		requests: AtomicU64::new(0),
+		sink,
+		dropped,
+		cfg
+	});
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), module);
+	Ok(())
+}
+
+struct Module {
+	cfg:      Config,
+	name:     String,
+	next:     ComponentRef<HttpStreamHandler>,
+	sink:     Sink,
+	requests: AtomicU64,
+	dropped:  Arc<AtomicU64>
+}
+
+impl Module {
+	/// Picks every n-th request, so exactly the configured fraction is logged.
+	fn sample(&self) -> bool {
+		let n = self.requests.fetch_add(1, Ordering::Relaxed) as f64;
+		(n * self.cfg.sample).floor() != ((n + 1.0) * self.cfg.sample).floor()
+	}
+	
+	fn write(&self, entry: &Entry) {
+		match &self.sink {
+			Sink::Writer(tx) => match tx.try_send(entry.format(self.cfg.format)) {
+				Ok(()) => (),
+				Err(mpsc::TrySendError::Full(_)) => { self.dropped.fetch_add(1, Ordering::Relaxed); }
+				Err(mpsc::TrySendError::Disconnected(_)) =>
+					log::error!("access log `{}`: failed to write entry: writer stopped", &self.name)
+			}
+			Sink::Otlp(rt) => rt.log(otel_mrt::LogParams::new()
+				.severity_number(SeverityNumber::Info)
+				.name("access_log".into())
+				.body(otel_mrt::AnyValue::String(entry.format(self.cfg.format)))
+				.attributes(entry.attributes()))
+		}
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let start = Instant::now();
+			let sampled = self.sample();
+			
+			if !sampled && !self.cfg.sample_errors {
+				return self.next.get().await?.accept(stream).await;
+			}
+			
+			let headers = stream.read_headers().await?;
+			let mut entry = Entry::new(&headers, &self.cfg.headers);
+			let mut inner = HeadersHttpStream::new(stream, headers);
+			let mut stream = AccessLogHttpStream {
+				inner:    &mut inner,
+				start,
+				status:   None,
+				upstream: None,
+				headers:  None,
+				read:     0,
+				written:  0
+			};
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream_static = unsafe { std::mem::transmute::<&mut AccessLogHttpStream<'_>, &'static mut AccessLogHttpStream<'static>>(&mut stream) };
+			let r = match self.next.get().await {
+				Ok(next) => next.accept(stream_static).await,
+				Err(e)   => Err(e)
+			};
+			
+			entry.status           = stream.status;
+			entry.upstream         = stream.upstream.take();
+			entry.bytes_in         = stream.read;
+			entry.bytes_out        = stream.written;
+			entry.duration_headers = stream.headers;
+			entry.duration         = start.elapsed();
+			
+			let is_error = r.is_err() || entry.status.map_or(true, |v| v as u16 >= 500);
+			if sampled || (self.cfg.sample_errors && is_error) {
+				self.write(&entry);
+			}
+			
+			r
+		})
+	}
+}
+
+enum Sink {
+	/// Lines are sent to a writer thread, see [`spawn_writer`].
+	Writer(mpsc::SyncSender<String>),
+	Otlp(otel_mrt::Runtime)
+}
+
+enum Output {
+	Stdout,
+	File(RotatingFile)
+}
+
+impl Output {
+	fn write_line(&mut self, line: &str) -> io::Result<()> {
+		match self {
+			Self::Stdout     => writeln!(io::stdout().lock(), "{}", line),
+			Self::File(file) => file.write_line(line)
+		}
+	}
+}
+
+/// Writes lines to `output` on a dedicated thread, since blocking writes and rotations would stall
+/// the executor. Lines that do not fit into the queue are dropped and counted in `dropped`. The
+/// thread stops once the returned sender is dropped.
+fn spawn_writer(name: &str, mut output: Output, dropped: Arc<AtomicU64>) -> io::Result<mpsc::SyncSender<String>> {
+	let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_LEN);
+	let name = name.to_string();
+	
+	std::thread::Builder::new()
+		.name(format!("accesslog-{}", &name))
+		.spawn(move || for line in rx.iter() {
+			if let Err(e) = output.write_line(&line) {
+				log::error!("access log `{}`: failed to write entry: {}", &name, e);
+			}
+			
+			match dropped.swap(0, Ordering::Relaxed) {
+				0 => (),
+				n => log::warn!("access log `{}`: dropped {} entries, the writer cannot keep up", &name, n)
+			}
+		})?;
+	
+	Ok(tx)
+}
+
+struct RotatingFile {
+	path:      PathBuf,
+	max_len:   u64,
+	max_files: usize,
+	file:      fs::File,
+	len:       u64
+}
+
+impl RotatingFile {
+	fn open(cfg: &ConfigSinkFile) -> io::Result<Self> {
+		let file = fs::OpenOptions::new().create(true).append(true).open(&cfg.path)?;
+		Ok(Self {
+			path:      cfg.path.clone(),
+			max_len:   cfg.max_len,
+			max_files: cfg.max_files,
+			len:       file.metadata()?.len(),
+			file
+		})
+	}
+	
+	fn write_line(&mut self, line: &str) -> io::Result<()> {
+		let len = line.len() as u64 + 1;
+		
+		if self.len > 0 && self.len + len > self.max_len {
+			self.rotate()?;
+		}
+		
+		writeln!(self.file, "{}", line)?;
+		self.len += len;
+		Ok(())
+	}
+	
+	/// Renames `path.n` to `path.n+1` and `path` to `path.1`, then reopens `path`.
+	fn rotate(&mut self) -> io::Result<()> {
+		fn rotated(path: &Path, n: usize) -> PathBuf {
+			let mut path = path.as_os_str().to_owned();
+			path.push(format!(".{}", n));
+			PathBuf::from(path)
+		}
+		
+		if self.max_files == 0 {
+			self.file.set_len(0)?;
+		} else {
+			let _ = fs::remove_file(rotated(&self.path, self.max_files));
+			for n in (1..self.max_files).rev() {
+				match fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1)) {
+					Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+					_ => ()
+				}
+			}
+			
+			fs::rename(&self.path, rotated(&self.path, 1))?;
+			self.file = fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+		}
+		
+		self.len = 0;
+		Ok(())
+	}
+}
+
+struct Entry {
+	time:             chrono::DateTime<chrono::Utc>,
+	connection:       ConnectionInfo,
+	identity:         Option<ClientIdentity>,
+	method:           Option<http::Method>,
+	path:             Option<String>,
+	host:             Option<String>,
+	referer:          Option<String>,
+	user_agent:       Option<String>,
+	headers:          Vec<(String, String)>,
+	status:           Option<http::Status>,
+	upstream:         Option<String>,
+	bytes_in:         usize,
+	bytes_out:        usize,
+	duration:         Duration,
+	duration_headers: Option<Duration>
+}
+
+impl Entry {
+	fn new(headers: &[http::Header], selected: &[String]) -> Self {
+		let mut entry = Self {
+			time:             chrono::Utc::now(),
+			connection:       ConnectionInfo::from_headers(headers),
+			identity:         ClientIdentity::from_headers(headers),
+			method:           None,
+			path:             None,
+			host:             None,
+			referer:          None,
+			user_agent:       None,
+			headers:          Vec::new(),
+			status:           None,
+			upstream:         None,
+			bytes_in:         0,
+			bytes_out:        0,
+			duration:         Duration::ZERO,
+			duration_headers: None
+		};
+		
+		for header in headers {
+			match header {
+				http::Header::Method(v)    => entry.method     = Some(v.clone()),
+				http::Header::Path(v)      => entry.path       = Some(v.clone()),
+				http::Header::Host(v)
+				| http::Header::Authority(v) => entry.host     = Some(v.clone()),
+				http::Header::Referer(v)   => entry.referer    = Some(v.clone()),
+				http::Header::UserAgent(v) => entry.user_agent = Some(v.clone()),
+				_ => ()
+			}
+			
+			let name = header.name_v1();
+			if selected.iter().any(|v| v.eq_ignore_ascii_case(name)) {
+				entry.headers.push((name.to_ascii_lowercase(), header.to_string()));
+			}
+		}
+		
+		entry
+	}
+	
+	fn format(&self, format: ConfigFormat) -> String {
+		match format {
+			ConfigFormat::Common   => self.format_common(),
+			ConfigFormat::Combined => format!(
+				"{} \"{}\" \"{}\"",
+				self.format_common(),
+				escape(self.referer.as_deref().unwrap_or("-")),
+				escape(self.user_agent.as_deref().unwrap_or("-"))
+			),
+			ConfigFormat::Json     => serde_json::Value::Object(self.fields()
+				.map(|(k, v)| (k.to_string(), v))
+				.collect())
+				.to_string()
+		}
+	}
+	
+	/// `host ident authuser [date] "request" status bytes`, with the certificate subject as user.
+	fn format_common(&self) -> String {
+		format!(
+			"{} - {} [{}] \"{} {} {}\" {} {}",
+			self.connection.addr.map_or_else(|| "-".to_string(), |v| v.ip().to_string()),
+			self.identity.as_ref().map_or_else(|| "-".to_string(), |v| escape(&v.subject)),
+			self.time.format(CLF_DATE_FORMAT),
+			self.method.as_ref().map_or_else(|| "-".to_string(), ToString::to_string),
+			escape(self.path.as_deref().unwrap_or("-")),
+			escape(self.connection.http_version.as_deref().unwrap_or("-")),
+			self.status.map_or_else(|| "-".to_string(), |v| (v as u16).to_string()),
+			match self.bytes_out {
+				0 => "-".to_string(),
+				v => v.to_string()
+			}
+		)
+	}
+	
+	fn fields(&self) -> impl Iterator<Item = (&'static str, serde_json::Value)> + '_ {
+		use serde_json::Value;
+		
+		let string = |v: Option<&str>| v.map_or(Value::Null, |v| Value::String(v.to_string()));
+		
+		[
+			("time", Value::String(self.time.to_rfc3339())),
+			("client_addr", string(self.connection.addr.map(|v| v.to_string()).as_deref())),
+			("client_subject", string(self.identity.as_ref().map(|v| v.subject.as_str()))),
+			("tls_version", string(self.connection.tls_version.as_deref())),
+			("tls_cipher", string(self.connection.tls_cipher.as_deref())),
+			("tls_sni", string(self.connection.tls_sni.as_deref())),
+			("http_version", string(self.connection.http_version.as_deref())),
+			("method", string(self.method.as_ref().map(ToString::to_string).as_deref())),
+			("host", string(self.host.as_deref())),
+			("path", string(self.path.as_deref())),
+			("status", self.status.map_or(Value::Null, |v| Value::from(v as u16))),
+			("bytes_in", Value::from(self.bytes_in)),
+			("bytes_out", Value::from(self.bytes_out)),
+			("duration_ms", Value::from(self.duration.as_secs_f64() * 1000.0)),
+			("duration_headers_ms", self.duration_headers.map_or(Value::Null, |v| Value::from(v.as_secs_f64() * 1000.0))),
+			("upstream", string(self.upstream.as_deref())),
+			("referer", string(self.referer.as_deref())),
+			("user_agent", string(self.user_agent.as_deref())),
+			("headers", Value::Object(self.headers.iter()
+				.map(|(k, v)| (k.clone(), Value::String(v.clone())))
+				.collect()))
+		].into_iter()
+	}
+	
+	fn attributes(&self) -> Vec<otel_mrt::KeyValue> {
+		self.fields()
+			.filter_map(|(k, v)| Some(otel_mrt::KeyValue {
+				key:   k.to_string(),
+				value: match v {
+					serde_json::Value::Null      => return None,
+					serde_json::Value::String(v) => otel_mrt::AnyValue::String(v),
+					serde_json::Value::Number(v) => match v.as_i64() {
+						Some(v) => otel_mrt::AnyValue::Int(v),
+						None    => otel_mrt::AnyValue::Double(v.as_f64().unwrap_or_default())
+					},
+					serde_json::Value::Object(v) if v.is_empty() => return None,
+					v => otel_mrt::AnyValue::String(v.to_string())
+				}
+			}))
+			.collect()
+	}
+}
+
+/// Escapes quotes, backslashes and control characters, so values cannot break the line format.
+fn escape(v: &str) -> String {
+	v.chars().fold(String::with_capacity(v.len()), |mut buf, ch| {
+		match ch {
+			'"' | '\\'            => { buf.push('\\'); buf.push(ch); }
+			ch if ch.is_control() => buf.push_str(&format!("\\x{:02x}", ch as u32)),
+			ch                    => buf.push(ch)
+		}
+		buf
+	})
+}
+
+/// Counts the body bytes and records the response of the next module.
+struct AccessLogHttpStream<'a> {
+	inner:    &'a mut dyn http::traits::AsyncStream,
+	start:    Instant,
+	status:   Option<http::Status>,
+	upstream: Option<String>,
+	headers:  Option<Duration>,
+	read:     usize,
+	written:  usize
+}
+
+impl<'a> smol::io::AsyncRead for AccessLogHttpStream<'a> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let self_ = unsafe { Pin::into_inner_unchecked(self) };
+		let r = unsafe { Pin::new_unchecked(&mut *self_.inner) }.poll_read(cx, buf);
+		
+		if let Poll::Ready(Ok(len)) = r {
+			self_.read += len;
+		}
+		
+		r
+	}
+}
+
+impl<'a> smol::io::AsyncWrite for AccessLogHttpStream<'a> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let self_ = unsafe { Pin::into_inner_unchecked(self) };
+		let r = unsafe { Pin::new_unchecked(&mut *self_.inner) }.poll_write(cx, buf);
+		
+		if let Poll::Ready(Ok(len)) = r {
+			self_.written += len;
+		}
+		
+		r
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_flush(cx)
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_close(cx)
+	}
+}
+
+impl<'a> http::traits::AsyncStream for AccessLogHttpStream<'a> {
+	fn poll_read_headers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read_headers(cx)
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		let self_ = unsafe { Pin::into_inner_unchecked(self) };
+		
+		for header in headers {
+			match header {
+				http::Header::Status(v) => self_.status = Some(*v),
+				http::Header::Custom(k, v) if k.eq_ignore_ascii_case(HEADER_UPSTREAM) => self_.upstream = Some(v.clone()),
+				_ => ()
+			}
+		}
+		
+		let elapsed = self_.start.elapsed();
+		self_.headers.get_or_insert(elapsed);
+		unsafe { Pin::new_unchecked(&mut *self_.inner) }.poll_write_headers(cx, headers)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn logger(sample: f64, sample_errors: bool, queue: usize) -> (Module, mpsc::Receiver<String>) {
+		let (tx, rx) = mpsc::sync_channel(queue);
+		let module = Module {
+			cfg:      Config {
+				next:    "next".to_string(),
+				format:  ConfigFormat::Common,
+				sink:    ConfigSink::Stdout,
+				headers: Vec::new(),
+				sample,
+				sample_errors
+			},
+			name:     "test".to_string(),
+			next:     test_component(Box::new(TestHttpHandler::default()) as HttpStreamHandler),
+			sink:     Sink::Writer(tx),
+			requests: AtomicU64::new(0),
+			dropped:  Arc::new(AtomicU64::new(0))
+		};
+		(module, rx)
+	}
+	
+	fn entry() -> Entry {
+		let headers = [
+			http::Header::Method(http::Method::Get),
+			http::Header::Path("/a\"b".to_string()),
+			http::Header::Host("example.com".to_string()),
+			http::Header::Referer("https://example.com/".to_string()),
+			http::Header::UserAgent("curl/8.0".to_string()),
+			http::Header::Custom(ConnectionInfo::HEADER_ADDR.to_string(), "192.0.2.1:1234".to_string()),
+			http::Header::Custom(ConnectionInfo::HEADER_HTTP_VERSION.to_string(), "HTTP/1.0".to_string()),
+			http::Header::Custom(ClientIdentity::HEADER_SUBJECT.to_string(), "CN=client".to_string()),
+			http::Header::Custom("X-Request-Id".to_string(), "abc".to_string())
+		];
+		
+		let mut entry = Entry::new(&headers, &["x-request-id".to_string()]);
+		entry.time      = chrono::DateTime::from_timestamp(0, 0).unwrap();
+		entry.status    = Some(http::Status::Ok);
+		entry.bytes_out = 2;
+		entry.duration  = Duration::from_micros(1500);
+		entry
+	}
+	
+	#[test]
+	fn format() {
+		let entry = entry();
+		assert_eq!(entry.format(ConfigFormat::Common),
+			r#"192.0.2.1 - CN=client [01/Jan/1970:00:00:00 +0000] "GET /a\"b HTTP/1.0" 200 2"#);
+		assert_eq!(entry.format(ConfigFormat::Combined),
+			r#"192.0.2.1 - CN=client [01/Jan/1970:00:00:00 +0000] "GET /a\"b HTTP/1.0" 200 2 "https://example.com/" "curl/8.0""#);
+		
+		let json = serde_json::from_str::<serde_json::Value>(&entry.format(ConfigFormat::Json)).unwrap();
+		assert_eq!(json["client_addr"], "192.0.2.1:1234");
+		assert_eq!(json["client_subject"], "CN=client");
+		assert_eq!(json["method"], "GET");
+		assert_eq!(json["host"], "example.com");
+		assert_eq!(json["path"], "/a\"b");
+		assert_eq!(json["status"], 200);
+		assert_eq!(json["bytes_out"], 2);
+		assert!((json["duration_ms"].as_f64().unwrap() - 1.5).abs() < 1e-9);
+		assert_eq!(json["headers"]["x-request-id"], "abc");
+		assert!(json["tls_version"].is_null());
+		assert_eq!(json["http_version"], "HTTP/1.0");
+		
+		// missing values
+		let entry = Entry::new(&[], &[]);
+		assert!(entry.format(ConfigFormat::Combined).starts_with("- - - ["));
+		assert!(entry.format(ConfigFormat::Combined).ends_with("] \"- - -\" - - \"-\" \"-\""));
+	}
+	
+	#[test]
+	fn escape() {
+		assert_eq!(super::escape("plain /path?a=b"), "plain /path?a=b");
+		assert_eq!(super::escape("a\"b\\c"), "a\\\"b\\\\c");
+		assert_eq!(super::escape("a\nb\r\x1b\x7f"), "a\\x0ab\\x0d\\x1b\\x7f");
+		assert_eq!(super::escape("ünïcödé"), "ünïcödé");
+	}
+	
+	#[test]
+	fn sample() {
+		let request = || (vec![http::Header::Method(http::Method::Get), http::Header::Path("/".to_string())], Vec::new());
+		
+		let (module, rx) = logger(0.25, false, 16);
+		for _ in 0..8 {
+			let (headers, body) = request();
+			assert!(TestHttpStream::new(headers, body).accept(&module).0.is_ok());
+		}
+		assert_eq!(rx.try_iter().count(), 2);
+		
+		// the next module does not respond with a status, which counts as an error
+		let (module, rx) = logger(0.25, true, 16);
+		for _ in 0..8 {
+			let (headers, body) = request();
+			assert!(TestHttpStream::new(headers, body).accept(&module).0.is_ok());
+		}
+		assert_eq!(rx.try_iter().count(), 8);
+		
+		let (module, _rx) = logger(0.0, false, 16);
+		assert!((0..100).all(|_| !module.sample()));
+		let (module, _rx) = logger(1.0, false, 16);
+		assert!((0..100).all(|_| module.sample()));
+		
+		// entries that do not fit into the queue are counted
+		let (module, rx) = logger(1.0, true, 1);
+		for _ in 0..3 {
+			let (headers, body) = request();
+			assert!(TestHttpStream::new(headers, body).accept(&module).0.is_ok());
+		}
+		assert_eq!(rx.try_iter().count(), 1);
+		assert_eq!(module.dropped.load(Ordering::Relaxed), 2);
+	}
+	
+	#[test]
+	fn rotate() {
+		let dir = test_dir("accesslog-rotate");
+		let path = dir.join("access.log");
+		let read = |n: usize| match n {
+			0 => fs::read_to_string(&path).ok(),
+			n => fs::read_to_string(dir.join(format!("access.log.{}", n))).ok()
+		};
+		
+		let mut file = RotatingFile::open(&ConfigSinkFile { path: path.clone(), max_len: 10, max_files: 2 }).unwrap();
+		for line in ["aaaa", "bbbb", "cccc"] {
+			file.write_line(line).unwrap();
+		}
+		assert_eq!(read(0).as_deref(), Some("cccc\n"));
+		assert_eq!(read(1).as_deref(), Some("aaaa\nbbbb\n"));
+		
+		for line in ["dddd", "eeee", "ffff", "gggg"] {
+			file.write_line(line).unwrap();
+		}
+		assert_eq!(read(0).as_deref(), Some("gggg\n"));
+		assert_eq!(read(1).as_deref(), Some("eeee\nffff\n"));
+		assert_eq!(read(2).as_deref(), Some("cccc\ndddd\n"));
+		assert_eq!(read(3), None);
+		
+		// the length of an existing file counts
+		std::mem::drop(file);
+		let mut file = RotatingFile::open(&ConfigSinkFile { path: path.clone(), max_len: 10, max_files: 0 }).unwrap();
+		file.write_line("hhhh").unwrap();
+		assert_eq!(read(0).as_deref(), Some("gggg\nhhhh\n"));
+		file.write_line("iiii").unwrap();
+		assert_eq!(read(0).as_deref(), Some("iiii\n"));
+		assert_eq!(read(1).as_deref(), Some("eeee\nffff\n"));
+		
+		let _ = fs::remove_dir_all(&dir);
+	}
+	
+	#[test]
+	fn writer() {
+		let dir = test_dir("accesslog-writer");
+		let path = dir.join("access.log");
+		let file = RotatingFile::open(&ConfigSinkFile { path: path.clone(), max_len: 0x1000, max_files: 1 }).unwrap();
+		let tx = spawn_writer("test", Output::File(file), Arc::new(AtomicU64::new(0))).unwrap();
+		
+		for i in 0..10 {
+			tx.send(format!("line {}", i)).unwrap();
+		}
+		std::mem::drop(tx);
+		
+		let expected = (0..10).map(|i| format!("line {}\n", i)).collect::<String>();
+		let start = Instant::now();
+		while fs::read_to_string(&path).unwrap() != expected {
+			assert!(start.elapsed() < Duration::from_secs(10), "entries were not written");
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
@@ -24,7 +24,7 @@ use {
 	super::*
 };
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct Config {
 	pub version: usize
 }
@@ -22,11 +22,11 @@
 
 use {
 	super::*,
-	crate::interfaces::*,
-	net::http
+	crate::{interfaces::*, utils::*},
+	net::http::{self, traits::AsyncStreamExt}
 };
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
 	#[serde(default)]
@@ -35,7 +35,7 @@ pub struct Config {
 	pub method:   ConfigMethod
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub enum ConfigBackendEnum {
 	Name(String),
 	Config(ConfigBackend)
@@ -50,7 +50,7 @@ impl From<ConfigBackendEnum> for ConfigBackend {
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(from = "ConfigBackendEnum")]
 #[serde(deny_unknown_fields)]
 pub struct ConfigBackend {
@@ -59,7 +59,7 @@ pub struct ConfigBackend {
 	pub weight: f32,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub enum ConfigMethod {
 	First,
 	Nearest,
@@ -124,6 +124,28 @@ impl StreamHandler<dyn http::traits::AsyncStream> for BackendSelector {
 		log::info!("processor `{}`: selected backend #{} `{}` (weight: {}, latency: {}ms)",
 			&self.name, idx, &backend.name, backend.weight, backend.latency.load(Ordering::Relaxed));
 
-		backend.backend.accept(stream)
+		Box::pin(async move {
+			let mut headers = stream.read_headers().await?;
+			let span = start_span(&headers, otel_mrt::SpanParams::new("balancer")
+				.attribute("balancer.backend", otel_mrt::AnyValue::String(backend.name.clone())));
+			span.context().inject(&mut headers);
+			
+			let mut stream = HeadersHttpStream::new(stream, headers);
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream = unsafe { std::mem::transmute::<_, &'static mut HeadersHttpStream<'static>>(&mut stream) };
+			let r = match backend.backend.get().await {
+				Ok(v)  => v.accept(stream).await,
+				Err(e) => Err(e)
+			};
+			
+			if r.is_err() {
+				span.set_status(otel_mrt::StatusCode::Error, None);
+			}
+			
+			span.end();
+			r
+		})
 	}
-}
\ No newline at end of file
+}
+fn f32_one() -> f32 { 1.0 } // LOCALFIX
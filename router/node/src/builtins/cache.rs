@@ -24,7 +24,7 @@ use {
 	super::*,
 };
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
 	pub expiration: Option<usize>,
@@ -34,7 +34,7 @@ pub struct Config {
 	pub size:       Option<usize>
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub enum ConfigCachingScheme {
 	#[serde(rename = "LRU")]
@@ -0,0 +1,655 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compresses responses and decompresses requests of another module.
+//!
+//! HTTP/1.1 responses are sent with a `Content-Length`, so the body is compressed while it is
+//! written and sent once the module finished writing it. To bound the memory used for this, only
+//! responses with a known length of at most `max_response_len` are compressed.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*},
+	std::{io::{self, Read, Write}, pin::Pin, task::{Context, Poll}},
+	net::http::{self, traits::AsyncStreamExt},
+	smol::io::AsyncReadExt
+};
+
+const BROTLI_BUF_LEN:     usize = 0x1000;
+const BROTLI_LG_WIN:      u32   = 22;
+const BROTLI_LEVEL:       u32   = 5;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// The module requests are forwarded to.
+	pub next:                String,
+	/// The supported encodings, the first one accepted by the client with the highest quality is used.
+	#[serde(default = "default_encodings")]
+	pub encodings:           Vec<ConfigEncoding>,
+	/// Content types that are compressed, `text/*` matches any subtype.
+	#[serde(default = "default_types")]
+	pub types:               Vec<String>,
+	/// Responses with a shorter body are sent uncompressed.
+	#[serde(default = "default_min_len")]
+	pub min_len:             usize,
+	/// Responses with a longer body or without a `Content-Length` are sent uncompressed.
+	#[serde(default = "default_max_response_len")]
+	pub max_response_len:    usize,
+	/// The compression level, uses the default of each encoding if omitted.
+	pub level:               Option<u32>,
+	/// Decompresses request bodies with a `Content-Encoding` before forwarding them.
+	#[serde(default)]
+	pub decompress_requests: bool,
+	/// The maximum length of a compressed and of a decompressed request body.
+	#[serde(default = "default_max_request_len")]
+	pub max_request_len:     usize
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigEncoding {
+	Br,
+	Zstd,
+	Gzip,
+	Deflate
+}
+
+impl ConfigEncoding {
+	fn to_encoding(self) -> http::Encoding {
+		match self {
+			Self::Br      => http::Encoding::Br,
+			Self::Zstd    => http::Encoding::Zstd,
+			Self::Gzip    => http::Encoding::GZip,
+			Self::Deflate => http::Encoding::Deflate
+		}
+	}
+}
+
+fn default_encodings() -> Vec<ConfigEncoding> {
+	vec![ConfigEncoding::Br, ConfigEncoding::Zstd, ConfigEncoding::Gzip, ConfigEncoding::Deflate]
+}
+
+fn default_types() -> Vec<String> {
+	[
+		"text/*",
+		"application/javascript",
+		"application/json",
+		"application/manifest+json",
+		"application/wasm",
+		"application/xml",
+		"image/svg+xml"
+	].iter().map(ToString::to_string).collect()
+}
+
+fn default_min_len() -> usize {
+	0x400
+}
+
+fn default_max_response_len() -> usize {
+	0x100_0000
+}
+
+fn default_max_request_len() -> usize {
+	0x100_0000
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	if cfg.encodings.is_empty() {
+		return Err("no encodings configured".into());
+	}
+	
+	let module: HttpStreamHandler = Box::new(Module {
+		next: crate::get_component::<HttpStreamHandler>(crate::component_id(&cfg.next)),
+		cfg
+	});
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), module);
+	Ok(())
+}
+
+struct Module {
+	cfg:  Config,
+	next: ComponentRef<HttpStreamHandler>
+}
+
+impl Module {
+	/// Selects the encoding with the highest quality in `Accept-Encoding`, the order of the
+	/// configured encodings breaks ties.
+	fn negotiate(&self, headers: &[http::Header]) -> Option<ConfigEncoding> {
+		let accepted = headers.iter()
+			.filter_map(|v| match v {
+				http::Header::AcceptEncoding(v) => Some(v),
+				_ => None
+			})
+			.flatten()
+			.map(http::Encoding::quality)
+			.collect::<Vec<_>>();
+		
+		let wildcard = accepted.iter()
+			.find(|(v, _)| matches!(v, http::Encoding::Other(v) if &**v == "*"))
+			.map(|(_, q)| *q);
+		
+		self.cfg.encodings.iter()
+			.filter_map(|encoding| {
+				let encoding_ = encoding.to_encoding();
+				let q = accepted.iter()
+					.find(|(v, _)| *v == encoding_)
+					.map(|(_, q)| *q)
+					.or(wildcard)?;
+				
+				Some((*encoding, q)).filter(|_| q > 0.0)
+			})
+			.fold(None, |best: Option<(ConfigEncoding, f32)>, v| match best {
+				Some(best) if best.1 >= v.1 => Some(best),
+				_ => Some(v)
+			})
+			.map(|(v, _)| v)
+	}
+	
+	/// Whether a response with these headers may be compressed, regardless of the client.
+	fn is_compressible(&self, headers: &[http::Header]) -> bool {
+		let mut status = None;
+		let mut content_type = None;
+		let mut len = None;
+		
+		for header in headers {
+			match header {
+				http::Header::Status(v)          => status = Some(*v),
+				http::Header::ContentType(v)     => content_type = Some(v.to_string()),
+				http::Header::ContentLength(v)   => len = Some(*v),
+				http::Header::ContentEncoding(_)
+				| http::Header::ContentRange(_)  => return false,
+				_ => ()
+			}
+		}
+		
+		let content_type = match content_type {
+			Some(v) => v,
+			None    => return false
+		};
+		
+		let mime = content_type.split(';').next().unwrap_or_default().trim();
+		
+		status == Some(http::Status::Ok)
+			&& len.map_or(false, |len| len >= self.cfg.min_len && len <= self.cfg.max_response_len)
+			&& self.cfg.types.iter().any(|v| match v.strip_suffix("/*") {
+				Some(main) => mime.split('/').next() == Some(main),
+				None       => v.eq_ignore_ascii_case(mime)
+			})
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let mut headers = stream.read_headers().await?;
+			let mut request_body = None;
+			
+			if let (true, Some(encoding)) = (
+				self.cfg.decompress_requests,
+				headers.iter().find_map(http::Header::as_content_encoding).cloned()
+			) {
+				let max_len = self.cfg.max_request_len;
+				
+				if headers.iter().find_map(http::Header::as_content_length).map_or(false, |len| *len > max_len) {
+					return send_response(stream, http::Status::PayloadTooLarge).await;
+				}
+				
+				let mut body = Vec::new();
+				(&mut *stream).take(max_len as u64 + 1).read_to_end(&mut body).await?;
+				
+				if body.len() > max_len {
+					return send_response(stream, http::Status::PayloadTooLarge).await;
+				}
+				
+				let body = match decode(&encoding, &body, max_len) {
+					Ok(v)       => v,
+					Err(status) => return send_response(stream, status).await
+				};
+				
+				headers.retain(|v| !matches!(v, http::Header::ContentEncoding(_) | http::Header::ContentLength(_)));
+				headers.push(http::Header::ContentLength(body.len()));
+				request_body = Some(io::Cursor::new(body));
+			}
+			
+			let encoding = match headers.iter().find_map(http::Header::as_method) {
+				Some(http::Method::Head) => None,
+				_ => self.negotiate(&headers)
+			};
+			
+			let mut stream = CompressHttpStream {
+				inner:           stream,
+				module:          self,
+				encoding,
+				request_headers: Some(headers),
+				request_body,
+				state:           State::Headers
+			};
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream_static = unsafe { std::mem::transmute::<&mut CompressHttpStream<'_>, &'static mut CompressHttpStream<'static>>(&mut stream) };
+			self.next.get().await?.accept(stream_static).await?;
+			
+			// send the compressed body, if the module did not flush or close the stream
+			smol::future::poll_fn(|cx| stream.poll_finish(cx)).await?;
+			Ok(())
+		})
+	}
+}
+
+fn decode(encoding: &http::Encoding, body: &[u8], max_len: usize) -> std::result::Result<Vec<u8>, http::Status> {
+	let reader: Box<dyn Read + '_> = match encoding {
+		http::Encoding::Identity => return Ok(body.to_vec()),
+		http::Encoding::GZip     => Box::new(flate2::read::GzDecoder::new(body)),
+		http::Encoding::Deflate  => Box::new(flate2::read::ZlibDecoder::new(body)),
+		http::Encoding::Br       => Box::new(brotli::Decompressor::new(body, BROTLI_BUF_LEN)),
+		http::Encoding::Zstd     => Box::new(zstd::stream::read::Decoder::new(body)
+			.map_err(|_| http::Status::BadRequest)?),
+		_ => return Err(http::Status::UnsupportedMediaType)
+	};
+	
+	let mut buf = Vec::new();
+	reader.take(max_len as u64 + 1).read_to_end(&mut buf)
+		.map_err(|_| http::Status::BadRequest)?;
+	
+	match buf.len() > max_len {
+		true  => Err(http::Status::PayloadTooLarge),
+		false => Ok(buf)
+	}
+}
+
+enum Encoder {
+	Br(Box<brotli::CompressorWriter<Vec<u8>>>),
+	Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+	Gzip(flate2::write::GzEncoder<Vec<u8>>),
+	Deflate(flate2::write::ZlibEncoder<Vec<u8>>)
+}
+
+impl Encoder {
+	fn new(encoding: ConfigEncoding, level: Option<u32>) -> io::Result<Self> {
+		let flate_level = level.map_or_else(flate2::Compression::default, flate2::Compression::new);
+		
+		Ok(match encoding {
+			ConfigEncoding::Br      => Self::Br(Box::new(brotli::CompressorWriter::new(
+				Vec::new(), BROTLI_BUF_LEN, level.unwrap_or(BROTLI_LEVEL), BROTLI_LG_WIN))),
+			ConfigEncoding::Zstd    => Self::Zstd(zstd::stream::write::Encoder::new(
+				Vec::new(), level.map_or(0, |v| v as i32))?),
+			ConfigEncoding::Gzip    => Self::Gzip(flate2::write::GzEncoder::new(Vec::new(), flate_level)),
+			ConfigEncoding::Deflate => Self::Deflate(flate2::write::ZlibEncoder::new(Vec::new(), flate_level))
+		})
+	}
+	
+	fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+		match self {
+			Self::Br(v)      => v.write_all(buf),
+			Self::Zstd(v)    => v.write_all(buf),
+			Self::Gzip(v)    => v.write_all(buf),
+			Self::Deflate(v) => v.write_all(buf)
+		}
+	}
+	
+	fn finish(self) -> io::Result<Vec<u8>> {
+		match self {
+			Self::Br(v)      => Ok(v.into_inner()),
+			Self::Zstd(v)    => v.finish(),
+			Self::Gzip(v)    => v.finish(),
+			Self::Deflate(v) => v.finish()
+		}
+	}
+}
+
+enum State {
+	/// No response headers were written yet.
+	Headers,
+	/// Writing the uncompressed response headers.
+	WriteHeaders(Vec<http::Header>),
+	/// The response is not compressed.
+	Passthrough,
+	/// Compressing the body, `rem` is the remaining length of the uncompressed body.
+	Compress { headers: Vec<http::Header>, encoding: ConfigEncoding, encoder: Encoder, rem: Option<usize> },
+	/// Sending the compressed response.
+	Send { headers: Vec<http::Header>, body: Vec<u8>, headers_sent: bool, written: usize },
+	Done
+}
+
+struct CompressHttpStream<'a> {
+	inner:           &'a mut dyn http::traits::AsyncStream,
+	module:          &'a Module,
+	encoding:        Option<ConfigEncoding>,
+	request_headers: Option<Vec<http::Header>>,
+	request_body:    Option<io::Cursor<Vec<u8>>>,
+	state:           State
+}
+
+impl<'a> CompressHttpStream<'a> {
+	/// Decides whether the response is compressed, the headers are written by the caller.
+	fn prepare(&mut self, headers: &[http::Header]) -> io::Result<State> {
+		if !self.module.is_compressible(headers) {
+			return Ok(State::WriteHeaders(headers.to_vec()));
+		}
+		
+		// the response depends on `Accept-Encoding`, even if it is not compressed
+		let mut headers = headers.to_vec();
+		match headers.iter_mut().find_map(|v| match v {
+			http::Header::Vary(v) => Some(v),
+			_ => None
+		}) {
+			Some(v) if !v.iter().any(|v| v.eq_ignore_ascii_case("accept-encoding")) => v.push("Accept-Encoding".to_string()),
+			Some(_) => (),
+			None    => headers.push(http::Header::Vary(vec!["Accept-Encoding".to_string()]))
+		}
+		
+		Ok(match self.encoding {
+			Some(encoding) => State::Compress {
+				rem:     headers.iter().find_map(http::Header::as_content_length).copied(),
+				headers,
+				encoding,
+				encoder: Encoder::new(encoding, self.module.cfg.level)?
+			},
+			None => State::WriteHeaders(headers)
+		})
+	}
+	
+	/// Finishes compression, if the body is complete or `force` is set.
+	fn finish(&mut self, force: bool) -> io::Result<()> {
+		match &self.state {
+			State::Compress { rem: Some(0), .. } => (),
+			State::Compress { .. } if force => (),
+			_ => return Ok(())
+		}
+		
+		let (mut headers, encoding, encoder) = match std::mem::replace(&mut self.state, State::Done) {
+			State::Compress { headers, encoding, encoder, .. } => (headers, encoding, encoder),
+			_ => unreachable!()
+		};
+		
+		let body = encoder.finish()?;
+		headers.retain(|v| !matches!(v, http::Header::ContentLength(_)));
+		headers.push(http::Header::ContentEncoding(encoding.to_encoding()));
+		headers.push(http::Header::ContentLength(body.len()));
+		self.state = State::Send { headers, body, headers_sent: false, written: 0 };
+		Ok(())
+	}
+	
+	fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let (headers, body, headers_sent, written) = match &mut self.state {
+			State::Send { headers, body, headers_sent, written } => (headers, body, headers_sent, written),
+			_ => return Poll::Ready(Ok(()))
+		};
+		
+		if !*headers_sent {
+			match Pin::new(&mut *self.inner).poll_write_headers(cx, headers) {
+				Poll::Ready(Ok(())) => *headers_sent = true,
+				v => return v
+			}
+		}
+		
+		while *written < body.len() {
+			match Pin::new(&mut *self.inner).poll_write(cx, &body[*written..]) {
+				Poll::Ready(Ok(0))  => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+				Poll::Ready(Ok(n))  => *written += n,
+				Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+				Poll::Pending       => return Poll::Pending
+			}
+		}
+		
+		self.state = State::Done;
+		Poll::Ready(Ok(()))
+	}
+	
+	fn poll_finish(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		self.finish(true)?;
+		
+		match self.poll_send(cx) {
+			Poll::Ready(Ok(())) => Pin::new(&mut *self.inner).poll_flush(cx),
+			v => v
+		}
+	}
+}
+
+impl<'a> smol::io::AsyncRead for CompressHttpStream<'a> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let self_ = Pin::into_inner(self);
+		match &mut self_.request_body {
+			Some(body) => Poll::Ready(body.read(buf)),
+			None       => Pin::new(&mut *self_.inner).poll_read(cx, buf)
+		}
+	}
+}
+
+impl<'a> smol::io::AsyncWrite for CompressHttpStream<'a> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let self_ = Pin::into_inner(self);
+		match &mut self_.state {
+			State::Compress { encoder, rem, .. } => {
+				encoder.write_all(buf)?;
+				*rem = rem.map(|v| v.saturating_sub(buf.len()));
+				self_.finish(false)?;
+				Poll::Ready(Ok(buf.len()))
+			}
+			State::Send { .. } | State::Done => Poll::Ready(Err(io::Error::new(
+				io::ErrorKind::Other, "body already complete"))),
+			_ => Pin::new(&mut *self_.inner).poll_write(cx, buf)
+		}
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let self_ = Pin::into_inner(self);
+		match &self_.state {
+			// nothing can be sent before the body is complete
+			State::Compress { .. } => Poll::Ready(Ok(())),
+			_ => match self_.poll_send(cx) {
+				Poll::Ready(Ok(())) => Pin::new(&mut *self_.inner).poll_flush(cx),
+				v => v
+			}
+		}
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let self_ = Pin::into_inner(self);
+		self_.finish(true)?;
+		
+		match self_.poll_send(cx) {
+			Poll::Ready(Ok(())) => Pin::new(&mut *self_.inner).poll_close(cx),
+			v => v
+		}
+	}
+}
+
+impl<'a> http::traits::AsyncStream for CompressHttpStream<'a> {
+	fn poll_read_headers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		let self_ = Pin::into_inner(self);
+		match self_.request_headers.take() {
+			Some(headers) => Poll::Ready(Ok(headers)),
+			None          => Pin::new(&mut *self_.inner).poll_read_headers(cx)
+		}
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		let self_ = Pin::into_inner(self);
+		
+		if let State::Headers = self_.state {
+			self_.state = self_.prepare(headers)?;
+		}
+		
+		match &self_.state {
+			State::WriteHeaders(headers) => match Pin::new(&mut *self_.inner).poll_write_headers(cx, headers) {
+				Poll::Ready(Ok(())) => {
+					self_.state = State::Passthrough;
+					Poll::Ready(Ok(()))
+				}
+				v => v
+			},
+			State::Passthrough => Pin::new(&mut *self_.inner).poll_write_headers(cx, headers),
+			State::Compress { .. } => Poll::Ready(Ok(())),
+			_ => Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "response already complete")))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn module(response: Vec<http::Header>, body: Vec<u8>) -> Module {
+		Module {
+			cfg:  Config {
+				next:                "next".to_string(),
+				encodings:           default_encodings(),
+				types:               default_types(),
+				min_len:             16,
+				max_response_len:    0x1000,
+				level:               None,
+				decompress_requests: true,
+				max_request_len:     0x100
+			},
+			next: test_component(Box::new(TestHttpHandler { response, body, ..TestHttpHandler::default() }) as HttpStreamHandler)
+		}
+	}
+	
+	fn accept_encoding(v: &[&str]) -> Vec<http::Header> {
+		vec![http::Header::AcceptEncoding(v.iter().map(|v| http::Encoding::Other((*v).into())).collect())]
+	}
+	
+	fn response(content_type: &str, len: Option<usize>) -> Vec<http::Header> {
+		let mut headers = vec![
+			http::Header::Status(http::Status::Ok),
+			http::Header::ContentType(Box::new(content_type.parse().unwrap()))
+		];
+		headers.extend(len.map(http::Header::ContentLength));
+		headers
+	}
+	
+	fn encode(encoding: ConfigEncoding, body: &[u8]) -> Vec<u8> {
+		let mut encoder = Encoder::new(encoding, None).unwrap();
+		encoder.write_all(body).unwrap();
+		encoder.finish().unwrap()
+	}
+	
+	#[test]
+	fn negotiate() {
+		let module = module(Vec::new(), Vec::new());
+		assert_eq!(module.negotiate(&[]), None);
+		assert_eq!(module.negotiate(&accept_encoding(&["gzip", "br"])), Some(ConfigEncoding::Br));
+		assert_eq!(module.negotiate(&accept_encoding(&["gzip;q=1.0", "br;q=0.5"])), Some(ConfigEncoding::Gzip));
+		assert_eq!(module.negotiate(&accept_encoding(&["br;q=0", "zstd;q=0"])), None);
+		assert_eq!(module.negotiate(&accept_encoding(&["*"])), Some(ConfigEncoding::Br));
+		assert_eq!(module.negotiate(&accept_encoding(&["*;q=0.5", "deflate"])), Some(ConfigEncoding::Deflate));
+		assert_eq!(module.negotiate(&accept_encoding(&["*", "br;q=0", "zstd;q=0"])), Some(ConfigEncoding::Gzip));
+		assert_eq!(module.negotiate(&accept_encoding(&["*;q=0"])), None);
+		assert_eq!(module.negotiate(&accept_encoding(&["identity", "compress"])), None);
+	}
+	
+	#[test]
+	fn is_compressible() {
+		let module = module(Vec::new(), Vec::new());
+		assert!(module.is_compressible(&response("text/html; charset=utf-8", Some(0x100))));
+		assert!(module.is_compressible(&response("application/json", Some(0x1000))));
+		assert!(!module.is_compressible(&response("image/png", Some(0x100))));
+		assert!(!module.is_compressible(&response("text/html", Some(15))));
+		assert!(!module.is_compressible(&response("text/html", Some(0x1001))));
+		assert!(!module.is_compressible(&response("text/html", None)));
+		
+		let mut headers = response("text/html", Some(0x100));
+		headers.push(http::Header::ContentEncoding(http::Encoding::GZip));
+		assert!(!module.is_compressible(&headers));
+		
+		let mut headers = response("text/html", Some(0x100));
+		headers[0] = http::Header::Status(http::Status::PartialContent);
+		assert!(!module.is_compressible(&headers));
+	}
+	
+	#[test]
+	fn decode() {
+		let body = b"hello hello hello hello hello".repeat(4);
+		
+		for encoding in default_encodings() {
+			let encoded = encode(encoding, &body);
+			assert_eq!(super::decode(&encoding.to_encoding(), &encoded, body.len()), Ok(body.clone()));
+			assert_eq!(super::decode(&encoding.to_encoding(), &encoded, body.len() - 1), Err(http::Status::PayloadTooLarge));
+		}
+		
+		assert_eq!(super::decode(&http::Encoding::GZip, b"not gzip", 0x100), Err(http::Status::BadRequest));
+		assert_eq!(super::decode(&http::Encoding::Compress, b"", 0x100), Err(http::Status::UnsupportedMediaType));
+		assert_eq!(super::decode(&http::Encoding::Identity, b"abc", 0x100), Ok(b"abc".to_vec()));
+	}
+	
+	#[test]
+	fn compress_response() {
+		let body = b"compressible ".repeat(0x40);
+		let module = module(response("text/plain", Some(body.len())), body.clone());
+		let mut request = accept_encoding(&["gzip"]);
+		request.push(http::Header::Method(http::Method::Get));
+		
+		let (result, stream) = TestHttpStream::new(request.clone(), Vec::new()).accept(&module);
+		result.unwrap();
+		assert!(stream.response().contains(&http::Header::ContentEncoding(http::Encoding::GZip)));
+		assert!(stream.response().contains(&http::Header::ContentLength(stream.response_body.len())));
+		assert_eq!(super::decode(&http::Encoding::GZip, &stream.response_body, body.len()), Ok(body.clone()));
+		
+		// too long to be buffered
+		let body = b"compressible ".repeat(0x400);
+		let module = self::module(response("text/plain", Some(body.len())), body.clone());
+		let (result, stream) = TestHttpStream::new(request, Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.response().iter().find_map(http::Header::as_content_encoding), None);
+		assert_eq!(stream.response_body, body);
+	}
+	
+	#[test]
+	fn decompress_request() {
+		let body = b"0123456789abcdef".repeat(0x10);
+		let handler = TestHttpHandler { response: vec![http::Header::Status(http::Status::NoContent)], ..TestHttpHandler::default() };
+		let requests = handler.requests.clone();
+		let module = Module {
+			next: test_component(Box::new(handler) as HttpStreamHandler),
+			..module(Vec::new(), Vec::new())
+		};
+		let request = |len: usize| vec![
+			http::Header::Method(http::Method::Post),
+			http::Header::ContentEncoding(http::Encoding::GZip),
+			http::Header::ContentLength(len)
+		];
+		
+		let encoded = encode(ConfigEncoding::Gzip, &body);
+		let (result, stream) = TestHttpStream::new(request(encoded.len()), encoded).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.status(), Some(http::Status::NoContent));
+		
+		let (headers, forwarded) = requests.lock().unwrap().pop().unwrap();
+		assert_eq!(forwarded, body);
+		assert!(headers.contains(&http::Header::ContentLength(body.len())));
+		assert_eq!(headers.iter().find_map(http::Header::as_content_encoding), None);
+		
+		// the decompressed body is too long
+		let encoded = encode(ConfigEncoding::Gzip, &[body.clone(), b"x".to_vec()].concat());
+		let (_, stream) = TestHttpStream::new(request(encoded.len()), encoded).accept(&module);
+		assert_eq!(stream.status(), Some(http::Status::PayloadTooLarge));
+		
+		// the compressed body is too long, it is not read at all
+		let (_, stream) = TestHttpStream::new(request(0x101), vec![0; 0x101]).accept(&module);
+		assert_eq!(stream.status(), Some(http::Status::PayloadTooLarge));
+		assert_eq!(stream.request_body.position(), 0);
+		assert!(requests.lock().unwrap().is_empty());
+	}
+}
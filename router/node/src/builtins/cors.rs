@@ -0,0 +1,494 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+
+//! Answers CORS preflight requests and adds CORS headers to the responses of another module.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*, HEADER_SERVER},
+	std::{io, pin::Pin, task::{Context, Poll}},
+	net::http::{self, traits::AsyncStreamExt}
+};
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// The module requests are forwarded to.
+	pub next:           String,
+	/// Allowed origins, e.g. `https://example.com`, `https://*.example.com`, a regular expression
+	/// enclosed in `###` or `*` for any origin.
+	pub origins:        Vec<OriginMatcher>,
+	/// Allowed methods of actual requests.
+	#[serde(default = "default_methods")]
+	pub methods:        Vec<String>,
+	/// Allowed request headers, `*` allows any header.
+	#[serde(default)]
+	pub headers:        Vec<String>,
+	/// Response headers the client may access.
+	#[serde(default)]
+	pub expose_headers: Vec<String>,
+	/// Allows requests with credentials, i.e. cookies or `Authorization`.
+	#[serde(default)]
+	pub credentials:    bool,
+	/// Seconds a preflight response may be cached by the client.
+	pub max_age:        Option<usize>
+}
+
+fn default_methods() -> Vec<String> {
+	["GET", "HEAD", "POST"].iter().map(ToString::to_string).collect()
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	if cfg.origins.is_empty() {
+		return Err("no origins configured".into());
+	}
+	
+	let module: HttpStreamHandler = Box::new(Module {
+		next:    crate::get_component::<HttpStreamHandler>(crate::component_id(&cfg.next)),
+		methods: cfg.methods.iter()
+			.map(|v| v.parse::<http::Method>().map_err(|_| Error::new(format!("invalid method `{}`", v))))
+			.collect::<Result<_>>()?,
+		cfg
+	});
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), module);
+	Ok(())
+}
+
+/// An allowed origin, a [`StringMatcher`] or a scheme and a wildcard for the subdomains of a
+/// host, e.g. `https://*.example.com`.
+#[derive(Clone, Debug)]
+pub enum OriginMatcher {
+	Matcher(StringMatcher),
+	Subdomains {
+		/// The scheme including `://`.
+		scheme: String,
+		/// The host and port including the leading `.`.
+		host:   String
+	}
+}
+
+impl OriginMatcher {
+	/// Origins are compared in lower case, as browsers serialize them.
+	pub fn matches(&self, origin: &str) -> bool {
+		let origin = origin.to_ascii_lowercase();
+		match self {
+			Self::Matcher(m) => m.matches(Some(&origin)),
+			Self::Subdomains { scheme, host } => origin.len() > scheme.len() + host.len()
+				&& origin.starts_with(scheme.as_str())
+				&& origin.ends_with(host.as_str())
+				&& is_subdomain(&origin[scheme.len()..origin.len() - host.len()])
+		}
+	}
+	
+	/// Whether any origin is allowed.
+	fn is_any(&self) -> bool {
+		matches!(self, Self::Matcher(StringMatcher::Present(true) | StringMatcher::Ignore))
+	}
+}
+
+/// Whether `s` consists of one or more DNS labels.
+fn is_subdomain(s: &str) -> bool {
+	s.split('.').all(|v| !v.is_empty() && v.bytes().all(|v| v.is_ascii_alphanumeric() || v == b'-'))
+}
+
+impl TryFrom<StringMatcher> for OriginMatcher {
+	type Error = String;
+	
+	fn try_from(matcher: StringMatcher) -> std::result::Result<Self, Self::Error> {
+		// `StringMatcher` only knows wildcards at the start and the end
+		let s = match &matcher {
+			StringMatcher::Exact(s) if s.contains('*') => s,
+			_ => return Ok(Self::Matcher(matcher))
+		};
+		
+		match s.split_once("://*.") {
+			Some((scheme, host)) if !scheme.is_empty() && !scheme.contains('*') && !host.is_empty() && !host.contains('*') => Ok(Self::Subdomains {
+				scheme: format!("{}://", scheme.to_ascii_lowercase()),
+				host:   format!(".{}", host.to_ascii_lowercase())
+			}),
+			_ => Err(format!("`{}` is not a valid origin, a wildcard in the middle is only allowed as `scheme://*.host`", s))
+		}
+	}
+}
+
+impl JsonSchema for OriginMatcher {
+	fn schema_name() -> String {
+		"OriginMatcher".to_string()
+	}
+	
+	fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		serde_json::from_value(serde_json::json!({
+			"type":        ["string", "boolean"],
+			"description": "A string matcher like `https://example.com`, `*` or `###^https://[a-z]+\\.example\\.com$###`, or `https://*.example.com` for any subdomain"
+		})).unwrap()
+	}
+}
+
+impl<'de> ::serde::Deserialize<'de> for OriginMatcher {
+	fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+		StringMatcher::deserialize(deserializer)?.try_into().map_err(::serde::de::Error::custom)
+	}
+}
+
+struct Module {
+	cfg:     Config,
+	methods: Vec<http::Method>,
+	next:    ComponentRef<HttpStreamHandler>
+}
+
+impl Module {
+	/// The value of `Access-Control-Allow-Origin` for this origin, if it is allowed.
+	fn allow_origin(&self, origin: &str) -> Option<String> {
+		if !self.cfg.origins.iter().any(|v| v.matches(origin)) {
+			return None;
+		}
+		
+		// `*` is not allowed for requests with credentials
+		let any = self.cfg.origins.iter().any(OriginMatcher::is_any);
+		Some(match any && !self.cfg.credentials {
+			true  => "*".to_string(),
+			false => origin.to_string()
+		})
+	}
+	
+	fn allows_header(&self, header: &str) -> bool {
+		self.cfg.headers.iter().any(|v| v == "*" || v.eq_ignore_ascii_case(header))
+	}
+	
+	/// Headers added to responses to actual requests.
+	fn response_headers(&self, origin: Option<&str>) -> Vec<http::Header> {
+		let allow_origin = match origin.and_then(|v| self.allow_origin(v)) {
+			Some(v) => v,
+			None    => return Vec::new()
+		};
+		
+		let mut headers = vec![http::Header::AccessControlAllowOrigin(allow_origin)];
+		
+		if self.cfg.credentials {
+			headers.push(http::Header::AccessControlAllowCredentials(true));
+		}
+		
+		if !self.cfg.expose_headers.is_empty() {
+			headers.push(http::Header::AccessControlAllowExposeHeaders(self.cfg.expose_headers.clone()));
+		}
+		
+		headers
+	}
+	
+	async fn preflight(&self, stream: &mut dyn http::traits::AsyncStream, headers: &[http::Header], origin: &str, method: &http::Method) -> Result<()> {
+		discard_body(stream).await?;
+		
+		let request_headers = headers.iter()
+			.filter_map(http::Header::as_access_control_allow_request_headers)
+			.flatten()
+			.cloned()
+			.collect::<Vec<_>>();
+		
+		let allow_origin = match self.allow_origin(origin) {
+			Some(v) if self.methods.contains(method)
+				&& request_headers.iter().all(|v| self.allows_header(v)) => v,
+			_ => return http::MessageBuilder::new()
+				.status(http::Status::Forbidden)
+				.server(HEADER_SERVER.to_string())
+				.vary(vec!["Origin".to_string()])
+				.content_length(0)
+				.send_async(stream)
+				.await.map_err(Into::into)
+		};
+		
+		let mut builder = http::MessageBuilder::new()
+			.status(http::Status::NoContent)
+			.server(HEADER_SERVER.to_string())
+			.access_control_allow_origin(allow_origin)
+			.access_control_allow_methods(self.methods.clone());
+		
+		if !request_headers.is_empty() {
+			builder = builder.access_control_allow_headers(request_headers);
+		}
+		
+		if self.cfg.credentials {
+			builder = builder.access_control_allow_credentials(true);
+		}
+		
+		if let Some(max_age) = self.cfg.max_age {
+			builder = builder.access_control_allow_max_age(max_age);
+		}
+		
+		builder
+			.vary(vec![
+				"Origin".to_string(),
+				"Access-Control-Request-Method".to_string(),
+				"Access-Control-Request-Headers".to_string()
+			])
+			.content_length(0)
+			.send_async(stream)
+			.await.map_err(Into::into)
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let headers = stream.read_headers().await?;
+			let origin = headers.iter().find_map(|v| match v {
+				http::Header::Custom(k, v) if k.eq_ignore_ascii_case("origin") => Some(v.clone()),
+				_ => None
+			});
+			
+			if let (Some(origin), Some(http::Method::Options), Some(method)) = (
+				origin.as_deref(),
+				headers.iter().find_map(http::Header::as_method),
+				headers.iter().find_map(http::Header::as_access_control_allow_request_method)
+			) {
+				return self.preflight(stream, &headers, origin, method).await;
+			}
+			
+			let mut inner = HeadersHttpStream::new(stream, headers);
+			let mut stream = CorsHttpStream {
+				inner:   &mut inner,
+				headers: Some(self.response_headers(origin.as_deref()))
+			};
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream = unsafe { std::mem::transmute::<&mut CorsHttpStream<'_>, &'static mut CorsHttpStream<'static>>(&mut stream) };
+			self.next.get().await?.accept(stream).await
+		})
+	}
+}
+
+/// Adds CORS headers and `Vary: Origin` to the response headers.
+struct CorsHttpStream<'a> {
+	inner:   &'a mut dyn http::traits::AsyncStream,
+	headers: Option<Vec<http::Header>>
+}
+
+impl<'a> smol::io::AsyncRead for CorsHttpStream<'a> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read(cx, buf)
+	}
+}
+
+impl<'a> smol::io::AsyncWrite for CorsHttpStream<'a> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write(cx, buf)
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_flush(cx)
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_close(cx)
+	}
+}
+
+impl<'a> http::traits::AsyncStream for CorsHttpStream<'a> {
+	fn poll_read_headers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read_headers(cx)
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		let Self { inner, headers: cors_headers } = unsafe { Pin::into_inner_unchecked(self) };
+		let inner = unsafe { Pin::new_unchecked(&mut **inner) };
+		
+		// informational responses are sent as they are
+		let cors_headers_ = match cors_headers {
+			Some(v) if !headers.iter().any(|v| matches!(v, http::Header::Status(v) if (*v as u16) < 200)) => v,
+			_ => return inner.poll_write_headers(cx, headers)
+		};
+		
+		let mut headers = headers.iter()
+			.filter(|v| !is_cors_header(v))
+			.cloned()
+			.chain(cors_headers_.iter().cloned())
+			.collect::<Vec<_>>();
+		
+		match headers.iter_mut().find_map(http::Header::as_mut_vary) {
+			Some(v) if !v.iter().any(|v| v.eq_ignore_ascii_case("origin")) => v.push("Origin".to_string()),
+			Some(_) => (),
+			None    => headers.push(http::Header::Vary(vec!["Origin".to_string()]))
+		}
+		
+		let result = inner.poll_write_headers(cx, &headers);
+		
+		if let Poll::Ready(Ok(())) = result {
+			*cors_headers = None;
+		}
+		
+		result
+	}
+}
+
+/// Headers set by this module, those set by the next module are replaced.
+fn is_cors_header(header: &http::Header) -> bool {
+	matches!(header,
+		http::Header::AccessControlAllowOrigin(_)
+		| http::Header::AccessControlAllowCredentials(_)
+		| http::Header::AccessControlAllowExposeHeaders(_))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn module(origins: &[&str], credentials: bool) -> Module {
+		let response = vec![
+			http::Header::Status(http::Status::Ok),
+			http::Header::Vary(vec!["Accept-Encoding".to_string()]),
+			http::Header::AccessControlAllowOrigin("https://evil.example".to_string())
+		];
+		
+		Module {
+			cfg:     Config {
+				next:           "next".to_string(),
+				origins:        origins.iter().map(|v| origin(v).unwrap()).collect(),
+				methods:        default_methods(),
+				headers:        vec!["Content-Type".to_string()],
+				expose_headers: Vec::new(),
+				credentials,
+				max_age:        Some(600)
+			},
+			methods: vec![http::Method::Get, http::Method::Head, http::Method::Post],
+			next:    test_component(Box::new(TestHttpHandler { response, ..TestHttpHandler::default() }) as HttpStreamHandler)
+		}
+	}
+	
+	fn request(method: http::Method, origin: Option<&str>) -> Vec<http::Header> {
+		let mut headers = vec![http::Header::Method(method), http::Header::Path("/".to_string())];
+		headers.extend(origin.map(|v| http::Header::Custom("Origin".to_string(), v.to_string())));
+		headers
+	}
+	
+	fn origin(s: &str) -> std::result::Result<OriginMatcher, serde_json::Error> {
+		serde_json::from_value(serde_json::Value::String(s.to_string()))
+	}
+	
+	#[test]
+	fn origins() {
+		let exact = origin("https://example.com").unwrap();
+		assert!(exact.matches("https://example.com"));
+		assert!(exact.matches("HTTPS://EXAMPLE.COM"));
+		assert!(!exact.matches("https://example.com.evil"));
+		assert!(!exact.matches("http://example.com"));
+		
+		let subdomains = origin("https://*.example.com").unwrap();
+		assert!(subdomains.matches("https://a.example.com"));
+		assert!(subdomains.matches("https://a.b-c.example.com"));
+		assert!(!subdomains.matches("https://example.com"));
+		assert!(!subdomains.matches("https://.example.com"));
+		assert!(!subdomains.matches("https://a..example.com"));
+		assert!(!subdomains.matches("http://a.example.com"));
+		assert!(!subdomains.matches("https://a.example.com.evil"));
+		assert!(!subdomains.matches("https://evil/a.example.com"));
+		assert!(!subdomains.matches("https://a.example.com:8443"));
+		assert!(origin("https://*.example.com:8443").unwrap().matches("https://a.example.com:8443"));
+		
+		assert!(origin("*").unwrap().is_any());
+		assert!(matches!(origin("*.example.com"), Ok(OriginMatcher::Matcher(StringMatcher::Suffix(_)))));
+		assert!(origin("https://a*.example.com").is_err());
+		assert!(origin("https://*.*.example.com").is_err());
+		
+		let module = module(&["https://*.example.com"], false);
+		assert_eq!(module.allow_origin("https://a.example.com").as_deref(), Some("https://a.example.com"));
+		assert_eq!(module.allow_origin("https://example.org"), None);
+		
+		assert_eq!(self::module(&["*"], false).allow_origin("https://example.org").as_deref(), Some("*"));
+		assert_eq!(self::module(&["*"], true).allow_origin("https://example.org").as_deref(), Some("https://example.org"));
+	}
+	
+	#[test]
+	#[cfg(feature = "regex")]
+	fn pattern() {
+		let pattern = origin("###^https://[a-z]+\\.example\\.com$###").unwrap();
+		assert!(pattern.matches("https://a.example.com"));
+		assert!(!pattern.matches("https://a.example.com.evil"));
+		
+		let module = module(&["###^https://[a-z]+\\.example\\.com$###"], false);
+		
+		let (result, stream) = TestHttpStream::new(request(http::Method::Get, Some("https://a.example.com")), Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin).map(String::as_str), Some("https://a.example.com"));
+		
+		let (result, stream) = TestHttpStream::new(request(http::Method::Get, Some("https://1.example.com")), Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.status(), Some(http::Status::Ok));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin), None);
+	}
+	
+	#[test]
+	fn preflight() {
+		let module = module(&["https://example.com"], true);
+		let mut headers = request(http::Method::Options, Some("https://example.com"));
+		headers.push(http::Header::AccessControlAllowRequestMethod(http::Method::Post));
+		headers.push(http::Header::AccessControlAllowRequestHeaders(vec!["content-type".to_string()]));
+		
+		let (result, stream) = TestHttpStream::new(headers.clone(), Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.status(), Some(http::Status::NoContent));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin).map(String::as_str), Some("https://example.com"));
+		assert!(stream.response().contains(&http::Header::AccessControlAllowMethods(module.methods.clone())));
+		assert!(stream.response().contains(&http::Header::AccessControlAllowCredentials(true)));
+		assert!(stream.response().contains(&http::Header::AccessControlAllowMaxAge(600)));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_vary).map(Vec::len), Some(3));
+		
+		// a method that is not allowed
+		headers[3] = http::Header::AccessControlAllowRequestMethod(http::Method::Delete);
+		let (result, stream) = TestHttpStream::new(headers.clone(), Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.status(), Some(http::Status::Forbidden));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin), None);
+		
+		// a header that is not allowed
+		headers[3] = http::Header::AccessControlAllowRequestMethod(http::Method::Post);
+		headers[4] = http::Header::AccessControlAllowRequestHeaders(vec!["x-secret".to_string()]);
+		let (_, stream) = TestHttpStream::new(headers.clone(), Vec::new()).accept(&module);
+		assert_eq!(stream.status(), Some(http::Status::Forbidden));
+		
+		// an origin that is not allowed
+		headers[2] = http::Header::Custom("Origin".to_string(), "https://example.org".to_string());
+		let (_, stream) = TestHttpStream::new(headers, Vec::new()).accept(&module);
+		assert_eq!(stream.status(), Some(http::Status::Forbidden));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_vary), Some(&vec!["Origin".to_string()]));
+	}
+	
+	#[test]
+	fn vary() {
+		let module = module(&["https://example.com"], false);
+		
+		let (result, stream) = TestHttpStream::new(request(http::Method::Get, Some("https://example.com")), Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.status(), Some(http::Status::Ok));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin).map(String::as_str), Some("https://example.com"));
+		assert_eq!(stream.response().iter().find_map(http::Header::as_vary), Some(&vec!["Accept-Encoding".to_string(), "Origin".to_string()]));
+		
+		// the header set by the next module is removed for other origins
+		let (result, stream) = TestHttpStream::new(request(http::Method::Get, Some("https://example.org")), Vec::new()).accept(&module);
+		result.unwrap();
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin), None);
+		assert_eq!(stream.response().iter().find_map(http::Header::as_vary), Some(&vec!["Accept-Encoding".to_string(), "Origin".to_string()]));
+		
+		let (_, stream) = TestHttpStream::new(request(http::Method::Get, None), Vec::new()).accept(&module);
+		assert_eq!(stream.response().iter().find_map(http::Header::as_access_control_allow_origin), None);
+	}
+}
@@ -0,0 +1,680 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Authoritative answers from the zone files of a storage module.
+
+use {
+	super::*,
+	crate::interfaces::*,
+	std::{
+		io,
+		cmp::Reverse,
+		collections::{HashMap, HashSet},
+		net::{IpAddr, SocketAddr},
+		path::{Path, PathBuf},
+		sync::{Arc, RwLock},
+		time::Duration
+	},
+	net::dns::{self, Class, Message, RCode, ResourceRecord, ResourceRecordData, Type},
+	smol::net::{TcpStream, UdpSocket}
+};
+
+const MAX_CNAME_CHAIN:          usize = 8;
+const MAX_MESSAGE_LEN:          usize = 0xFFFF;
+const DEFAULT_UPSTREAM_PORT:    u16 = 53;
+const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// Loopback, private and link-local networks, so the upstream is no open resolver.
+const DEFAULT_UPSTREAM_CLIENTS: [&str; 7] = [
+	"127.0.0.0/8", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "::1", "fc00::/7", "fe80::/10"
+];
+/// The upper bits of the `BADVERS` RCODE, sent in the OPT record.
+const EXT_RCODE_BADVERS:        u8 = 1;
+/// Stripped from file names to get the origin of zones without `$ORIGIN`.
+const ZONE_FILE_EXTENSION:      &str = ".zone";
+
+/// The zones of a storage module.
+pub(super) struct Zones {
+	name:             String,
+	dir:              PathBuf,
+	filter:           storage::ConfigFilter,
+	recursive:        bool,
+	cfg:              storage::ConfigDns,
+	upstream_clients: Vec<Cidr>,
+	/// Sorted by descending origin length, so the first matching zone is the closest one.
+	zones:            RwLock<Arc<Vec<Arc<Zone>>>>
+}
+
+impl Zones {
+	pub(super) async fn new(name: &str, dir: &str, filter: storage::ConfigFilter, recursive: bool, cfg: storage::ConfigDns) -> Self {
+		let upstream_clients = cfg.upstream_clients.clone().unwrap_or_else(|| DEFAULT_UPSTREAM_CLIENTS.iter()
+			.map(|v| v.parse().unwrap())
+			.collect());
+		let self_ = Self {
+			name:  name.to_string(),
+			dir:   PathBuf::from(dir),
+			filter,
+			recursive,
+			cfg,
+			upstream_clients,
+			zones: RwLock::new(Arc::new(Vec::new()))
+		};
+		
+		self_.reload().await;
+		self_
+	}
+	
+	/// Loads all zone files, zones whose file can no longer be parsed keep their previous version.
+	pub(super) async fn reload(&self) {
+		let previous = self.zones.read().unwrap().clone();
+		let mut zones = Vec::<Arc<Zone>>::new();
+		let mut files = Vec::new();
+		zone_files(&self.dir, &self.dir, &self.filter, self.recursive, &mut files);
+		
+		for path in files {
+			let zone = match smol::fs::read_to_string(&path).await.map_err(Into::into)
+				.and_then(|v| Zone::parse(&path, &v)) {
+				Ok(zone) => {
+					log::info!("backend `{}` zone `{}`: loaded {} records from `{}`",
+						&self.name, &zone.origin, zone.len(), path.display());
+					Arc::new(zone)
+				}
+				Err(e) => match previous.iter().find(|v| v.file == path) {
+					Some(zone) => {
+						log::error!("backend `{}` zone `{}`: failed to load `{}`, keeping the previous version: {}",
+							&self.name, &zone.origin, path.display(), e.display());
+						zone.clone()
+					}
+					None => {
+						log::error!("backend `{}`: failed to load zone `{}`: {}", &self.name, path.display(), e.display());
+						continue;
+					}
+				}
+			};
+			
+			match zones.iter().find(|v| v.origin == zone.origin) {
+				Some(other) => log::error!("backend `{}` zone `{}`: defined in `{}` and `{}`, ignoring the latter",
+					&self.name, &zone.origin, other.file.display(), path.display()),
+				None => zones.push(zone)
+			}
+		}
+		
+		zones.sort_by_key(|v| Reverse(v.origin.len()));
+		*self.zones.write().unwrap() = Arc::new(zones);
+	}
+	
+	/// Reloads the zones when a file in the directory changes.
+	#[cfg(feature = "hot-reload")]
+	pub(super) fn watch(self: Arc<Self>) -> io::Result<()> {
+		use notify::{Watcher, DebouncedEvent};
+		
+		std::thread::Builder::new()
+			.name(format!("zone-watcher-{}", &self.name))
+			.spawn(move || {
+				let (tx, rx) = std::sync::mpsc::channel();
+				let mut watcher = match notify::watcher(tx, Duration::from_secs(1)) {
+					Ok(v) => v,
+					Err(e) => {
+						log::error!("backend `{}` zone watcher: {}", &self.name, e);
+						return;
+					}
+				};
+				
+				let mode = match self.recursive {
+					true  => notify::RecursiveMode::Recursive,
+					false => notify::RecursiveMode::NonRecursive
+				};
+				
+				if let Err(e) = watcher.watch(&self.dir, mode) {
+					log::error!("backend `{}` zone watcher: {}", &self.name, e);
+					return;
+				}
+				
+				log::info!("backend `{}` zone watcher: started", &self.name);
+				
+				while let Ok(event) = rx.recv() {
+					match event {
+						DebouncedEvent::Error(e, _) => log::error!("backend `{}` zone watcher: {}", &self.name, e),
+						DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) | DebouncedEvent::Chmod(_) => (),
+						_ => {
+							// a single reload covers all changes reported so far
+							while rx.try_recv().is_ok() {}
+							smol::block_on(self.reload());
+						}
+					}
+				}
+			})
+			.map(|_| ())
+	}
+	
+	/// Forwards a query to the upstream resolver, over TCP if the response was truncated.
+	async fn forward(&self, upstream: &str, query: &Message) -> Result<Message> {
+		let addr = match upstream.parse::<IpAddr>() {
+			Ok(ip) => SocketAddr::new(ip, DEFAULT_UPSTREAM_PORT),
+			Err(_) => smol::net::resolve(upstream).await?
+				.into_iter()
+				.next()
+				.ok_or_else(|| Error::new(format!("`{}` did not resolve to any address", upstream)))?
+		};
+		
+		let timeout = Some(self.cfg.timeout.unwrap_or(DEFAULT_UPSTREAM_TIMEOUT));
+		let socket = UdpSocket::bind(match addr {
+			SocketAddr::V4(_) => "0.0.0.0:0",
+			SocketAddr::V6(_) => "[::]:0"
+		}).await?;
+		socket.connect(addr).await?;
+		socket.send(&query.to_bytes()?).await?;
+		
+		let response = crate::utils::timeout(timeout, async {
+			let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+			
+			// anything but the response to this query is ignored
+			loop {
+				let len = socket.recv(&mut buf).await?;
+				
+				match Message::parse(&buf[..len]) {
+					Ok(v) if v.id == query.id && v.flags.qr() && v.questions == query.questions => break Ok::<_, io::Error>(v),
+					_ => continue
+				}
+			}
+		}).await?;
+		
+		if !response.flags.tc() {
+			return Ok(response);
+		}
+		
+		crate::utils::timeout(timeout, async {
+			let mut stream = TcpStream::connect(addr).await?;
+			query.write_async_with_len(&mut stream).await?;
+			Message::read_async_with_len(&mut stream).await
+		}).await.map_err(Into::into)
+	}
+}
+
+impl StreamHandler<DnsExchange> for Zones {
+	fn accept<'a>(&'a self, exchange: &'static mut DnsExchange) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let query = &exchange.query;
+			let question = match (query.flags.op_code(), &query.questions[..]) {
+				(dns::OPCODE_QUERY, [question]) => question,
+				(dns::OPCODE_QUERY, _) => {
+					exchange.response = Some(response(query, RCode::FormatError));
+					return Ok(());
+				}
+				_ => {
+					exchange.response = Some(response(query, RCode::NotImplemented));
+					return Ok(());
+				}
+			};
+			
+			let zones = self.zones.read().unwrap().clone();
+			let name  = question.name.to_ascii_lowercase();
+			
+			if find_zone(&zones, &name).is_some() {
+				exchange.response = Some(match (question.class, question.r#type) {
+					(Class::IN | Class::Any, Type::AXFR | Type::MailA | Type::MailB) => response(query, RCode::NotImplemented),
+					(Class::IN | Class::Any, ty) => resolve(&zones, query, name, ty),
+					_ => response(query, RCode::QueryRefused)
+				});
+				return Ok(());
+			}
+			
+			// clients connected over Unix domain sockets are local
+			let forward = exchange.connection.addr
+				.map_or(true, |addr| self.upstream_clients.iter().any(|v| v.contains(addr.ip())));
+			
+			exchange.response = Some(match &self.cfg.upstream {
+				Some(upstream) if forward => match self.forward(upstream, query).await {
+					Ok(v) => v,
+					Err(e) => {
+						log::warn!("backend `{}`: failed to forward query to `{}`: {}", &self.name, upstream, e.display());
+						response(query, RCode::ServerFailure)
+					}
+				},
+				_ => response(query, RCode::QueryRefused)
+			});
+			Ok(())
+		})
+	}
+}
+
+/// A response to `query` without any records.
+pub(super) fn response(query: &Message, rcode: RCode) -> Message {
+	let mut flags = dns::Flags::default();
+	flags.set_qr(true);
+	flags.set_op_code(query.flags.op_code());
+	flags.set_rd(query.flags.rd());
+	flags.set_cd(query.flags.cd());
+	flags.set_rcode(Ok(rcode));
+	
+	Message {
+		id:        query.id,
+		flags,
+		questions: query.questions.clone(),
+		..Message::default()
+	}
+}
+
+/// Answers a query with a DNS component. The response has an OPT record announcing
+/// `udp_payload_size` if the query has one, queries with an unsupported EDNS version are answered
+/// with `BADVERS`.
+pub(super) async fn answer(
+	processor:        &ComponentRef<DnsHandler>,
+	query:            &Message,
+	connection:       ConnectionInfo,
+	udp_payload_size: u16
+) -> Result<Message> {
+	if query.edns().map_or(false, |v| v.version > 0) {
+		let mut response = response(query, RCode::NoError);
+		set_edns(query, &mut response, EXT_RCODE_BADVERS, udp_payload_size);
+		return Ok(response);
+	}
+	
+	let mut exchange = DnsExchange { query: query.clone(), response: None, connection };
+	// this is unsafe, but that's ok, see HttpStreamHandler::accept
+	let exchange_static = unsafe { std::mem::transmute::<
+		&'_      mut DnsExchange,
+		&'static mut DnsExchange
+	>(&mut exchange) };
+	
+	processor.get().await?.accept(exchange_static).await?;
+	let mut response = exchange.response.ok_or("no response")?;
+	response.id = query.id;
+	response.flags.set_qr(true);
+	set_edns(query, &mut response, 0, udp_payload_size);
+	Ok(response)
+}
+
+/// A `SERVFAIL` response to `query`, for failures of the DNS component.
+pub(super) fn failure(query: &Message, udp_payload_size: u16) -> Message {
+	let mut response = response(query, RCode::ServerFailure);
+	set_edns(query, &mut response, 0, udp_payload_size);
+	response
+}
+
+/// Replaces the OPT record of the response with ours, if the query has one.
+fn set_edns(query: &Message, response: &mut Message, extended_rcode: u8, udp_payload_size: u16) {
+	response.additional_records.retain(|rr| !matches!(rr.data, ResourceRecordData::OPT(_)));
+	
+	if query.edns().is_some() {
+		response.additional_records.push(ResourceRecord {
+			name:  ".".to_string(),
+			ttl:   0,
+			class: Class::IN,
+			data:  ResourceRecordData::OPT(dns::OptRecord {
+				udp_payload_size,
+				extended_rcode,
+				..dns::OptRecord::default()
+			})
+		});
+	}
+}
+
+/// Answers a query for a name in one of the zones, following CNAMEs through all of them.
+fn resolve(zones: &[Arc<Zone>], query: &Message, mut name: String, ty: Type) -> Message {
+	let mut response = response(query, RCode::NoError);
+	response.flags.set_aa(true);
+	
+	for _ in 0..MAX_CNAME_CHAIN {
+		let zone = match find_zone(zones, &name) {
+			Some(v) => v,
+			// the client resolves the target of a CNAME in another zone itself
+			None => break
+		};
+		
+		if let Some(cut) = zone.delegation(&name) {
+			// only the answers so far are authoritative
+			if response.answers.is_empty() {
+				response.flags.set_aa(false);
+			}
+			
+			response.authority_records.extend(zone.rrset(cut, Type::NS).cloned());
+			break;
+		}
+		
+		let records = match zone.records.get(&name) {
+			Some(v) => Some(v),
+			None if zone.names.contains(&name) => None,
+			None => match zone.wildcard(&name) {
+				Some(v) => Some(v),
+				None => {
+					response.flags.set_rcode(Ok(RCode::NonExistentDomain));
+					response.authority_records.push(zone.negative_soa());
+					break;
+				}
+			}
+		};
+		
+		// records of a wildcard are synthesized with the queried name as owner
+		let owned = |rr: &ResourceRecord| ResourceRecord { name: name.clone(), ..rr.clone() };
+		let records = records.map_or(&[][..], Vec::as_slice).iter()
+			.filter(|rr| rr.class == Class::IN)
+			.collect::<Vec<_>>();
+		let answers = records.iter()
+			.filter(|rr| ty == Type::All || rr.data.to_type() == Ok(ty))
+			.map(|rr| owned(rr))
+			.collect::<Vec<_>>();
+		
+		if !answers.is_empty() {
+			response.answers.extend(answers);
+			break;
+		}
+		
+		match records.iter().find_map(|rr| match &rr.data {
+			ResourceRecordData::CName(target) => Some((rr, target)),
+			_ => None
+		}) {
+			Some((rr, target)) => {
+				response.answers.push(owned(rr));
+				name = target.to_ascii_lowercase();
+			}
+			None => {
+				response.authority_records.push(zone.negative_soa());
+				break;
+			}
+		}
+	}
+	
+	additional(zones, &mut response);
+	response
+}
+
+/// Adds the addresses of name servers, mail exchanges and services in the zones.
+fn additional(zones: &[Arc<Zone>], response: &mut Message) {
+	let mut targets = response.answers.iter()
+		.chain(&response.authority_records)
+		.filter_map(|rr| match &rr.data {
+			ResourceRecordData::NS(v) => Some(v),
+			ResourceRecordData::MX(v) => Some(&v.exchange),
+			ResourceRecordData::Srv(v) => Some(&v.target),
+			_ => None
+		})
+		.map(|v| v.to_ascii_lowercase())
+		.collect::<Vec<_>>();
+	targets.sort();
+	targets.dedup();
+	
+	for target in targets {
+		if let Some(zone) = find_zone(zones, &target) {
+			response.additional_records.extend(zone.rrset(&target, Type::A)
+				.chain(zone.rrset(&target, Type::AAAA))
+				.cloned());
+		}
+	}
+}
+
+fn find_zone<'a>(zones: &'a [Arc<Zone>], name: &str) -> Option<&'a Zone> {
+	zones.iter().find(|v| in_zone(name, &v.origin)).map(|v| &**v)
+}
+
+/// Whether `name` is `origin` or a subdomain of it, both lowercase and absolute.
+fn in_zone(name: &str, origin: &str) -> bool {
+	origin == "." || name == origin || (name.len() > origin.len() && name.ends_with(origin)
+		&& name.as_bytes()[name.len() - origin.len() - 1] == b'.')
+}
+
+/// The parent of an absolute name, `None` for the root.
+fn parent(name: &str) -> Option<&str> {
+	match name.split_once('.') {
+		Some((_, "")) if name != "." => Some("."),
+		Some((_, "")) | None => None,
+		Some((_, parent)) => Some(parent)
+	}
+}
+
+/// Collects the files of a directory the filter allows.
+fn zone_files(root: &Path, dir: &Path, filter: &storage::ConfigFilter, recursive: bool, files: &mut Vec<PathBuf>) {
+	let entries = match std::fs::read_dir(dir) {
+		Ok(v) => v,
+		Err(e) => {
+			log::error!("failed to read zone directory `{}`: {}", dir.display(), e);
+			return;
+		}
+	};
+	
+	for entry in entries.filter_map(|v| v.ok()) {
+		let path = entry.path();
+		let relative = format!("/{}", path.strip_prefix(root).unwrap_or(&path).to_string_lossy());
+		
+		match entry.file_type() {
+			Ok(ty) if ty.is_dir() && recursive && !filter.denies(&format!("{}/", relative)) =>
+				zone_files(root, &path, filter, recursive, files),
+			Ok(ty) if ty.is_file() && relative.ends_with(ZONE_FILE_EXTENSION) && filter.allows(&relative) =>
+				files.push(path),
+			_ => ()
+		}
+	}
+}
+
+/// The records of a zone file by owner name.
+struct Zone {
+	/// Lowercase and absolute.
+	origin:  String,
+	file:    PathBuf,
+	soa:     ResourceRecord,
+	/// Records by lowercase owner name.
+	records: HashMap<String, Vec<ResourceRecord>>,
+	/// All names that exist in the zone, including those without records but with descendants.
+	names:   HashSet<String>
+}
+
+impl Zone {
+	fn parse(file: &Path, s: &str) -> Result<Self> {
+		let file_name = file.file_name().and_then(|v| v.to_str()).unwrap_or_default();
+		let origin = file_name.strip_suffix(ZONE_FILE_EXTENSION).unwrap_or(file_name);
+		// the origin directive takes the first line, so lines are counted from 1
+		let zone = format!("$ORIGIN {}\n{}", dns::qualify_name(origin, "."), s).parse::<dns::Zone>()
+			.map_err(|e| Error::new(format!("line {}: invalid {:?}", e.line, e.ty)))?;
+		
+		let origin = zone.origin.to_ascii_lowercase();
+		let mut soa = None;
+		let mut records = HashMap::<_, Vec<_>>::new();
+		let mut names = HashSet::new();
+		
+		for rr in zone.records {
+			let mut name = rr.name.to_ascii_lowercase();
+			
+			if !in_zone(&name, &origin) {
+				return Err(Error::new(format!("`{}` is outside of the zone `{}`", &rr.name, &origin)));
+			}
+			
+			if let (ResourceRecordData::SOA(_), true, None) = (&rr.data, name == origin, &soa) {
+				soa = Some(rr.clone());
+			}
+			
+			records.entry(name.clone()).or_default().push(rr);
+			
+			while names.insert(name.clone()) && name != origin {
+				name = parent(&name).unwrap_or(".").to_string();
+			}
+		}
+		
+		Ok(Self {
+			soa: soa.ok_or_else(|| Error::new(format!("no SOA record for `{}`", &origin)))?,
+			origin,
+			file: file.to_path_buf(),
+			records,
+			names
+		})
+	}
+	
+	fn len(&self) -> usize {
+		self.records.values().map(Vec::len).sum()
+	}
+	
+	fn rrset<'a>(&'a self, name: &str, ty: Type) -> impl Iterator<Item = &'a ResourceRecord> {
+		self.records.get(name)
+			.into_iter()
+			.flatten()
+			.filter(move |rr| rr.class == Class::IN && rr.data.to_type() == Ok(ty))
+	}
+	
+	/// The highest name with NS records between the origin and `name`, if it is delegated.
+	fn delegation<'a>(&self, name: &'a str) -> Option<&'a str> {
+		let mut cut = None;
+		let mut current = name;
+		
+		while current != self.origin {
+			if self.rrset(current, Type::NS).next().is_some() {
+				cut = Some(current);
+			}
+			
+			current = parent(current)?;
+		}
+		
+		cut
+	}
+	
+	/// The records of the wildcard below the closest existing ancestor of `name`.
+	fn wildcard(&self, name: &str) -> Option<&Vec<ResourceRecord>> {
+		let mut encloser = parent(name)?;
+		
+		while !self.names.contains(encloser) {
+			encloser = parent(encloser)?;
+		}
+		
+		match encloser {
+			"." => self.records.get("*."),
+			_   => self.records.get(&format!("*.{}", encloser))
+		}
+	}
+	
+	/// The SOA record for negative answers, whose TTL is limited to the minimum TTL.
+	fn negative_soa(&self) -> ResourceRecord {
+		match &self.soa.data {
+			ResourceRecordData::SOA(v) => ResourceRecord { ttl: self.soa.ttl.min(v.minimum), ..self.soa.clone() },
+			_ => self.soa.clone()
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const ZONE: &str = "$TTL 1h
+@       IN SOA   ns1 hostmaster 1 2h 1h 1w 5m
+        IN NS    ns1
+        IN MX    10 mail
+ns1     IN A     192.0.2.1
+mail    IN A     192.0.2.2
+www     IN CNAME web.internal
+web.internal IN A 192.0.2.3
+*.apps  IN A     192.0.2.4
+sub     IN NS    ns.sub
+ns.sub  IN A     192.0.2.5
+";
+	
+	fn query(name: &str, ty: Type) -> Message {
+		let mut query = Message { id: 4711, ..Message::default() };
+		query.flags.set_rd(true);
+		query.questions.push(dns::Question { name: name.to_string(), r#type: ty, class: Class::IN });
+		query
+	}
+	
+	fn lookup(name: &str, ty: Type) -> Message {
+		let zones = [Arc::new(Zone::parse(Path::new("example.com.zone"), ZONE).unwrap())];
+		resolve(&zones, &query(name, ty), name.to_string(), ty)
+	}
+	
+	#[test]
+	fn zone() {
+		let zone = Zone::parse(Path::new("example.com.zone"), ZONE).unwrap();
+		assert_eq!(zone.origin, "example.com.");
+		assert_eq!(zone.len(), 10);
+		assert!(zone.names.contains("internal.example.com."));
+		assert_eq!(zone.delegation("a.ns.sub.example.com."), Some("sub.example.com."));
+		assert_eq!(zone.negative_soa().ttl, 300);
+		
+		assert!(Zone::parse(Path::new("example.com.zone"), "www IN A 192.0.2.1\n").is_err());
+		assert!(Zone::parse(Path::new("example.com.zone"), "$ORIGIN example.org.\n@ IN SOA ns1 hostmaster 1 2 3 4 5\nwww.example.com. IN A 192.0.2.1\n").is_err());
+	}
+	
+	#[test]
+	fn answers() {
+		let response = lookup("mail.example.com.", Type::A);
+		assert_eq!((response.id, response.flags.aa(), response.flags.rd()), (4711, true, true));
+		assert_eq!(response.flags.rcode(), Ok(RCode::NoError));
+		assert_eq!(response.answers.len(), 1);
+		
+		// the CNAME is followed within the zones
+		let response = lookup("www.example.com.", Type::A);
+		assert_eq!(response.answers.iter().map(|rr| rr.name.as_str()).collect::<Vec<_>>(),
+			["www.example.com.", "web.internal.example.com."]);
+		
+		// the wildcard answers with the queried name as owner
+		let response = lookup("foo.apps.example.com.", Type::A);
+		assert_eq!(response.answers[0].name, "foo.apps.example.com.");
+		
+		// the address of the mail exchange is added
+		let response = lookup("example.com.", Type::MX);
+		assert_eq!(response.additional_records[0].name, "mail.example.com.");
+	}
+	
+	#[test]
+	fn upstream_clients() {
+		let dir = crate::utils::stream::test_dir("dns-upstream");
+		let cfg = |clients: Option<Vec<Cidr>>| storage::ConfigDns {
+			// nothing listens there, queries that are forwarded fail
+			upstream:         Some("127.0.0.1:9".to_string()),
+			upstream_clients: clients,
+			timeout:          Some(Duration::from_millis(100))
+		};
+		let rcode = |zones: &Zones, addr: Option<&str>| smol::block_on(async {
+			let mut exchange = DnsExchange { query: query("example.org.", Type::A), ..DnsExchange::default() };
+			exchange.connection.addr = addr.map(|v| v.parse().unwrap());
+			zones.accept(unsafe { std::mem::transmute::<&mut DnsExchange, &'static mut DnsExchange>(&mut exchange) }).await.unwrap();
+			exchange.response.unwrap().flags.rcode()
+		});
+		
+		let zones = smol::block_on(Zones::new("dns", dir.to_str().unwrap(), Default::default(), false, cfg(None)));
+		assert_eq!(rcode(&zones, Some("203.0.113.1:53")), Ok(RCode::QueryRefused));
+		assert_eq!(rcode(&zones, Some("[2001:db8::1]:53")), Ok(RCode::QueryRefused));
+		assert_eq!(rcode(&zones, Some("192.168.1.1:53")), Ok(RCode::ServerFailure));
+		assert_eq!(rcode(&zones, Some("[::1]:53")), Ok(RCode::ServerFailure));
+		assert_eq!(rcode(&zones, None), Ok(RCode::ServerFailure));
+		
+		let zones = smol::block_on(Zones::new("dns", dir.to_str().unwrap(), Default::default(), false,
+			cfg(Some(vec!["203.0.113.0/24".parse().unwrap()]))));
+		assert_eq!(rcode(&zones, Some("203.0.113.1:53")), Ok(RCode::ServerFailure));
+		assert_eq!(rcode(&zones, Some("192.168.1.1:53")), Ok(RCode::QueryRefused));
+	}
+	
+	#[test]
+	fn negative() {
+		let response = lookup("missing.example.com.", Type::A);
+		assert_eq!(response.flags.rcode(), Ok(RCode::NonExistentDomain));
+		assert!(matches!(response.authority_records[0].data, ResourceRecordData::SOA(_)));
+		
+		// empty non-terminals exist
+		let response = lookup("internal.example.com.", Type::A);
+		assert_eq!(response.flags.rcode(), Ok(RCode::NoError));
+		assert!(response.answers.is_empty());
+		assert_eq!(response.authority_records.len(), 1);
+		
+		let response = lookup("a.sub.example.com.", Type::A);
+		assert!(!response.flags.aa());
+		assert!(response.answers.is_empty());
+		assert!(matches!(response.authority_records[0].data, ResourceRecordData::NS(_)));
+		assert_eq!(response.additional_records[0].name, "ns.sub.example.com.");
+	}
+}
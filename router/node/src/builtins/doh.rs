@@ -0,0 +1,313 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Answers DNS queries over HTTPS (RFC 8484) with a DNS component, e.g. the zones of a storage
+//! module.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*, controller::parse_query, HEADER_SERVER},
+	net::http::{self, traits::AsyncStreamExt},
+	smol::io::{AsyncReadExt, AsyncWriteExt}
+};
+
+const MAX_MESSAGE_LEN: usize = 0xFFFF;
+const MEDIA_TYPE:      &str = "application/dns-message";
+const PARAM_DNS:       &str = "dns";
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// The DNS component queries are answered by, e.g. a storage module with `dns`.
+	pub next: String
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	let module = Module {
+		name: name.to_string(),
+		next: crate::get_component::<DnsHandler>(crate::component_id(&cfg.next))
+	};
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), Box::new(module));
+	Ok(())
+}
+
+struct Module {
+	name: String,
+	next: ComponentRef<DnsHandler>
+}
+
+impl Module {
+	async fn reject(&self, stream: &mut dyn http::traits::AsyncStream, status: http::Status) -> Result<()> {
+		discard_body(stream).await?;
+		send_response(stream, status).await
+	}
+	
+	/// Reads the query from the body of a `POST` request.
+	async fn read_query(&self, stream: &mut dyn http::traits::AsyncStream) -> std::result::Result<Vec<u8>, http::Status> {
+		let mut body = Vec::new();
+		let mut buf = [0u8; 0x1000];
+		
+		loop {
+			match stream.read(&mut buf).await {
+				Ok(0) => return Ok(body),
+				Ok(len) if body.len() + len <= MAX_MESSAGE_LEN => body.extend_from_slice(&buf[..len]),
+				Ok(_) => return Err(http::Status::PayloadTooLarge),
+				Err(_) => return Err(http::Status::BadRequest)
+			}
+		}
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let headers = stream.read_headers().await?;
+			let path = headers.iter().find_map(http::Header::as_path).map(String::as_str).unwrap_or("/");
+			let content_type = headers.iter().find_map(http::Header::as_content_type);
+			
+			let query = match headers.iter().find_map(http::Header::as_method) {
+				Some(http::Method::Get) => {
+					let params = match parse_query(path.split_once('?').map_or("", |(_, v)| v)) {
+						Ok(v) => v,
+						Err(_) => return self.reject(stream, http::Status::BadRequest).await
+					};
+					
+					match params.get(PARAM_DNS).and_then(|v| base64::decode_config(v.as_bytes(), base64::URL_SAFE_NO_PAD).ok()) {
+						Some(v) => v,
+						None => return self.reject(stream, http::Status::BadRequest).await
+					}
+				}
+				Some(http::Method::Post) if content_type.map_or(false, |v|
+					v.r#type == http::MainType::Application && v.subtype == "dns-message") =>
+					match self.read_query(stream).await {
+						Ok(v) => v,
+						Err(status) => return self.reject(stream, status).await
+					},
+				Some(http::Method::Post) => return self.reject(stream, http::Status::UnsupportedMediaType).await,
+				_ => return self.reject(stream, http::Status::MethodNotAllowed).await
+			};
+			
+			discard_body(stream).await?;
+			
+			let query = match net::dns::Message::parse(&query) {
+				Ok(v) if !v.flags.qr() => v,
+				_ => return send_response(stream, http::Status::BadRequest).await
+			};
+			
+			let connection = ConnectionInfo::from_headers(&headers);
+			let response = match dns::answer(&self.next, &query, connection, u16::MAX).await {
+				Ok(v) => v,
+				Err(e) => {
+					log::error!("DoH `{}`: #{} error: {}", &self.name, query.id, e.display());
+					dns::failure(&query, u16::MAX)
+				}
+			};
+			
+			let body = match response.to_bytes() {
+				Ok(v) => v,
+				Err(e) => {
+					log::error!("DoH `{}`: #{} failed to encode response: {}", &self.name, query.id, e);
+					dns::failure(&query, u16::MAX).to_bytes()?
+				}
+			};
+			
+			stream.write_headers(&[
+				http::Header::Status(http::Status::Ok),
+				http::Header::Server(HEADER_SERVER.to_string()),
+				http::Header::ContentType(Box::new(MEDIA_TYPE.parse().unwrap())),
+				http::Header::CacheControl(http::CacheControl::MaxAge(max_age(&response))),
+				http::Header::ContentLength(body.len())
+			]).await?;
+			stream.write_all(&body).await?;
+			Ok(())
+		})
+	}
+}
+
+/// The time the response may be cached, the minimum TTL of the answers or, for negative
+/// responses, the TTL of the SOA record capped by its minimum field (RFC 2308, section 5).
+fn max_age(response: &net::dns::Message) -> u32 {
+	match response.answers.is_empty() {
+		false => response.answers.iter().map(|rr| rr.ttl).min().unwrap_or(0),
+		true  => response.authority_records.iter()
+			.find_map(|rr| match &rr.data {
+				net::dns::ResourceRecordData::SOA(soa) => Some(rr.ttl.min(soa.minimum)),
+				_ => None
+			})
+			.unwrap_or(0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, net::dns::{Message, RCode, ResourceRecord}};
+	
+	/// Answers queries for `example.com.` with two addresses and all others with `NXDOMAIN`.
+	struct TestZone;
+	
+	impl StreamHandler<DnsExchange> for TestZone {
+		fn accept<'a>(&'a self, exchange: &'static mut DnsExchange) -> DynFuture<'a, Result<()>> {
+			Box::pin(async move {
+				let mut response = Message {
+					id:        exchange.query.id,
+					questions: exchange.query.questions.clone(),
+					..Message::default()
+				};
+				
+				match exchange.query.questions[0].name.as_str() {
+					"example.com." => response.answers = vec![
+						"example.com. 300 IN A 192.0.2.1".parse().unwrap(),
+						"example.com. 60 IN A 192.0.2.2".parse().unwrap()
+					],
+					_ => {
+						response.flags.set_rcode(Ok(RCode::NonExistentDomain));
+						response.authority_records.push(soa(3600, 300));
+					}
+				}
+				
+				exchange.response = Some(response);
+				Ok(())
+			})
+		}
+	}
+	
+	fn soa(ttl: u32, minimum: u32) -> ResourceRecord {
+		format!("example.com. {} IN SOA ns1.example.com. hostmaster.example.com. 1 7200 3600 604800 {}", ttl, minimum)
+			.parse().unwrap()
+	}
+	
+	fn module() -> Module {
+		Module {
+			name: "test".to_string(),
+			next: test_component(Box::new(TestZone) as DnsHandler)
+		}
+	}
+	
+	fn query(name: &str) -> Message {
+		let mut query = Message { id: 4711, ..Message::default() };
+		query.flags.set_rd(true);
+		query.questions.push(net::dns::Question { name: name.to_string(), r#type: net::dns::Type::A, class: net::dns::Class::IN });
+		query
+	}
+	
+	fn get(query: &[u8]) -> TestHttpStream {
+		let path = format!("/dns-query?{}={}", PARAM_DNS, base64::encode_config(query, base64::URL_SAFE_NO_PAD));
+		request(vec![http::Header::Method(http::Method::Get), http::Header::Path(path)], Vec::new())
+	}
+	
+	fn post(content_type: &str, query: Vec<u8>) -> TestHttpStream {
+		request(vec![
+			http::Header::Method(http::Method::Post),
+			http::Header::Path("/dns-query".to_string()),
+			http::Header::ContentType(Box::new(content_type.parse().unwrap())),
+			http::Header::ContentLength(query.len())
+		], query)
+	}
+	
+	fn request(headers: Vec<http::Header>, body: Vec<u8>) -> TestHttpStream {
+		let (r, stream) = TestHttpStream::new(headers, body).accept(&module());
+		assert!(r.is_ok());
+		stream
+	}
+	
+	/// The DNS response of a successful request.
+	fn dns_response(stream: &TestHttpStream) -> Message {
+		assert_eq!(stream.status(), Some(http::Status::Ok));
+		assert!(stream.response().contains(&http::Header::ContentType(Box::new(MEDIA_TYPE.parse().unwrap()))));
+		assert!(stream.response().contains(&http::Header::ContentLength(stream.response_body.len())));
+		Message::parse(&stream.response_body).unwrap()
+	}
+	
+	#[test]
+	fn get_query() {
+		// the encoded query would need padding, which DoH omits
+		let query = query("example.com.").to_bytes().unwrap();
+		assert_ne!(query.len() % 3, 0);
+		
+		let stream = get(&query);
+		let response = dns_response(&stream);
+		assert_eq!(response.id, 4711);
+		assert!(response.flags.qr());
+		assert_eq!(response.answers.len(), 2);
+		assert!(stream.response().contains(&http::Header::CacheControl(http::CacheControl::MaxAge(60))));
+		
+		let stream = get(&self::query("missing.example.com.").to_bytes().unwrap());
+		assert_eq!(dns_response(&stream).flags.rcode(), Ok(RCode::NonExistentDomain));
+		assert!(stream.response().contains(&http::Header::CacheControl(http::CacheControl::MaxAge(300))));
+	}
+	
+	#[test]
+	fn post_query() {
+		let stream = post(MEDIA_TYPE, query("example.com.").to_bytes().unwrap());
+		let response = dns_response(&stream);
+		assert_eq!(response.id, 4711);
+		assert_eq!(response.questions[0].name, "example.com.");
+		assert_eq!(response.answers.len(), 2);
+	}
+	
+	#[test]
+	fn errors() {
+		let query = query("example.com.").to_bytes().unwrap();
+		
+		assert_eq!(post("text/plain", query.clone()).status(), Some(http::Status::UnsupportedMediaType));
+		assert_eq!(post(MEDIA_TYPE, vec![0; MAX_MESSAGE_LEN + 1]).status(), Some(http::Status::PayloadTooLarge));
+		
+		let stream = request(vec![http::Header::Method(http::Method::Put), http::Header::Path("/dns-query".to_string())], query.clone());
+		assert_eq!(stream.status(), Some(http::Status::MethodNotAllowed));
+		
+		// no or invalid parameters, malformed messages and responses instead of queries
+		let get_path = |path: &str| request(vec![
+			http::Header::Method(http::Method::Get),
+			http::Header::Path(path.to_string())
+		], Vec::new()).status();
+		assert_eq!(get_path("/dns-query"), Some(http::Status::BadRequest));
+		assert_eq!(get_path("/dns-query?dns=!!!"), Some(http::Status::BadRequest));
+		assert_eq!(get(&query[..10]).status(), Some(http::Status::BadRequest));
+		assert_eq!(post(MEDIA_TYPE, Vec::new()).status(), Some(http::Status::BadRequest));
+		
+		let mut response = self::query("example.com.");
+		response.flags.set_qr(true);
+		assert_eq!(get(&response.to_bytes().unwrap()).status(), Some(http::Status::BadRequest));
+	}
+	
+	#[test]
+	fn cache_max_age() {
+		let mut response = Message::default();
+		assert_eq!(max_age(&response), 0);
+		
+		response.answers = vec![
+			"example.com. 300 IN A 192.0.2.1".parse().unwrap(),
+			"example.com. 60 IN A 192.0.2.2".parse().unwrap()
+		];
+		response.authority_records.push(soa(10, 10));
+		assert_eq!(max_age(&response), 60);
+		
+		// negative responses are cached for the SOA minimum, but no longer than the SOA itself
+		response.answers.clear();
+		assert_eq!(max_age(&response), 10);
+		response.authority_records = vec!["example.com. 3600 IN NS ns1.example.com.".parse().unwrap(), soa(3600, 300)];
+		assert_eq!(max_age(&response), 300);
+		response.authority_records = vec![soa(60, 300)];
+		assert_eq!(max_age(&response), 60);
+	}
+}
@@ -0,0 +1,514 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*},
+	std::{io, net::{IpAddr, SocketAddr}, time::Duration},
+	net::http::{self, traits::{AsyncSharedConnectionExt, AsyncStreamExt}},
+	smol::io::{AsyncReadExt, AsyncWriteExt}
+};
+
+const DEFAULT_BUF_LEN:         usize = 0x1000;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_IDLE_TIMEOUT:    Duration = Duration::from_secs(120);
+const DEFAULT_HTTP_PORT:       u16 = 80;
+const DEFAULT_CONNECT_PORT:    u16 = 443;
+
+/// Headers that apply to a single connection, besides those named in `Connection`.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+	"connection", "keep-alive", "proxy-connection", "proxy-authorization", "te", "trailer", "upgrade"
+];
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// Destinations that may be reached, any destination that is not denied if empty.
+	#[serde(default)]
+	pub allow:         ConfigDestinations,
+	/// Destinations that may not be reached, even if they are allowed.
+	#[serde(default)]
+	pub deny:          ConfigDestinations,
+	/// Ports `CONNECT` tunnels may be opened to.
+	#[serde(default = "default_connect_ports")]
+	pub connect_ports: Vec<u16>,
+	/// Addresses of host names, which are resolved with the system resolver otherwise.
+	#[serde(default)]
+	pub hosts:         HashMap<String, Vec<IpAddr>>,
+	pub buf_len:       Option<usize>,
+	#[serde(default)]
+	pub timeouts:      ConfigTimeouts
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigDestinations {
+	/// Host names as requested by the client, without the port.
+	pub hosts:    Vec<StringMatcher>,
+	/// Networks of the addresses host names resolve to.
+	pub networks: Vec<Cidr>
+}
+
+impl ConfigDestinations {
+	fn is_empty(&self) -> bool {
+		self.hosts.is_empty() && self.networks.is_empty()
+	}
+	
+	fn matches(&self, host: &str, addr: IpAddr) -> bool {
+		self.hosts.iter().any(|v| v.matches(Some(host)))
+			|| self.networks.iter().any(|v| v.contains(addr))
+	}
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigTimeouts {
+	/// The time until a host name is resolved and a connection to the destination is established.
+	pub connect: Option<Duration>,
+	/// Closes tunnels and fails requests after no data was sent in either direction.
+	pub idle:    Option<Duration>
+}
+
+fn default_connect_ports() -> Vec<u16> {
+	vec![DEFAULT_CONNECT_PORT]
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	let module = Module {
+		name:            name.to_string(),
+		buf_len:         cfg.buf_len.unwrap_or(DEFAULT_BUF_LEN),
+		connect_timeout: cfg.timeouts.connect.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+		idle_timeout:    cfg.timeouts.idle.unwrap_or(DEFAULT_IDLE_TIMEOUT),
+		cfg
+	};
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), Box::new(module));
+	Ok(())
+}
+
+struct Module {
+	name:            String,
+	cfg:             Config,
+	buf_len:         usize,
+	connect_timeout: Duration,
+	idle_timeout:    Duration
+}
+
+impl Module {
+	/// Whether an address a host resolved to may be connected to.
+	fn is_allowed(&self, host: &str, addr: IpAddr) -> bool {
+		!self.cfg.deny.matches(host, addr) && (self.cfg.allow.is_empty() || self.cfg.allow.matches(host, addr))
+	}
+	
+	/// Resolves a host name, unless it is an IP address or configured in `hosts`.
+	async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		if let Ok(addr) = host.parse::<IpAddr>() {
+			return Ok(vec![SocketAddr::new(addr, port)]);
+		}
+		
+		if let Some(addrs) = self.cfg.hosts.get(host) {
+			return Ok(addrs.iter().map(|v| SocketAddr::new(*v, port)).collect());
+		}
+		
+		timeout(Some(self.connect_timeout), smol::net::resolve((host, port))).await
+	}
+	
+	/// Connects to a destination. Only the addresses that are allowed are tried, so a host name
+	/// cannot be used to reach a denied network.
+	async fn connect(&self, host: &str, port: u16) -> std::result::Result<smol::net::TcpStream, http::Status> {
+		let status = |e: io::Error| match e.kind() {
+			io::ErrorKind::TimedOut => http::Status::GatewayTimeout,
+			_ => http::Status::BadGateway
+		};
+		
+		let addrs = match self.resolve(host, port).await {
+			Ok(v) => v,
+			Err(e) => {
+				log::debug!("forward proxy `{}`: failed to resolve `{}`: {}", &self.name, host, e);
+				return Err(status(e));
+			}
+		};
+		
+		let mut last = None;
+		for addr in addrs.iter().filter(|v| self.is_allowed(host, v.ip())) {
+			match timeout(Some(self.connect_timeout), smol::net::TcpStream::connect(*addr)).await {
+				Ok(v)  => return Ok(v),
+				Err(e) => last = Some(e)
+			}
+		}
+		
+		match last {
+			Some(e) => {
+				log::debug!("forward proxy `{}`: failed to connect to `{}:{}`: {}", &self.name, host, port, e);
+				Err(status(e))
+			}
+			// every address is denied, or the host does not resolve to any address
+			None if addrs.is_empty() => Err(http::Status::BadGateway),
+			None => Err(http::Status::Forbidden)
+		}
+	}
+	
+	async fn reject(&self, stream: &mut dyn http::traits::AsyncStream, status: http::Status) -> Result<()> {
+		discard_body(stream).await?;
+		send_response(stream, status).await
+	}
+	
+	/// Opens a tunnel for a `CONNECT` request, which carries raw bytes in both directions.
+	async fn tunnel(&self, stream_src: &mut dyn http::traits::AsyncStream, target: &str) -> Result<()> {
+		let (host, port) = match parse_authority(target, None) {
+			Some(v) => v,
+			None    => return self.reject(stream_src, http::Status::BadRequest).await
+		};
+		
+		if !self.cfg.connect_ports.contains(&port) {
+			return self.reject(stream_src, http::Status::Forbidden).await;
+		}
+		
+		let mut stream_dst = match self.connect(&host, port).await {
+			Ok(v) => v,
+			Err(status) => return self.reject(stream_src, status).await
+		};
+		
+		discard_body(stream_src).await?;
+		stream_src.write_headers(&[
+			http::Header::Status(http::Status::Ok),
+			http::Header::Server(HEADER_SERVER.to_string())
+		]).await?;
+		stream_src.flush().await?;
+		
+		let (mut buf_src, mut buf_dst) = (vec![0u8; self.buf_len], vec![0u8; self.buf_len]);
+		
+		enum Side { Src(usize), Dst(usize) }
+		
+		loop {
+			let read = smol::future::or(
+				smol::future::race(
+					async { stream_src.read(&mut buf_src).await.map(Side::Src) },
+					async { stream_dst.read(&mut buf_dst).await.map(Side::Dst) }),
+				async {
+					smol::Timer::after(self.idle_timeout).await;
+					Err(io::Error::from(io::ErrorKind::TimedOut))
+				}
+			).await;
+			
+			match read {
+				Ok(Side::Src(0)) | Ok(Side::Dst(0)) => break,
+				Ok(Side::Src(len)) => stream_dst.write_all(&buf_src[..len]).await?,
+				Ok(Side::Dst(len)) => {
+					stream_src.write_all(&buf_dst[..len]).await?;
+					stream_src.flush().await?;
+				}
+				Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+					log::debug!("forward proxy `{}`: closing idle tunnel to `{}:{}`", &self.name, &host, port);
+					break;
+				}
+				Err(e) => return Err(e.into())
+			}
+		}
+		
+		stream_dst.close().await?;
+		Ok(())
+	}
+	
+	/// Forwards a request with an absolute-form target to the origin server of its URL.
+	async fn forward(&self, stream_src: &mut dyn http::traits::AsyncStream, mut headers: Vec<http::Header>, target: &str) -> Result<()> {
+		let (authority, path) = match split_absolute(target) {
+			Some(v) => v,
+			None    => return self.reject(stream_src, http::Status::BadRequest).await
+		};
+		
+		let (host, port) = match parse_authority(authority, Some(DEFAULT_HTTP_PORT)) {
+			Some(v) => v,
+			None    => return self.reject(stream_src, http::Status::BadRequest).await
+		};
+		
+		let stream_dst = match self.connect(&host, port).await {
+			Ok(v) => v,
+			Err(status) => return self.reject(stream_src, status).await
+		};
+		
+		// the origin server expects an origin-form target and the authority of the URL as host
+		headers.retain(|v| !matches!(v, http::Header::Host(_)));
+		for header in &mut headers {
+			if let http::Header::Path(v) = header {
+				*v = path.clone();
+			}
+		}
+		headers.push(http::Header::Host(authority.to_string()));
+		
+		let conn: http::traits::BoxedAsyncSharedConnection = Box::pin(net::http::v1::AsyncSharedConnection::new(
+			net::http::v1::AsyncConnection::new(
+				net::buffered::AsyncBufStream::new(stream_dst))));
+		let id = conn.open().await?;
+		let mut stream_dst = http::AsyncStream(&conn, id);
+		let mut buf = vec![0u8; self.buf_len];
+		stream_dst.write_headers(&headers).await?;
+		
+		let head = matches!(headers.iter().find_map(http::Header::as_method), Some(http::Method::Head));
+		// a GET or HEAD may have a body too, which must not be left on the client connection
+		let len = Some(headers.iter().find_map(http::Header::as_content_length).copied().unwrap_or(0));
+		
+		relay::copy_body(&mut *stream_src, &mut stream_dst, len, &mut buf, Some(self.idle_timeout)).await?;
+		stream_dst.flush().await?;
+		let mut headers = timeout(Some(self.idle_timeout), stream_dst.read_headers()).await?;
+		strip_hop_by_hop(&mut headers);
+		stream_src.write_headers(&headers).await?;
+		
+		let len = match headers.iter().find_map(http::Header::as_status) {
+			_ if head => Some(0),
+			Some(http::Status::NoContent | http::Status::NotModified) => Some(0),
+			_ => headers.iter().find_map(http::Header::as_content_length).copied()
+		};
+		
+		relay::copy_body(&mut stream_dst, &mut *stream_src, len, &mut buf, Some(self.idle_timeout)).await?;
+		stream_src.flush().await?;
+		Ok(())
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let mut headers = stream.read_headers().await?;
+			// the headers of this node are not meant for the destination
+			headers.retain(|v| !ClientIdentity::is_header(v.name_v1())
+				&& !ConnectionInfo::is_header(v.name_v1())
+				&& !RequestTimeouts::is_header(v.name_v1())
+				&& !v.name_v1().eq_ignore_ascii_case(HEADER_JWT_CLAIMS));
+			// neither are those of the connection to this proxy, including its credentials
+			strip_hop_by_hop(&mut headers);
+			
+			let target = headers.iter().find_map(http::Header::as_path).cloned().unwrap_or_default();
+			let span = start_span(&headers, otel_mrt::SpanParams::new("forward proxy")
+				.kind(otel_mrt::SpanKind::Client)
+				.attribute("http.target", otel_mrt::AnyValue::String(target.clone())));
+			
+			let r = match headers.iter().find_map(http::Header::as_method) {
+				Some(http::Method::Connect) => self.tunnel(stream, &target).await,
+				Some(_) => self.forward(stream, headers, &target).await,
+				None    => self.reject(stream, http::Status::BadRequest).await
+			};
+			
+			if r.is_err() {
+				span.set_status(otel_mrt::StatusCode::Error, None);
+			}
+			
+			span.end();
+			r
+		})
+	}
+}
+
+/// Removes the headers that only apply to the connection they were received on.
+fn strip_hop_by_hop(headers: &mut Vec<http::Header>) {
+	let named = headers.iter()
+		.filter(|v| matches!(v, http::Header::Connection(_)))
+		.flat_map(|v| v.to_string().split(',').map(|v| v.trim().to_ascii_lowercase()).collect::<Vec<_>>())
+		.collect::<Vec<_>>();
+	
+	headers.retain(|v| {
+		let name = v.name_v1();
+		!HOP_BY_HOP_HEADERS.iter().any(|v| v.eq_ignore_ascii_case(name))
+			&& !named.iter().any(|v| v.eq_ignore_ascii_case(name))
+	});
+}
+
+/// Splits an authority into the lowercase host and the port. IPv6 addresses are enclosed in
+/// brackets, which are removed.
+fn parse_authority(authority: &str, default_port: Option<u16>) -> Option<(String, u16)> {
+	let (host, port) = match authority.strip_prefix('[') {
+		Some(v) => {
+			let (host, rest) = v.split_once(']')?;
+			(host, match rest {
+				"" => None,
+				v  => Some(v.strip_prefix(':')?)
+			})
+		}
+		None => match authority.rsplit_once(':') {
+			Some((host, port)) => (host, Some(port)),
+			None => (authority, None)
+		}
+	};
+	
+	if host.is_empty() || (host.contains(&['@', ':', '/'][..]) && !authority.starts_with('[')) {
+		return None;
+	}
+	
+	let port = match port {
+		Some(v) => v.parse().ok()?,
+		None    => default_port?
+	};
+	
+	Some((host.to_ascii_lowercase(), port))
+}
+
+/// Splits an absolute-form `http` target into the authority and the origin-form target.
+fn split_absolute(target: &str) -> Option<(&str, String)> {
+	let (scheme, rest) = target.split_once("://")?;
+	
+	if !scheme.eq_ignore_ascii_case("http") {
+		return None;
+	}
+	
+	let (authority, path) = rest.split_at(rest.find(&['/', '?'][..]).unwrap_or(rest.len()));
+	Some((authority, match path.starts_with('/') {
+		true  => path.to_string(),
+		false => format!("/{}", path)
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, smol::net::TcpListener};
+	
+	fn config() -> Config {
+		serde_json::from_str("{}").unwrap()
+	}
+	
+	fn module(cfg: Config) -> Module {
+		Module {
+			name:            "test".to_string(),
+			cfg,
+			buf_len:         DEFAULT_BUF_LEN,
+			connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+			idle_timeout:    DEFAULT_IDLE_TIMEOUT
+		}
+	}
+	
+	#[test]
+	fn targets() {
+		assert_eq!(parse_authority("example.com:443", None), Some(("example.com".to_string(), 443)));
+		assert_eq!(parse_authority("Example.COM", Some(80)), Some(("example.com".to_string(), 80)));
+		assert_eq!(parse_authority("[::1]:8443", None), Some(("::1".to_string(), 8443)));
+		assert_eq!(parse_authority("example.com", None), None);
+		assert_eq!(parse_authority("user@example.com:443", None), None);
+		assert_eq!(parse_authority(":443", None), None);
+		
+		assert_eq!(split_absolute("http://example.com/a?b"), Some(("example.com", "/a?b".to_string())));
+		assert_eq!(split_absolute("http://example.com:8080?b"), Some(("example.com:8080", "/?b".to_string())));
+		assert_eq!(split_absolute("http://example.com"), Some(("example.com", "/".to_string())));
+		assert_eq!(split_absolute("https://example.com/"), None);
+		assert_eq!(split_absolute("/a"), None);
+	}
+	
+	#[test]
+	fn destinations() {
+		let module = |allow, deny| module(Config { allow, deny, ..config() });
+		let public  = "93.184.216.34".parse().unwrap();
+		let private = "10.1.2.3".parse().unwrap();
+		
+		let private_denied = module(ConfigDestinations::default(), ConfigDestinations {
+			hosts:    vec![StringMatcher::Suffix(".internal".to_string())],
+			networks: vec!["10.0.0.0/8".parse().unwrap()]
+		});
+		assert!(private_denied.is_allowed("example.com", public));
+		assert!(!private_denied.is_allowed("example.com", private));
+		assert!(!private_denied.is_allowed("db.internal", public));
+		assert!(!private_denied.is_allowed("example.com", "::ffff:10.0.0.1".parse().unwrap()));
+		
+		let example_only = module(ConfigDestinations {
+			hosts:    vec![StringMatcher::Exact("example.com".to_string())],
+			networks: Vec::new()
+		}, ConfigDestinations::default());
+		assert!(example_only.is_allowed("example.com", public));
+		assert!(!example_only.is_allowed("example.org", public));
+	}
+	
+	#[test]
+	fn forward() {
+		smol::block_on(deadline(async {
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let addr = listener.local_addr().unwrap();
+			let origin = smol::spawn(async move {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let request = read_head(&mut stream).await;
+				stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: X-Hop\r\nX-Hop: 1\r\nKeep-Alive: timeout=5\r\n\r\nok").await.unwrap();
+				request
+			});
+			
+			let module = module(config());
+			let (server, mut client) = TestDuplex::pair();
+			let (r, response) = smol::future::zip(serve_http(&module, server), async {
+				client.write_all(format!(concat!(
+					"GET http://{0}/a?b HTTP/1.1\r\nHost: {0}\r\nConnection: keep-alive, X-Hop\r\nKeep-Alive: timeout=5\r\n",
+					"TE: trailers\r\nTrailer: X-Checksum\r\nUpgrade: websocket\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n",
+					"Proxy-Connection: keep-alive\r\nX-Hop: 1\r\nX-End-To-End: 1\r\n\r\n"
+				), addr).as_bytes()).await.unwrap();
+				let mut buf = Vec::new();
+				client.read_to_end(&mut buf).await.unwrap();
+				String::from_utf8(buf).unwrap().to_ascii_lowercase()
+			}).await;
+			r.unwrap();
+			
+			let request = origin.await.to_ascii_lowercase();
+			assert!(request.starts_with("get /a?b http/1.1\r\n"));
+			assert!(request.contains(&format!("\r\nhost: {}\r\n", addr)));
+			assert!(request.contains("\r\nx-end-to-end: 1\r\n"));
+			
+			for name in ["connection", "keep-alive", "te", "trailer", "upgrade", "proxy-authorization", "proxy-connection", "x-hop"] {
+				assert!(!request.contains(&format!("\r\n{}:", name)), "`{}` was forwarded", name);
+			}
+			
+			assert!(response.starts_with("http/1.1 200"));
+			assert!(!response.contains("\r\nx-hop:") && !response.contains("\r\nkeep-alive:"));
+			assert!(response.ends_with("\r\n\r\nok"));
+		}));
+	}
+	
+	#[test]
+	fn tunnel() {
+		smol::block_on(deadline(async {
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			let addr = listener.local_addr().unwrap();
+			let _echo = smol::spawn(async move {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let mut buf = [0u8; 64];
+				
+				while let Ok(len @ 1..) = stream.read(&mut buf).await {
+					stream.write_all(&buf[..len]).await.unwrap();
+				}
+			});
+			
+			let module = module(Config { connect_ports: vec![addr.port()], ..config() });
+			let (server, mut client) = TestDuplex::pair();
+			let (r, ()) = smol::future::zip(serve_http(&module, server), async {
+				client.write_all(format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", addr).as_bytes()).await.unwrap();
+				assert!(read_head(&mut client).await.starts_with("HTTP/1.1 200"));
+				
+				let mut buf = [0u8; 4];
+				client.write_all(b"ping").await.unwrap();
+				client.read_exact(&mut buf).await.unwrap();
+				assert_eq!(&buf, b"ping");
+				client.close().await.unwrap();
+			}).await;
+			r.unwrap();
+			
+			// tunnels may only be opened to the configured ports
+			let (server, mut client) = TestDuplex::pair();
+			let (r, ()) = smol::future::zip(serve_http(&self::module(config()), server), async {
+				client.write_all(format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", addr).as_bytes()).await.unwrap();
+				assert!(read_head(&mut client).await.starts_with("HTTP/1.1 403"));
+			}).await;
+			r.unwrap();
+		}));
+	}
+}
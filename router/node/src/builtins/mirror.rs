@@ -0,0 +1,348 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+
+//! Duplicates requests to a shadow module, e.g. to test a new backend version with real traffic.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*},
+	std::{io::{self, Read}, pin::Pin, task::{Context, Poll}},
+	net::http::{self, traits::AsyncStreamExt},
+	smol::io::AsyncReadExt
+};
+
+const DEFAULT_BODY_LIMIT:    usize = 0x10_0000;
+const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// The module requests are forwarded to, its responses are sent to the client.
+	pub next:          String,
+	/// The module requests are duplicated to, its responses are discarded.
+	pub shadow:        String,
+	/// Requests with larger bodies or bodies of unknown length are not mirrored.
+	#[serde(default = "default_body_limit")]
+	pub body_limit:    usize,
+	/// Requests are not mirrored while this many mirrored requests are in progress.
+	#[serde(default = "default_max_in_flight")]
+	pub max_in_flight: usize
+}
+
+fn default_body_limit() -> usize {
+	DEFAULT_BODY_LIMIT
+}
+
+fn default_max_in_flight() -> usize {
+	DEFAULT_MAX_IN_FLIGHT
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	let rt = otel_mrt::runtime();
+	let labels = vec![
+		(Cow::Borrowed("module"), otel_mrt::AnyValue::String(name.to_string())),
+		(Cow::Borrowed("shadow"), otel_mrt::AnyValue::String(cfg.shadow.clone()))
+	];
+	
+	let module = Module {
+		next:      crate::get_component::<HttpStreamHandler>(crate::component_id(&cfg.next)),
+		shadow:    crate::get_component::<HttpStreamHandler>(crate::component_id(&cfg.shadow)),
+		in_flight: Arc::new(AtomicUsize::new(0)),
+		telemetry: Arc::new(Telemetry {
+			requests: rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("mirror_requests")
+				.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true))
+				.bind(labels.clone()),
+			skipped:  rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("mirror_requests_skipped")
+				.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true))
+				.bind(labels.clone()),
+			errors:   rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("mirror_request_errors")
+				.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true))
+				.bind(labels.clone()),
+			status:   rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("mirror_response_http_status")
+				.aggregation_histogram(otel_mrt::AggregationTemporality::Unspecified))
+				.bind(labels)
+		}),
+		cfg
+	};
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), Box::new(module));
+	Ok(())
+}
+
+struct Module {
+	cfg:       Config,
+	next:      ComponentRef<HttpStreamHandler>,
+	shadow:    ComponentRef<HttpStreamHandler>,
+	in_flight: Arc<AtomicUsize>,
+	telemetry: Arc<Telemetry>
+}
+
+struct Telemetry {
+	requests: otel_mrt::BoundInstrument<usize>,
+	skipped:  otel_mrt::BoundInstrument<usize>,
+	errors:   otel_mrt::BoundInstrument<usize>,
+	status:   otel_mrt::BoundInstrument<usize>
+}
+
+impl Module {
+	/// The length of the body, if the request can be mirrored.
+	fn mirrored_len(&self, headers: &[http::Header]) -> Option<usize> {
+		// upgraded connections cannot be duplicated
+		if headers.iter().any(|v| matches!(v, http::Header::Upgrade(_))) {
+			return None;
+		}
+		
+		// a request without a length has no body, whatever the method
+		let len = headers.iter().find_map(http::Header::as_content_length).copied().unwrap_or(0);
+		
+		match len <= self.cfg.body_limit {
+			true  => Some(len),
+			false => None
+		}
+	}
+	
+	/// Sends the request to the shadow module in the background, if not too many are in progress.
+	fn mirror(&self, headers: Vec<http::Header>, body: Vec<u8>) {
+		if self.in_flight.fetch_add(1, Ordering::AcqRel) >= self.cfg.max_in_flight {
+			self.in_flight.fetch_sub(1, Ordering::AcqRel);
+			self.telemetry.skipped.record(1);
+			return;
+		}
+		
+		self.telemetry.requests.record(1);
+		let (shadow, in_flight, telemetry) = (self.shadow.clone(), self.in_flight.clone(), self.telemetry.clone());
+		
+		crate::spawn(async move {
+			let mut stream = ShadowHttpStream {
+				headers: Some(headers),
+				body:    io::Cursor::new(body),
+				status:  None
+			};
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream_ = unsafe { std::mem::transmute::<&mut ShadowHttpStream, &'static mut ShadowHttpStream>(&mut stream) };
+			let r = match shadow.get().await {
+				Ok(v)  => v.accept(stream_).await,
+				Err(e) => Err(e)
+			};
+			
+			if let Some(status) = stream.status {
+				telemetry.status.record(status as _);
+			}
+			
+			if let Err(e) = r {
+				telemetry.errors.record(1);
+				log::debug!("mirror: shadow request failed: {}", e.display());
+			}
+			
+			in_flight.fetch_sub(1, Ordering::AcqRel);
+		});
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let headers = stream.read_headers().await?;
+			
+			let body = match self.mirrored_len(&headers) {
+				Some(len) => {
+					let mut body = vec![0u8; len];
+					stream.read_exact(&mut body).await?;
+					self.mirror(headers.to_vec(), body.clone());
+					Some(io::Cursor::new(body))
+				}
+				None => {
+					self.telemetry.skipped.record(1);
+					None
+				}
+			};
+			
+			let mut inner = HeadersHttpStream::new(stream, headers);
+			let mut stream = BufferedHttpStream { inner: &mut inner, body };
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream = unsafe { std::mem::transmute::<&mut BufferedHttpStream<'_>, &'static mut BufferedHttpStream<'static>>(&mut stream) };
+			self.next.get().await?.accept(stream).await
+		})
+	}
+}
+
+/// Serves the buffered request body to the next module, instead of the consumed one.
+struct BufferedHttpStream<'a> {
+	inner: &'a mut dyn http::traits::AsyncStream,
+	body:  Option<io::Cursor<Vec<u8>>>
+}
+
+impl<'a> smol::io::AsyncRead for BufferedHttpStream<'a> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let self_ = Pin::into_inner(self);
+		match &mut self_.body {
+			Some(body) => Poll::Ready(body.read(buf)),
+			None       => Pin::new(&mut *self_.inner).poll_read(cx, buf)
+		}
+	}
+}
+
+impl<'a> smol::io::AsyncWrite for BufferedHttpStream<'a> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write(cx, buf)
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_flush(cx)
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_close(cx)
+	}
+}
+
+impl<'a> http::traits::AsyncStream for BufferedHttpStream<'a> {
+	fn poll_read_headers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read_headers(cx)
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write_headers(cx, headers)
+	}
+}
+
+/// A request without a client, the response is discarded except for its status.
+struct ShadowHttpStream {
+	headers: Option<Vec<http::Header>>,
+	body:    io::Cursor<Vec<u8>>,
+	status:  Option<http::Status>
+}
+
+impl smol::io::AsyncRead for ShadowHttpStream {
+	fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Poll::Ready(Pin::into_inner(self).body.read(buf))
+	}
+}
+
+impl smol::io::AsyncWrite for ShadowHttpStream {
+	fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Poll::Ready(Ok(buf.len()))
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl http::traits::AsyncStream for ShadowHttpStream {
+	fn poll_read_headers(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		match Pin::into_inner(self).headers.take() {
+			Some(headers) => Poll::Ready(Ok(headers)),
+			None          => Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
+		}
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, _cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		let self_ = Pin::into_inner(self);
+		self_.status = headers.iter().find_map(http::Header::as_status).copied().or(self_.status);
+		Poll::Ready(Ok(()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn module(body_limit: usize, next: TestHttpHandler) -> Module {
+		// the exporter is never run, nothing is sent anywhere
+		let rt = otel_mrt::Runtime::new(otel_mrt::Config::disabled(), Some(Box::new(|_| ())));
+		let instrument = || rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+			.name_str("mirror_test")
+			.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true))
+			.bind(Vec::new());
+		
+		Module {
+			cfg:       Config {
+				next:          "next".to_string(),
+				shadow:        "shadow".to_string(),
+				body_limit,
+				// nothing is sent to the shadow module, which would require a running node
+				max_in_flight: 0
+			},
+			next:      test_component(Box::new(next) as HttpStreamHandler),
+			shadow:    test_component(Box::new(TestHttpHandler::default()) as HttpStreamHandler),
+			in_flight: Arc::new(AtomicUsize::new(0)),
+			telemetry: Arc::new(Telemetry {
+				requests: instrument(),
+				skipped:  instrument(),
+				errors:   instrument(),
+				status:   instrument()
+			})
+		}
+	}
+	
+	fn request(method: http::Method, len: Option<usize>) -> Vec<http::Header> {
+		let mut headers = vec![http::Header::Method(method), http::Header::Path("/".to_string())];
+		headers.extend(len.map(http::Header::ContentLength));
+		headers
+	}
+	
+	#[test]
+	fn body_limit() {
+		let module = module(16, TestHttpHandler::default());
+		assert_eq!(module.mirrored_len(&request(http::Method::Get, None)), Some(0));
+		assert_eq!(module.mirrored_len(&request(http::Method::Post, None)), Some(0));
+		assert_eq!(module.mirrored_len(&request(http::Method::Post, Some(16))), Some(16));
+		assert_eq!(module.mirrored_len(&request(http::Method::Post, Some(17))), None);
+		// the length is taken from the header for every method
+		assert_eq!(module.mirrored_len(&request(http::Method::Get, Some(8))), Some(8));
+		assert_eq!(module.mirrored_len(&request(http::Method::Delete, Some(17))), None);
+		
+		let mut headers = request(http::Method::Get, None);
+		headers.push(http::Header::Upgrade("websocket".to_string()));
+		assert_eq!(module.mirrored_len(&headers), None);
+	}
+	
+	#[test]
+	fn forward() {
+		for (len, body) in [(4, &b"body"[..]), (64, &[b'x'; 64][..])] {
+			let next = TestHttpHandler {
+				response: vec![http::Header::Status(http::Status::Ok), http::Header::ContentLength(2)],
+				body:     b"ok".to_vec(),
+				..TestHttpHandler::default()
+			};
+			let requests = next.requests.clone();
+			let module = module(16, next);
+			
+			// buffered and unbuffered bodies both reach the next module unchanged
+			let (r, stream) = TestHttpStream::new(request(http::Method::Post, Some(len)), body).accept(&module);
+			assert!(r.is_ok());
+			assert_eq!(stream.status(), Some(http::Status::Ok));
+			assert_eq!(stream.response_body, b"ok");
+			assert_eq!(requests.lock().unwrap()[0].1, body);
+			assert_eq!(module.in_flight.load(Ordering::Acquire), 0);
+		}
+	}
+}
@@ -22,75 +22,301 @@
 
 ///! Contains all builtin modules.
 
-use {serde::Deserialize, std::str::FromStr, super::*};
-
+use {
+	serde::Deserialize,
+	schemars::JsonSchema,
+	std::{str::FromStr, collections::BTreeMap, path::PathBuf},
+	super::*,
+	crate::{interfaces::{Lifecycle, LifecycleHandler}, utils::graph::*}
+};
+
+pub mod accesslog;
 pub mod api;
 pub mod auth;
 pub mod balancer;
 pub mod cache;
+pub mod compress;
+pub mod cors;
+pub mod doh;
+pub mod forward_proxy;
+pub mod mirror;
 pub mod relay;
 pub mod router;
 pub mod socket;
+pub mod split;
 pub mod storage;
 
+mod dns;
+mod proxy_protocol;
+mod smtp;
+mod tls;
+mod transport;
+
 pub async fn run<'a>(cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)) -> Result<()> {
-	let cfg = Config::deserialize(cfg)
+	let mut cfg = Config::deserialize(cfg)
 		.with_msg("failed to parse config")?;
-
-	for (name, cfg) in cfg.builtin {
-		let (spec, e) = match cfg {
-			Module::Socket(cfg) => ("socket", socket::run(&name, cfg)),
-
+	
+	// components of plugins may not be registered yet, unknown references are reported on start
+	let graph = cfg.graph();
+	let order = toposort(&graph, |_| true)
+		.map_err(|errors| Error::new(errors.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+			.join(", ")))?;
+	
+	for name in order {
+		let module = cfg.builtin.remove(name).unwrap();
+		let dependencies = module.dependencies();
+		let (spec, r) = match module {
+			Module::AccessLog(cfg) => ("accesslog", accesslog::run(name, cfg).await),
+			Module::Api(cfg)       => ("api",       api::run(name, cfg).await),
+			Module::Auth(cfg)      => ("auth",      auth::run(name, cfg).await),
+			Module::Balancer(cfg)  => ("balancer",  balancer::run(name, cfg).await),
+			Module::Cache(cfg)     => ("cache",     cache::run(name, cfg).await),
+			Module::Compress(cfg)  => ("compress",  compress::run(name, cfg).await),
+			Module::Cors(cfg)      => ("cors",      cors::run(name, cfg).await),
+			Module::Doh(cfg)       => ("doh",       doh::run(name, cfg).await),
+			Module::ForwardProxy(cfg) => ("forward_proxy", forward_proxy::run(name, cfg).await),
+			Module::Mirror(cfg)    => ("mirror",    mirror::run(name, cfg).await),
+			Module::Relay(cfg)     => ("relay",     relay::run(name, cfg).await),
+			Module::Router(cfg)    => ("router",    router::run(name, cfg).await),
+			Module::Socket(cfg)    => ("socket",    socket::run(name, cfg).await),
+			Module::Split(cfg)     => ("split",     split::run(name, cfg).await),
+			Module::Storage(cfg)   => ("storage",   storage::run(name, cfg).await)
 		};
-
+		
 		match r {
-			Ok(_)  => log::info!("builtin module `{}` ({}) successfully initialized", &name, spec),
-			Err(e) => log::error!("builtin module `{}` ({}) failed to initialize: {:?}", &name, spec, e)
+			Ok(_)  => {
+				// modules with their own lifecycle hooks report their dependencies themselves
+				let id = crate::component_id(name);
+				if crate::get_component::<LifecycleHandler>(id).try_get().is_none() {
+					let _ = crate::add_component::<LifecycleHandler>(id, Box::new(Dependencies(dependencies)));
+				}
+				
+				log::info!("builtin module `{}` ({}) successfully initialized", name, spec)
+			}
+			Err(e) => log::error!("builtin module `{}` ({}) failed to initialize: {:?}", name, spec, e)
 		}
 	}
-
+	
 	Ok(())
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+/// Deserializes all builtin modules and checks the references between them and the components
+/// of the given plugins, without initializing anything.
+pub fn check(cfg: &HashMap<String, serde_dyn_repr::Value>, components: &[String]) -> Vec<ConfigError> {
+	let builtin = match cfg.get("builtin") {
+		Some(serde_dyn_repr::Value::Map(v)) => v,
+		Some(_) => return vec![ConfigError::new("builtin", "expected a map")],
+		None    => return Vec::new()
+	};
+	
+	let mut errors  = Vec::new();
+	let mut modules = BTreeMap::new();
+	
+	for (name, value) in builtin {
+		match Module::parse(&format!("builtin.{}", name), value) {
+			Ok(v)  => { modules.insert(name.clone(), v); }
+			Err(e) => errors.push(e)
+		}
+	}
+	
+	for (name, module) in &modules {
+		for (key, reference) in module.references() {
+			if !builtin.contains_key(&reference) && !components.contains(&reference) {
+				errors.push(ConfigError::new(
+					format!("builtin.{}.{}", name, key),
+					format!("unknown component `{}`", reference)));
+			}
+		}
+	}
+	
+	let graph = modules.iter()
+		.map(|(name, module)| (name.clone(), module.dependencies()))
+		.collect::<BTreeMap<_, _>>();
+	
+	if let Err(e) = toposort(&graph, |_| true) {
+		errors.extend(e.into_iter().map(|e| ConfigError::new("builtin", e)));
+	}
+	
+	errors
+}
+
+struct Dependencies(Vec<String>);
+
+impl Lifecycle for Dependencies {
+	fn dependencies(&self) -> Vec<String> {
+		self.0.clone()
+	}
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default)]
 pub struct Config {
 	pub builtin: HashMap<String, Module>
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+impl Config {
+	fn graph(&self) -> BTreeMap<String, Vec<String>> {
+		self.builtin.iter()
+			.map(|(name, module)| (name.clone(), module.dependencies()))
+			.collect()
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Module {
+	AccessLog(accesslog::Config),
 	Api(api::Config),
 	Auth(auth::Config),
 	Balancer(balancer::Config),
 	Cache(cache::Config),
+	Compress(compress::Config),
+	Cors(cors::Config),
+	Doh(doh::Config),
+	#[serde(rename = "forward_proxy")]
+	ForwardProxy(forward_proxy::Config),
+	Mirror(mirror::Config),
 	Relay(relay::Config),
 	Router(router::Config),
 	Socket(socket::Config),
+	Split(split::Config),
 	Storage(storage::Config)
 }
 
-#[derive(Clone, Debug, Deserialize)]
+impl Module {
+	/// Deserializes a module, errors contain the path of the key below `prefix`. Errors inside an
+	/// internally tagged enum have no path, so the tag is read first and the config of the variant
+	/// is deserialized on its own.
+	fn parse(prefix: &str, value: &serde_dyn_repr::Value) -> std::result::Result<Self, ConfigError> {
+		fn config<T: serde::de::DeserializeOwned>(
+			prefix:  &str,
+			value:   serde_dyn_repr::Value,
+			variant: fn(T) -> Module
+		) -> std::result::Result<Module, ConfigError> {
+			serde_path_to_error::deserialize(value)
+				.map(variant)
+				.map_err(|e| ConfigError::from_path(prefix, e))
+		}
+		
+		let mut map = match value {
+			serde_dyn_repr::Value::Map(v) => v.clone(),
+			_ => return Err(ConfigError::new(prefix, "expected a map"))
+		};
+		
+		let ty = match map.remove("type") {
+			Some(serde_dyn_repr::Value::String(v)) => v,
+			Some(_) => return Err(ConfigError::new(format!("{}.type", prefix), "expected a string")),
+			None    => return Err(ConfigError::new(prefix, "missing field `type`"))
+		};
+		
+		let value = serde_dyn_repr::Value::Map(map);
+		match ty.as_str() {
+			"accesslog"     => config(prefix, value, Self::AccessLog),
+			"api"           => config(prefix, value, Self::Api),
+			"auth"          => config(prefix, value, Self::Auth),
+			"balancer"      => config(prefix, value, Self::Balancer),
+			"cache"         => config(prefix, value, Self::Cache),
+			"compress"      => config(prefix, value, Self::Compress),
+			"cors"          => config(prefix, value, Self::Cors),
+			"doh"           => config(prefix, value, Self::Doh),
+			"forward_proxy" => config(prefix, value, Self::ForwardProxy),
+			"mirror"        => config(prefix, value, Self::Mirror),
+			"relay"         => config(prefix, value, Self::Relay),
+			"router"        => config(prefix, value, Self::Router),
+			"socket"        => config(prefix, value, Self::Socket),
+			"split"         => config(prefix, value, Self::Split),
+			"storage"       => config(prefix, value, Self::Storage),
+			ty => Err(ConfigError::new(format!("{}.type", prefix), format!("unknown module type `{}`", ty)))
+		}
+	}
+	
+	/// The names of the components this module references.
+	pub fn dependencies(&self) -> Vec<String> {
+		self.references()
+			.into_iter()
+			.map(|(_, name)| name)
+			.collect()
+	}
+	
+	/// The key paths and names of the components this module references.
+	pub fn references(&self) -> Vec<(String, String)> {
+		match self {
+			Self::AccessLog(cfg) => vec![("next".to_string(), cfg.next.clone())],
+			Self::Auth(cfg)      => vec![("next".to_string(), cfg.next.clone())],
+			Self::Compress(cfg)  => vec![("next".to_string(), cfg.next.clone())],
+			Self::Cors(cfg)      => vec![("next".to_string(), cfg.next.clone())],
+			Self::Doh(cfg)       => vec![("next".to_string(), cfg.next.clone())],
+			Self::Balancer(cfg)  => cfg.backends.iter()
+				.enumerate()
+				.map(|(i, v)| (format!("backends[{}]", i), v.name.clone()))
+				.collect(),
+			Self::Router(cfg)    => cfg.filters.iter()
+				.enumerate()
+				.filter_map(|(i, v)| match &v.action {
+					router::ConfigAction::Forward(v) => Some((format!("filters[{}].action.forward", i), v.clone())),
+					_ => None
+				})
+				.collect(),
+			Self::Mirror(cfg)    => vec![
+				("next".to_string(), cfg.next.clone()),
+				("shadow".to_string(), cfg.shadow.clone())
+			],
+			Self::Socket(cfg)    => std::iter::once(("processor".to_string(), cfg.processor.clone()))
+				.chain(cfg.socket.smtp.as_ref()
+					.and_then(|v| v.auth.clone())
+					.map(|v| ("smtp.auth".to_string(), v)))
+				.collect(),
+			Self::Split(cfg)     => cfg.branches.iter()
+				.enumerate()
+				.map(|(i, v)| (format!("branches[{}].next", i), v.next.clone()))
+				.collect(),
+			_ => Vec::new()
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocket {
-	pub pipe:  Option<String>,
+	/// A Unix domain socket.
+	pub pipe:      Option<ConfigSocketPipe>,
+	/// Adopts a listening socket passed with `LISTEN_FDS`, e.g. by systemd socket activation, by
+	/// its name in `LISTEN_FDNAMES` or its index.
+	pub listen_fd: Option<String>,
 	pub udp:   Option<ConfigSocketUdp>,
 	pub tcp:   Option<ConfigSocketTcp>,
 	pub quic:  Option<ConfigSocketQuic>,
 	pub tls:   Option<ConfigSocketTls>,
 	pub http1: Option<ConfigSocketHttp1>,
 	pub http3: Option<ConfigSocketHttp3>,
+	/// Serves DNS over `udp` and `tcp`, or over TLS if `tls` is set.
+	pub dns:   Option<ConfigSocketDns>,
+	/// Accepts mail over SMTP, or relays it to an SMTP server in a `relay` module.
+	pub smtp:  Option<ConfigSocketSmtp>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSocketPipe {
+	pub path:  PathBuf,
+	/// The permissions of the socket file, e.g. `0o660`.
+	pub mode:  Option<u32>,
+	/// The user that owns the socket file, by name or ID.
+	pub owner: Option<String>,
+	/// The group that owns the socket file, by name or ID.
+	pub group: Option<String>
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocketUdp {
 	pub host: Option<String>,
 	pub port: Option<u16>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocketTcp {
 	pub host:           Option<String>,
@@ -100,9 +326,12 @@ pub struct ConfigSocketTcp {
 	pub ttl:            Option<u32>,
 	pub ipv6_flow_info: Option<u32>,
 	pub ipv6_scope_id:  Option<u32>,
+	/// Sets `SO_REUSEPORT`, so other processes can listen on the same address.
+	#[serde(default)]
+	pub reuse_port:     bool,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocketQuic {
 	#[serde(default = "usize_max")]
@@ -113,16 +342,65 @@ pub struct ConfigSocketQuic {
 	pub initial_connection_window_size: usize
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocketTls {
-	pub private_key: String,
-	pub certificate: String,
+	/// The default certificate, used if no other certificate matches the SNI of the client.
+	pub private_key:   Option<String>,
+	pub certificate:   Option<String>,
+	/// Additional certificates, selected by SNI.
+	#[serde(default)]
+	pub certificates:  Vec<ConfigSocketTlsCertificate>,
+	#[serde(default)]
+	pub alpn:          bool,
+	/// The enabled protocol versions, all supported versions if empty.
+	#[serde(default)]
+	pub versions:      Vec<ConfigSocketTlsVersion>,
+	/// The enabled cipher suites by IANA name, all supported suites if empty.
+	#[serde(default)]
+	pub cipher_suites: Vec<String>,
+	/// Reload certificates and private keys when their files change.
+	#[serde(default = "default_true")]
+	pub reload:        bool,
+	/// Requests or requires client certificates.
+	pub client_auth:   Option<ConfigSocketTlsClientAuth>
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSocketTlsClientAuth {
+	/// Rejects clients without a valid certificate, otherwise anonymous clients are accepted.
+	#[serde(default = "default_true")]
+	pub required:       bool,
+	/// PEM files with the CA certificates client certificates must be issued by.
+	pub ca_certs:       Vec<String>,
+	/// PEM or DER files with certificate revocation lists.
 	#[serde(default)]
-	pub alpn:        bool,
+	pub crls:           Vec<String>,
+	/// A header the identity of the client is sent to backends in, e.g. `x-forwarded-client-cert`.
+	pub forward_header: Option<String>
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSocketTlsCertificate {
+	/// Server names this certificate is used for, `*.` prefixes match any single label. If empty,
+	/// the names the certificate is valid for are used.
+	#[serde(default)]
+	pub names:       Vec<String>,
+	pub private_key: String,
+	pub certificate: String
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, JsonSchema)]
+pub enum ConfigSocketTlsVersion {
+	#[serde(rename = "1.2", alias = "TLSv1.2", alias = "TLSv1_2")]
+	Tls12,
+	#[serde(rename = "1.3", alias = "TLSv1.3", alias = "TLSv1_3")]
+	Tls13
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocketHttp1 {
 	#[serde(default = "usize_max")]
@@ -145,7 +423,7 @@ pub struct ConfigSocketHttp1 {
 	pub max_payload_length:      usize,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSocketHttp3 {
 	#[serde(default = "usize_max")]
@@ -158,6 +436,60 @@ pub struct ConfigSocketHttp3 {
 	pub num_placeholders:     usize,
 }
 
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSocketDns {
+	/// The maximum size of UDP responses to clients that support EDNS, larger responses are
+	/// truncated. Other clients receive at most 512 bytes.
+	#[serde(default = "default_dns_udp_payload_size")]
+	pub max_udp_payload_size: u16,
+	/// Closes TCP connections without queries for this long.
+	pub idle_timeout:         Option<Duration>
+}
+
+fn default_dns_udp_payload_size() -> u16 {
+	1232
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSocketSmtp {
+	/// The name of this host in greetings, `EHLO` and `Received` headers.
+	#[serde(default = "default_smtp_hostname")]
+	pub hostname:       String,
+	/// The maximum size of a message in bytes, larger messages are rejected.
+	#[serde(default = "default_smtp_max_size")]
+	pub max_size:       usize,
+	/// The maximum number of recipients of a message.
+	#[serde(default = "default_smtp_max_recipients")]
+	pub max_recipients: usize,
+	/// Offers `STARTTLS` with `tls` instead of accepting TLS connections only.
+	#[serde(default)]
+	pub starttls:       bool,
+	/// The `auth` module that verifies the credentials of `AUTH`, which is only offered over TLS.
+	pub auth:           Option<String>,
+	/// Rejects mail of clients that are not authenticated, e.g. for submission.
+	#[serde(default)]
+	pub require_auth:   bool,
+	/// Offers `AUTH` without TLS, e.g. on a Unix domain socket.
+	#[serde(default)]
+	pub insecure_auth:  bool,
+	/// Closes connections without commands for this long.
+	pub idle_timeout:   Option<Duration>
+}
+
+fn default_smtp_hostname() -> String {
+	"localhost".to_string()
+}
+
+fn default_smtp_max_size() -> usize {
+	0x200_0000
+}
+
+fn default_smtp_max_recipients() -> usize {
+	100
+}
+
 #[derive(Clone, Debug)]
 pub enum StringMatcher {
 	Ignore,
@@ -192,6 +524,19 @@ impl Default for StringMatcher {
 	}
 }
 
+impl JsonSchema for StringMatcher {
+	fn schema_name() -> String {
+		"StringMatcher".to_string()
+	}
+	
+	fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		serde_json::from_value(serde_json::json!({
+			"type":        ["string", "boolean"],
+			"description": "A boolean to match presence, a string with optional leading/trailing `*` wildcards or a regular expression enclosed in `###`"
+		})).unwrap()
+	}
+}
+
 impl<'de> serde::Deserialize<'de> for StringMatcher {
 	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
 		struct Visitor;
@@ -216,6 +561,10 @@ impl<'de> serde::Deserialize<'de> for StringMatcher {
 						.map_err(|e| E::custom(format!("expected a regular expression, but {}", e)))?));
 				}
 
+				if v == "*" {
+					return Ok(StringMatcher::Present(true));
+				}
+
 				Ok(match (v.starts_with('*'), v.ends_with('*')) {
 					(true,  true)  => StringMatcher::Contains(v.strip_prefix('*').unwrap().strip_suffix('*').unwrap().to_string()),
 					(true,  false) => StringMatcher::Suffix(v.strip_prefix('*').unwrap().to_string()),
@@ -229,7 +578,72 @@ impl<'de> serde::Deserialize<'de> for StringMatcher {
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// An IP network like `10.0.0.0/8` or `2001:db8::/32`, a single address without prefix length.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Cidr {
+	addr: u128,
+	mask: u128
+}
+
+impl Cidr {
+	/// IPv4 addresses are compared as IPv4-mapped IPv6 addresses.
+	fn to_bits(addr: std::net::IpAddr) -> u128 {
+		match addr {
+			std::net::IpAddr::V4(v) => u128::from_be_bytes(v.to_ipv6_mapped().octets()),
+			std::net::IpAddr::V6(v) => u128::from_be_bytes(v.octets())
+		}
+	}
+	
+	pub fn contains(&self, addr: std::net::IpAddr) -> bool {
+		Self::to_bits(addr) & self.mask == self.addr
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = String;
+	
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		let (addr, len) = s.split_once('/').map_or((s, None), |(addr, len)| (addr, Some(len)));
+		let addr = std::net::IpAddr::from_str(addr)
+			.map_err(|_| format!("`{}` is not a valid IP address", addr))?;
+		let (max, offset) = match addr {
+			std::net::IpAddr::V4(_) => (32, 96),
+			std::net::IpAddr::V6(_) => (128, 0)
+		};
+		let len = match len.map(u32::from_str) {
+			None => max,
+			Some(Ok(v)) if v <= max => v,
+			Some(_) => return Err(format!("`{}` has an invalid prefix length", s))
+		};
+		let mask = match len + offset {
+			0 => 0,
+			v => !0u128 << (128 - v)
+		};
+		
+		Ok(Self { addr: Self::to_bits(addr) & mask, mask })
+	}
+}
+
+impl JsonSchema for Cidr {
+	fn schema_name() -> String {
+		"Cidr".to_string()
+	}
+	
+	fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		serde_json::from_value(serde_json::json!({
+			"type":        "string",
+			"description": "An IP network like `10.0.0.0/8`, or a single IP address"
+		})).unwrap()
+	}
+}
+
+impl<'de> serde::Deserialize<'de> for Cidr {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+		Self::from_str(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigRateLimits {
 	#[serde(default = "usize_max")]
@@ -264,6 +678,10 @@ fn usize_zero() -> usize {
 	0
 }
 
+fn default_true() -> bool {
+	true
+}
+
 type HeaderId<T> = std::result::Result<T, String>;
 
 fn http_parse_header_ids<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<HeaderId<net::http::HeaderId>>, D::Error> {
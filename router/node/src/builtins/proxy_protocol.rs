@@ -0,0 +1,317 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! PROXY protocol headers and `Forwarded`/`X-Forwarded-*` headers, which carry the address of the
+//! client through proxies.
+
+use {
+	super::*,
+	std::{io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}},
+	net::http,
+	smol::io::{AsyncRead, AsyncReadExt}
+};
+
+const SIGNATURE_V2: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const MAX_LEN_V1:   usize = 107;
+
+pub(super) const HEADER_FORWARDED:         &str = "forwarded";
+pub(super) const HEADER_X_FORWARDED_FOR:   &str = "x-forwarded-for";
+pub(super) const HEADER_X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+pub(super) const HEADER_X_FORWARDED_HOST:  &str = "x-forwarded-host";
+
+/// The source and destination address of a proxied connection, `None` if the proxy did not send
+/// them, e.g. for its own health checks.
+pub(super) type ProxiedAddrs = Option<(SocketAddr, SocketAddr)>;
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads a PROXY protocol v1 or v2 header, nothing after it.
+pub(super) async fn read_header(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<ProxiedAddrs> {
+	let mut head = [0u8; 16];
+	stream.read_exact(&mut head[..1]).await?;
+	
+	// v1 headers have no length, so they are read up to the CRLF
+	if head[0] == b'P' {
+		let mut line = vec![head[0]];
+		
+		while !line.ends_with(b"\r\n") {
+			if line.len() >= MAX_LEN_V1 {
+				return Err(invalid("PROXY protocol v1 header too long"));
+			}
+			
+			let mut b = [0u8];
+			stream.read_exact(&mut b).await?;
+			line.push(b[0]);
+		}
+		
+		return parse_v1(&line);
+	}
+	
+	stream.read_exact(&mut head[1..]).await?;
+	let mut body = vec![0u8; u16::from_be_bytes([head[14], head[15]]) as usize];
+	stream.read_exact(&mut body).await?;
+	parse_v2(&head, &body)
+}
+
+pub(super) fn parse_v1(line: &[u8]) -> io::Result<ProxiedAddrs> {
+	let e = || invalid("invalid PROXY protocol v1 header");
+	let line = std::str::from_utf8(line).ok()
+		.and_then(|v| v.strip_suffix("\r\n"))
+		.ok_or_else(e)?;
+	let mut parts = line.split(' ');
+	
+	match (parts.next(), parts.next()) {
+		(Some("PROXY"), Some("UNKNOWN")) => Ok(None),
+		(Some("PROXY"), Some("TCP4" | "TCP6")) => {
+			let parts = parts.collect::<Vec<_>>();
+			let (src, dst, src_port, dst_port) = match parts[..] {
+				[src, dst, src_port, dst_port] => (src, dst, src_port, dst_port),
+				_ => return Err(e())
+			};
+			
+			Ok(Some((
+				SocketAddr::new(src.parse().map_err(|_| e())?, src_port.parse().map_err(|_| e())?),
+				SocketAddr::new(dst.parse().map_err(|_| e())?, dst_port.parse().map_err(|_| e())?)
+			)))
+		}
+		_ => Err(e())
+	}
+}
+
+pub(super) fn parse_v2(head: &[u8; 16], body: &[u8]) -> io::Result<ProxiedAddrs> {
+	if head[..12] != SIGNATURE_V2 || head[12] >> 4 != 2 {
+		return Err(invalid("invalid PROXY protocol v2 header"));
+	}
+	
+	let port = |i: usize| u16::from_be_bytes([body[i], body[i + 1]]);
+	
+	match (head[12] & 0xF, head[13] >> 4) {
+		// LOCAL, the connection was opened by the proxy itself
+		(0, _) => Ok(None),
+		(1, 1) if body.len() >= 12 => {
+			let addr = |i: usize| IpAddr::V4(Ipv4Addr::new(body[i], body[i + 1], body[i + 2], body[i + 3]));
+			Ok(Some((SocketAddr::new(addr(0), port(8)), SocketAddr::new(addr(4), port(10)))))
+		}
+		(1, 2) if body.len() >= 36 => {
+			let addr = |i: usize| IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&body[i..i + 16]).unwrap()));
+			Ok(Some((SocketAddr::new(addr(0), port(32)), SocketAddr::new(addr(16), port(34)))))
+		}
+		// unspecified or UNIX addresses
+		(1, 0 | 3) => Ok(None),
+		_ => Err(invalid("invalid PROXY protocol v2 header"))
+	}
+}
+
+/// The addresses in the same family, IPv4 addresses are mapped to IPv6 if the other is IPv6.
+fn same_family(src: SocketAddr, dst: SocketAddr) -> (SocketAddr, SocketAddr) {
+	let v6 = |v: SocketAddr| match v.ip() {
+		IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), v.port()),
+		IpAddr::V6(_)  => v
+	};
+	
+	match src.is_ipv4() == dst.is_ipv4() {
+		true  => (src, dst),
+		false => (v6(src), v6(dst))
+	}
+}
+
+pub(super) fn encode_v1(addrs: ProxiedAddrs) -> Vec<u8> {
+	match addrs.map(|(src, dst)| same_family(src, dst)) {
+		Some((src, dst)) => format!(
+			"PROXY {} {} {} {} {}\r\n",
+			if src.is_ipv4() { "TCP4" } else { "TCP6" },
+			src.ip(),
+			dst.ip(),
+			src.port(),
+			dst.port()
+		).into_bytes(),
+		None => b"PROXY UNKNOWN\r\n".to_vec()
+	}
+}
+
+pub(super) fn encode_v2(addrs: ProxiedAddrs) -> Vec<u8> {
+	let mut buf = SIGNATURE_V2.to_vec();
+	
+	match addrs.map(|(src, dst)| same_family(src, dst)) {
+		Some((src, dst)) => {
+			let mut body = Vec::with_capacity(36);
+			
+			for addr in [src, dst] {
+				match addr.ip() {
+					IpAddr::V4(v) => body.extend_from_slice(&v.octets()),
+					IpAddr::V6(v) => body.extend_from_slice(&v.octets())
+				}
+			}
+			
+			body.extend_from_slice(&src.port().to_be_bytes());
+			body.extend_from_slice(&dst.port().to_be_bytes());
+			buf.extend_from_slice(&[0x21, if src.is_ipv4() { 0x11 } else { 0x21 }]);
+			buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+			buf.extend_from_slice(&body);
+		}
+		None => buf.extend_from_slice(&[0x20, 0x00, 0x00, 0x00])
+	}
+	
+	buf
+}
+
+/// Whether `name` is one of the headers that carry the address of the client.
+pub(super) fn is_header(name: &str) -> bool {
+	[HEADER_FORWARDED, HEADER_X_FORWARDED_FOR, HEADER_X_FORWARDED_PROTO, HEADER_X_FORWARDED_HOST].iter()
+		.any(|v| v.eq_ignore_ascii_case(name))
+}
+
+/// Parses a node of a `Forwarded` or `X-Forwarded-For` header, e.g. `"[2001:db8::1]:4711"`.
+/// Unknown and obfuscated nodes are `None`.
+fn parse_node(v: &str) -> Option<SocketAddr> {
+	let v = v.trim().trim_matches('"');
+	
+	v.parse::<SocketAddr>().ok()
+		.or_else(|| v.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok()
+			.map(|ip| SocketAddr::new(ip, 0)))
+}
+
+/// The address of the client in the `Forwarded` or, if there are none, `X-Forwarded-For` headers
+/// of a request of `peer`. Proxies in `trusted` are skipped from the right, the first other node is
+/// the client. Returns `None` if there are no such headers or the client is unknown.
+pub(super) fn client_addr(headers: &[http::Header], peer: SocketAddr, trusted: &[Cidr]) -> Option<SocketAddr> {
+	let values = |name: &str| headers.iter()
+		.filter_map(|v| match v {
+			http::Header::Custom(k, v) if k.eq_ignore_ascii_case(name) => Some(v.as_str()),
+			_ => None
+		})
+		.flat_map(|v| v.split(','))
+		.collect::<Vec<_>>();
+	
+	let mut nodes = values(HEADER_FORWARDED).into_iter()
+		.filter_map(|element| element.split(';')
+			.filter_map(|pair| pair.split_once('='))
+			.find(|(k, _)| k.trim().eq_ignore_ascii_case("for"))
+			.map(|(_, v)| v))
+		.collect::<Vec<_>>();
+	
+	if nodes.is_empty() {
+		nodes = values(HEADER_X_FORWARDED_FOR);
+	}
+	
+	if nodes.is_empty() {
+		return None;
+	}
+	
+	let mut client = peer;
+	
+	for node in nodes.iter().rev() {
+		if !trusted.iter().any(|v| v.contains(client.ip())) {
+			break;
+		}
+		
+		client = parse_node(node)?;
+	}
+	
+	Some(client)
+}
+
+/// An element of a `Forwarded` header, with IPv6 addresses quoted as required.
+pub(super) fn forwarded_element(addr: SocketAddr, proto: &str, host: Option<&str>) -> String {
+	let mut buf = match addr.ip() {
+		IpAddr::V4(v) => format!("for={}", v),
+		IpAddr::V6(v) => format!("for=\"[{}]\"", v)
+	};
+	
+	buf.push_str(";proto=");
+	buf.push_str(proto);
+	
+	if let Some(host) = host {
+		buf.push_str(";host=\"");
+		buf.push_str(host);
+		buf.push('"');
+	}
+	
+	buf
+}
+
+/// Appends `value` to the list in the header `name`, or adds the header.
+pub(super) fn append_header(headers: &mut Vec<http::Header>, name: &str, value: &str) {
+	for header in headers.iter_mut() {
+		if let http::Header::Custom(k, v) = header {
+			if k.eq_ignore_ascii_case(name) {
+				v.push_str(", ");
+				v.push_str(value);
+				return;
+			}
+		}
+	}
+	
+	headers.push(http::Header::Custom(name.to_string(), value.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn addr(v: &str) -> SocketAddr {
+		v.parse().unwrap()
+	}
+	
+	#[test]
+	fn proxy_protocol() {
+		let addrs = Some((addr("192.0.2.1:4711"), addr("198.51.100.2:443")));
+		
+		assert_eq!(encode_v1(addrs), b"PROXY TCP4 192.0.2.1 198.51.100.2 4711 443\r\n");
+		assert_eq!(parse_v1(&encode_v1(addrs)).unwrap(), addrs);
+		assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+		assert!(parse_v1(b"PROXY TCP4 192.0.2.1\r\n").is_err());
+		
+		for addrs in [addrs, Some((addr("[2001:db8::1]:4711"), addr("198.51.100.2:443"))), None] {
+			let buf = encode_v2(addrs);
+			let head = <[u8; 16]>::try_from(&buf[..16]).unwrap();
+			let expected = addrs.map(|(src, dst)| same_family(src, dst));
+			assert_eq!(parse_v2(&head, &buf[16..]).unwrap(), expected);
+		}
+	}
+	
+	#[test]
+	fn forwarded() {
+		let trusted = ["10.0.0.0/8".parse::<Cidr>().unwrap()];
+		let peer = addr("10.0.0.1:1234");
+		let header = |k: &str, v: &str| http::Header::Custom(k.to_string(), v.to_string());
+		
+		let headers = [header("Forwarded", "for=192.0.2.60;proto=http, for=\"[2001:db8::1]:4711\"")];
+		assert_eq!(client_addr(&headers, peer, &trusted), Some(addr("[2001:db8::1]:4711")));
+		
+		let headers = [header("X-Forwarded-For", "192.0.2.60, 10.0.0.2")];
+		assert_eq!(client_addr(&headers, peer, &trusted), Some(addr("192.0.2.60:0")));
+		
+		// the client of an untrusted peer is the peer itself
+		assert_eq!(client_addr(&headers, addr("192.0.2.1:1"), &trusted), Some(addr("192.0.2.1:1")));
+		assert_eq!(client_addr(&[header("Forwarded", "for=unknown")], peer, &trusted), None);
+		assert_eq!(client_addr(&[], peer, &trusted), None);
+		
+		let mut headers = vec![header("X-Forwarded-For", "192.0.2.60")];
+		append_header(&mut headers, HEADER_X_FORWARDED_FOR, "10.0.0.1");
+		assert_eq!(headers, vec![header("X-Forwarded-For", "192.0.2.60, 10.0.0.1")]);
+		assert_eq!(forwarded_element(addr("[2001:db8::1]:1"), "https", Some("a.example")),
+			"for=\"[2001:db8::1]\";proto=https;host=\"a.example\"");
+	}
+}
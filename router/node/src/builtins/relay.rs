@@ -24,9 +24,10 @@ use std::io;
 use {
 	super::*,
 	crate::{interfaces::*, utils::*},
-	std::time::Duration,
+	std::time::{Duration, Instant},
 	net::{
 		http::{self, traits::{AsyncSharedConnectionExt, AsyncStreamExt}},
+		smtp::{self, traits::{AsyncClientConnection, AsyncClientConnectionExt}},
 		utils::connection::*,
 	},
 	smol::{io::{AsyncReadExt, AsyncWriteExt}}
@@ -34,8 +35,9 @@ use {
 
 const LOCALHOST:        &str = "localhost";
 const DEFAULT_BUF_SIZE: usize = 0x1000;
+const DEFAULT_UPGRADE_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct Config {
 	#[serde(flatten)]
 	pub socket:         super::ConfigSocket,
@@ -43,7 +45,89 @@ pub struct Config {
 	#[serde(default = "usize_zero")]
 	pub retries:        usize,
 	pub retry_interval: Option<Duration>,
-	pub retry_backoff:  Option<Duration>
+	pub retry_backoff:  Option<Duration>,
+	/// Closes upgraded connections, e.g. WebSockets, after no data was sent in either direction.
+	pub upgrade_idle_timeout: Option<Duration>,
+	/// Timeouts of requests to the backend, a `504` is sent if one expires before the response.
+	#[serde(default)]
+	pub timeouts:        ConfigTimeouts,
+	/// Rejects requests with a `503` while the backend is failing.
+	pub circuit_breaker: Option<ConfigCircuitBreaker>,
+	/// How the address of the client is sent to the backend.
+	#[serde(default)]
+	pub forward_client:  Vec<ConfigForwardClient>
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigForwardClient {
+	/// A `Forwarded` header.
+	Forwarded,
+	/// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers.
+	XForwarded,
+	/// A PROXY protocol v1 header, which requires a connection per request.
+	ProxyProtocolV1,
+	/// A PROXY protocol v2 header, which requires a connection per request.
+	ProxyProtocolV2
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigTimeouts {
+	/// The time until a connection to the backend is established.
+	pub connect:    Option<Duration>,
+	/// The time until the first byte of the response is received, after the request was sent.
+	pub first_byte: Option<Duration>,
+	/// The time until the response is complete.
+	pub total:      Option<Duration>,
+	/// The time without progress while transmitting a body.
+	pub idle:       Option<Duration>
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigCircuitBreaker {
+	/// Opens the circuit after this many consecutive failures.
+	#[serde(default = "default_consecutive_failures")]
+	pub consecutive_failures: usize,
+	/// Opens the circuit if this fraction of the requests in `window` failed.
+	#[serde(default = "default_error_rate")]
+	pub error_rate:           f64,
+	/// The number of requests in `window` before `error_rate` applies.
+	#[serde(default = "default_min_requests")]
+	pub min_requests:         usize,
+	#[serde(default = "default_window")]
+	pub window:               Duration,
+	/// The time the circuit stays open before trial requests are sent.
+	#[serde(default = "default_open_duration")]
+	pub open_duration:        Duration,
+	/// The number of successful trial requests that close the circuit.
+	#[serde(default = "default_half_open_requests")]
+	pub half_open_requests:   usize
+}
+
+fn default_consecutive_failures() -> usize {
+	5
+}
+
+fn default_error_rate() -> f64 {
+	0.5
+}
+
+fn default_min_requests() -> usize {
+	20
+}
+
+fn default_window() -> Duration {
+	Duration::from_secs(10)
+}
+
+fn default_open_duration() -> Duration {
+	Duration::from_secs(30)
+}
+
+fn default_half_open_requests() -> usize {
+	1
 }
 
 pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
@@ -59,19 +143,37 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 		)
 	}
 	
-	match cfg.socket {
-		ConfigSocket { pipe: None, tcp: Some(tcp), udp: None, tls: None, http1: Some(http), .. } =>
+	match &cfg.socket {
+		ConfigSocket { pipe, tcp, udp: None, tls: None, smtp: Some(cfg_smtp), .. } => {
+			let target = match (pipe, tcp) {
+				(Some(pipe), None) => transport::Target::Unix(pipe.path.clone()),
+				(None, Some(tcp))  => transport::Target::Tcp(endpoint(tcp, smtp::DEFAULT_PORT)),
+				_ => return Err("expected either `tcp` or `pipe`".into())
+			};
+			crate::add_component::<MailHandler>(id, Box::new(ModuleSmtp {
+				name,
+				target,
+				hostname: cfg_smtp.hostname.clone(),
+				timeouts: cfg.timeouts.clone()
+			}));
+		}
+		ConfigSocket { pipe, tcp, udp: None, tls: None, http1: Some(_), .. } => {
+			let target = match (pipe, tcp) {
+				(Some(pipe), None) => transport::Target::Unix(pipe.path.clone()),
+				(None, Some(tcp))  => transport::Target::Tcp(endpoint(tcp, net::http::v1::DEFAULT_PORT_TLS)),
+				_ => return Err("expected either `tcp` or `pipe`".into())
+			};
 			crate::add_component::<HttpStreamHandler>(id, Box::new(ModuleShared::new(
-				name, buf_len, net::http::traits::DynAsyncSharedConnector::new(
+				name, target.clone(), &cfg, net::http::traits::DynAsyncSharedConnector::new(
 					net::http::v1::AsyncSharedConnector::new(
 						net::http::v1::AsyncConnector::new(
-							net::buffered::AsyncConnector::new(
-								net::tcp::AsyncConnector::new(
-									endpoint(&tcp, net::http::v1::DEFAULT_PORT_TLS))))))).await?)),
-		ConfigSocket { pipe: None, tcp: Some(tcp), udp: None, .. } =>
+							net::buffered::AsyncConnector::new(target))))).await?));
+		}
+		ConfigSocket { pipe: None, tcp: Some(tcp), udp: None, .. } => {
 			crate::add_component::<ByteStreamHandler>(id, Box::new(Module::new(
 				name, buf_len, net::tcp::AsyncConnector::new(
-					endpoint(&tcp, 1024))))),
+					endpoint(tcp, 1024)))));
+		}
 		_ => return Err("invalid config".into())
 	};
 	
@@ -99,90 +201,945 @@ impl<T: AsyncConnector<Connection = smol::net::TcpStream>> StreamHandler<dyn Asy
 	}
 }
 
+/// Relays mail to an SMTP server. Transactions are only relayed as a whole, so the reply of
+/// the server to the first rejected recipient is the reply to the transaction.
+struct ModuleSmtp {
+	name:     String,
+	target:   transport::Target,
+	hostname: String,
+	timeouts: ConfigTimeouts
+}
+
+impl ModuleSmtp {
+	/// Returns the reply of the server to the message, or the first negative reply before.
+	async fn relay(&self, transaction: &MailTransaction) -> Result<(u16, String)> {
+		let stream = timeout(self.timeouts.connect, self.target.connect()).await?;
+		let mut conn = smtp::AsyncClientConnection::new(net::buffered::AsyncBufStream::new(stream));
+		
+		let greeting = smtp::parse_reply(&conn.read_response().await?)?;
+		if greeting.0 != 220 {
+			return Ok(greeting);
+		}
+		
+		let (code, extensions) = exchange_smtp(&mut conn, &smtp::Command::Ehlo(Cow::Borrowed(&self.hostname))).await?;
+		if code != 250 {
+			return Ok(quit_smtp(&mut conn, (code, extensions)).await);
+		}
+		
+		// 8-bit messages are sent as they are to servers without `8BITMIME`
+		let is_8bit = !transaction.message.is_ascii();
+		let has_8bitmime = extensions.lines().any(|v| v.trim().eq_ignore_ascii_case("8BITMIME"));
+		let mut commands = vec![smtp::Command::Mail(Cow::Owned(match is_8bit && has_8bitmime {
+			true  => format!("<{}> BODY=8BITMIME", &transaction.sender),
+			false => format!("<{}>", &transaction.sender)
+		}))];
+		commands.extend(transaction.recipients.iter()
+			.map(|v| smtp::Command::Rcpt(Cow::Owned(format!("<{}>", v)))));
+		commands.push(smtp::Command::Data);
+		
+		for command in &commands {
+			let reply = exchange_smtp(&mut conn, command).await?;
+			
+			if reply.0 >= 400 {
+				return Ok(quit_smtp(&mut conn, reply).await);
+			}
+		}
+		
+		conn.write_data(&transaction.message).await?;
+		let reply = smtp::parse_reply(&conn.read_response().await?)?;
+		Ok(quit_smtp(&mut conn, reply).await)
+	}
+}
+
+impl StreamHandler<MailTransaction> for ModuleSmtp {
+	fn accept<'a>(&'a self, transaction: &'static mut MailTransaction) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			transaction.reply = Some(match timeout(self.timeouts.total, self.relay(transaction)).await {
+				Ok(v) => v,
+				Err(e) => {
+					log::error!("backend `{}` (smtp://{}): failed to relay mail: {}", &self.name, &self.target, e.display());
+					(451, "4.4.1 Upstream not available".to_string())
+				}
+			});
+			Ok(())
+		})
+	}
+}
+
+async fn exchange_smtp(conn: &mut impl AsyncClientConnection, command: &smtp::Command<'_>) -> Result<(u16, String)> {
+	conn.write_command(command).await?;
+	Ok(smtp::parse_reply(&conn.read_response().await?)?)
+}
+
+/// Ends a session, errors are ignored since `reply` is already known.
+async fn quit_smtp(conn: &mut impl AsyncClientConnection, reply: (u16, String)) -> (u16, String) {
+	let _ = exchange_smtp(conn, &smtp::Command::Quit).await;
+	reply
+}
+
 struct ModuleShared<T: AsyncConnector> {
-	name:       String,
-	buf_len:    usize,
-	connector:  T,
-	connection: smol::lock::RwLock<T::Connection>
+	name:            String,
+	target:          transport::Target,
+	endpoint:        String,
+	buf_len:         usize,
+	idle_timeout:    Duration,
+	connect_timeout: Option<Duration>,
+	timeouts:        RequestTimeouts,
+	breaker:         Option<CircuitBreaker>,
+	forward_client:  Vec<ConfigForwardClient>,
+	telemetry:       RelayTelemetry,
+	connector:       T,
+	connection:      smol::lock::RwLock<T::Connection>
+}
+
+struct RelayTelemetry {
+	timeouts:         otel_mrt::BoundInstrument<usize>,
+	circuit_state:    otel_mrt::BoundInstrument<usize>,
+	circuit_rejected: otel_mrt::BoundInstrument<usize>
+}
+
+impl RelayTelemetry {
+	fn new(name: &str, endpoint: &str) -> Self {
+		let rt = otel_mrt::runtime();
+		let labels = vec![
+			(Cow::Borrowed("module"), otel_mrt::AnyValue::String(name.to_string())),
+			(Cow::Borrowed("endpoint"), otel_mrt::AnyValue::String(endpoint.to_string()))
+		];
+		
+		Self {
+			timeouts:         rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("relay_request_timeouts")
+				.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true))
+				.bind(labels.clone()),
+			circuit_state:    rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("relay_circuit_state")
+				.aggregation_last_value())
+				.bind(labels.clone()),
+			circuit_rejected: rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+				.name_str("relay_circuit_rejected")
+				.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true))
+				.bind(labels)
+		}
+	}
 }
 
 impl<T: AsyncConnector> ModuleShared<T> {
-	async fn new(name: String, buf_len: usize, connector: T) -> io::Result<Self> {
-		let connection = smol::lock::RwLock::new(connector.connect().await?);
-		Ok(Self { name, buf_len, connector, connection })
+	async fn new(name: String, target: transport::Target, cfg: &Config, connector: T) -> io::Result<Self> {
+		let connection = smol::lock::RwLock::new(timeout(cfg.timeouts.connect, connector.connect()).await?);
+		let endpoint = target.to_string();
+		let telemetry = RelayTelemetry::new(&name, &endpoint);
+		telemetry.circuit_state.record(CircuitState::Closed as _);
+		
+		Ok(Self {
+			name,
+			target,
+			endpoint,
+			buf_len:         cfg.buf_len.unwrap_or(DEFAULT_BUF_SIZE),
+			idle_timeout:    cfg.upgrade_idle_timeout.unwrap_or(DEFAULT_UPGRADE_IDLE_TIMEOUT),
+			connect_timeout: cfg.timeouts.connect,
+			timeouts:        RequestTimeouts {
+				first_byte: cfg.timeouts.first_byte,
+				total:      cfg.timeouts.total,
+				idle:       cfg.timeouts.idle
+			},
+			breaker:         cfg.circuit_breaker.clone().map(|cfg| CircuitBreaker::new(cfg, Instant::now())),
+			forward_client:  cfg.forward_client.clone(),
+			telemetry,
+			connector,
+			connection
+		})
+	}
+	
+	/// Response headers of the backend, with the backend for access logs.
+	fn response_headers(&self, mut headers: Vec<http::Header>) -> Vec<http::Header> {
+		headers.push(http::Header::Custom(HEADER_UPSTREAM.to_string(), self.endpoint.clone()));
+		headers
+	}
+	
+	/// Records the response of the backend in the client span of the request.
+	fn record_response(&self, span: &otel_mrt::tracing::Span, headers: &[http::Header]) -> Option<http::Status> {
+		span.add_event("response headers received", std::iter::empty(), None);
+		let status = headers.iter().find_map(http::Header::as_status).copied()?;
+		span.set_attribute("http.status_code", otel_mrt::AnyValue::Int(status as u16 as _));
+		
+		if status as u16 >= 500 {
+			span.set_status(otel_mrt::StatusCode::Error, None);
+		}
+		
+		Some(status)
+	}
+	
+	/// Adds the `Forwarded` or `X-Forwarded-*` headers for the client, after those of trusted
+	/// proxies before this node.
+	fn forward_headers(&self, headers: &mut Vec<http::Header>, client: &ConnectionInfo) {
+		let addr = match client.addr {
+			Some(v) => v,
+			None    => return
+		};
+		
+		let proto = match client.tls_version {
+			Some(_) => "https",
+			None    => "http"
+		};
+		
+		let host = headers.iter().find_map(http::Header::as_host).cloned();
+		let has_header = |headers: &[http::Header], name: &str| headers.iter()
+			.any(|v| v.name_v1().eq_ignore_ascii_case(name));
+		
+		for v in &self.forward_client {
+			match v {
+				ConfigForwardClient::Forwarded => proxy_protocol::append_header(
+					headers,
+					proxy_protocol::HEADER_FORWARDED,
+					&proxy_protocol::forwarded_element(addr, proto, host.as_deref())
+				),
+				ConfigForwardClient::XForwarded => {
+					proxy_protocol::append_header(headers, proxy_protocol::HEADER_X_FORWARDED_FOR, &addr.ip().to_string());
+					
+					// a trusted proxy before this node knows better
+					if !has_header(headers, proxy_protocol::HEADER_X_FORWARDED_PROTO) {
+						headers.push(http::Header::Custom(proxy_protocol::HEADER_X_FORWARDED_PROTO.to_string(), proto.to_string()));
+					}
+					
+					if let (Some(host), false) = (&host, has_header(headers, proxy_protocol::HEADER_X_FORWARDED_HOST)) {
+						headers.push(http::Header::Custom(proxy_protocol::HEADER_X_FORWARDED_HOST.to_string(), host.clone()));
+					}
+				}
+				ConfigForwardClient::ProxyProtocolV1 | ConfigForwardClient::ProxyProtocolV2 => ()
+			}
+		}
+	}
+	
+	fn buf(&self) -> Vec<u8> {
+		let mut buf = Vec::with_capacity(self.buf_len);
+		unsafe { buf.set_len(self.buf_len) }; // SAFE: len matches capacity
+		buf
+	}
+}
+
+impl<T: AsyncConnector<Connection = http::traits::BoxedAsyncSharedConnection>> ModuleShared<T> {
+	/// Opens a connection with a PROXY protocol header for the client, if configured. The header
+	/// applies to all requests of a connection, so it cannot be shared.
+	async fn connect_proxied(&self, client: &ConnectionInfo) -> io::Result<Option<T::Connection>> {
+		let addrs = client.addr.zip(client.local_addr);
+		let header = match self.forward_client.iter().find_map(|v| match v {
+			ConfigForwardClient::ProxyProtocolV1 => Some(proxy_protocol::encode_v1(addrs)),
+			ConfigForwardClient::ProxyProtocolV2 => Some(proxy_protocol::encode_v2(addrs)),
+			_ => None
+		}) {
+			Some(v) => v,
+			None    => return Ok(None)
+		};
+		
+		let mut stream = timeout(self.connect_timeout, self.target.connect()).await?;
+		stream.write_all(&header).await?;
+		
+		let conn: http::traits::BoxedAsyncSharedConnection = Box::pin(net::http::v1::AsyncSharedConnection::new(
+			net::http::v1::AsyncConnection::new(
+				net::buffered::AsyncBufStream::new(stream))));
+		Ok(Some(conn))
+	}
+	
+	/// Relays a request and answers it with a `504` if a timeout expires before the response.
+	async fn relay(&self, stream_src: &mut dyn http::traits::AsyncStream, headers: Vec<http::Header>, client: &ConnectionInfo, timeouts: RequestTimeouts, span: &otel_mrt::tracing::Span) -> Result<Option<http::Status>> {
+		let mut responded = false;
+		let r = timeout(timeouts.total, self.exchange(&mut *stream_src, headers, client, timeouts, span, &mut responded)).await;
+		
+		match r {
+			Err(e) if is_timeout(&e) => {
+				self.telemetry.timeouts.record(1);
+				span.add_event("timeout", std::iter::empty(), None);
+				
+				// the response can only be replaced if it was not sent yet
+				if responded {
+					return Err(e);
+				}
+				
+				log::debug!("backend `{}`: request timed out", &self.name);
+				span.set_attribute("http.status_code", otel_mrt::AnyValue::Int(http::Status::GatewayTimeout as u16 as _));
+				send_response(stream_src, http::Status::GatewayTimeout).await?;
+				Ok(Some(http::Status::GatewayTimeout))
+			}
+			r => r
+		}
+	}
+	
+	#[allow(clippy::too_many_arguments)]
+	async fn exchange(&self, stream_src: &mut dyn http::traits::AsyncStream, headers: Vec<http::Header>, client: &ConnectionInfo, timeouts: RequestTimeouts, span: &otel_mrt::tracing::Span, responded: &mut bool) -> Result<Option<http::Status>> {
+		let mut buf = self.buf();
+		let proxied = self.connect_proxied(client).await?;
+		let shared;
+		let conn = match &proxied {
+			Some(v) => v,
+			None    => {
+				shared = self.connection.read().await;
+				&*shared
+			}
+		};
+		let id = conn.open().await?;
+		let mut stream_dst = http::AsyncStream(&*conn, id);
+		stream_dst.write_headers(&headers).await?;
+		
+		let method = match headers.iter().find_map(http::Header::as_method) {
+			Some(v) => v.clone(),
+			None    => return send_response(stream_src, http::Status::BadRequest).await
+				.map(|_| Some(http::Status::BadRequest))
+		};
+		
+		let len = headers.iter().find_map(http::Header::as_content_length).copied();
+		let len = match method {
+			// a GET or HEAD may have a body too, which must not be left on the client connection
+			http::Method::Get | http::Method::Head => Some(len.unwrap_or(0)),
+			_ => len
+		};
+		
+		copy_body(&mut *stream_src, &mut stream_dst, len, &mut buf, timeouts.idle).await?;
+		std::mem::drop(headers);
+		stream_dst.flush().await?;
+		let headers = timeout(timeouts.first_byte, stream_dst.read_headers()).await?;
+		let status = self.record_response(span, &headers);
+		*responded = true;
+		stream_src.write_headers(&self.response_headers(headers.clone())).await?;
+		
+		let len = match status {
+			None => {
+				log::warn!("backend `{}`: failed to transmit response: :status header missing", &self.name);
+				return send_response(stream_src, http::Status::InternalServerError).await.map(|_| None);
+			}
+			_ if method == http::Method::Head => Some(0),
+			Some(http::Status::NoContent) => Some(0),
+			_ => headers.iter().find_map(http::Header::as_content_length).copied()
+		};
+		
+		copy_body(&mut stream_dst, &mut *stream_src, len, &mut buf, timeouts.idle).await?;
+		stream_src.flush().await?;
+		Ok(status)
+	}
+	
+	/// Relays a request to upgrade the connection to another protocol. The upgraded connection
+	/// cannot be shared, so a new connection to the backend is used.
+	async fn upgrade(&self, stream_src: &mut dyn http::traits::AsyncStream, headers: Vec<http::Header>, client: &ConnectionInfo, timeouts: RequestTimeouts, span: &otel_mrt::tracing::Span) -> Result<Option<http::Status>> {
+		let conn = match self.connect_proxied(client).await? {
+			Some(v) => v,
+			None    => timeout(self.connect_timeout, self.connector.connect()).await?
+		};
+		let id = conn.open().await?;
+		let mut stream_dst = http::AsyncStream(&conn, id);
+		
+		stream_dst.write_headers(&headers).await?;
+		stream_dst.flush().await?;
+		let headers = match timeout(timeouts.first_byte, stream_dst.read_headers()).await {
+			Ok(v) => v,
+			Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+				self.telemetry.timeouts.record(1);
+				send_response(stream_src, http::Status::GatewayTimeout).await?;
+				return Ok(Some(http::Status::GatewayTimeout));
+			}
+			Err(e) => return Err(e.into())
+		};
+		let status = self.record_response(span, &headers);
+		stream_src.write_headers(&self.response_headers(headers.clone())).await?;
+		
+		match status {
+			Some(http::Status::SwitchingProtocols) => (),
+			// the backend refused to upgrade, this is an ordinary response
+			_ => {
+				let len = headers.iter().find_map(http::Header::as_content_length).copied();
+				copy_body(&mut stream_dst, &mut *stream_src, len, &mut self.buf(), timeouts.idle).await?;
+				stream_src.flush().await?;
+				return Ok(status);
+			}
+		}
+		
+		stream_src.flush().await?;
+		span.add_event("connection upgraded", std::iter::empty(), None);
+		let (mut buf_src, mut buf_dst) = (self.buf(), self.buf());
+		
+		enum Side { Src(usize), Dst(usize) }
+		
+		loop {
+			let read = smol::future::or(
+				smol::future::race(
+					async { stream_src.read(&mut buf_src).await.map(Side::Src) },
+					async { stream_dst.read(&mut buf_dst).await.map(Side::Dst) }),
+				async {
+					smol::Timer::after(self.idle_timeout).await;
+					Err(io::Error::from(io::ErrorKind::TimedOut))
+				}
+			).await;
+			
+			match read {
+				Ok(Side::Src(0)) | Ok(Side::Dst(0)) => break,
+				Ok(Side::Src(len)) => {
+					stream_dst.write_all(&buf_src[..len]).await?;
+					stream_dst.flush().await?;
+				}
+				Ok(Side::Dst(len)) => {
+					stream_src.write_all(&buf_dst[..len]).await?;
+					stream_src.flush().await?;
+				}
+				Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+					log::debug!("backend `{}`: closing idle upgraded connection", &self.name);
+					break;
+				}
+				Err(e) => return Err(e.into())
+			}
+		}
+		
+		stream_dst.close().await?;
+		Ok(status)
 	}
 }
 
 impl<T: AsyncConnector<Connection = http::traits::BoxedAsyncSharedConnection>> StreamHandler<dyn http::traits::AsyncStream> for ModuleShared<T> {
 	fn accept<'a>(&'a self, stream_src: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
 		Box::pin(async move {
-			let mut buf = Vec::with_capacity(self.buf_len);
-			unsafe { buf.set_len(self.buf_len) }; // SAFE: len matches capacity
-			let conn = self.connection.read().await;
-			let id = conn.open().await?;
-			let mut stream_dst = http::AsyncStream(&*conn, id);
-			
 			let mut headers = stream_src.read_headers().await?;
-			stream_dst.write_headers(&headers).await?;
+			let timeouts = RequestTimeouts::from_headers(&headers).or(self.timeouts);
+			let client = ConnectionInfo::from_headers(&headers);
+			// the client identity, connection info, route timeouts and JWT claims are only meant for modules of this node
+			headers.retain(|v| !ClientIdentity::is_header(v.name_v1())
+				&& !ConnectionInfo::is_header(v.name_v1())
+				&& !RequestTimeouts::is_header(v.name_v1())
+				&& !v.name_v1().eq_ignore_ascii_case(HEADER_JWT_CLAIMS));
+			self.forward_headers(&mut headers, &client);
 			
-			let len = match headers.iter().find_map(http::Header::as_method) {
-				None => return send_response(stream_src, http::Status::BadRequest).await,
-				Some(http::Method::Get| http::Method::Head) => Some(0),
-				_ => headers.iter().find_map(http::Header::as_content_length).copied()
-			};
+			let span = start_span(&headers, otel_mrt::SpanParams::new("relay")
+				.kind(otel_mrt::SpanKind::Client)
+				.attribute("net.peer.name", otel_mrt::AnyValue::String(self.endpoint.clone())));
+			// the backend continues the trace of this span
+			span.context().inject(&mut headers);
 			
-			match len {
-				Some(mut len) => while len > 0 {
-					let __len__ = buf.len().min(len);
-					let read = stream_src.read(&mut buf[..__len__]).await?;
-					stream_dst.write_all(&buf[..read]).await?;
-					len -= read;
-				},
-				None => loop {
-					let read = stream_src.read(&mut buf).await?;
-					
-					if read == 0 {
-						break;
-					}
-					
-					stream_dst.write_all(&buf[..read]).await?;
+			let permit = match self.breaker.as_ref().map(|v| v.acquire(Instant::now())) {
+				Some(None) => {
+					self.telemetry.circuit_rejected.record(1);
+					span.add_event("circuit open", std::iter::empty(), None);
+					span.set_status(otel_mrt::StatusCode::Error, None);
+					span.end();
+					discard_body(stream_src).await?;
+					return send_response(stream_src, http::Status::ServiceUnavailable).await;
 				}
-			}
+				Some(Some(v)) => Some(v),
+				None => None
+			};
 			
-			headers.clear();
-			stream_dst.flush().await?;
-			let headers = stream_dst.read_headers().await?;
-			stream_src.write_headers(&headers).await?;
+			let r = match headers.iter().any(|v| matches!(v, http::Header::Upgrade(_))) {
+				true  => self.upgrade(stream_src, headers, &client, timeouts, &span).await,
+				false => self.relay(stream_src, headers, &client, timeouts, &span).await
+			};
 			
-			let len = match headers.iter().find_map(http::Header::as_status) {
-				None => {
-					log::warn!("backend `{}`: failed to transmit response: :status header missing", &self.name);
-					return send_response(stream_src, http::Status::InternalServerError).await;
-				}
-				Some(http::Status::NoContent) => Some(0),
-				_ => headers.iter().find_map(http::Header::as_content_length).copied()
+			let failed = match &r {
+				Ok(status) => status.map_or(true, |v| v as u16 >= 500),
+				Err(_) => true
 			};
 			
-			match len {
-				Some(mut len) => while len > 0 {
-					let __len__ = buf.len().min(len);
-					let read = stream_dst.read(&mut buf[..__len__]).await?;
-					stream_src.write_all(&buf[..read]).await?;
-					len -= read;
-				},
-				None => loop {
-					let read = stream_dst.read(&mut buf).await?;
-					
-					if read == 0 {
-						break;
-					}
-					
-					stream_src.write_all(&buf[..read]).await?;
+			if let Some(permit) = permit {
+				let (state, changed) = permit.complete(!failed, Instant::now());
+				self.telemetry.circuit_state.record(state as _);
+				
+				if changed {
+					log::warn!("backend `{}`: circuit {}", &self.name, state);
 				}
 			}
 			
-			stream_src.flush().await?;
-			Ok(())
+			if r.is_err() {
+				span.set_status(otel_mrt::StatusCode::Error, None);
+			}
+			
+			span.end();
+			r.map(|_| ())
 		})
 	}
-}
\ No newline at end of file
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum CircuitState {
+	Closed   = 0,
+	Open     = 1,
+	HalfOpen = 2
+}
+
+impl std::fmt::Display for CircuitState {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Self::Closed   => "closed",
+			Self::Open     => "open",
+			Self::HalfOpen => "half-open"
+		})
+	}
+}
+
+/// Stops sending requests to a failing backend. The circuit opens after consecutive failures or
+/// if the error rate in a window is too high. Once `open_duration` elapsed, trial requests are
+/// sent, which close the circuit if they succeed or open it again otherwise.
+struct CircuitBreaker {
+	cfg:   ConfigCircuitBreaker,
+	inner: Mutex<CircuitBreakerInner>
+}
+
+struct CircuitBreakerInner {
+	state:                CircuitState,
+	opened:               Instant,
+	consecutive_failures: usize,
+	window_start:         Instant,
+	window_requests:      usize,
+	window_failures:      usize,
+	trials:               usize,
+	trial_successes:      usize
+}
+
+impl CircuitBreakerInner {
+	fn set_state(&mut self, state: CircuitState, now: Instant) {
+		self.state                = state;
+		self.opened               = now;
+		self.consecutive_failures = 0;
+		self.window_start         = now;
+		self.window_requests      = 0;
+		self.window_failures      = 0;
+		self.trials               = 0;
+		self.trial_successes      = 0;
+	}
+}
+
+impl CircuitBreaker {
+	fn new(cfg: ConfigCircuitBreaker, now: Instant) -> Self {
+		Self {
+			cfg,
+			inner: Mutex::new(CircuitBreakerInner {
+				state:                CircuitState::Closed,
+				opened:               now,
+				consecutive_failures: 0,
+				window_start:         now,
+				window_requests:      0,
+				window_failures:      0,
+				trials:               0,
+				trial_successes:      0
+			})
+		}
+	}
+	
+	/// Returns a permit if a request may be sent, its outcome must be passed to
+	/// [`CircuitPermit::complete`].
+	fn acquire(&self, now: Instant) -> Option<CircuitPermit<'_>> {
+		let mut inner = self.inner.lock().unwrap();
+		
+		match inner.state {
+			CircuitState::Closed => (),
+			CircuitState::Open if now.duration_since(inner.opened) < self.cfg.open_duration => return None,
+			CircuitState::Open => {
+				inner.set_state(CircuitState::HalfOpen, now);
+				inner.trials = 1;
+			}
+			CircuitState::HalfOpen if inner.trials < self.cfg.half_open_requests => inner.trials += 1,
+			CircuitState::HalfOpen => return None
+		}
+		
+		Some(CircuitPermit { breaker: self, completed: false })
+	}
+	
+	/// Records the outcome of a request, returns the new state and whether it changed.
+	fn record(&self, success: bool, now: Instant) -> (CircuitState, bool) {
+		let mut inner = self.inner.lock().unwrap();
+		let state = inner.state;
+		
+		match inner.state {
+			CircuitState::Closed => {
+				if now.duration_since(inner.window_start) >= self.cfg.window {
+					inner.window_start    = now;
+					inner.window_requests = 0;
+					inner.window_failures = 0;
+				}
+				
+				inner.window_requests += 1;
+				
+				if success {
+					inner.consecutive_failures = 0;
+				} else {
+					inner.consecutive_failures += 1;
+					inner.window_failures += 1;
+				}
+				
+				if inner.consecutive_failures >= self.cfg.consecutive_failures
+					|| (inner.window_requests >= self.cfg.min_requests
+						&& inner.window_failures as f64 >= self.cfg.error_rate * inner.window_requests as f64)
+				{
+					inner.set_state(CircuitState::Open, now);
+				}
+			}
+			CircuitState::HalfOpen if !success => inner.set_state(CircuitState::Open, now),
+			CircuitState::HalfOpen => {
+				inner.trial_successes += 1;
+				
+				if inner.trial_successes >= self.cfg.half_open_requests {
+					inner.set_state(CircuitState::Closed, now);
+				}
+			}
+			// requests that were sent before the circuit opened
+			CircuitState::Open => ()
+		}
+		
+		(inner.state, inner.state != state)
+	}
+}
+
+/// A request the circuit breaker let through. A permit that is dropped without being completed,
+/// e.g. because the client disconnected or the request was cancelled, counts as a failure, so
+/// that it does not hold on to a trial slot of a half-open circuit.
+struct CircuitPermit<'a> {
+	breaker:   &'a CircuitBreaker,
+	completed: bool
+}
+
+impl CircuitPermit<'_> {
+	/// Records the outcome of the request, returns the new state and whether it changed.
+	fn complete(mut self, success: bool, now: Instant) -> (CircuitState, bool) {
+		self.completed = true;
+		self.breaker.record(success, now)
+	}
+}
+
+impl Drop for CircuitPermit<'_> {
+	fn drop(&mut self) {
+		if !self.completed {
+			self.breaker.record(false, Instant::now());
+		}
+	}
+}
+
+/// Copies a body of `len` bytes, or until the end of `src` if the length is unknown. Fails if
+/// nothing was read for `idle`.
+pub(super) async fn copy_body<R, W>(src: &mut R, dst: &mut W, len: Option<usize>, buf: &mut [u8], idle: Option<Duration>) -> io::Result<()>
+	where R: smol::io::AsyncRead + Unpin + ?Sized, W: smol::io::AsyncWrite + Unpin + ?Sized
+{
+	match len {
+		Some(mut len) => while len > 0 {
+			let __len__ = buf.len().min(len);
+			let read = timeout(idle, src.read(&mut buf[..__len__])).await?;
+			
+			if read == 0 {
+				return Err(io::ErrorKind::UnexpectedEof.into());
+			}
+			
+			dst.write_all(&buf[..read]).await?;
+			len -= read;
+		},
+		None => loop {
+			let read = timeout(idle, src.read(buf)).await?;
+			
+			if read == 0 {
+				break;
+			}
+			
+			dst.write_all(&buf[..read]).await?;
+		}
+	}
+	
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		smol::net::{TcpListener, TcpStream},
+		std::{future::Future, net::SocketAddr}
+	};
+	
+	/// Requests the backend received, up to the empty line after the headers.
+	type Requests = Arc<Mutex<Vec<String>>>;
+	
+	fn config() -> Config {
+		serde_json::from_str("{}").unwrap()
+	}
+	
+	/// A relay to `addr`, the backend must be listening already.
+	async fn module(addr: SocketAddr, cfg: Config) -> ModuleShared<impl AsyncConnector<Connection = http::traits::BoxedAsyncSharedConnection>> {
+		test_telemetry();
+		let target = transport::Target::Tcp(addr.to_string());
+		ModuleShared::new("relay".to_string(), target.clone(), &cfg, http::traits::DynAsyncSharedConnector::new(
+			net::http::v1::AsyncSharedConnector::new(
+				net::http::v1::AsyncConnector::new(
+					net::buffered::AsyncConnector::new(target))))).await.unwrap()
+	}
+	
+	/// A backend that answers every connection with `session`.
+	async fn backend<F: Future<Output = ()> + Send + 'static>(session: fn(TcpStream, Requests) -> F) -> (SocketAddr, Requests, smol::Task<()>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let requests = Requests::default();
+		let requests_ = requests.clone();
+		
+		(addr, requests, smol::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				smol::spawn(session(stream, requests_.clone())).detach();
+			}
+		}))
+	}
+	
+	/// Accepts an upgrade to a WebSocket, greets the client and echoes everything it receives.
+	async fn websocket(mut stream: TcpStream, requests: Requests) {
+		let request = read_head(&mut stream).await;
+		
+		// the shared connection of the relay, which does not send anything
+		if request.is_empty() {
+			return;
+		}
+		
+		requests.lock().unwrap().push(request);
+		stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\nhello").await.unwrap();
+		let mut buf = [0u8; 64];
+		
+		while let Ok(len @ 1..) = stream.read(&mut buf).await {
+			stream.write_all(&buf[..len]).await.unwrap();
+		}
+	}
+	
+	const UPGRADE_REQUEST: &[u8] = b"GET /chat HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Protocol: chat\r\n\r\n";
+	
+	#[test]
+	fn upgrade() {
+		smol::block_on(deadline(async {
+			let (addr, requests, _backend) = backend(websocket).await;
+			let module = module(addr, config()).await;
+			let (server, mut client) = TestDuplex::pair();
+			
+			let (r, ()) = smol::future::zip(serve_http(&module, server), async {
+				client.write_all(UPGRADE_REQUEST).await.unwrap();
+				assert!(read_head(&mut client).await.starts_with("HTTP/1.1 101"));
+				
+				let mut buf = [0u8; 5];
+				client.read_exact(&mut buf).await.unwrap();
+				assert_eq!(&buf, b"hello");
+				client.write_all(b"ping").await.unwrap();
+				client.read_exact(&mut buf[..4]).await.unwrap();
+				assert_eq!(&buf[..4], b"ping");
+				client.close().await.unwrap();
+			}).await;
+			
+			assert!(r.is_ok());
+			let requests = requests.lock().unwrap();
+			assert_eq!(requests.len(), 1);
+			assert!(requests[0].starts_with("GET /chat HTTP/1.1\r\n"));
+			assert!(requests[0].to_ascii_lowercase().contains("upgrade: websocket\r\n"));
+			assert!(requests[0].to_ascii_lowercase().contains("sec-websocket-protocol: chat\r\n"));
+		}));
+	}
+	
+	#[test]
+	fn upgrade_idle_timeout() {
+		smol::block_on(deadline(async {
+			let (addr, _, _backend) = backend(websocket).await;
+			let module = module(addr, Config { upgrade_idle_timeout: Some(Duration::from_millis(100)), ..config() }).await;
+			let (server, mut client) = TestDuplex::pair();
+			
+			let (r, ()) = smol::future::zip(serve_http(&module, server), async {
+				client.write_all(UPGRADE_REQUEST).await.unwrap();
+				assert!(read_head(&mut client).await.starts_with("HTTP/1.1 101"));
+				
+				// nothing is sent after the greeting, so the tunnel is closed
+				let mut buf = Vec::new();
+				client.read_to_end(&mut buf).await.unwrap();
+				assert_eq!(buf, b"hello");
+			}).await;
+			
+			assert!(r.is_ok());
+		}));
+	}
+	
+	/// Reads a request and never answers it.
+	async fn stall(mut stream: TcpStream, requests: Requests) {
+		let request = read_head(&mut stream).await;
+		
+		if request.is_empty() {
+			return;
+		}
+		
+		requests.lock().unwrap().push(request);
+		let mut buf = [0u8; 64];
+		while let Ok(1..) = stream.read(&mut buf).await {}
+	}
+	
+	/// Answers a request with the headers and the first half of the body only.
+	async fn truncated(mut stream: TcpStream, requests: Requests) {
+		let request = read_head(&mut stream).await;
+		
+		if request.is_empty() {
+			return;
+		}
+		
+		requests.lock().unwrap().push(request);
+		stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n12345").await.unwrap();
+		let mut buf = [0u8; 64];
+		while let Ok(1..) = stream.read(&mut buf).await {}
+	}
+	
+	const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+	
+	/// Sends `request` through a relay to a backend answering with `session`, returns the result
+	/// of the relay and what the client received.
+	async fn exchange<F: Future<Output = ()> + Send + 'static>(session: fn(TcpStream, Requests) -> F, cfg: Config, request: &[u8]) -> (Result<()>, String) {
+		let (addr, _, _backend) = backend(session).await;
+		let module = module(addr, cfg).await;
+		let (server, mut client) = TestDuplex::pair();
+		
+		smol::future::zip(async move { serve_http(&module, server).await }, async move {
+			client.write_all(request).await.unwrap();
+			let mut buf = Vec::new();
+			let _ = client.read_to_end(&mut buf).await;
+			String::from_utf8(buf).unwrap()
+		}).await
+	}
+	
+	fn timeouts(timeouts: ConfigTimeouts) -> Config {
+		Config { timeouts, ..config() }
+	}
+	
+	#[test]
+	fn first_byte_timeout() {
+		smol::block_on(deadline(async {
+			let (r, response) = exchange(stall, timeouts(ConfigTimeouts {
+				first_byte: Some(Duration::from_millis(100)),
+				..ConfigTimeouts::default()
+			}), REQUEST).await;
+			assert!(r.is_ok());
+			assert!(response.starts_with("HTTP/1.1 504"));
+		}));
+	}
+	
+	#[test]
+	fn total_timeout() {
+		smol::block_on(deadline(async {
+			let cfg = || timeouts(ConfigTimeouts { total: Some(Duration::from_millis(100)), ..ConfigTimeouts::default() });
+			let (r, response) = exchange(stall, cfg(), REQUEST).await;
+			assert!(r.is_ok());
+			assert!(response.starts_with("HTTP/1.1 504"));
+			
+			// once the response was started, it cannot be replaced
+			let (r, response) = exchange(truncated, cfg(), REQUEST).await;
+			assert!(r.err().map_or(false, |e| is_timeout(&e)));
+			assert!(!response.contains("504"));
+		}));
+	}
+	
+	#[test]
+	fn idle_timeout() {
+		smol::block_on(deadline(async {
+			// the client stops sending the request body halfway
+			let (r, response) = exchange(stall, timeouts(ConfigTimeouts {
+				idle: Some(Duration::from_millis(100)),
+				..ConfigTimeouts::default()
+			}), b"POST / HTTP/1.1\r\nHost: example.com\r\nContent-Length: 10\r\n\r\n12345").await;
+			
+			assert!(r.is_ok());
+			assert!(response.starts_with("HTTP/1.1 504"));
+		}));
+	}
+	
+	fn breaker_config() -> ConfigCircuitBreaker {
+		ConfigCircuitBreaker {
+			consecutive_failures: 3,
+			error_rate:           0.5,
+			min_requests:         10,
+			window:               Duration::from_secs(10),
+			open_duration:        Duration::from_secs(30),
+			half_open_requests:   2
+		}
+	}
+	
+	fn breaker() -> CircuitBreaker {
+		CircuitBreaker::new(breaker_config(), Instant::now())
+	}
+	
+	#[test]
+	fn circuit_breaker_consecutive_failures() {
+		let (breaker, now) = (breaker(), Instant::now());
+		
+		for _ in 0..2 {
+			assert_eq!(breaker.acquire(now).unwrap().complete(false, now), (CircuitState::Closed, false));
+		}
+		
+		assert_eq!(breaker.record(true, now), (CircuitState::Closed, false));
+		assert_eq!(breaker.record(false, now), (CircuitState::Closed, false));
+		assert_eq!(breaker.record(false, now), (CircuitState::Closed, false));
+		assert_eq!(breaker.record(false, now), (CircuitState::Open, true));
+		assert!(breaker.acquire(now + Duration::from_secs(29)).is_none());
+	}
+	
+	#[test]
+	fn circuit_breaker_error_rate() {
+		let (breaker, now) = (breaker(), Instant::now());
+		
+		for i in 0..9 {
+			assert_eq!(breaker.record(i % 2 == 0, now), (CircuitState::Closed, false));
+		}
+		
+		assert_eq!(breaker.record(false, now), (CircuitState::Open, true));
+	}
+	
+	#[test]
+	fn circuit_breaker_half_open() {
+		let (breaker, now) = (breaker(), Instant::now());
+		
+		for _ in 0..3 {
+			breaker.record(false, now);
+		}
+		
+		let now = now + Duration::from_secs(30);
+		let permits = (breaker.acquire(now).unwrap(), breaker.acquire(now).unwrap());
+		assert!(breaker.acquire(now).is_none());
+		assert_eq!(permits.0.complete(true, now), (CircuitState::HalfOpen, false));
+		assert_eq!(permits.1.complete(true, now), (CircuitState::Closed, true));
+		
+		for _ in 0..3 {
+			breaker.record(false, now);
+		}
+		
+		let now = now + Duration::from_secs(30);
+		assert_eq!(breaker.acquire(now).unwrap().complete(false, now), (CircuitState::Open, true));
+		assert!(breaker.acquire(now).is_none());
+	}
+	
+	#[test]
+	fn circuit_breaker_dropped_permit() {
+		let (breaker, now) = (breaker(), Instant::now());
+		
+		for _ in 0..3 {
+			breaker.record(false, now);
+		}
+		
+		// a trial request that never completes opens the circuit again instead of holding its slot
+		let now = now + Duration::from_secs(30);
+		let permit = breaker.acquire(now).unwrap();
+		assert!(breaker.acquire(now).is_some());
+		std::mem::drop(permit);
+		assert_eq!(breaker.inner.lock().unwrap().state, CircuitState::Open);
+		
+		let now = Instant::now() + Duration::from_secs(30);
+		assert_eq!(breaker.acquire(now).unwrap().complete(true, now), (CircuitState::HalfOpen, false));
+		assert_eq!(breaker.acquire(now).unwrap().complete(true, now), (CircuitState::Closed, true));
+	}
+	
+	#[test]
+	fn circuit_breaker_cancelled_request() {
+		smol::block_on(deadline(async {
+			let (addr, requests, _backend) = backend(stall).await;
+			let module = module(addr, Config {
+				circuit_breaker: Some(ConfigCircuitBreaker { consecutive_failures: 1, ..breaker_config() }),
+				..config()
+			}).await;
+			let (server, mut client) = TestDuplex::pair();
+			client.write_all(REQUEST).await.unwrap();
+			
+			// the request is cancelled while waiting for the backend, e.g. by a socket timeout
+			smol::future::or(async { serve_http(&module, server).await.map(|_| false).unwrap() }, async {
+				while requests.lock().unwrap().is_empty() {
+					smol::Timer::after(Duration::from_millis(10)).await;
+				}
+				
+				true
+			}).await;
+			
+			assert_eq!(module.breaker.as_ref().unwrap().inner.lock().unwrap().state, CircuitState::Open);
+		}));
+	}
+}
@@ -20,11 +20,10 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use smol::io::AsyncReadExt;
 use {
 	super::*,
 	crate::{interfaces::*, utils::*},
-	std::{io, pin::Pin, task::Poll, task::Context, path::*},
+	std::{io, pin::Pin, task::Poll, task::Context, path::*, sync::Arc},
 	net::{*, http::traits::AsyncStreamExt},
 	smol::io::AsyncWriteExt
 };
@@ -32,7 +31,7 @@ use {
 const DEFAULT_ALLOW: [http::Method; 2] = [http::Method::Head, http::Method::Get];
 
 pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
-	let module: HttpStreamHandler = Box::new(Module {
+	let module = Arc::new(Module {
 		filters: cfg.filters.into_iter()
 			.enumerate()
 			.filter_map(|(idx, cfg)| smol::block_on(Filter::from_cfg(name, idx, cfg)))
@@ -40,7 +39,8 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 	});
 	
 	let id = crate::component_id(name);
-	crate::add_component::<HttpStreamHandler>(id, module);
+	crate::add_component::<MailHandler>(id, Box::new(module.clone()));
+	crate::add_component::<HttpStreamHandler>(id, Box::new(module));
 	Ok(())
 }
 
@@ -48,158 +48,227 @@ struct Module {
 	filters: Vec<Filter>
 }
 
-impl StreamHandler<dyn http::traits::AsyncStream> for Module {
-	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
-		Box::pin(async move {
-			let headers = stream.read_headers().await?;
+impl Module {
+	async fn route(&self, stream: &'static mut dyn http::traits::AsyncStream, headers: Vec<http::Header>, span: &otel_mrt::tracing::Span) -> Result<()> {
+		// TODO match HTTP response
+		for filter in &self.filters {
+			if !filter.match_ip(&headers)
+				|| !filter.match_tls_client(&headers)
+				|| !filter.match_http_upgrade(&headers)
+				|| !filter.match_http_jwt_claims(&headers)
+				|| !filter.match_http_request_headers(&headers)
+			{
+				continue;
+			}
 			
-			// TODO match HTTP response
-			for filter in &self.filters {
-				if !filter.match_http_request_headers(&headers) {
-					continue;
-				}
-				
-				let (method, path) = (
-					headers.iter().find_map(http::Header::as_method),
-					headers.iter().find_map(http::Header::as_path)
-				);
-				
-				let (method, path) = match (method, path) {
-					(Some(v0), Some(v1)) => (v0, v1),
-					_ => {
-						discard_body(stream).await?;
-						return http::MessageBuilder::new()
-							.status(http::Status::BadRequest)
-							.content_length(0)
-							.send_async(stream)
-							.await.map_err(Into::into)
-					}
-				};
-				
-				// allowed
-				
-				let allowed_methods = filter.http_headers.iter()
-					.find_map(http::Header::as_allow)
-					.map(Vec::as_slice)
-					.unwrap_or_else(|| DEFAULT_ALLOW.as_slice());
-				
-				if !allowed_methods.contains(method) {
+			span.set_attribute("router.filter", otel_mrt::AnyValue::String(filter.name.clone()));
+			
+			let (method, path) = (
+				headers.iter().find_map(http::Header::as_method),
+				headers.iter().find_map(http::Header::as_path)
+			);
+			
+			let (method, path) = match (method, path) {
+				(Some(v0), Some(v1)) => (v0, v1),
+				_ => {
 					discard_body(stream).await?;
 					return http::MessageBuilder::new()
-						.status(http::Status::MethodNotAllowed)
+						.status(http::Status::BadRequest)
 						.content_length(0)
-						.allow(allowed_methods.to_vec())
 						.send_async(stream)
-						.await.map_err(Into::into);
+						.await.map_err(Into::into)
 				}
+			};
+			
+			// allowed
+			
+			let allowed_methods = filter.http_headers.iter()
+				.find_map(http::Header::as_allow)
+				.map(Vec::as_slice)
+				.unwrap_or_else(|| DEFAULT_ALLOW.as_slice());
+			
+			if !allowed_methods.contains(method) {
+				discard_body(stream).await?;
+				return http::MessageBuilder::new()
+					.status(http::Status::MethodNotAllowed)
+					.content_length(0)
+					.allow(allowed_methods.to_vec())
+					.send_async(stream)
+					.await.map_err(Into::into);
+			}
+			
+			// content negotiation
+			
+			/*if !filter.http_content_locations.is_empty() {
+				let (accept_types, accept_encoding, accept_language, _accept_charset) = (
+					headers.iter().find_map(http::Header::as_accept_types),
+					headers.iter().find_map(http::Header::as_accept_encoding),
+					headers.iter().find_map(http::Header::as_accept_language),
+					headers.iter().find_map(http::Header::as_accept_charset),
+				);
 				
-				// content negotiation
-				
-				/*if !filter.http_content_locations.is_empty() {
-					let (accept_types, accept_encoding, accept_language, _accept_charset) = (
-						headers.iter().find_map(http::Header::as_accept_types),
-						headers.iter().find_map(http::Header::as_accept_encoding),
-						headers.iter().find_map(http::Header::as_accept_language),
-						headers.iter().find_map(http::Header::as_accept_charset),
-					);
+				match filter.content_locations.iter().find(|res| {
+					let res = smol::block_on(res.data.read());
+					let res = res.as_ref().unwrap();
 					
-					match filter.content_locations.iter().find(|res| {
-						let res = smol::block_on(res.data.read());
-						let res = res.as_ref().unwrap();
-						
-						let res = match res.iter().find(|v| v.filter.matches(&headers)) {
-							Some(v) => v,
-							None => return false
-						};
-						
-						match (accept_types, res.headers.iter().find_map(http::Header::as_content_type)) {
-							(Some(accept), Some(actual)) => accept.contains(actual),
-							_ => true
-						} && match (accept_encoding, res.headers.iter().find_map(http::Header::as_content_encoding)) {
-							(_, Some(http::Encoding::Identity)) => true,
-							(Some(accept), Some(actual)) => accept.contains(actual),
-							_ => true
-						} && match (accept_language, res.headers.iter().find_map(http::Header::as_content_language)) {
+					let res = match res.iter().find(|v| v.filter.matches(&headers)) {
+						Some(v) => v,
+						None => return false
+					};
+					
+					match (accept_types, res.headers.iter().find_map(http::Header::as_content_type)) {
+						(Some(accept), Some(actual)) => accept.contains(actual),
+						_ => true
+					} && match (accept_encoding, res.headers.iter().find_map(http::Header::as_content_encoding)) {
+						(_, Some(http::Encoding::Identity)) => true,
+						(Some(accept), Some(actual)) => accept.contains(actual),
+						_ => true
+					} && match (accept_language, res.headers.iter().find_map(http::Header::as_content_language)) {
+						(Some(accept), Some(actual)) => accept.contains(actual),
+						_ => true
+					} /*&& match (accept_charset, res.headers.iter().find_map(http::Header::as_content_type)) {
 							(Some(accept), Some(actual)) => accept.contains(actual),
 							_ => true
-						} /*&& match (accept_charset, res.headers.iter().find_map(http::Header::as_content_type)) {
-								(Some(accept), Some(actual)) => accept.contains(actual),
-								_ => true
-							}*/
-					}) {
-						Some(v) => {
-							// TODO
-							//response_headers.push(http::Header::ContentLocation(resource.headers.path_ref().clone()));
-							node_new = Some(v.clone());
-							continue 'outer;
-						}
-						None => break http::Status::NotAcceptable
+						}*/
+				}) {
+					Some(v) => {
+						// TODO
+						//response_headers.push(http::Header::ContentLocation(resource.headers.path_ref().clone()));
+						node_new = Some(v.clone());
+						continue 'outer;
 					}
-				}*/
-				
-				// conditional requests
-				
-				let status = match (
-					filter.http_headers.iter().find_map(http::Header::as_e_tag),
-					filter.http_headers.iter().find_map(http::Header::as_last_modified),
-					headers.iter().find_map(http::Header::as_if_none_match),
-					headers.iter().find_map(http::Header::as_if_modified_since),
-					headers.iter().find_map(http::Header::as_if_match),
-					headers.iter().find_map(http::Header::as_if_unmodified_since)
-				) {
-					(Some(etag), _, Some(etags), ..) if etags.contains(&etag.value)        => Some(http::Status::NotModified),
-					(_, Some(date), _, Some(since), ..) if date <= since                      => Some(http::Status::NotModified),
-					(Some(etag), _, _, _, Some(etags), ..) if !etags.contains(&etag.value) => Some(http::Status::PreconditionFailed),
-					(_, Some(date), _, _, _, Some(since)) if date > since                     => Some(http::Status::PreconditionFailed),
-					_ => None
-				};
-				
-				if let Some(status) = status {
+					None => break http::Status::NotAcceptable
+				}
+			}*/
+			
+			// conditional requests
+			
+			let status = match (
+				filter.http_headers.iter().find_map(http::Header::as_e_tag),
+				filter.http_headers.iter().find_map(http::Header::as_last_modified),
+				headers.iter().find_map(http::Header::as_if_none_match),
+				headers.iter().find_map(http::Header::as_if_modified_since),
+				headers.iter().find_map(http::Header::as_if_match),
+				headers.iter().find_map(http::Header::as_if_unmodified_since)
+			) {
+				(Some(etag), _, Some(etags), ..) if etags.contains(&etag.value)        => Some(http::Status::NotModified),
+				(_, Some(date), _, Some(since), ..) if date <= since                      => Some(http::Status::NotModified),
+				(Some(etag), _, _, _, Some(etags), ..) if !etags.contains(&etag.value) => Some(http::Status::PreconditionFailed),
+				(_, Some(date), _, _, _, Some(since)) if date > since                     => Some(http::Status::PreconditionFailed),
+				_ => None
+			};
+			
+			if let Some(status) = status {
+				discard_body(stream).await?;
+				return http::MessageBuilder::new()
+					.status(status)
+					.content_length(0)
+					.send_async(stream)
+					.await.map_err(Into::into);
+			}
+			
+			match &filter.action {
+				FilterAction::Forward(module, _) => {
+					// the next module continues the trace of this span
+					let mut request_headers = headers.clone();
+					span.context().inject(&mut request_headers);
+					
+					if filter.http_timeouts != RequestTimeouts::default() {
+						request_headers.retain(|v| !RequestTimeouts::is_header(v.name_v1()));
+						request_headers.extend(filter.http_timeouts.headers());
+					}
+					
+					let mut stream = FilteredHttpStream {
+						inner:            stream,
+						filter,
+						request_headers,
+						response_headers: Vec::new()
+					};
+					
+					// this is unsafe, but that's ok, see HttpStreamHandler::accept
+					let stream = unsafe { std::mem::transmute::<_, &'static mut FilteredHttpStream<'static>>(&mut stream) };
+					return module.get().await?.accept(stream).await;
+				}
+				FilterAction::Reply(v)   => {
 					discard_body(stream).await?;
-					return http::MessageBuilder::new()
-						.status(status)
-						.content_length(0)
-						.send_async(stream)
-						.await.map_err(Into::into);
+					stream.write_headers(&filter.http_headers).await?;
+					stream.write_all(v).await?;
+					stream.close().await?;
+					return Ok(());
+				}
+				FilterAction::Close      => return Ok(()),
+				FilterAction::Abort      => return Err(io::Error::from(io::ErrorKind::ConnectionAborted).into())
+			}
+		}
+		
+		span.add_event("no filter matched", std::iter::empty(), None);
+		send_response(stream, http::Status::NotFound).await
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let headers = stream.read_headers().await?;
+			let span = start_span(&headers, otel_mrt::SpanParams::new("router"));
+			let r = self.route(stream, headers, &span).await;
+			
+			if r.is_err() {
+				span.set_status(otel_mrt::StatusCode::Error, None);
+			}
+			
+			span.end();
+			r
+		})
+	}
+}
+
+impl StreamHandler<MailTransaction> for Module {
+	fn accept<'a>(&'a self, transaction: &'static mut MailTransaction) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			for filter in &self.filters {
+				if !filter.match_addr(transaction.connection.addr)
+					|| !filter.match_smtp(transaction)
+					|| !filter.match_imf_request(transaction)
+				{
+					continue;
 				}
 				
 				match &filter.action {
-					FilterAction::Forward(module) => {
-						let mut stream = FilteredHttpStream {
-							inner:            stream,
-							filter,
-							request_headers:  headers.clone(),
-							response_headers: Vec::new()
-						};
-						
-						// this is unsafe, but that's ok, see HttpStreamHandler::accept
-						let stream = unsafe { std::mem::transmute::<_, &'static mut FilteredHttpStream<'static>>(&mut stream) };
-						return module.accept(stream).await;
+					FilterAction::Forward(_, module) => {
+						filter.filter_mail(transaction);
+						return module.get().await?.accept(transaction).await;
 					}
 					FilterAction::Reply(v)   => {
-						discard_body(stream).await?;
-						stream.write_headers(&filter.http_headers).await?;
-						stream.write_all(v).await?;
-						stream.close().await?;
+						// a reply may start with its own code, e.g. `554 5.7.1 Message refused`
+						let text = String::from_utf8_lossy(v).into_owned();
+						transaction.reply = Some(net::smtp::parse_reply(&text)
+							.ok()
+							.filter(|(code, _)| (400..600).contains(code))
+							.unwrap_or((550, text)));
+						return Ok(());
+					}
+					FilterAction::Close      => {
+						transaction.reply = Some((554, "5.7.1 Transaction failed".to_string()));
 						return Ok(());
 					}
-					FilterAction::Close      => return Ok(()),
 					FilterAction::Abort      => return Err(io::Error::from(io::ErrorKind::ConnectionAborted).into())
 				}
 			}
 			
-			send_response(stream, http::Status::NotFound).await
+			transaction.reply = Some((550, "5.7.1 Relaying denied".to_string()));
+			Ok(())
 		})
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
 	pub filters: Vec<ConfigFilter>
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilter {
 	pub name:   Option<String>,
@@ -242,32 +311,60 @@ impl<'de> Deserialize<'de> for ConfigAction {
 	}
 }
 
+impl JsonSchema for ConfigAction {
+	fn schema_name() -> String {
+		"ConfigAction".to_string()
+	}
+	
+	fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+		serde_json::from_value(serde_json::json!({
+			"type":                 "object",
+			"minProperties":        1,
+			"maxProperties":        1,
+			"additionalProperties": false,
+			"properties": {
+				"forward": { "type": "string" },
+				"reply":   { "type": "string" },
+				"close":   {},
+				"abort":   {}
+			}
+		})).unwrap()
+	}
+}
+
 impl Default for ConfigAction {
 	fn default() -> Self {
 		Self::Abort
 	}
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterIp {
 	pub addr: Option<String>,
 	pub port: Option<(u16, u16)>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterTls {
-	pub hostname: StringMatcher,
-	pub ca_certs: Option<Vec<String>>,
-	pub alpn:     Option<Vec<String>>
+	pub hostname:           StringMatcher,
+	pub ca_certs:           Option<Vec<String>>,
+	pub alpn:               Option<Vec<String>>,
+	/// Matches the subject of a verified client certificate.
+	pub client_subject:     StringMatcher,
+	/// Matches any subject alternative name of a verified client certificate, e.g. `DNS:example.com`.
+	pub client_san:         StringMatcher,
+	/// Matches the SHA-256 fingerprint of a verified client certificate.
+	pub client_fingerprint: StringMatcher
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterHttp {
 	/// Headers used for built-in filters, like content negotiation
 	#[serde(deserialize_with = "http_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub headers:                 Vec<http::Header>,
 	pub content_locations:       Vec<String>,
 	pub path_match:              StringMatcher,
@@ -275,36 +372,59 @@ pub struct ConfigFilterHttp {
 	pub path_add_prefix:         Option<String>,
 	pub path_add_suffix:         Option<String>,
 	pub query_match:             HashMap<String, StringMatcher>,
+	/// Matches any subprotocol offered in `Sec-WebSocket-Protocol`.
+	pub websocket_protocol:      StringMatcher,
+	/// Matches claims of the JWT verified by an auth module in front of the router.
+	pub jwt_claims:              HashMap<String, StringMatcher>,
 	pub query_add:               HashMap<String, String>,
 	pub query_modify:            HashMap<String, String>,
 	pub query_del:               Vec<String>,
 	#[serde(deserialize_with = "http_parse_headers_match")]
+	#[schemars(with = "HashMap<String, StringMatcher>")]
 	pub request_headers_match:   Vec<(HeaderId<http::HeaderId>, StringMatcher)>,
 	#[serde(deserialize_with = "http_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub request_headers_add:     Vec<http::Header>,
 	#[serde(deserialize_with = "http_parse_header_ids")]
+	#[schemars(with = "Vec<String>")]
 	pub request_headers_del:     Vec<HeaderId<http::HeaderId>>,
 	#[serde(deserialize_with = "http_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub request_headers_modify:  Vec<http::Header>,
 	pub request_content_match:   StringMatcher,
 	pub request_content_modify:  Option<String>,
 	#[serde(deserialize_with = "http_parse_headers_match")]
+	#[schemars(with = "HashMap<String, StringMatcher>")]
 	pub response_headers_match:  Vec<(HeaderId<http::HeaderId>, StringMatcher)>,
 	#[serde(deserialize_with = "http_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub response_headers_add:    Vec<http::Header>,
 	#[serde(deserialize_with = "http_parse_header_ids")]
+	#[schemars(with = "Vec<String>")]
 	pub response_headers_del:    Vec<HeaderId<http::HeaderId>>,
 	#[serde(deserialize_with = "http_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub response_headers_modify: Vec<http::Header>,
 	pub response_content_match:  StringMatcher,
-	pub response_content_modify: Option<String>
+	pub response_content_modify: Option<String>,
+	/// Timeouts of this route, they override the timeouts of the relay.
+	pub timeouts:                ConfigFilterTimeouts
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFilterTimeouts {
+	pub first_byte: Option<Duration>,
+	pub total:      Option<Duration>,
+	pub idle:       Option<Duration>
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterRtsp {
 	/// Headers used for built-in filters, like content negotiation
 	#[serde(deserialize_with = "rtsp_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub headers:                 Vec<rtsp::Header>,
 	pub path_match:              StringMatcher,
 	pub path_strip_prefix:       Option<String>,
@@ -315,63 +435,79 @@ pub struct ConfigFilterRtsp {
 	pub query_modify:            HashMap<String, String>,
 	pub query_del:               Vec<String>,
 	#[serde(deserialize_with = "rtsp_parse_headers_match")]
+	#[schemars(with = "HashMap<String, StringMatcher>")]
 	pub request_headers_match:   Vec<(HeaderId<rtsp::HeaderId>, StringMatcher)>,
 	#[serde(deserialize_with = "rtsp_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub request_headers_add:     Vec<rtsp::Header>,
 	#[serde(deserialize_with = "rtsp_parse_header_ids")]
+	#[schemars(with = "Vec<String>")]
 	pub request_headers_del:     Vec<HeaderId<rtsp::HeaderId>>,
 	#[serde(deserialize_with = "rtsp_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub request_headers_modify:  Vec<rtsp::Header>,
 	pub request_content_match:   StringMatcher,
 	pub request_content_modify:  Option<String>,
 	#[serde(deserialize_with = "rtsp_parse_headers_match")]
+	#[schemars(with = "HashMap<String, StringMatcher>")]
 	pub response_headers_match:  Vec<(HeaderId<rtsp::HeaderId>, StringMatcher)>,
 	#[serde(deserialize_with = "rtsp_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub response_headers_add:    Vec<rtsp::Header>,
 	#[serde(deserialize_with = "rtsp_parse_header_ids")]
+	#[schemars(with = "Vec<String>")]
 	pub response_headers_del:    Vec<HeaderId<rtsp::HeaderId>>,
 	#[serde(deserialize_with = "rtsp_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub response_headers_modify: Vec<rtsp::Header>,
 	pub response_content_match:  StringMatcher,
 	pub response_content_modify: Option<String>
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterImf {
 	#[serde(deserialize_with = "imf_parse_headers_match")]
+	#[schemars(with = "HashMap<String, StringMatcher>")]
 	pub request_headers_match:   Vec<(HeaderId<imf::HeaderId>, StringMatcher)>,
 	#[serde(deserialize_with = "imf_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub request_headers_add:     Vec<imf::Header>,
 	#[serde(deserialize_with = "imf_parse_header_ids")]
+	#[schemars(with = "Vec<String>")]
 	pub request_headers_del:     Vec<HeaderId<imf::HeaderId>>,
 	#[serde(deserialize_with = "imf_parse_headers")]
+	#[schemars(with = "HashMap<String, String>")]
 	pub request_headers_modify:  Vec<imf::Header>,
 	pub request_content_match:   StringMatcher,
 	pub request_content_modify:  Option<String>
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterSmtp {
 	pub sender:        StringMatcher,
 	pub recipient_any: StringMatcher,
 	pub recipient_all: StringMatcher,
+	/// Comma separated addresses that are added to the recipients
 	pub recipient_add: String,
+	/// Comma separated addresses that are removed from the recipients
 	pub recipient_del: String
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
 pub struct ConfigFilterDns {
 	pub domain:      StringMatcher,
 	#[serde(deserialize_with = "dns_parse_record_types")]
+	#[schemars(with = "Option<Vec<String>>")]
 	pub record_type: Option<Vec<net::dns::Type>>
 }
 
 #[derive(Clone)]
 enum FilterAction {
-	Forward(ComponentRef<HttpStreamHandler>),
+	/// The next module, as handler of HTTP requests and of mail.
+	Forward(ComponentRef<HttpStreamHandler>, ComponentRef<MailHandler>),
 	Reply(Box<[u8]>),
 	Close,
 	Abort
@@ -389,12 +525,14 @@ struct Filter {
 	action:                       FilterAction,
 	match_invert:                 bool,
 	match_exact:                  bool,
-	ip_addr:                      u128,
-	ip_mask:                      u128,
-	ports:                        (u16, u16),
+	ip_addr:                      Option<Cidr>,
+	ip_ports:                     Option<(u16, u16)>,
 	tls_hostname:                 StringMatcher,
 	tls_ca_certs:                 Option<Vec<Box<[u8]>>>,
 	tls_alpn:                     Option<Vec<String>>,
+	tls_client_subject:           StringMatcher,
+	tls_client_san:               StringMatcher,
+	tls_client_fingerprint:       StringMatcher,
 	http_headers:                 Vec<http::Header>,
 	http_content_locations:       Vec<String>,
 	http_path_match:              StringMatcher,
@@ -402,6 +540,8 @@ struct Filter {
 	http_path_add_prefix:         Option<Box<Path>>,
 	http_path_add_suffix:         Option<Box<Path>>,
 	http_query_match:             HashMap<String, StringMatcher>,
+	http_websocket_protocol:      StringMatcher,
+	http_jwt_claims:              HashMap<String, StringMatcher>,
 	http_query_add:               HashMap<String, String>,
 	http_query_modify:            HashMap<String, String>,
 	http_query_del:               Vec<String>,
@@ -417,6 +557,7 @@ struct Filter {
 	http_response_headers_modify: Vec<http::Header>,
 	http_response_content_match:  StringMatcher,
 	http_response_content_modify: Option<String>,
+	http_timeouts:                RequestTimeouts,
 	rtsp_headers:                 Vec<rtsp::Header>,
 	rtsp_path_match:              StringMatcher,
 	rtsp_path_strip_prefix:       Option<Box<Path>>,
@@ -470,7 +611,8 @@ impl Filter {
 		self_.name = cfg.name.unwrap_or_else(|| format!("#{:03}", idx));
 		self_.action = match cfg.action {
 			ConfigAction::Forward(v) => FilterAction::Forward(
-				crate::get_component::<HttpStreamHandler>(crate::component_id(&v))),
+				crate::get_component::<HttpStreamHandler>(crate::component_id(&v)),
+				crate::get_component::<MailHandler>(crate::component_id(&v))),
 			ConfigAction::Reply(v)   => FilterAction::Reply(v.into_boxed_str().into_boxed_bytes()),
 			ConfigAction::Close      => FilterAction::Close,
 			ConfigAction::Abort      => FilterAction::Abort
@@ -480,30 +622,16 @@ impl Filter {
 		
 		if let Some(cfg) = cfg.ip {
 			if let Some(addr) = cfg.addr {
-				let (ip, mask) = addr.split_once('/').unwrap_or((&addr, "128"));
-				
-				self_.ip_addr = match std::net::IpAddr::from_str(ip) {
-					Ok(v)  => match v {
-						std::net::IpAddr::V4(v) => (u32::from_be_bytes(v.octets()) as u128) << 96,
-						std::net::IpAddr::V6(v) => u128::from_be_bytes(v.octets())
-					},
-					Err(_) => {
-						log::error!("processor `{}` filter `{}`: `ip.addr` is not a valid IP address", name, &self_.name);
-						return None;
-					}
-				};
-				self_.ip_mask = match usize::from_str(ip) {
-					Ok(v)  => !0 << (128 - v),
-					Err(_) => {
-						log::error!("processor `{}` filter `{}`: `ip.addr` is not a valid IP address", name, &self_.name);
+				self_.ip_addr = match Cidr::from_str(&addr) {
+					Ok(v)  => Some(v),
+					Err(e) => {
+						log::error!("processor `{}` filter `{}`: `ip.addr` {}", name, &self_.name, e);
 						return None;
 					}
 				};
-			} else {
-				self_.ip_mask = !0;
 			}
 			
-			self_.ports = cfg.port.unwrap_or((0, u16::MAX));
+			self_.ip_ports = cfg.port;
 		}
 		
 		if let Some(cfg) = cfg.tls {
@@ -523,6 +651,9 @@ impl Filter {
 				None => None
 			};
 			self_.tls_alpn = cfg.alpn;
+			self_.tls_client_subject     = cfg.client_subject;
+			self_.tls_client_san         = cfg.client_san;
+			self_.tls_client_fingerprint = cfg.client_fingerprint;
 		}
 		
 		if let Some(cfg) = cfg.http {
@@ -533,6 +664,8 @@ impl Filter {
 			self_.http_path_add_prefix         = cfg.path_add_prefix.map(|v| PathBuf::from(v).into_boxed_path());
 			self_.http_path_add_suffix         = cfg.path_add_suffix.map(|v| PathBuf::from(v).into_boxed_path());
 			self_.http_query_match             = cfg.query_match;
+			self_.http_websocket_protocol      = cfg.websocket_protocol;
+			self_.http_jwt_claims              = cfg.jwt_claims;
 			self_.http_query_add               = cfg.query_add;
 			self_.http_query_modify            = cfg.query_modify;
 			self_.http_query_del               = cfg.query_del;
@@ -548,6 +681,11 @@ impl Filter {
 			self_.http_response_headers_modify = cfg.response_headers_modify;
 			self_.http_response_content_match  = cfg.response_content_match;
 			self_.http_response_content_modify = cfg.response_content_modify;
+			self_.http_timeouts                = RequestTimeouts {
+				first_byte: cfg.timeouts.first_byte,
+				total:      cfg.timeouts.total,
+				idle:       cfg.timeouts.idle
+			};
 		}
 		
 		if let Some(cfg) = cfg.rtsp {
@@ -555,16 +693,92 @@ impl Filter {
 		}
 		
 		if let Some(cfg) = cfg.smtp {
-		
+			self_.smtp_sender        = cfg.sender;
+			self_.smtp_recipient_any = cfg.recipient_any;
+			self_.smtp_recipient_all = cfg.recipient_all;
+			self_.smtp_recipient_add = smtp_split_addresses(&cfg.recipient_add);
+			self_.smtp_recipient_del = smtp_split_addresses(&cfg.recipient_del);
 		}
 		
 		if let Some(cfg) = cfg.imf {
-		
+			self_.imf_request_headers_match  = cfg.request_headers_match;
+			self_.imf_request_headers_add    = cfg.request_headers_add;
+			self_.imf_request_headers_del    = cfg.request_headers_del;
+			self_.imf_request_headers_modify = cfg.request_headers_modify;
+			self_.imf_request_content_match  = cfg.request_content_match;
+			self_.imf_request_content_modify = cfg.request_content_modify;
 		}
 		
 		Some(self_)
 	}
 	
+	/// Matches the client address the socket attached to the request, which is the address sent by
+	/// a trusted proxy if any.
+	fn match_ip(&self, headers: &[http::Header]) -> bool {
+		if self.ip_addr.is_none() && self.ip_ports.is_none() {
+			return true;
+		}
+		
+		self.match_addr(ConnectionInfo::from_headers(headers).addr)
+	}
+	
+	/// Matches the client address of a connection, e.g. of an SMTP session.
+	fn match_addr(&self, addr: Option<std::net::SocketAddr>) -> bool {
+		if self.ip_addr.is_none() && self.ip_ports.is_none() {
+			return true;
+		}
+		
+		match addr {
+			Some(addr) => self.ip_addr.map_or(true, |v| v.contains(addr.ip()))
+				&& self.ip_ports.map_or(true, |(min, max)| (min..=max).contains(&addr.port())),
+			None => false
+		}
+	}
+	
+	/// Matches the client certificate identity the socket attached to the request.
+	fn match_tls_client(&self, headers: &[http::Header]) -> bool {
+		let ignore = |v: &StringMatcher| matches!(v, StringMatcher::Ignore);
+		if ignore(&self.tls_client_subject) && ignore(&self.tls_client_san) && ignore(&self.tls_client_fingerprint) {
+			return true;
+		}
+		
+		let identity = ClientIdentity::from_headers(headers);
+		let sans = identity.as_ref().map_or(&[][..], |v| &v.sans[..]);
+		
+		self.tls_client_subject.matches(identity.as_ref().map(|v| v.subject.as_str()))
+			&& self.tls_client_fingerprint.matches(identity.as_ref().map(|v| v.fingerprint.as_str()))
+			&& match sans.is_empty() {
+				true  => self.tls_client_san.matches(None),
+				false => sans.iter().any(|v| self.tls_client_san.matches(Some(v)))
+			}
+	}
+	
+	/// Matches the subprotocols offered in a WebSocket upgrade request.
+	fn match_http_upgrade(&self, headers: &[http::Header]) -> bool {
+		let mut protocols = headers.iter()
+			.filter_map(|v| match v {
+				http::Header::SecWebSocketProtocol(v) => Some(v),
+				_ => None
+			})
+			.flatten()
+			.peekable();
+		
+		match protocols.peek() {
+			Some(_) => protocols.any(|v| self.http_websocket_protocol.matches(Some(v))),
+			None    => self.http_websocket_protocol.matches(None)
+		}
+	}
+	
+	/// Matches the JWT claims an auth module attached to the request.
+	fn match_http_jwt_claims(&self, headers: &[http::Header]) -> bool {
+		if self.http_jwt_claims.is_empty() {
+			return true;
+		}
+		
+		let claims = super::auth::forwarded_claims(headers).unwrap_or_default();
+		self.http_jwt_claims.iter().all(|(name, matcher)| super::auth::match_claim(&claims, name, matcher))
+	}
+	
 	fn match_http_request_headers<'a>(&self, headers: impl IntoIterator<Item = &'a http::Header>) -> bool {
 		if matches!(self.http_path_match, StringMatcher::Ignore) && self.http_query_match.is_empty() && self.http_request_headers_match.is_empty() {
 			return true;
@@ -682,12 +896,126 @@ impl Filter {
 				&h.id(), |k| k.as_ref().map_err(String::as_str).map(|v| *v)).is_err())
 			.chain(self.http_response_headers_add.iter())
 	}
+	
+	/// Matches the envelope of a mail transaction.
+	fn match_smtp(&self, transaction: &MailTransaction) -> bool {
+		self.smtp_sender.matches(Some(&transaction.sender))
+			&& match transaction.recipients.is_empty() {
+				true  => self.smtp_recipient_any.matches(None),
+				false => transaction.recipients.iter().any(|v| self.smtp_recipient_any.matches(Some(v)))
+			}
+			&& transaction.recipients.iter().all(|v| self.smtp_recipient_all.matches(Some(v)))
+	}
+	
+	/// Matches the headers and the body of a message. Header names are case-insensitive, a
+	/// matcher applies to any header with its name.
+	fn match_imf_request(&self, transaction: &MailTransaction) -> bool {
+		if self.imf_request_headers_match.is_empty() && matches!(self.imf_request_content_match, StringMatcher::Ignore) {
+			return true;
+		}
+		
+		let headers = transaction.headers().collect::<Vec<_>>();
+		
+		for (id, matcher) in &self.imf_request_headers_match {
+			let name = imf_header_name(id);
+			let mut values = headers.iter()
+				.filter(|(k, _)| k.eq_ignore_ascii_case(name))
+				.peekable();
+			
+			let matches = match values.peek() {
+				Some(_) => values.any(|(_, v)| matcher.matches(Some(v))),
+				None    => matcher.matches(None)
+			};
+			
+			if !matches {
+				return false;
+			}
+		}
+		
+		if self.match_exact && !headers.iter().all(|(k, _)| self.imf_request_headers_match.iter()
+			.any(|(id, _)| imf_header_name(id).eq_ignore_ascii_case(k)))
+		{
+			return false;
+		}
+		
+		let body = &transaction.message[transaction.headers_len()..];
+		self.imf_request_content_match.matches(std::str::from_utf8(body).ok())
+	}
+	
+	/// Rewrites the recipients and the headers of a mail transaction.
+	fn filter_mail(&self, transaction: &mut MailTransaction) {
+		transaction.recipients.retain(|v| !self.smtp_recipient_del.iter().any(|d| d.eq_ignore_ascii_case(v)));
+		
+		for recipient in &self.smtp_recipient_add {
+			if !transaction.recipients.iter().any(|v| v.eq_ignore_ascii_case(recipient)) {
+				transaction.recipients.push(recipient.clone());
+			}
+		}
+		
+		if self.imf_request_headers_add.is_empty()
+			&& self.imf_request_headers_del.is_empty()
+			&& self.imf_request_headers_modify.is_empty()
+			&& self.imf_request_content_modify.is_none()
+		{
+			return;
+		}
+		
+		let headers_len = transaction.headers_len();
+		let mut buf = Vec::with_capacity(transaction.message.len());
+		
+		// header fields with their folded lines, which are kept as they are unless modified
+		let headers = String::from_utf8_lossy(&transaction.message[..headers_len]);
+		let mut fields = Vec::<String>::new();
+		
+		for line in headers.split_inclusive("\r\n").filter(|v| *v != "\r\n") {
+			match fields.last_mut() {
+				Some(field) if line.starts_with([' ', '\t']) => field.push_str(line),
+				_ => fields.push(line.to_string())
+			}
+		}
+		
+		for field in &fields {
+			let name = field.split_once(':').map_or(field.as_str(), |(k, _)| k).trim();
+			
+			if self.imf_request_headers_del.iter().any(|id| imf_header_name(id).eq_ignore_ascii_case(name)) {
+				continue;
+			}
+			
+			match self.imf_request_headers_modify.iter().find(|h| h.name().eq_ignore_ascii_case(name)) {
+				Some(header) => buf.extend_from_slice(format!("{}: {}\r\n", header.name(), header).as_bytes()),
+				None         => buf.extend_from_slice(field.as_bytes())
+			}
+		}
+		
+		for header in &self.imf_request_headers_add {
+			buf.extend_from_slice(format!("{}: {}\r\n", header.name(), header).as_bytes());
+		}
+		
+		buf.extend_from_slice(b"\r\n");
+		
+		match &self.imf_request_content_modify {
+			Some(content) => buf.extend_from_slice(content.as_bytes()),
+			None          => buf.extend_from_slice(&transaction.message[headers_len..])
+		}
+		
+		transaction.message = buf;
+	}
 }
 
-async fn discard_body(stream: &mut dyn http::traits::AsyncStream) -> io::Result<()> {
-	let mut buf = Vec::new();
-	stream.read_to_end(&mut buf).await?;
-	Ok(())
+fn imf_header_name(id: &HeaderId<imf::HeaderId>) -> &str {
+	match id {
+		Ok(id)    => id.name(),
+		Err(name) => name
+	}
+}
+
+/// Splits a comma separated list of addresses, e.g. of `smtp.recipient_add`.
+fn smtp_split_addresses(list: &str) -> Vec<String> {
+	list.split(',')
+		.map(str::trim)
+		.filter(|v| !v.is_empty())
+		.map(|v| v.trim_start_matches('<').trim_end_matches('>').to_string())
+		.collect()
 }
 
 // TODO implement content filtering
@@ -756,4 +1084,78 @@ impl<'a> http::traits::AsyncStream for FilteredHttpStream<'a> {
 			Poll::Pending       => Poll::Pending
 		}
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn transaction() -> MailTransaction {
+		MailTransaction {
+			sender:     "alice@example.com".to_string(),
+			recipients: vec!["bob@example.com".to_string()],
+			message:    b"Subject: Hello\r\nX-Spam: yes\r\nX-Long: a\r\n b\r\n\r\nHi\r\n".to_vec(),
+			..MailTransaction::default()
+		}
+	}
+	
+	#[test]
+	fn match_mail() {
+		let mut filter = Filter {
+			smtp_sender:               StringMatcher::Suffix("@example.com".to_string()),
+			imf_request_headers_match: vec![(Ok(imf::HeaderId::Subject), StringMatcher::Exact("Hello".to_string()))],
+			..Filter::default()
+		};
+		
+		let transaction = transaction();
+		assert!(filter.match_smtp(&transaction));
+		assert!(filter.match_imf_request(&transaction));
+		
+		filter.imf_request_headers_match.push((Err("x-long".to_string()), StringMatcher::Exact("a b".to_string())));
+		assert!(filter.match_imf_request(&transaction));
+		
+		filter.match_exact = true;
+		assert!(!filter.match_imf_request(&transaction));
+		
+		filter.smtp_recipient_all = StringMatcher::Suffix("@example.org".to_string());
+		assert!(!filter.match_smtp(&transaction));
+	}
+	
+	#[test]
+	fn match_websocket_protocol() {
+		let upgrade = |protocols: &[&str]| vec![
+			http::Header::Method(http::Method::Get),
+			http::Header::Upgrade("websocket".to_string()),
+			http::Header::SecWebSocketProtocol(protocols.iter().map(ToString::to_string).collect())
+		];
+		
+		let mut filter = Filter::default();
+		assert!(filter.match_http_upgrade(&upgrade(&["chat"])));
+		assert!(filter.match_http_upgrade(&[]));
+		
+		filter.http_websocket_protocol = StringMatcher::Exact("chat".to_string());
+		assert!(filter.match_http_upgrade(&upgrade(&["superchat", "chat"])));
+		assert!(!filter.match_http_upgrade(&upgrade(&["superchat"])));
+		assert!(!filter.match_http_upgrade(&[http::Header::Method(http::Method::Get)]));
+		
+		filter.http_websocket_protocol = StringMatcher::Present(false);
+		assert!(filter.match_http_upgrade(&[http::Header::Method(http::Method::Get)]));
+		assert!(!filter.match_http_upgrade(&upgrade(&["chat"])));
+	}
+	
+	#[test]
+	fn filter_mail() {
+		let filter = Filter {
+			smtp_recipient_add:         smtp_split_addresses("<carol@example.com>, ,bob@example.com"),
+			smtp_recipient_del:         smtp_split_addresses("BOB@example.com"),
+			imf_request_headers_del:    vec![Err("X-Spam".to_string())],
+			imf_request_headers_modify: vec![imf::Header::Subject("Re: Hello".to_string())],
+			imf_request_headers_add:    vec![imf::Header::Custom("X-Filtered".to_string(), "1".to_string())],
+			..Filter::default()
+		};
+		
+		let mut transaction = transaction();
+		filter.filter_mail(&mut transaction);
+		assert_eq!(transaction.recipients, ["carol@example.com", "bob@example.com"]);
+		assert_eq!(transaction.message, b"Subject: Re: Hello\r\nX-Long: a\r\n b\r\nX-Filtered: 1\r\n\r\nHi\r\n");
+	}
+}
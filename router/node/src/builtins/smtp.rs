@@ -0,0 +1,771 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A mail server, that hands the transactions of its sessions to a `MailHandler`.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*, global::upgrade},
+	std::{io, sync::Arc, time::Instant},
+	net::{
+		smtp::{self, traits::{AsyncServerConnection, AsyncServerConnectionExt}},
+		utils::AsyncAcceptorExt,
+		buffered::AsyncBufStream,
+		tls::r#async::{TlsAcceptor, rustls::Session as _}
+	},
+	smol::io::{AsyncRead, AsyncWrite},
+	dyn_error::Result
+};
+
+const DEFAULT_PORT_TLS: u16 = 465;
+const IDLE_TIMEOUT:     Duration = Duration::from_secs(300);
+const DATA_TIMEOUT:     Duration = Duration::from_secs(600);
+const TLS_TIMEOUT:      Duration = Duration::from_secs(30);
+
+/// Failed `AUTH` attempts after which the connection is closed.
+const MAX_AUTH_FAILURES: usize = 3;
+
+/// Accepts mail over TLS, or over plain connections that may start TLS with `STARTTLS`.
+pub(super) async fn run(name: Arc<String>, cfg: ConfigSocket, smtp: ConfigSocketSmtp, id: u128) -> Result<()> {
+	let tls = match &cfg.tls {
+		Some(tls) => Some(TlsAcceptor::from(tls::server_config(&name, tls).await?)),
+		None      => None
+	};
+	
+	let implicit_tls = tls.is_some() && !smtp.starttls;
+	let default_port = if implicit_tls { DEFAULT_PORT_TLS } else { net::smtp::DEFAULT_PORT };
+	let (listener, endpoint) = transport::Listener::bind(&name, &cfg, default_port).await?;
+	log::info!("frontend `{}` (smtp://{}): up", &name, &endpoint);
+	
+	let server = Arc::new(Server {
+		name,
+		endpoint,
+		auth:      smtp.auth.as_deref()
+			.map(|v| crate::get_component::<CredentialsHandler>(crate::component_id(v))),
+		processor: crate::get_component::<MailHandler>(id),
+		tls,
+		cfg:       smtp
+	});
+	
+	let mut acceptor = transport::Acceptor::new(listener);
+	crate::spawn(async move {
+		loop {
+			let server = server.clone();
+			let f = acceptor.accept().await;
+			crate::spawn(async move {
+				let stream = match f.await {
+					Ok(v) => v,
+					Err(e) => {
+						log::error!("frontend `{}` (smtp://{}): failed to accept connection: {}", &server.name, &server.endpoint, e);
+						return;
+					}
+				};
+				
+				server.handle(stream, implicit_tls).await
+			});
+		}
+	});
+	
+	Ok(())
+}
+
+struct Server {
+	name:      Arc<String>,
+	endpoint:  String,
+	cfg:       ConfigSocketSmtp,
+	tls:       Option<TlsAcceptor>,
+	processor: ComponentRef<MailHandler>,
+	auth:      Option<ComponentRef<CredentialsHandler>>
+}
+
+/// The state of a session, which is discarded by `STARTTLS`.
+#[derive(Default)]
+struct SessionState {
+	/// The domain sent with `EHLO` or `HELO`.
+	helo:          Option<String>,
+	esmtp:         bool,
+	user:          Option<String>,
+	/// The number of failed `AUTH` attempts.
+	auth_failures: usize,
+	transaction:   Option<MailTransaction>,
+	connection:    ConnectionInfo
+}
+
+type Reply = (u16, String);
+
+impl Server {
+	async fn handle(&self, stream: transport::Stream, implicit_tls: bool) {
+		let _connection = upgrade::track_connection();
+		let connection = ConnectionInfo {
+			addr:       stream.peer_addr().ok().flatten(),
+			local_addr: stream.local_addr().ok().flatten(),
+			..ConnectionInfo::default()
+		};
+		
+		let r = match (&self.tls, implicit_tls) {
+			(Some(tls), true) => self.session_tls(tls, stream, connection, true).await,
+			(tls, _) => match self.session(stream, connection.clone(), true).await {
+				Ok(Some(stream)) => match tls {
+					Some(tls) => self.session_tls(tls, stream, connection, false).await,
+					None      => Ok(())
+				},
+				Ok(None) => Ok(()),
+				Err(e)   => Err(e)
+			}
+		};
+		
+		if let Err(e) = r {
+			log::debug!("frontend `{}` (smtp://{}): connection aborted: {}", &self.name, &self.endpoint, e.display());
+		}
+	}
+	
+	/// Runs a session after the TLS handshake, the client is only greeted if it did not send
+	/// `STARTTLS` before.
+	async fn session_tls(&self, tls: &TlsAcceptor, stream: transport::Stream, mut connection: ConnectionInfo, greet: bool) -> Result<()> {
+		let stream = timeout(Some(TLS_TIMEOUT), tls.accept(stream)).await?;
+		let (_, session) = stream.get_ref();
+		connection.tls_version = session.get_protocol_version().map(|v| format!("{:?}", v));
+		connection.tls_cipher  = session.get_negotiated_ciphersuite().map(|v| format!("{:?}", v.suite));
+		connection.tls_sni     = session.get_sni_hostname().map(ToString::to_string);
+		self.session(stream, connection, greet).await.map(|_| ())
+	}
+	
+	/// Runs a session until the client quits, returns the stream if the client sent `STARTTLS`.
+	async fn session<S: AsyncRead + AsyncWrite + Unpin + Send>(&self, stream: S, connection: ConnectionInfo, greet: bool) -> Result<Option<S>> {
+		let mut conn = smtp::AsyncServerConnection::new(AsyncBufStream::new(stream));
+		let is_tls = connection.tls_version.is_some();
+		let mut session = SessionState { connection, ..SessionState::default() };
+		
+		if greet {
+			conn.write_response(&smtp::reply(220, &format!("{} ESMTP", &self.cfg.hostname))).await?;
+		}
+		
+		loop {
+			// the process exits once all connections are closed
+			if upgrade::is_draining() && session.transaction.is_none() {
+				conn.write_response(&smtp::reply(421, "4.3.2 Service shutting down")).await?;
+				return Ok(None);
+			}
+			
+			let command = match timeout(Some(self.cfg.idle_timeout.unwrap_or(IDLE_TIMEOUT)), conn.read_command()).await {
+				Ok(v) => v,
+				Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+					conn.write_response(&smtp::reply(500, "5.5.2 Syntax error")).await?;
+					continue;
+				}
+				Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+					conn.write_response(&smtp::reply(421, "4.4.2 Idle timeout")).await?;
+					return Ok(None);
+				}
+				Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+				Err(e) => return Err(e.into())
+			};
+			
+			let (code, text) = match command {
+				smtp::Command::Ehlo(domain) => {
+					session.helo = Some(domain.into_owned());
+					session.esmtp = true;
+					session.transaction = None;
+					(250, self.extensions(&session, is_tls))
+				}
+				smtp::Command::Helo(domain) => {
+					session.helo = Some(domain.into_owned());
+					session.esmtp = false;
+					session.transaction = None;
+					(250, self.cfg.hostname.clone())
+				}
+				smtp::Command::Mail(arg) => self.mail(&mut session, &arg),
+				smtp::Command::Rcpt(arg) => self.rcpt(&mut session, &arg),
+				smtp::Command::Data => self.data(&mut conn, &mut session).await?,
+				smtp::Command::Rset => {
+					session.transaction = None;
+					(250, "2.0.0 OK".to_string())
+				}
+				smtp::Command::Noop => (250, "2.0.0 OK".to_string()),
+				smtp::Command::Vrfy(_) => (252, "2.5.0 Cannot verify the user".to_string()),
+				smtp::Command::Expn(_) => (502, "5.5.1 Command not implemented".to_string()),
+				smtp::Command::Help(_) => (214, "2.0.0 See RFC 5321".to_string()),
+				smtp::Command::Quit => {
+					conn.write_response(&smtp::reply(221, "2.0.0 Bye")).await?;
+					return Ok(None);
+				}
+				smtp::Command::Other(verb, None) if verb.eq_ignore_ascii_case("STARTTLS") => {
+					if self.tls.is_none() || is_tls {
+						(503, "5.5.1 TLS not available".to_string())
+					} else {
+						conn.write_response(&smtp::reply(220, "2.0.0 Ready to start TLS")).await?;
+						// commands pipelined after `STARTTLS` are discarded with the buffer
+						return Ok(Some(conn.into_inner().into_inner()));
+					}
+				}
+				smtp::Command::Other(verb, Some(arg)) if verb.eq_ignore_ascii_case("AUTH") =>
+					self.auth(&mut conn, &mut session, &arg, is_tls).await?,
+				smtp::Command::Other(..) => (500, "5.5.2 Command not recognized".to_string())
+			};
+			
+			conn.write_response(&smtp::reply(code, &text)).await?;
+			
+			if session.auth_failures >= MAX_AUTH_FAILURES {
+				conn.write_response(&smtp::reply(421, "4.7.0 Too many failed authentication attempts")).await?;
+				return Ok(None);
+			}
+		}
+	}
+	
+	/// The reply to `EHLO`, with the supported extensions.
+	fn extensions(&self, session: &SessionState, is_tls: bool) -> String {
+		let mut lines = vec![
+			self.cfg.hostname.clone(),
+			format!("SIZE {}", self.cfg.max_size),
+			"8BITMIME".to_string(),
+			"PIPELINING".to_string()
+		];
+		
+		if self.tls.is_some() && !is_tls {
+			lines.push("STARTTLS".to_string());
+		}
+		
+		if self.auth.is_some() && (is_tls || self.cfg.insecure_auth) && session.user.is_none() {
+			lines.push("AUTH PLAIN LOGIN".to_string());
+		}
+		
+		// always offered, so it stays the last line and the only one sent as `250 `
+		lines.push("ENHANCEDSTATUSCODES".to_string());
+		lines.join("\n")
+	}
+	
+	/// Starts a transaction, `SIZE` is checked against the maximum size and `BODY` may be
+	/// `7BIT` or `8BITMIME`.
+	fn mail(&self, session: &mut SessionState, arg: &str) -> Reply {
+		if session.helo.is_none() {
+			return (503, "5.5.1 Send EHLO first".to_string());
+		} else if session.transaction.is_some() {
+			return (503, "5.5.1 Nested MAIL command".to_string());
+		} else if self.cfg.require_auth && session.user.is_none() {
+			return (530, "5.7.0 Authentication required".to_string());
+		}
+		
+		let (sender, params) = match smtp::split_path(arg) {
+			Some(v) => v,
+			None    => return (501, "5.5.4 Invalid sender".to_string())
+		};
+		
+		for param in params.split_whitespace() {
+			let (key, value) = param.split_once('=').unwrap_or((param, ""));
+			
+			match key.to_ascii_uppercase().as_str() {
+				"SIZE" => match value.parse::<usize>() {
+					Ok(v) if v > self.cfg.max_size => return (552, "5.3.4 Message too big".to_string()),
+					Ok(_)  => (),
+					Err(_) => return (501, "5.5.4 Invalid SIZE".to_string())
+				},
+				"BODY" if value.eq_ignore_ascii_case("7BIT") || value.eq_ignore_ascii_case("8BITMIME") => (),
+				// the identity of a message submitted by an authenticated relay
+				"AUTH" => (),
+				_ => return (555, format!("5.5.4 Unsupported parameter {}", key))
+			}
+		}
+		
+		session.transaction = Some(MailTransaction {
+			sender:     sender.to_string(),
+			connection: session.connection.clone(),
+			..MailTransaction::default()
+		});
+		(250, "2.1.0 OK".to_string())
+	}
+	
+	fn rcpt(&self, session: &mut SessionState, arg: &str) -> Reply {
+		let transaction = match &mut session.transaction {
+			Some(v) => v,
+			None    => return (503, "5.5.1 Send MAIL first".to_string())
+		};
+		
+		let recipient = match smtp::split_path(arg) {
+			Some((path, "")) if !path.is_empty() => path,
+			Some((path, _)) if !path.is_empty() => return (555, "5.5.4 Unsupported parameters".to_string()),
+			_ => return (501, "5.1.3 Invalid recipient".to_string())
+		};
+		
+		if transaction.recipients.len() >= self.cfg.max_recipients {
+			return (452, "4.5.3 Too many recipients".to_string());
+		}
+		
+		transaction.recipients.push(recipient.to_string());
+		(250, "2.1.5 OK".to_string())
+	}
+	
+	/// Receives the message of a transaction and delivers it.
+	async fn data(&self, conn: &mut impl AsyncServerConnection, session: &mut SessionState) -> Result<Reply> {
+		match &session.transaction {
+			Some(v) if v.recipients.is_empty() => return Ok((554, "5.5.1 No valid recipients".to_string())),
+			Some(_) => (),
+			None    => return Ok((503, "5.5.1 Send MAIL first".to_string()))
+		}
+		
+		let mut transaction = session.transaction.take().unwrap(); // SAFE: checked above
+		conn.write_response(&smtp::reply(354, "End data with <CR><LF>.<CR><LF>")).await?;
+		
+		let message = match timeout(Some(DATA_TIMEOUT), conn.read_data(self.cfg.max_size)).await? {
+			Some(v) => v,
+			None    => return Ok((552, "5.3.4 Message too big".to_string()))
+		};
+		
+		transaction.message = self.received(session).into_bytes();
+		transaction.message.extend_from_slice(&message);
+		transaction.user = session.user.clone();
+		Ok(self.deliver(transaction).await)
+	}
+	
+	/// The trace header that is prepended to a message of this session.
+	fn received(&self, session: &SessionState) -> String {
+		let protocol = match (session.esmtp, session.connection.tls_version.is_some(), session.user.is_some()) {
+			(false, _, _)        => "SMTP",
+			(true, false, false) => "ESMTP",
+			(true, true, false)  => "ESMTPS",
+			(true, false, true)  => "ESMTPA",
+			(true, true, true)   => "ESMTPSA"
+		};
+		
+		let helo = session.helo.as_deref().unwrap_or("unknown");
+		let from = match session.connection.addr {
+			Some(addr) => format!("{} ([{}])", helo, addr.ip()),
+			None       => helo.to_string()
+		};
+		
+		format!("Received: {}\r\n", net::imf::Received {
+			token: format!("from {} by {} with {}", from, &self.cfg.hostname, protocol),
+			date:  chrono::Utc::now()
+		})
+	}
+	
+	async fn deliver(&self, mut transaction: MailTransaction) -> Reply {
+		let start = Instant::now();
+		
+		// this is unsafe, but that's ok, see HttpStreamHandler::accept
+		let transaction_static = unsafe { std::mem::transmute::<
+			&'_      mut MailTransaction,
+			&'static mut MailTransaction
+		>(&mut transaction) };
+		
+		let r = match self.processor.get().await {
+			Ok(processor) => processor.accept(transaction_static).await,
+			Err(e)        => Err(e)
+		};
+		
+		let (code, text) = match (r, transaction.reply.take()) {
+			(Ok(()), Some(reply)) => reply,
+			(r, _) => {
+				if let Err(e) = r {
+					log::error!("frontend `{}` (smtp://{}): error: {}", &self.name, &self.endpoint, e.display());
+				}
+				
+				(451, "4.3.0 Local error in processing".to_string())
+			}
+		};
+		
+		log::info!(
+			"frontend `{}` (smtp://{}): <{}> -> {} recipient(s), {} bytes -> {} ({} ms)",
+			&self.name,
+			&self.endpoint,
+			&transaction.sender,
+			transaction.recipients.len(),
+			transaction.message.len(),
+			code,
+			start.elapsed().as_millis()
+		);
+		(code, text)
+	}
+	
+	/// Authenticates the client with the `PLAIN` or `LOGIN` mechanism.
+	async fn auth(&self, conn: &mut impl AsyncServerConnection, session: &mut SessionState, arg: &str, is_tls: bool) -> Result<Reply> {
+		let auth = match &self.auth {
+			Some(v) if is_tls || self.cfg.insecure_auth => v,
+			_ => return Ok((503, "5.5.1 AUTH not available".to_string()))
+		};
+		
+		if session.user.is_some() {
+			return Ok((503, "5.5.1 Already authenticated".to_string()));
+		} else if session.transaction.is_some() {
+			return Ok((503, "5.5.1 AUTH not permitted during a transaction".to_string()));
+		}
+		
+		let (mechanism, initial) = match arg.split_once(' ') {
+			Some((mechanism, initial)) => (mechanism, Some(initial.trim())),
+			None => (arg, None)
+		};
+		
+		let credentials = if mechanism.eq_ignore_ascii_case("PLAIN") {
+			let response = match initial {
+				Some(v) => decode_sasl_response(v.as_bytes()),
+				None    => self.challenge(conn, "").await?
+			};
+			
+			// authorization identity, authentication identity and password
+			response.and_then(|v| {
+				let mut parts = v.splitn(3, '\0');
+				let (authzid, user, password) = (parts.next()?, parts.next()?, parts.next()?);
+				(authzid.is_empty() || authzid == user).then(|| (user.to_string(), password.to_string()))
+			})
+		} else if mechanism.eq_ignore_ascii_case("LOGIN") {
+			let user = match initial {
+				Some(v) => decode_sasl_response(v.as_bytes()),
+				None    => self.challenge(conn, "VXNlcm5hbWU6").await?
+			};
+			
+			match user {
+				Some(user) => self.challenge(conn, "UGFzc3dvcmQ6").await?.map(|password| (user, password)),
+				None       => None
+			}
+		} else {
+			return Ok((504, "5.5.4 Unrecognized authentication mechanism".to_string()));
+		};
+		
+		let (user, password) = match credentials {
+			Some(v) => v,
+			None => {
+				session.auth_failures += 1;
+				return Ok((501, "5.5.2 Invalid or cancelled response".to_string()));
+			}
+		};
+		
+		let mut credentials = Credentials { user, password, connection: session.connection.clone(), valid: false };
+		
+		// this is unsafe, but that's ok, see HttpStreamHandler::accept
+		let credentials_static = unsafe { std::mem::transmute::<
+			&'_      mut Credentials,
+			&'static mut Credentials
+		>(&mut credentials) };
+		
+		auth.get().await?.accept(credentials_static).await?;
+		
+		if !credentials.valid {
+			log::info!("frontend `{}` (smtp://{}): authentication of `{}` failed", &self.name, &self.endpoint, &credentials.user);
+			session.auth_failures += 1;
+			return Ok((535, "5.7.8 Authentication credentials invalid".to_string()));
+		}
+		
+		session.user = Some(credentials.user);
+		Ok((235, "2.7.0 Authentication successful".to_string()))
+	}
+	
+	/// Sends a base64 encoded challenge and returns the decoded response, `None` if it is
+	/// invalid or the client cancelled.
+	async fn challenge(&self, conn: &mut impl AsyncServerConnection, challenge: &str) -> Result<Option<String>> {
+		conn.write_response(&format!("334 {}\r\n", challenge)).await?;
+		// read as is, a response may look like a command, e.g. `Data`
+		let response = timeout(Some(self.cfg.idle_timeout.unwrap_or(IDLE_TIMEOUT)), conn.read_line()).await?;
+		Ok(decode_sasl_response(&response))
+	}
+}
+
+/// Decodes a base64 encoded SASL response, `=` is an empty response and `*` cancels.
+fn decode_sasl_response(v: &[u8]) -> Option<String> {
+	match v {
+		b"*" => None,
+		b"=" => Some(String::new()),
+		v   => String::from_utf8(base64::decode(v).ok()?).ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::builtins::storage::{Maildir, ConfigSmtp},
+		net::{smtp::traits::AsyncClientConnectionExt, tls::r#async::rustls},
+		smol::io::AsyncReadExt,
+		std::{future::Future, sync::Mutex}
+	};
+	
+	type Client = smtp::AsyncClientConnection<AsyncBufStream<TestDuplex>>;
+	
+	/// Records transactions and accepts them.
+	#[derive(Clone, Default)]
+	struct Queue(Arc<Mutex<Vec<MailTransaction>>>);
+	
+	impl StreamHandler<MailTransaction> for Queue {
+		fn accept<'a>(&'a self, transaction: &'static mut MailTransaction) -> DynFuture<'a, Result<()>> {
+			Box::pin(async move {
+				transaction.reply = Some((250, "2.0.0 Queued".to_string()));
+				self.0.lock().unwrap().push(transaction.clone());
+				Ok(())
+			})
+		}
+	}
+	
+	/// Accepts `Fǭ` with the password `secret`.
+	struct Users;
+	
+	impl StreamHandler<Credentials> for Users {
+		fn accept<'a>(&'a self, credentials: &'static mut Credentials) -> DynFuture<'a, Result<()>> {
+			Box::pin(async move {
+				credentials.valid = credentials.user == "Fǭ" && credentials.password == "secret";
+				Ok(())
+			})
+		}
+	}
+	
+	fn config() -> ConfigSocketSmtp {
+		ConfigSocketSmtp {
+			hostname:       "localhost".to_string(),
+			max_size:       1024,
+			max_recipients: 2,
+			starttls:       false,
+			auth:           None,
+			require_auth:   false,
+			insecure_auth:  false,
+			idle_timeout:   None
+		}
+	}
+	
+	fn new_server(cfg: ConfigSocketSmtp, processor: MailHandler, auth: Option<CredentialsHandler>, tls: Option<TlsAcceptor>) -> Server {
+		Server {
+			name:      Arc::new("smtp".to_string()),
+			endpoint:  "test".to_string(),
+			cfg,
+			tls,
+			processor: test_component(processor),
+			auth:      auth.map(test_component)
+		}
+	}
+	
+	/// Runs a session with the client, returns the stream if the client sent `STARTTLS`.
+	fn run<F: Future<Output = ()>>(server: &Server, connection: ConnectionInfo, client: impl FnOnce(Client) -> F) -> Option<TestDuplex> {
+		let (stream, client_stream) = TestDuplex::pair();
+		let client = client(smtp::AsyncClientConnection::new(AsyncBufStream::new(client_stream)));
+		
+		match smol::block_on(smol::future::zip(server.session(stream, connection, true), client)) {
+			(Ok(v), ())  => v,
+			(Err(e), ()) => panic!("{}", e.display())
+		}
+	}
+	
+	/// Sends a line as is and returns the reply.
+	async fn command(client: &mut Client, line: &str) -> String {
+		client.write_command(&smtp::Command::Other(line.into(), None)).await.unwrap();
+		client.read_response().await.unwrap()
+	}
+	
+	async fn greeting(client: &mut Client) {
+		assert_eq!(client.read_response().await.unwrap(), "220 localhost ESMTP\r\n");
+	}
+	
+	#[test]
+	fn order() {
+		let queue = Queue::default();
+		let server = new_server(config(), Box::new(queue.clone()), None, None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "503 5.5.1 Send EHLO first\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<b@example.com>").await, "503 5.5.1 Send MAIL first\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "503 5.5.1 Send MAIL first\r\n");
+			assert_eq!(command(&mut client, "EHLO client.example.com").await,
+				"250-localhost\r\n250-SIZE 1024\r\n250-8BITMIME\r\n250-PIPELINING\r\n250 ENHANCEDSTATUSCODES\r\n");
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "250 2.1.0 OK\r\n");
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "503 5.5.1 Nested MAIL command\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "554 5.5.1 No valid recipients\r\n");
+			assert_eq!(command(&mut client, "RSET").await, "250 2.0.0 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<b@example.com>").await, "503 5.5.1 Send MAIL first\r\n");
+			assert_eq!(command(&mut client, "QUIT").await, "221 2.0.0 Bye\r\n");
+		});
+		
+		// a missing `EHLO` is reported before missing authentication
+		let server = new_server(ConfigSocketSmtp { require_auth: true, ..config() }, Box::new(queue.clone()), None, None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "503 5.5.1 Send EHLO first\r\n");
+			assert!(command(&mut client, "EHLO client.example.com").await.starts_with("250-"));
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "530 5.7.0 Authentication required\r\n");
+		});
+		
+		assert!(queue.0.lock().unwrap().is_empty());
+	}
+	
+	#[test]
+	fn parameters() {
+		let server = new_server(config(), Box::new(Queue::default()), None, None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(command(&mut client, "EHLO client.example.com").await.starts_with("250-"));
+			
+			for (line, reply) in [
+				("MAIL FROM:<a@example.com> SIZE=1025", "552 5.3.4 Message too big\r\n"),
+				("MAIL FROM:<a@example.com> SIZE=large", "501 5.5.4 Invalid SIZE\r\n"),
+				("MAIL FROM:<a@example.com> BODY=BINARYMIME", "555 5.5.4 Unsupported parameter BODY\r\n"),
+				("MAIL FROM:<a@example.com> RET=HDRS", "555 5.5.4 Unsupported parameter RET\r\n"),
+				("MAIL FROM:a@example.com", "501 5.5.4 Invalid sender\r\n")
+			] {
+				assert_eq!(command(&mut client, line).await, reply, "{}", line);
+			}
+			
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com> SIZE=1024 BODY=8bitmime").await, "250 2.1.0 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<b@example.com> NOTIFY=NEVER").await, "555 5.5.4 Unsupported parameters\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<>").await, "501 5.1.3 Invalid recipient\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<b@example.com>").await, "250 2.1.5 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<c@example.com>").await, "250 2.1.5 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<d@example.com>").await, "452 4.5.3 Too many recipients\r\n");
+			assert_eq!(command(&mut client, "RSET").await, "250 2.0.0 OK\r\n");
+			assert_eq!(command(&mut client, "MAIL FROM:<> BODY=7BIT").await, "250 2.1.0 OK\r\n");
+		});
+	}
+	
+	#[test]
+	fn data() {
+		let queue = Queue::default();
+		let server = new_server(config(), Box::new(queue.clone()), None, None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(command(&mut client, "EHLO client.example.com").await.starts_with("250-"));
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "250 2.1.0 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<b@example.com>").await, "250 2.1.5 OK\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "354 End data with <CR><LF>.<CR><LF>\r\n");
+			client.write_data(b"Subject: test\r\n\r\n.leading dot\r\nbody\r\n").await.unwrap();
+			assert_eq!(client.read_response().await.unwrap(), "250 2.0.0 Queued\r\n");
+			
+			// the transaction ends with the message, even if it was too big
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "250 2.1.0 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<b@example.com>").await, "250 2.1.5 OK\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "354 End data with <CR><LF>.<CR><LF>\r\n");
+			client.write_data(&b"0123456789\r\n".repeat(100)).await.unwrap();
+			assert_eq!(client.read_response().await.unwrap(), "552 5.3.4 Message too big\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "503 5.5.1 Send MAIL first\r\n");
+		});
+		
+		let transactions = queue.0.lock().unwrap();
+		assert_eq!(transactions.len(), 1);
+		assert_eq!(transactions[0].sender, "a@example.com");
+		assert_eq!(transactions[0].recipients, ["b@example.com"]);
+		assert_eq!(transactions[0].user, None);
+		
+		let message = String::from_utf8(transactions[0].message.clone()).unwrap();
+		assert!(message.starts_with("Received: from client.example.com by localhost with ESMTP; "), "{}", message);
+		assert!(message.ends_with("\r\nSubject: test\r\n\r\n.leading dot\r\nbody\r\n"), "{}", message);
+	}
+	
+	#[test]
+	fn auth() {
+		let cfg = ConfigSocketSmtp { auth: Some("users".to_string()), insecure_auth: true, ..config() };
+		let server = new_server(cfg, Box::new(Queue::default()), Some(Box::new(Users)), None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(command(&mut client, "EHLO client.example.com").await.contains("\r\n250-AUTH PLAIN LOGIN\r\n"));
+			assert_eq!(command(&mut client, "AUTH LOGIN").await, "334 VXNlcm5hbWU6\r\n");
+			// the user name looks like `RSET`, but must not be read as a command
+			assert_eq!(command(&mut client, "Rset").await, "334 UGFzc3dvcmQ6\r\n");
+			assert_eq!(command(&mut client, "c2VjcmV0").await, "235 2.7.0 Authentication successful\r\n");
+			assert!(!command(&mut client, "EHLO client.example.com").await.contains("AUTH"));
+			assert_eq!(command(&mut client, "AUTH PLAIN AEbHrQBzZWNyZXQ=").await, "503 5.5.1 Already authenticated\r\n");
+		});
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(command(&mut client, "EHLO client.example.com").await.starts_with("250-"));
+			assert_eq!(command(&mut client, "AUTH PLAIN AHVzZXIAd3Jvbmc=").await, "535 5.7.8 Authentication credentials invalid\r\n");
+			assert_eq!(command(&mut client, "AUTH LOGIN").await, "334 VXNlcm5hbWU6\r\n");
+			assert_eq!(command(&mut client, "*").await, "501 5.5.2 Invalid or cancelled response\r\n");
+			assert_eq!(command(&mut client, "AUTH PLAIN =").await, "501 5.5.2 Invalid or cancelled response\r\n");
+			assert_eq!(client.read_response().await.unwrap(), "421 4.7.0 Too many failed authentication attempts\r\n");
+			assert!(client.read_response().await.is_err());
+		});
+		
+		// `AUTH` is only offered over TLS by default
+		let cfg = ConfigSocketSmtp { auth: Some("users".to_string()), ..config() };
+		let server = new_server(cfg, Box::new(Queue::default()), Some(Box::new(Users)), None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(!command(&mut client, "EHLO client.example.com").await.contains("AUTH"));
+			assert_eq!(command(&mut client, "AUTH PLAIN AEbHrQBzZWNyZXQ=").await, "503 5.5.1 AUTH not available\r\n");
+		});
+	}
+	
+	#[test]
+	fn starttls() {
+		let tls = TlsAcceptor::from(Arc::new(rustls::ServerConfig::new(rustls::NoClientAuth::new())));
+		let server = new_server(ConfigSocketSmtp { starttls: true, ..config() }, Box::new(Queue::default()), None, Some(tls));
+		
+		let stream = run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(command(&mut client, "EHLO client.example.com").await.contains("\r\n250-STARTTLS\r\n"));
+			// commands pipelined after `STARTTLS` are not executed
+			assert_eq!(command(&mut client, "STARTTLS\r\nNOOP").await, "220 2.0.0 Ready to start TLS\r\n");
+		});
+		
+		let mut buf = Vec::new();
+		smol::block_on(stream.expect("session did not return the stream").read_to_end(&mut buf)).unwrap();
+		assert!(buf.is_empty(), "{:?}", String::from_utf8_lossy(&buf));
+		
+		let connection = ConnectionInfo { tls_version: Some("TLSv1_3".to_string()), ..ConnectionInfo::default() };
+		let stream = run(&server, connection, |mut client| async move {
+			greeting(&mut client).await;
+			assert!(!command(&mut client, "EHLO client.example.com").await.contains("STARTTLS"));
+			assert_eq!(command(&mut client, "STARTTLS").await, "503 5.5.1 TLS not available\r\n");
+			assert_eq!(command(&mut client, "QUIT").await, "221 2.0.0 Bye\r\n");
+		});
+		
+		assert!(stream.is_none());
+	}
+	
+	#[test]
+	fn maildir() {
+		let dir = test_dir("smtp-maildir");
+		let cfg = ConfigSmtp { per_recipient: true, hostname: "mx.example.com".to_string() };
+		let server = new_server(config(), Box::new(Maildir::new("mail", dir.clone(), cfg)), None, None);
+		
+		run(&server, ConnectionInfo::default(), |mut client| async move {
+			greeting(&mut client).await;
+			assert!(command(&mut client, "EHLO client.example.com").await.starts_with("250-"));
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "250 2.1.0 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<B@example.com>").await, "250 2.1.5 OK\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "354 End data with <CR><LF>.<CR><LF>\r\n");
+			client.write_data(b"Subject: test\r\n\r\nbody\r\n").await.unwrap();
+			assert!(client.read_response().await.unwrap().starts_with("250 2.0.0 OK queued as "));
+			
+			assert_eq!(command(&mut client, "MAIL FROM:<a@example.com>").await, "250 2.1.0 OK\r\n");
+			assert_eq!(command(&mut client, "RCPT TO:<../b@example.com>").await, "250 2.1.5 OK\r\n");
+			assert_eq!(command(&mut client, "DATA").await, "354 End data with <CR><LF>.<CR><LF>\r\n");
+			client.write_data(b"Subject: test\r\n\r\nbody\r\n").await.unwrap();
+			assert_eq!(client.read_response().await.unwrap(), "550 5.1.3 Bad recipient address syntax\r\n");
+		});
+		
+		let mailbox = dir.join("b@example.com");
+		assert_eq!(std::fs::read_dir(mailbox.join("tmp")).unwrap().count(), 0);
+		assert_eq!(std::fs::read_dir(mailbox.join("cur")).unwrap().count(), 0);
+		
+		let files = std::fs::read_dir(mailbox.join("new")).unwrap()
+			.map(|v| v.unwrap().path())
+			.collect::<Vec<_>>();
+		assert_eq!(files.len(), 1);
+		assert!(files[0].to_str().unwrap().ends_with(".mx.example.com"), "{}", files[0].display());
+		
+		let message = std::fs::read_to_string(&files[0]).unwrap();
+		assert!(message.starts_with("Received: from client.example.com by localhost with ESMTP; "), "{}", message);
+		assert!(message.ends_with("\r\nSubject: test\r\n\r\nbody\r\n"), "{}", message);
+		
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
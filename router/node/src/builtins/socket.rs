@@ -22,50 +22,97 @@
 
 use {
 	super::*,
-	crate::interfaces::*,
-	std::{io, sync::Arc, task::{Poll, Context}, pin::Pin},
-	net::{tls, http::{self, traits::AsyncSharedConnectionExt}, utils::AsyncAcceptorExt},
-	smol::{io::AsyncWriteExt},
+	crate::{interfaces::*, utils::*, global::upgrade},
+	std::{io, sync::Arc, task::{Poll, Context}, pin::Pin, future::Future, net::SocketAddr},
+	net::{http::{self, traits::AsyncSharedConnectionExt}, utils::{AsyncAcceptor, AsyncAcceptorExt}, tls::r#async::rustls::Session},
+	smol::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
 	dyn_error::Result
 };
 
-const LOCALHOST: &str = "localhost";
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const DNS_DEFAULT_PORT:     u16 = 53;
+const DNS_DEFAULT_PORT_TLS: u16 = 853;
+const DNS_IDLE_TIMEOUT:     Duration = Duration::from_secs(10);
+const DNS_READ_TIMEOUT:     Duration = Duration::from_secs(10);
+const DNS_MAX_MESSAGE_LEN:  usize = 0xFFFF;
+const DNS_HEADER_LEN:       usize = 12;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct Config {
 	#[serde(flatten)]
 	pub socket:     super::ConfigSocket,
 	#[serde(default)]
 	pub processor: String,
+	/// Proxies in front of the socket, that send the address of the client.
+	pub proxy:     Option<ConfigProxy>
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigProxy {
+	/// The networks of the trusted proxies, e.g. `10.0.0.0/8`.
+	pub trusted:        Vec<Cidr>,
+	/// Requires a PROXY protocol v1 or v2 header on connections of trusted proxies.
+	#[serde(default)]
+	pub proxy_protocol: bool,
+	/// Takes the client address from `Forwarded` or `X-Forwarded-For` headers of trusted proxies,
+	/// these headers of other clients are removed.
+	#[serde(default)]
+	pub forwarded:      bool
 }
 
 pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 	let id   = crate::component_id(&cfg.processor);
 	let name = Arc::new(name.to_string());
 	
+	if let Some(dns) = cfg.socket.dns.clone() {
+		if cfg.proxy.is_some() {
+			return Err("`proxy` is not supported by DNS sockets".into());
+		}
+		
+		return dns_run(name, cfg.socket, dns, id).await;
+	}
+	
+	if let Some(smtp) = cfg.socket.smtp.clone() {
+		if cfg.proxy.is_some() {
+			return Err("`proxy` is not supported by SMTP sockets".into());
+		}
+		
+		return super::smtp::run(name, cfg.socket, smtp, id).await;
+	}
+	
+	// PROXY protocol headers are only read from trusted proxies, so none are read if empty
+	let proxy_trusted: Arc<[Cidr]> = match &cfg.proxy {
+		Some(ConfigProxy { trusted, proxy_protocol: true, .. }) => trusted.clone().into(),
+		_ => Vec::new().into()
+	};
+	let forwarded = Arc::new(match cfg.proxy {
+		Some(ConfigProxy { trusted, forwarded: true, .. }) => Some(trusted),
+		_ => None
+	});
+	
+	let (listener, endpoint) = transport::Listener::bind(&name, &cfg.socket, net::http::v1::DEFAULT_PORT_TLS).await?;
+	let endpoint = Arc::new(endpoint);
+	
 	match cfg.socket {
-		ConfigSocket { tcp: Some(tcp), tls: None, http1: Some(_), .. } => {
-			let endpoint = Arc::new(format!(
-				"{}:{}",
-				tcp.host.as_deref().unwrap_or(LOCALHOST),
-				tcp.port.unwrap_or(net::http::v1::DEFAULT_PORT_TLS)
-			));
+		ConfigSocket { tls: None, http1: Some(http), .. } => {
 			let processor = crate::get_component::<HttpStreamHandler>(id);
 			let telemetry = Arc::new(HttpTelemetry::new(&name, &endpoint));
-			let mut acceptor = net::http::v1::AsyncSharedAcceptor::new(
-				net::http::v1::AsyncAcceptor::new(
-					net::buffered::AsyncAcceptor::new(
-							net::tcp::AsyncAcceptor::new(&*endpoint).await?)));
+			let limits = HttpLimits::new(&http);
+			let mut acceptor = ProxyAcceptor {
+				inner:   transport::Acceptor::new(listener),
+				trusted: proxy_trusted
+			};
 			
 			log::info!("frontend `{}` (https://{}): up", &name, &endpoint);
 			
-			let ctx = (processor, name, endpoint, telemetry);
+			let ctx = (processor, name, endpoint, telemetry, forwarded);
 			crate::spawn(async move {
 				loop {
-					let (processor, name, endpoint, telemetry) = ctx.clone();
+					let (processor, name, endpoint, telemetry, forwarded) = ctx.clone();
 					let f = acceptor.accept().await;
 					crate::spawn(async move {
-						let conn = match f.await {
+						let stream = match f.await {
 							Ok(v) => v,
 							Err(e) => {
 								log::error!("frontend `{}` (https://{}): failed to accept connection: {}", &name, &endpoint, e);
@@ -73,35 +120,42 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 							}
 						};
 						
-						http_handle(conn, &name, &endpoint, &*processor, &telemetry).await
+						let client = ClientInfo {
+							connection: ConnectionInfo {
+								addr:       stream.peer_addr,
+								local_addr: stream.local_addr,
+								..ConnectionInfo::default()
+							},
+							forwarded: forwarded.as_deref(),
+							..ClientInfo::default()
+						};
+						let conn = net::http::v1::AsyncSharedConnection::new(
+							net::http::v1::AsyncConnection::new(
+								net::buffered::AsyncBufStream::new(stream)));
+						
+						http_handle(conn, &name, &endpoint, &processor, &telemetry, &client, limits).await
 					});
 				}
 			});
 		}
-		ConfigSocket { tcp: Some(tcp), tls: Some(tls @ ConfigSocketTls { alpn: false, .. }), http1: Some(_), .. } => {
-			let endpoint = Arc::new(format!(
-				"{}:{}",
-				tcp.host.as_deref().unwrap_or(LOCALHOST),
-				tcp.port.unwrap_or(net::http::v1::DEFAULT_PORT_TLS)
-			));
+		ConfigSocket { tls: Some(tls @ ConfigSocketTls { alpn: false, .. }), http1: Some(http), .. } => {
 			let processor = crate::get_component::<HttpStreamHandler>(id);
 			let telemetry = Arc::new(HttpTelemetry::new(&name, &endpoint));
-			let mut acceptor = net::http::v1::AsyncSharedAcceptor::new(
-				net::http::v1::AsyncAcceptor::new(
-					net::buffered::AsyncAcceptor::new(
-						net::tls::AsyncAcceptor::new(
-							net::tcp::AsyncAcceptor::new(&*endpoint).await?,
-							tls_config(&tls).await?))));
+			let forward_header = Arc::new(tls.client_auth.as_ref().and_then(|v| v.forward_header.clone()));
+			let limits = HttpLimits::new(&http);
+			let mut acceptor = net::tls::AsyncAcceptor::new(
+				ProxyAcceptor { inner: transport::Acceptor::new(listener), trusted: proxy_trusted },
+				tls::server_config(&name, &tls).await?);
 			
 			log::info!("frontend `{}` (https://{}): up", &name, &endpoint);
 			
-			let ctx = (processor, name, endpoint, telemetry);
+			let ctx = (processor, name, endpoint, telemetry, forward_header, forwarded);
 			crate::spawn(async move {
 				loop {
-					let (processor, name, endpoint, telemetry) = ctx.clone();
+					let (processor, name, endpoint, telemetry, forward_header, forwarded) = ctx.clone();
 					let f = acceptor.accept().await;
 					crate::spawn(async move {
-						let conn = match f.await {
+						let stream = match f.await {
 							Ok(v) => v,
 							Err(e) => {
 								log::error!("frontend `{}` (https://{}): failed to accept connection: {}", &name, &endpoint, e);
@@ -109,7 +163,28 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 							}
 						};
 						
-						http_handle(conn, &name, &endpoint, &*processor, &telemetry).await
+						let (tcp, session) = stream.get_ref();
+						let identity = session.get_peer_certificates()
+							.and_then(|certs| tls::client_identity(&certs));
+						let connection = ConnectionInfo {
+							addr:        tcp.peer_addr,
+							local_addr:  tcp.local_addr,
+							tls_version: session.get_protocol_version().map(|v| format!("{:?}", v)),
+							tls_cipher:  session.get_negotiated_ciphersuite().map(|v| format!("{:?}", v.suite)),
+							tls_sni:     session.get_sni_hostname().map(ToString::to_string),
+							..ConnectionInfo::default()
+						};
+						let client = ClientInfo {
+							identity,
+							connection,
+							forward_header: forward_header.as_deref(),
+							forwarded:      forwarded.as_deref()
+						};
+						let conn = net::http::v1::AsyncSharedConnection::new(
+							net::http::v1::AsyncConnection::new(
+								net::buffered::AsyncBufStream::new(stream)));
+						
+						http_handle(conn, &name, &endpoint, &processor, &telemetry, &client, limits).await
 					});
 				}
 			});
@@ -120,35 +195,342 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 	Ok(())
 }
 
-async fn tls_config(cfg: &ConfigSocketTls) -> Result<Arc<tls::r#async::rustls::ServerConfig>> {
-	let cert = rustls_pemfile::certs(&mut io::BufReader::new(
-		std::fs::File::open(&cfg.certificate)?))
-		.with_msg("failed to parse certificate")?
-		.into_iter()
-		.map(net::tls::r#async::rustls::Certificate)
-		.collect::<Vec<_>>();
-	
-	let key = net::tls::r#async::rustls::PrivateKey(match rustls_pemfile::read_one(
-		&mut io::BufReader::new(std::fs::File::open(&cfg.private_key)?))
-		.with_msg("failed to parse private key")?
-	{
-		Some(rustls_pemfile::Item::RSAKey(v)) => v,
-		Some(rustls_pemfile::Item::X509Certificate(v)) => v,
-		Some(rustls_pemfile::Item::PKCS8Key(v)) => v,
-		None => return Err("no private key found in file".into()),
-	});
+/// Serves DNS over UDP and over TCP or TLS, whichever are configured.
+async fn dns_run(name: Arc<String>, cfg: ConfigSocket, dns: ConfigSocketDns, id: u128) -> Result<()> {
+	let processor = crate::get_component::<DnsHandler>(id);
+	let has_listener = cfg.tcp.is_some() || cfg.pipe.is_some() || cfg.listen_fd.is_some();
+	
+	if cfg.udp.is_none() && !has_listener {
+		return Err("expected at least one of `udp`, `tcp`, `pipe` or `listen_fd`".into());
+	}
+	
+	if let Some(udp) = &cfg.udp {
+		let (socket, endpoint) = transport::bind_udp(&name, udp, DNS_DEFAULT_PORT).await?;
+		let local_addr = socket.local_addr().ok();
+		log::info!("frontend `{}` (dns://{}): up", &name, &endpoint);
+		
+		let ctx = (processor.clone(), name.clone(), Arc::new(endpoint));
+		let max_payload_size = dns.max_udp_payload_size;
+		crate::spawn(async move {
+			let mut buf = vec![0u8; DNS_MAX_MESSAGE_LEN];
+			
+			loop {
+				let (len, peer) = match socket.recv_from(&mut buf).await {
+					Ok(v) => v,
+					Err(e) => {
+						log::error!("frontend `{}` (dns://{}): failed to receive query: {}", &ctx.1, &ctx.2, e);
+						continue;
+					}
+				};
+				
+				let (processor, name, endpoint) = ctx.clone();
+				let (socket, query) = (socket.clone(), buf[..len].to_vec());
+				crate::spawn(async move {
+					let _connection = upgrade::track_connection();
+					let connection = ConnectionInfo { addr: Some(peer), local_addr, ..ConnectionInfo::default() };
+					let response = match dns_answer(&query, connection, &name, &endpoint, &processor, Some(max_payload_size)).await {
+						Some(v) => v,
+						None    => return
+					};
+					
+					if let Err(e) = socket.send_to(&response, peer).await {
+						log::error!("frontend `{}` (dns://{}): failed to send response to {}: {}", &name, &endpoint, peer, e);
+					}
+				});
+				
+				// the next process receives the queries from now on
+				if upgrade::is_draining() {
+					break;
+				}
+			}
+		});
+	}
+	
+	if !has_listener {
+		return Ok(());
+	}
+	
+	let default_port = if cfg.tls.is_some() { DNS_DEFAULT_PORT_TLS } else { DNS_DEFAULT_PORT };
+	let (listener, endpoint) = transport::Listener::bind(&name, &cfg, default_port).await?;
+	let endpoint = Arc::new(endpoint);
+	let idle_timeout = dns.idle_timeout.unwrap_or(DNS_IDLE_TIMEOUT);
+	log::info!("frontend `{}` (dns://{}): up", &name, &endpoint);
+	
+	match cfg.tls {
+		None => {
+			let mut acceptor = transport::Acceptor::new(listener);
+			let ctx = (processor, name, endpoint);
+			crate::spawn(async move {
+				loop {
+					let (processor, name, endpoint) = ctx.clone();
+					let f = acceptor.accept().await;
+					crate::spawn(async move {
+						let stream = match f.await {
+							Ok(v) => v,
+							Err(e) => {
+								log::error!("frontend `{}` (dns://{}): failed to accept connection: {}", &name, &endpoint, e);
+								return;
+							}
+						};
+						
+						let connection = ConnectionInfo {
+							addr:       stream.peer_addr().ok().flatten(),
+							local_addr: stream.local_addr().ok().flatten(),
+							..ConnectionInfo::default()
+						};
+						dns_handle_stream(stream, connection, &name, &endpoint, &processor, idle_timeout).await
+					});
+				}
+			});
+		}
+		Some(tls) => {
+			let mut acceptor = net::tls::AsyncAcceptor::new(
+				transport::Acceptor::new(listener), tls::server_config(&name, &tls).await?);
+			let ctx = (processor, name, endpoint);
+			crate::spawn(async move {
+				loop {
+					let (processor, name, endpoint) = ctx.clone();
+					let f = acceptor.accept().await;
+					crate::spawn(async move {
+						let stream = match f.await {
+							Ok(v) => v,
+							Err(e) => {
+								log::error!("frontend `{}` (dns://{}): failed to accept connection: {}", &name, &endpoint, e);
+								return;
+							}
+						};
+						
+						let (tcp, session) = stream.get_ref();
+						let connection = ConnectionInfo {
+							addr:        tcp.peer_addr().ok().flatten(),
+							local_addr:  tcp.local_addr().ok().flatten(),
+							tls_version: session.get_protocol_version().map(|v| format!("{:?}", v)),
+							tls_cipher:  session.get_negotiated_ciphersuite().map(|v| format!("{:?}", v.suite)),
+							tls_sni:     session.get_sni_hostname().map(ToString::to_string),
+							..ConnectionInfo::default()
+						};
+						dns_handle_stream(stream, connection, &name, &endpoint, &processor, idle_timeout).await
+					});
+				}
+			});
+		}
+	}
+	
+	Ok(())
+}
+
+/// Answers the length prefixed queries of a TCP or TLS connection one after another.
+async fn dns_handle_stream(
+	mut stream:   impl AsyncRead + AsyncWrite + Unpin,
+	connection:   ConnectionInfo,
+	name:         &str,
+	endpoint:     &str,
+	processor:    &ComponentRef<DnsHandler>,
+	idle_timeout: Duration
+) {
+	let _connection = upgrade::track_connection();
+	
+	let r = loop {
+		let mut len = [0u8; 2];
+		
+		match timeout(Some(idle_timeout), stream.read_exact(&mut len)).await {
+			Ok(()) => (),
+			Err(e) if matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::TimedOut) => break Ok(()),
+			Err(e) => break Err(e)
+		}
+		
+		let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+		
+		if let Err(e) = timeout(Some(DNS_READ_TIMEOUT), stream.read_exact(&mut query)).await {
+			break Err(e);
+		}
+		
+		let response = match dns_answer(&query, connection.clone(), name, endpoint, processor, None).await {
+			Some(v) => v,
+			None    => continue
+		};
+		
+		let mut buf = Vec::with_capacity(2 + response.len());
+		buf.extend_from_slice(&(response.len() as u16).to_be_bytes());
+		buf.extend_from_slice(&response);
+		
+		if let Err(e) = stream.write_all(&buf).await {
+			break Err(e);
+		}
+		
+		// the process exits once all connections are closed
+		if upgrade::is_draining() {
+			break Ok(());
+		}
+	};
 	
-	let mut cfg = tls::r#async::rustls::ServerConfig::new(
-		Arc::new(tls::r#async::rustls::NoClientAuth));
-	cfg.set_single_cert(cert, key)
-		.with_msg("bad certificate or private key")?;
-	Ok(Arc::new(cfg))
-	
-	/*Ok(Arc::new(tls::ServerConfig::builder()
-		.with_safe_defaults()
-		.with_no_client_auth()
-		.with_single_cert(cert, key)
-		.with_msg("bad certificate or private key")?))*/
+	if let Err(e) = r.and(stream.close().await) {
+		log::debug!("frontend `{}` (dns://{}): connection aborted: {}", name, endpoint, e);
+	}
+}
+
+/// Answers an encoded query, returns the encoded response or `None` if the message is ignored.
+/// Responses over UDP are truncated to the payload size of the client, but at most
+/// `max_payload_size`.
+async fn dns_answer(
+	buf:              &[u8],
+	connection:       ConnectionInfo,
+	name:             &str,
+	endpoint:         &str,
+	processor:        &ComponentRef<DnsHandler>,
+	max_payload_size: Option<u16>
+) -> Option<Vec<u8>> {
+	let start = std::time::Instant::now();
+	let query = match net::dns::Message::parse(buf) {
+		Ok(v) if v.flags.qr() => return None,
+		Ok(v) => v,
+		// without an ID there is nothing to respond to
+		Err(_) if buf.len() < DNS_HEADER_LEN => return None,
+		Err(e) => {
+			log::debug!("frontend `{}` (dns://{}): malformed query: {}", name, endpoint, e);
+			let mut flags = net::dns::Flags(u16::from_be_bytes([buf[2], buf[3]]) & (net::dns::OPCODE_MASK | net::dns::RD_BIT));
+			flags.set_qr(true);
+			flags.set_rcode(Ok(net::dns::RCode::FormatError));
+			return net::dns::Message { id: u16::from_be_bytes([buf[0], buf[1]]), flags, ..Default::default() }
+				.to_bytes().ok();
+		}
+	};
+	
+	let udp_payload_size = max_payload_size.unwrap_or(u16::MAX);
+	let mut response = match super::dns::answer(processor, &query, connection, udp_payload_size).await {
+		Ok(v) => v,
+		Err(e) => {
+			log::error!("frontend `{}` (dns://{}): #{} error: {}", name, endpoint, query.id, e.display());
+			super::dns::failure(&query, udp_payload_size)
+		}
+	};
+	
+	let limit = match (max_payload_size, query.edns()) {
+		(None, _)            => DNS_MAX_MESSAGE_LEN,
+		(Some(max), Some(v)) => v.udp_payload_size.max(net::dns::MAX_UDP_LEN as u16).min(max) as usize,
+		(Some(_), None)      => net::dns::MAX_UDP_LEN
+	};
+	
+	let mut buf = match response.to_bytes() {
+		Ok(v) => v,
+		Err(e) => {
+			log::error!("frontend `{}` (dns://{}): #{} failed to encode response: {}", name, endpoint, response.id, e);
+			response = super::dns::failure(&query, udp_payload_size);
+			response.to_bytes().ok()?
+		}
+	};
+	
+	// the client retries over TCP
+	if buf.len() > limit {
+		response.answers.clear();
+		response.authority_records.clear();
+		response.additional_records.retain(|rr| matches!(rr.data, net::dns::ResourceRecordData::OPT(_)));
+		response.flags.set_tc(true);
+		buf = response.to_bytes().ok()?;
+	}
+	
+	let question = query.questions.first();
+	log::info!(
+		"frontend `{}` (dns://{}): #{} {} {} -> {:?}{} ({} ms)",
+		name,
+		endpoint,
+		response.id,
+		question.map_or("?", |v| &v.name),
+		question.map_or_else(|| "?".to_string(), |v| v.r#type.to_string()),
+		response.flags.rcode(),
+		if response.flags.tc() { " (truncated)" } else { "" },
+		start.elapsed().as_millis()
+	);
+	Some(buf)
+}
+
+/// Accepts connections and reads the PROXY protocol header of trusted proxies.
+struct ProxyAcceptor {
+	inner:   transport::Acceptor,
+	/// Empty if PROXY protocol is disabled.
+	trusted: Arc<[Cidr]>
+}
+
+impl AsyncAcceptor for ProxyAcceptor {
+	type Connection = ProxiedConnection;
+	
+	fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Pin<Box<dyn Future<Output = io::Result<Self::Connection>> + Send + 'static>>> {
+		let Self { inner, trusted } = unsafe { Pin::into_inner_unchecked(self) };
+		
+		match unsafe { Pin::new_unchecked(inner) }.poll_accept(cx) {
+			Poll::Pending  => Poll::Pending,
+			Poll::Ready(f) => {
+				let trusted = trusted.clone();
+				Poll::Ready(Box::pin(async move {
+					let mut inner = f.await?;
+					let (peer_addr, local_addr) = (inner.peer_addr()?, inner.local_addr()?);
+					// peers on Unix domain sockets have no address to trust
+					let addrs = match peer_addr.map_or(false, |addr| trusted.iter().any(|v| v.contains(addr.ip()))) {
+						true  => timeout(Some(PROXY_HEADER_TIMEOUT), proxy_protocol::read_header(&mut inner)).await?,
+						false => None
+					};
+					
+					let (peer_addr, local_addr) = match addrs {
+						Some((peer_addr, local_addr)) => (Some(peer_addr), Some(local_addr)),
+						None => (peer_addr, local_addr)
+					};
+					Ok(ProxiedConnection { inner, peer_addr, local_addr })
+				}))
+			}
+		}
+	}
+}
+
+/// A connection with the addresses sent by a proxy, if any.
+struct ProxiedConnection {
+	inner:      transport::Stream,
+	peer_addr:  Option<SocketAddr>,
+	local_addr: Option<SocketAddr>
+}
+
+impl smol::io::AsyncRead for ProxiedConnection {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+	
+	fn poll_read_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [io::IoSliceMut<'_>]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_read_vectored(cx, bufs)
+	}
+}
+
+impl smol::io::AsyncWrite for ProxiedConnection {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_write(cx, buf)
+	}
+	
+	fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+	}
+	
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+	
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_close(cx)
+	}
+}
+
+/// Limits of the `http1` config that are enforced per stream.
+#[derive(Copy, Clone, Debug)]
+struct HttpLimits {
+	max_stream_duration: Option<Duration>
+}
+
+impl HttpLimits {
+	fn new(cfg: &ConfigSocketHttp1) -> Self {
+		Self {
+			max_stream_duration: match cfg.max_stream_duration {
+				usize::MAX => None,
+				v => Some(Duration::from_millis(v as _))
+			}
+		}
+	}
 }
 
 struct HttpTelemetry {
@@ -212,15 +594,31 @@ impl HttpTelemetry {
 	}
 }
 
+/// What is known about the client of a connection.
+#[derive(Default)]
+struct ClientInfo<'a> {
+	/// The identity from a verified client certificate.
+	identity:       Option<ClientIdentity>,
+	/// The peer address and TLS parameters.
+	connection:     ConnectionInfo,
+	/// The header the identity is forwarded to backends in.
+	forward_header: Option<&'a str>,
+	/// The trusted proxies, if the client address is taken from `Forwarded` headers.
+	forwarded:      Option<&'a [Cidr]>
+}
+
 #[allow(clippy::needless_lifetimes)]
 async fn http_handle<T: http::traits::AsyncSharedConnection>(
 	connection: T,
 	name:       &str,
 	endpoint:   &str,
-	processor:  &HttpStreamHandler,
-	telemetry:  &HttpTelemetry
+	processor:  &ComponentRef<HttpStreamHandler>,
+	telemetry:  &HttpTelemetry,
+	client:     &ClientInfo<'_>,
+	limits:     HttpLimits
 ) {
 	let conn_start = std::time::Instant::now();
+	let _connection = upgrade::track_connection();
 	telemetry.connections_accepted.record(1);
 	telemetry.connections_established.record(1);
 	log::trace!("frontend `{}` (https://{}): connection established", name, endpoint);
@@ -234,7 +632,7 @@ async fn http_handle<T: http::traits::AsyncSharedConnection>(
 		};
 		
 		let start = std::time::Instant::now();
-		let mut stream: StreamInterceptor<http::AsyncStream<'_, T>> = StreamInterceptor::new(stream);
+		let mut stream: StreamInterceptor<'_, http::AsyncStream<'_, T>> = StreamInterceptor::new(stream, client);
 		let stream_static = unsafe { std::mem::transmute::<
 			&'_      mut (dyn http::traits::AsyncStream + '_),
 			&'static mut (dyn http::traits::AsyncStream + 'static)
@@ -243,7 +641,21 @@ async fn http_handle<T: http::traits::AsyncSharedConnection>(
 		telemetry.requests_accepted.record(1);
 		telemetry.requests_in_progress.record(1);
 		
-		let mut r = processor.accept(stream_static).await;
+		let mut r = timeout(limits.max_stream_duration, async {
+			match processor.get().await {
+				Ok(processor) => processor.accept(stream_static).await,
+				Err(e)        => Err(e)
+			}
+		}).await;
+		
+		match &r {
+			// the response can only be replaced if it was not sent yet
+			Err(e) if is_timeout(e) && stream.status.is_none() => {
+				log::debug!("frontend `{}` (https://{}): #{} exceeded max_stream_duration", name, endpoint, id);
+				r = send_response(&mut stream, http::Status::GatewayTimeout).await;
+			}
+			_ => ()
+		}
 		
 		if r.is_ok() {
 			r = stream.close().await.map_err(Into::into);
@@ -256,6 +668,8 @@ async fn http_handle<T: http::traits::AsyncSharedConnection>(
 			telemetry.request_status.record(status as _);
 		}
 		
+		stream.end_span(r.is_err());
+		
 		match r {
 			Ok(()) => log::info!(
 				"frontend `{}` (https://{}): #{} {} {} -> {} ({} ms)",
@@ -286,6 +700,11 @@ async fn http_handle<T: http::traits::AsyncSharedConnection>(
 				e.display()
 			)
 		}
+		
+		// the process exits once all connections are closed
+		if upgrade::is_draining() {
+			break Ok(());
+		}
 	};
 	
 	telemetry.connection_time.record(conn_start.elapsed().as_millis() as _);
@@ -299,23 +718,68 @@ async fn http_handle<T: http::traits::AsyncSharedConnection>(
 	}
 }
 
-struct StreamInterceptor<T: http::traits::AsyncStream> {
+struct StreamInterceptor<'a, T: http::traits::AsyncStream> {
 	inner:  T,
+	client: &'a ClientInfo<'a>,
 	method: Option<http::Method>,
 	path:   Option<String>,
-	status: Option<http::Status>
+	status: Option<http::Status>,
+	span:   Option<otel_mrt::tracing::Span>
 }
 
-impl<T: http::traits::AsyncStream> StreamInterceptor<T> {
-	fn new(inner: T) -> Self {
+impl<'a, T: http::traits::AsyncStream> StreamInterceptor<'a, T> {
+	fn new(inner: T, client: &'a ClientInfo<'a>) -> Self {
 		Self {
 			inner,
+			client,
 			method: None,
 			path:   None,
-			status: None
+			status: None,
+			span:   None
 		}
 	}
 	
+	/// Starts the server span of the request and propagates it to the next modules, instead of the
+	/// span of the client.
+	fn start_span(&mut self, headers: &mut Vec<http::Header>) {
+		let method = match (&self.span, &self.method) {
+			(None, Some(v)) => v.to_string(),
+			_ => return
+		};
+		
+		let connection = &self.client.connection;
+		let span = start_span(headers, otel_mrt::SpanParams::new(format!("HTTP {}", method))
+			.kind(otel_mrt::SpanKind::Server)
+			.attribute("http.method", otel_mrt::AnyValue::String(method))
+			.attribute("http.target", otel_mrt::AnyValue::String(self.path.clone().unwrap_or_default()))
+			.attribute("http.flavor", otel_mrt::AnyValue::String("1.1".to_string()))
+			.attribute("http.scheme", otel_mrt::AnyValue::String(match connection.tls_version {
+				Some(_) => "https".to_string(),
+				None    => "http".to_string()
+			}))
+			.attributes(connection.addr.map(|v| (Cow::Borrowed("net.peer.ip"), otel_mrt::AnyValue::String(v.ip().to_string())))));
+		
+		span.context().inject(headers);
+		self.span = Some(span);
+	}
+	
+	fn end_span(&mut self, failed: bool) {
+		let span = match self.span.take() {
+			Some(v) => v,
+			None    => return
+		};
+		
+		if let Some(status) = self.status {
+			span.set_attribute("http.status_code", otel_mrt::AnyValue::Int(status as u16 as _));
+		}
+		
+		if failed || self.status.map_or(false, |v| v as u16 >= 500) {
+			span.set_status(otel_mrt::StatusCode::Error, None);
+		}
+		
+		span.end();
+	}
+	
 	fn set_headers(&mut self, headers: &[http::Header]) {
 		for header in headers {
 			match header {
@@ -326,14 +790,62 @@ impl<T: http::traits::AsyncStream> StreamInterceptor<T> {
 			}
 		}
 	}
+	
+	/// Replaces client identity and connection headers sent by the client with the verified ones.
+	fn set_client_headers(&self, headers: &mut Vec<http::Header>) {
+		let mut http_version = None;
+		headers.retain(|header| match header {
+			http::Header::Custom(k, v) if k == http::v1::HEADER_VERSION => {
+				http_version = Some(v.clone());
+				false
+			}
+			_ => true
+		});
+		
+		headers.retain(|header| {
+			let name = header.name_v1();
+			!ClientIdentity::is_header(name)
+				&& !ConnectionInfo::is_header(name)
+				&& !RequestTimeouts::is_header(name)
+				&& !name.eq_ignore_ascii_case(HEADER_JWT_CLAIMS)
+				&& !self.client.forward_header.map_or(false, |v| v.eq_ignore_ascii_case(name))
+		});
+		
+		// trailers have no method
+		let is_head = headers.iter().any(|v| matches!(v, http::Header::Method(_)));
+		
+		if is_head {
+			let mut connection = self.client.connection.clone();
+			connection.http_version = http_version;
+			
+			if let (Some(trusted), Some(peer)) = (self.client.forwarded, connection.addr) {
+				match trusted.iter().any(|v| v.contains(peer.ip())) {
+					true  => connection.addr = proxy_protocol::client_addr(headers, peer, trusted).or(connection.addr),
+					false => headers.retain(|v| !proxy_protocol::is_header(v.name_v1()))
+				}
+			}
+			
+			headers.extend(connection.headers());
+		}
+		
+		if let (Some(identity), true) = (&self.client.identity, is_head) {
+			headers.extend(identity.headers());
+			
+			if let Some(header) = self.client.forward_header {
+				headers.push(http::Header::Custom(header.to_string(), identity.to_forwarded()));
+			}
+		}
+	}
 }
 
-impl<T: http::traits::AsyncStream> http::traits::AsyncStream for StreamInterceptor<T> {
+impl<T: http::traits::AsyncStream> http::traits::AsyncStream for StreamInterceptor<'_, T> {
 	fn poll_read_headers<'a>(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
 		let self_ = unsafe { Pin::into_inner_unchecked(self) };
 		match unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_read_headers(cx) {
-			Poll::Ready(Ok(headers)) => {
+			Poll::Ready(Ok(mut headers)) => {
 				self_.set_headers(&headers);
+				self_.set_client_headers(&mut headers);
+				self_.start_span(&mut headers);
 				Poll::Ready(Ok(headers))
 			},
 			v => v
@@ -343,11 +855,21 @@ impl<T: http::traits::AsyncStream> http::traits::AsyncStream for StreamIntercept
 	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
 		let self_ = unsafe { Pin::into_inner_unchecked(self) };
 		self_.set_headers(headers);
+		
+		// the upstream is only meant for modules of this node
+		if headers.iter().any(|v| v.name_v1().eq_ignore_ascii_case(HEADER_UPSTREAM)) {
+			let headers = headers.iter()
+				.filter(|v| !v.name_v1().eq_ignore_ascii_case(HEADER_UPSTREAM))
+				.cloned()
+				.collect::<Vec<_>>();
+			return unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_write_headers(cx, &headers);
+		}
+		
 		unsafe { Pin::new_unchecked(&mut self_.inner) }.poll_write_headers(cx, headers)
 	}
 }
 
-impl<T: http::traits::AsyncStream> smol::io::AsyncWrite for StreamInterceptor<T> {
+impl<T: http::traits::AsyncStream> smol::io::AsyncWrite for StreamInterceptor<'_, T> {
 	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
 		unsafe { self.map_unchecked_mut(|v| &mut v.inner) }.poll_write(cx, buf)
 	}
@@ -365,7 +887,7 @@ impl<T: http::traits::AsyncStream> smol::io::AsyncWrite for StreamInterceptor<T>
 	}
 }
 
-impl<T: http::traits::AsyncStream> smol::io::AsyncRead for StreamInterceptor<T> {
+impl<T: http::traits::AsyncStream> smol::io::AsyncRead for StreamInterceptor<'_, T> {
 	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
 		unsafe { self.map_unchecked_mut(|v| &mut v.inner) }.poll_read(cx, buf)
 	}
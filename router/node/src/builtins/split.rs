@@ -0,0 +1,268 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+
+//! Splits traffic between modules by percentages, e.g. for canary releases.
+
+use {
+	super::*,
+	crate::{interfaces::*, utils::*},
+	net::http::{self, traits::AsyncStreamExt}
+};
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	pub branches: Vec<ConfigBranch>,
+	/// Sends requests with the same cookie or header value to the same branch.
+	pub sticky:   Option<ConfigSticky>
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigBranch {
+	/// The module requests of this branch are forwarded to.
+	pub next:    String,
+	/// The percentage of requests, weights that do not add up to 100 are scaled.
+	pub percent: f64
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub enum ConfigSticky {
+	Cookie(String),
+	Header(String)
+}
+
+pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	if cfg.branches.iter().any(|v| !v.percent.is_finite() || v.percent < 0.0) {
+		return Err("percentages must not be negative".into());
+	}
+	
+	let total = cfg.branches.iter().map(|v| v.percent).sum::<f64>();
+	
+	if total <= 0.0 {
+		return Err("no branches configured".into());
+	}
+	
+	let rt = otel_mrt::runtime();
+	let requests = rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+		.name_str("split_requests")
+		.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true));
+	let errors = rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+		.name_str("split_request_errors")
+		.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true));
+	
+	let mut bound = 0.0;
+	let branches = cfg.branches.iter()
+		.map(|cfg_| {
+			bound += cfg_.percent / total;
+			let labels = vec![
+				(Cow::Borrowed("module"), otel_mrt::AnyValue::String(name.to_string())),
+				(Cow::Borrowed("branch"), otel_mrt::AnyValue::String(cfg_.next.clone()))
+			];
+			
+			Branch {
+				name:     cfg_.next.clone(),
+				next:     crate::get_component::<HttpStreamHandler>(crate::component_id(&cfg_.next)),
+				bound,
+				requests: requests.clone().bind(labels.clone()),
+				errors:   errors.clone().bind(labels)
+			}
+		})
+		.collect();
+	
+	crate::add_component::<HttpStreamHandler>(crate::component_id(name), Box::new(Module {
+		sticky:  cfg.sticky,
+		counter: AtomicUsize::new(0),
+		branches
+	}));
+	Ok(())
+}
+
+struct Module {
+	sticky:   Option<ConfigSticky>,
+	counter:  AtomicUsize,
+	branches: Vec<Branch>
+}
+
+struct Branch {
+	name:     String,
+	next:     ComponentRef<HttpStreamHandler>,
+	/// The upper bound of this branch in `[0, 1)`, the lower bound is the one of the previous branch.
+	bound:    f64,
+	requests: otel_mrt::BoundInstrument<usize>,
+	errors:   otel_mrt::BoundInstrument<usize>
+}
+
+impl Module {
+	/// The value requests are sticky by, if present in the request.
+	fn sticky_key(&self, headers: &[http::Header]) -> Option<String> {
+		match self.sticky.as_ref()? {
+			ConfigSticky::Cookie(name) => headers.iter()
+				.filter_map(|v| match v {
+					http::Header::Cookie(v) => Some(v),
+					_ => None
+				})
+				.flatten()
+				.find(|(k, _)| k == name)
+				.map(|(_, v)| v.clone()),
+			ConfigSticky::Header(name) => headers.iter()
+				.find(|v| v.name_v1().eq_ignore_ascii_case(name))
+				.map(ToString::to_string)
+		}
+	}
+	
+	fn select(&self, headers: &[http::Header]) -> &Branch {
+		let point = match self.sticky_key(headers) {
+			// FNV-1a is fully specified, the result is the same on all nodes and across releases
+			Some(key) => fnv1a(key.as_bytes()),
+			None => self.counter.fetch_add(1, Ordering::Relaxed) as u64
+		};
+		
+		// spreads similar keys and consecutive requests evenly over the branches
+		let point = point.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+		
+		select(&self.branches, (point >> 11) as f64 / (1u64 << 53) as f64)
+	}
+}
+
+/// The 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, v| (hash ^ *v as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+/// Selects the branch `point` in `[0, 1)` falls into.
+fn select(branches: &[Branch], point: f64) -> &Branch {
+	branches.iter()
+		.find(|v| point < v.bound)
+		.unwrap_or_else(|| branches.last().unwrap())
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Module {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let mut headers = stream.read_headers().await?;
+			let branch = self.select(&headers);
+			branch.requests.record(1);
+			
+			let span = start_span(&headers, otel_mrt::SpanParams::new("split")
+				.attribute("split.branch", otel_mrt::AnyValue::String(branch.name.clone())));
+			span.context().inject(&mut headers);
+			
+			let mut stream = HeadersHttpStream::new(stream, headers);
+			
+			// this is unsafe, but that's ok, see HttpStreamHandler::accept
+			let stream = unsafe { std::mem::transmute::<&mut HeadersHttpStream<'_>, &'static mut HeadersHttpStream<'static>>(&mut stream) };
+			let r = match branch.next.get().await {
+				Ok(v)  => v.accept(stream).await,
+				Err(e) => Err(e)
+			};
+			
+			if r.is_err() {
+				branch.errors.record(1);
+				span.set_status(otel_mrt::StatusCode::Error, None);
+			}
+			
+			span.end();
+			r
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn module(percents: &[f64], sticky: Option<ConfigSticky>) -> Module {
+		// the exporter is never run, nothing is sent anywhere
+		let rt = otel_mrt::Runtime::new(otel_mrt::Config::disabled(), Some(Box::new(|_| ())));
+		let instrument = rt.instrument::<usize>(otel_mrt::InstrumentParameters::new()
+			.name_str("split_test")
+			.aggregation_sum(otel_mrt::AggregationTemporality::Unspecified, true));
+		
+		let total = percents.iter().sum::<f64>();
+		let mut bound = 0.0;
+		let branches = percents.iter().enumerate()
+			.map(|(i, percent)| {
+				bound += percent / total;
+				Branch {
+					name:     i.to_string(),
+					next:     test_component(Box::new(TestHttpHandler::default()) as HttpStreamHandler),
+					bound,
+					requests: instrument.clone().bind(Vec::new()),
+					errors:   instrument.clone().bind(Vec::new())
+				}
+			})
+			.collect();
+		
+		Module { sticky, counter: AtomicUsize::new(0), branches }
+	}
+	
+	fn cookie(value: &str) -> Vec<http::Header> {
+		vec![http::Header::Cookie(vec![("session".to_string(), value.to_string())])]
+	}
+	
+	#[test]
+	fn stable_hash() {
+		// reference values of the FNV-1a specification, these must never change
+		assert_eq!(fnv1a(b""), 0xCBF2_9CE4_8422_2325);
+		assert_eq!(fnv1a(b"a"), 0xAF63_DC4C_8601_EC8C);
+		assert_eq!(fnv1a(b"foobar"), 0x8594_4171_F739_67E8);
+	}
+	
+	#[test]
+	fn weights() {
+		let split = module(&[25.0, 75.0], None);
+		assert_eq!(select(&split.branches, 0.0).name, "0");
+		assert_eq!(select(&split.branches, 0.2499).name, "0");
+		assert_eq!(select(&split.branches, 0.25).name, "1");
+		assert_eq!(select(&split.branches, 0.9999).name, "1");
+		
+		// requests without a sticky key are spread by a counter, so the counts are always the same
+		let mut counts = [0usize; 2];
+		for _ in 0..1000 {
+			counts[split.select(&[]).name.parse::<usize>().unwrap()] += 1;
+		}
+		assert!((245..=255).contains(&counts[0]), "{:?}", counts);
+		assert_eq!(counts[0] + counts[1], 1000);
+		
+		let split = module(&[0.0, 1.0, 0.0], None);
+		assert!((0..100).all(|_| split.select(&[]).name == "1"));
+	}
+	
+	#[test]
+	fn sticky() {
+		let module = module(&[50.0, 50.0], Some(ConfigSticky::Cookie("session".to_string())));
+		let mut counts = [0usize; 2];
+		
+		for i in 0..1000 {
+			let key = format!("session-{}", i);
+			let branch = &module.select(&cookie(&key)).name;
+			assert!((0..10).all(|_| &module.select(&cookie(&key)).name == branch));
+			counts[branch.parse::<usize>().unwrap()] += 1;
+		}
+		
+		// similar keys are still spread over all branches
+		assert!((400..=600).contains(&counts[0]), "{:?}", counts);
+		
+		// requests without the cookie are not sticky
+		let requests = (0..10).map(|_| module.select(&[]).name.clone()).collect::<Vec<_>>();
+		assert!(requests.iter().any(|v| v == "0") && requests.iter().any(|v| v == "1"));
+	}
+}
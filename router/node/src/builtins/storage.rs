@@ -30,10 +30,69 @@ use {
 	notify::{Watcher, DebouncedEvent}
 };
 
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+const MIME_TYPES: &[(&str, &str)] = &[
+	("html",  "text/html; charset=utf-8"),
+	("htm",   "text/html; charset=utf-8"),
+	("css",   "text/css; charset=utf-8"),
+	("js",    "text/javascript; charset=utf-8"),
+	("mjs",   "text/javascript; charset=utf-8"),
+	("txt",   "text/plain; charset=utf-8"),
+	("md",    "text/markdown; charset=utf-8"),
+	("csv",   "text/csv; charset=utf-8"),
+	("xml",   "application/xml"),
+	("json",  "application/json"),
+	("map",   "application/json"),
+	("wasm",  "application/wasm"),
+	("pdf",   "application/pdf"),
+	("zip",   "application/zip"),
+	("gz",    "application/gzip"),
+	("tar",   "application/x-tar"),
+	("png",   "image/png"),
+	("jpg",   "image/jpeg"),
+	("jpeg",  "image/jpeg"),
+	("gif",   "image/gif"),
+	("webp",  "image/webp"),
+	("avif",  "image/avif"),
+	("svg",   "image/svg+xml"),
+	("ico",   "image/x-icon"),
+	("woff",  "font/woff"),
+	("woff2", "font/woff2"),
+	("ttf",   "font/ttf"),
+	("otf",   "font/otf"),
+	("mp3",   "audio/mpeg"),
+	("ogg",   "audio/ogg"),
+	("wav",   "audio/wav"),
+	("mp4",   "video/mp4"),
+	("webm",  "video/webm")
+];
+
+/// Precompressed siblings in order of preference.
+const PRECOMPRESSED: [(http::Encoding, &str); 2] = [
+	(http::Encoding::Br,   ".br"),
+	(http::Encoding::GZip, ".gz")
+];
+
+/// The maximum number of ranges of a request, more are not worth the overhead of a multipart
+/// response and the whole resource is sent instead.
+const MAX_RANGES: usize = 16;
+
+static BOUNDARY_COUNTER: AtomicU64 = AtomicU64::new(0);
+static MAILDIR_COUNTER:  AtomicU64 = AtomicU64::new(0);
+
 pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
+	// the zone files are served to DNS sockets
+	let zones = match &cfg.dns {
+		Some(dns) => Some(Arc::new(dns::Zones::new(name, &cfg.dir, cfg.filter.clone(), cfg.recursive, dns.clone()).await)),
+		None => None
+	};
+	
 	let backend = Arc::new(FsBackend {
 		name:      name.to_string(),
-		resources: TrieNode::default()
+		resources: TrieNode::default(),
+		filter:    cfg.filter,
+		http:      cfg.http.unwrap_or_default()
 	});
 	
 	FsBackend::add_resource(
@@ -41,9 +100,27 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 		name,
 		cfg.dir.trim_end_matches('/'),
 		&mut PathBuf::from(&cfg.dir),
-		cfg.http.unwrap().load
+		&backend.filter,
+		cfg.preload,
+		cfg.recursive,
+		true
 	).await;
 	
+	let id = crate::component_id(name);
+	
+	if let Some(smtp) = cfg.smtp {
+		crate::add_component::<MailHandler>(id, Box::new(Maildir::new(name, PathBuf::from(&cfg.dir), smtp)));
+	}
+	
+	if let Some(zones) = zones {
+		#[cfg(feature = "hot-reload")]
+		if cfg.reload {
+			zones.clone().watch()?;
+		}
+		
+		crate::add_component::<DnsHandler>(id, Box::new(zones));
+	}
+	
 	#[cfg(feature = "hot-reload")]
 	if cfg.reload {
 		let moved_dir = cfg.dir;
@@ -54,12 +131,11 @@ pub(super) async fn run(name: &str, cfg: Config) -> Result<()> {
 				async move { backend.watch(&moved_dir).await; }))?;
 	}
 	
-	let id = crate::component_id(name);
 	crate::add_component::<HttpStreamHandler>(id, Box::new(__Arc_StorageBackend__(backend)));
 	Ok(())
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
 	pub dir:       String,
@@ -71,28 +147,79 @@ pub struct Config {
 	pub writable:  bool,
 	#[serde(default)]
 	pub recursive: bool,
+	#[serde(default)]
 	pub filter:    ConfigFilter,
 	pub http:      Option<ConfigHttp>,
 	pub smtp:      Option<ConfigSmtp>,
 	pub dns:       Option<ConfigDns>
 }
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
 pub struct ConfigFilter {
 	pub allow_list: Vec<StringMatcher>,
 	pub deny_list:  Vec<StringMatcher>
 }
 
-#[derive(Clone, Debug, Deserialize)]
+impl ConfigFilter {
+	/// Whether the resource at `path` may be served, an empty allow list allows every path.
+	pub fn allows(&self, path: &str) -> bool {
+		(self.allow_list.is_empty() || self.allow_list.iter().any(|v| v.matches(Some(path))))
+			&& !self.denies(path)
+	}
+	
+	/// Whether the file at `path` is kept in memory, precompressed siblings of allowed files are
+	/// kept too, but only served in place of them.
+	pub fn allows_stored(&self, path: &str) -> bool {
+		self.allows(path) || PRECOMPRESSED.iter()
+			.any(|(_, ext)| path.strip_suffix(ext).map_or(false, |v| self.allows(v)))
+	}
+	
+	/// Whether the resource at `path` is excluded explicitly, directories are only checked
+	/// against the deny list, since the allow list usually matches files.
+	pub fn denies(&self, path: &str) -> bool {
+		self.deny_list.iter().any(|v| v.matches(Some(path)))
+	}
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigHttp {
-	pub etag: Option<ConfigHttpEtag>,
+	pub etag:          Option<ConfigHttpEtag>,
+	#[serde(default)]
+	pub date:          bool,
+	/// Files that are served for requests to a directory, the first existing one is used.
+	#[serde(default = "default_index")]
+	pub index:         Vec<String>,
+	/// Generate a listing for directories without an index file.
 	#[serde(default)]
-	pub date: bool,
+	pub autoindex:     bool,
+	/// Serve `<file>.br` and `<file>.gz` instead of `<file>` if the client accepts the encoding.
+	#[serde(default)]
+	pub precompressed: bool,
+	/// Additional MIME types by file extension, overriding the builtin ones.
+	#[serde(default)]
+	pub mime_types:    HashMap<String, String>
+}
+
+impl Default for ConfigHttp {
+	fn default() -> Self {
+		Self {
+			etag:          None,
+			date:          false,
+			index:         default_index(),
+			autoindex:     false,
+			precompressed: false,
+			mime_types:    HashMap::new()
+		}
+	}
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+fn default_index() -> Vec<String> {
+	vec!["index.html".to_string()]
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, JsonSchema)]
 pub enum ConfigHttpEtag {
 	#[serde(alias = "sha3")]
 	#[serde(alias = "SHA3")]
@@ -106,26 +233,143 @@ pub enum ConfigHttpEtag {
 	Xxh3
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigSmtp {
-
+	/// Delivers mail into a Maildir per recipient, `<dir>/<recipient>`, instead of `dir` itself.
+	#[serde(default)]
+	pub per_recipient: bool,
+	/// The host name in the names of delivered files.
+	#[serde(default = "default_smtp_hostname")]
+	pub hostname:      String
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ConfigDns {
+	/// A resolver queries for names outside of the zones are forwarded to, as `host:port` or IP
+	/// address. Such queries are refused if not set.
+	pub upstream:         Option<String>,
+	/// Networks of the clients whose queries are forwarded to the upstream, loopback and private
+	/// networks by default. Queries of other clients are refused.
+	pub upstream_clients: Option<Vec<Cidr>>,
+	/// The time to wait for a response of the upstream.
+	pub timeout:          Option<Duration>
+}
+
+/// Spools mail into Maildir directories, see <https://cr.yp.to/proto/maildir.html>.
+pub(super) struct Maildir {
+	name: String,
+	dir:  PathBuf,
+	cfg:  ConfigSmtp
+}
 
+impl Maildir {
+	pub(super) fn new(name: &str, dir: PathBuf, cfg: ConfigSmtp) -> Self {
+		Self { name: name.to_string(), dir, cfg }
+	}
+	
+	/// The Maildir of a recipient, `None` if the address cannot be used as a directory name.
+	fn recipient_dir(&self, recipient: &str) -> Option<PathBuf> {
+		if !self.cfg.per_recipient {
+			return Some(self.dir.clone());
+		}
+		
+		let recipient = recipient.to_ascii_lowercase();
+		match recipient.is_empty() || recipient.starts_with('.') || recipient.contains(['/', '\\', '\0']) {
+			true  => None,
+			false => Some(self.dir.join(recipient))
+		}
+	}
+	
+	/// Writes a message into `tmp` and moves it into `new` once it is synced, returns its unique name.
+	async fn deliver(&self, dir: &Path, message: &[u8]) -> std::io::Result<String> {
+		let time = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+		let unique = format!(
+			"{}.M{}P{}Q{}.{}",
+			time.as_secs(),
+			time.subsec_micros(),
+			std::process::id(),
+			MAILDIR_COUNTER.fetch_add(1, Ordering::Relaxed),
+			self.cfg.hostname
+		);
+		
+		for sub in ["tmp", "new", "cur"] {
+			smol::fs::create_dir_all(dir.join(sub)).await?;
+		}
+		
+		let tmp = dir.join("tmp").join(&unique);
+		let mut file = smol::fs::File::create(&tmp).await?;
+		let r = async {
+			file.write_all(message).await?;
+			file.sync_all().await
+		}.await;
+		
+		if let Err(e) = r {
+			let _ = smol::fs::remove_file(&tmp).await;
+			return Err(e);
+		}
+		
+		smol::fs::rename(&tmp, dir.join("new").join(&unique)).await?;
+		Ok(unique)
+	}
+}
+
+impl StreamHandler<MailTransaction> for Maildir {
+	fn accept<'a>(&'a self, transaction: &'static mut MailTransaction) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let mut dirs = Vec::new();
+			
+			for recipient in &transaction.recipients {
+				match self.recipient_dir(recipient) {
+					Some(dir) if !dirs.contains(&dir) => dirs.push(dir),
+					Some(_) => (),
+					None    => {
+						transaction.reply = Some((550, "5.1.3 Bad recipient address syntax".to_string()));
+						return Ok(());
+					}
+				}
+			}
+			
+			let mut names = Vec::new();
+			
+			for dir in &dirs {
+				match self.deliver(dir, &transaction.message).await {
+					Ok(v)  => names.push(v),
+					Err(e) => {
+						log::error!("backend `{}` maildir `{}`: failed to deliver mail: {}", &self.name, dir.display(), e);
+						transaction.reply = Some((451, "4.3.0 Mail not queued".to_string()));
+						return Ok(());
+					}
+				}
+			}
+			
+			transaction.reply = Some((250, format!("2.0.0 OK queued as {}", names.join(" "))));
+			Ok(())
+		})
+	}
 }
 
 struct FsBackend {
 	name:      String,
-	resources: TrieNode<StorageBackendResource>
+	resources: TrieNode<StorageBackendResource>,
+	filter:    ConfigFilter,
+	http:      ConfigHttp
 }
 
 impl FsBackend {
+	#[allow(clippy::too_many_arguments)]
 	#[async_recursion]
-	async fn add_resource(resources: &TrieNode<StorageBackendResource>, backend: &str, root: &str, path: &mut PathBuf, load: bool) {
+	async fn add_resource(
+		resources: &TrieNode<StorageBackendResource>,
+		backend:   &str,
+		root:      &str,
+		path:      &mut PathBuf,
+		filter:    &ConfigFilter,
+		load:      bool,
+		recursive: bool,
+		is_root:   bool
+	) {
 		let metadata = match smol::fs::metadata(&path).await {
 			Ok(v) => v,
 			Err(e) => {
@@ -142,6 +386,10 @@ impl FsBackend {
 			let __tmp__ = path_relative.ends_with('/');
 			let path_relative = path_relative + if __tmp__ { "" } else { "/" };
 			
+			if !is_root && (!recursive || filter.denies(&path_relative)) {
+				return;
+			}
+			
 			resources.insert(&path_relative, StorageBackendResource {
 				state:      State::Dir,
 				path:       path.clone().into_boxed_path(),
@@ -169,10 +417,15 @@ impl FsBackend {
 				};
 				
 				path.push(entry.file_name());
-				Self::add_resource(resources, backend, root, &mut*path, load).await;
+				Self::add_resource(resources, backend, root, &mut*path, filter, load, recursive, false).await;
 				path.pop();
 			}
 		} else if metadata.is_file() {
+			if !filter.allows_stored(&path_relative) {
+				log::debug!("backend `{}` resource `{}`: skipped (filtered)", backend, path.display());
+				return;
+			}
+			
 			resources.insert(&path_relative, StorageBackendResource {
 				state:      if load {
 					match smol::fs::read(&path).await {
@@ -231,6 +484,10 @@ impl FsBackend {
 			match rx.recv() {
 				Ok(DebouncedEvent::Create(fs_path)) => {
 					let path = prepare_path(&fs_path, &prefix);
+					if !self.filter.allows_stored(&path) || fs_path.is_dir() {
+						continue;
+					}
+					
 					let (parent, relative_path, _idx) = match self.resources.child(&path).await {
 						Ok(_)  => continue,
 						Err(v) => v
@@ -308,6 +565,298 @@ impl FsBackend {
 	}
 }
 
+impl FsBackend {
+	async fn is_resource(&self, path: &str, dir: bool) -> bool {
+		match self.resources.get(path).await {
+			Some(node) => matches!(&*node.data.read().await, Some(v) if (v.state == State::Dir) == dir),
+			None       => false
+		}
+	}
+	
+	async fn redirect_dir(&self, stream: &mut dyn http::traits::AsyncStream, path: &str) -> Result<()> {
+		let dir = format!("{}/", path);
+		
+		if path.ends_with('/') || self.filter.denies(&dir) || !self.is_resource(&dir, true).await {
+			return send_response(stream, http::Status::NotFound).await;
+		}
+		
+		stream.write_headers(&[
+			http::Header::Status(http::Status::MovedPermanently),
+			http::Header::Server(HEADER_SERVER.to_string()),
+			http::Header::Location(dir),
+			http::Header::ContentLength(0)
+		]).await?;
+		Ok(())
+	}
+	
+	async fn send_file(
+		&self,
+		stream:    &mut dyn http::traits::AsyncStream,
+		method:    http::Method,
+		path:      &str,
+		node:      TrieNode<StorageBackendResource>,
+		range:     Option<http::Ranges>,
+		encodings: &[http::Encoding]
+	) -> Result<()> {
+		let (mut node, mut encoding) = (node, None);
+		
+		if self.http.precompressed {
+			for (candidate, extension) in &PRECOMPRESSED {
+				let compressed = format!("{}{}", path, extension);
+				
+				if accepts_encoding(encodings, candidate) && self.is_resource(&compressed, false).await {
+					node     = self.resources.get(&compressed).await.unwrap();
+					encoding = Some(candidate.clone());
+					break;
+				}
+			}
+		}
+		
+		let data = node.data.read().await;
+		let resource = match data.as_ref() {
+			Some(v) => v,
+			None    => return send_response(stream, http::Status::NotFound).await
+		};
+		resource.last_read.store(UNIX_EPOCH.elapsed().unwrap().as_millis() as _, Ordering::Relaxed);
+		
+		#[allow(unused_assignments)]
+		let mut data_owned = None;
+		let data = match &resource.state {
+			State::Dir          => return send_response(stream, http::Status::NotFound).await,
+			State::Loaded(data) => &**data,
+			State::NotLoaded    => match smol::fs::read(&*resource.path).await {
+				Ok(v)  => {
+					log::info!("backend `{}` resource `{}`: loaded (on demand)", &self.name, resource.path.display());
+					data_owned = Some(v);
+					data_owned.as_ref().unwrap().as_slice()
+				}
+				Err(e) => {
+					log::error!("backend `{}` resource `{}`: failed to load file: {}", &self.name, resource.path.display(), e);
+					return send_response(stream, http::Status::InternalServerError).await;
+				}
+			}
+		};
+		
+		let mime = self.mime_type(path);
+		let mut headers = vec![
+			http::Header::Status(http::Status::Ok),
+			http::Header::Server(HEADER_SERVER.to_string()),
+			http::Header::AcceptRanges(http::AcceptRanges::Bytes)
+		];
+		
+		if self.http.precompressed {
+			headers.push(http::Header::Vary(vec!["Accept-Encoding".to_string()]));
+		}
+		
+		if let Some(encoding) = encoding {
+			headers.push(http::Header::ContentEncoding(encoding));
+		}
+		
+		// ranges with other units than bytes are ignored and the whole file is sent
+		let ranges = match range {
+			Some(http::Ranges { unit: http::Unit::Bytes, ranges }) => resolve_ranges(&ranges, data.len()),
+			_ => None
+		};
+		
+		let body = match ranges.as_deref() {
+			None => {
+				headers.push(http::Header::ContentType(mime));
+				headers.push(http::Header::ContentLength(data.len()));
+				std::borrow::Cow::Borrowed(data)
+			}
+			Some([]) => {
+				headers[0] = http::Header::Status(http::Status::RangeNotSatisfiable);
+				headers.push(http::Header::ContentRange(http::ContentRange {
+					unit:  http::Unit::Bytes,
+					range: None,
+					size:  Some(data.len())
+				}));
+				headers.push(http::Header::ContentLength(0));
+				std::borrow::Cow::Borrowed(&[][..])
+			}
+			Some(&[(start, end)]) => {
+				headers[0] = http::Header::Status(http::Status::PartialContent);
+				headers.push(http::Header::ContentType(mime));
+				headers.push(http::Header::ContentRange(http::ContentRange {
+					unit:  http::Unit::Bytes,
+					range: Some((start, end)),
+					size:  Some(data.len())
+				}));
+				headers.push(http::Header::ContentLength(end - start + 1));
+				std::borrow::Cow::Borrowed(&data[start..=end])
+			}
+			Some(ranges) => {
+				let boundary = format!("{:016x}{:08x}",
+					UNIX_EPOCH.elapsed().unwrap().as_nanos() as u64,
+					BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed) as u32);
+				let mut body = Vec::new();
+				
+				for &(start, end) in ranges {
+					body.extend_from_slice(format!(
+						"\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+						boundary, mime, start, end, data.len()).as_bytes());
+					body.extend_from_slice(&data[start..=end]);
+				}
+				
+				body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+				headers[0] = http::Header::Status(http::Status::PartialContent);
+				headers.push(http::Header::ContentType(Box::new(
+					format!("multipart/byteranges; boundary={}", boundary).parse().unwrap())));
+				headers.push(http::Header::ContentLength(body.len()));
+				std::borrow::Cow::Owned(body)
+			}
+		};
+		
+		stream.write_headers(&headers).await?;
+		stream.write_all(if method == http::Method::Head { &[] } else { &*body }).await?;
+		Ok(())
+	}
+	
+	async fn send_index(
+		&self,
+		stream: &mut dyn http::traits::AsyncStream,
+		method: http::Method,
+		path:   &str,
+		node:   TrieNode<StorageBackendResource>
+	) -> Result<()> {
+		let dir = match &*node.data.read().await {
+			Some(v) => v.path.to_path_buf(),
+			None    => return send_response(stream, http::Status::NotFound).await
+		};
+		
+		let mut entries = match smol::fs::read_dir(&dir).await {
+			Ok(v)  => v,
+			Err(e) => {
+				log::error!("backend `{}` resource `{}`: failed to read dir: {}", &self.name, dir.display(), e);
+				return send_response(stream, http::Status::InternalServerError).await;
+			}
+		};
+		
+		let mut names = Vec::new();
+		
+		while let Some(entry) = entries.next().await {
+			let entry = match entry {
+				Ok(v)  => v,
+				Err(_) => continue
+			};
+			
+			let is_dir = entry.file_type().await.map_or(false, |v| v.is_dir());
+			let name = match entry.file_name().into_string() {
+				Ok(v) if is_dir => v + "/",
+				Ok(v)           => v,
+				Err(_)          => continue
+			};
+			
+			// only list what would actually be served
+			let full = format!("{}{}", path, name);
+			if self.is_resource(&full, is_dir).await && (is_dir || self.filter.allows(&full)) {
+				names.push(name);
+			}
+		}
+		
+		names.sort_unstable();
+		
+		let title = escape_html(path);
+		let mut body = format!(
+			"<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+			title);
+		
+		if path != "/" {
+			body.push_str("<li><a href=\"../\">../</a></li>\n");
+		}
+		
+		for name in &names {
+			let name = escape_html(name);
+			body.push_str(&format!("<li><a href=\"{0}\">{0}</a></li>\n", name));
+		}
+		
+		body.push_str("</ul>\n</body>\n</html>\n");
+		
+		stream.write_headers(&[
+			http::Header::Status(http::Status::Ok),
+			http::Header::Server(HEADER_SERVER.to_string()),
+			http::Header::ContentType(Box::new(MIME_TYPES[0].1.parse().unwrap())),
+			http::Header::ContentLength(body.len())
+		]).await?;
+		stream.write_all(if method == http::Method::Head { &[] } else { body.as_bytes() }).await?;
+		Ok(())
+	}
+	
+	fn mime_type(&self, path: &str) -> Box<http::MediaType> {
+		let extension = path.rsplit('/').next()
+			.and_then(|name| name.rsplit_once('.'))
+			.map(|(_, v)| v.to_ascii_lowercase());
+		
+		let mime = extension.as_deref()
+			.and_then(|ext| self.http.mime_types.get(ext).map(String::as_str)
+				.or_else(|| MIME_TYPES.iter().find(|(v, _)| *v == ext).map(|(_, v)| *v)))
+			.unwrap_or(DEFAULT_MIME_TYPE);
+		
+		Box::new(mime.parse().unwrap_or_else(|_| DEFAULT_MIME_TYPE.parse().unwrap()))
+	}
+}
+
+/// Resolves the byte ranges of a request against a resource of length `len`, sorted and with
+/// overlapping or adjacent ranges merged, so that no byte is sent twice. Returns `None` if there
+/// are more than [`MAX_RANGES`] ranges, in which case the whole resource is sent instead.
+fn resolve_ranges(ranges: &[http::Range], len: usize) -> Option<Vec<(usize, usize)>> {
+	if ranges.len() > MAX_RANGES {
+		return None;
+	}
+	
+	let mut resolved = ranges.iter()
+		.filter_map(|range| resolve_range(range, len))
+		.collect::<Vec<_>>();
+	resolved.sort_unstable();
+	
+	let mut merged = Vec::<(usize, usize)>::with_capacity(resolved.len());
+	
+	for (start, end) in resolved {
+		match merged.last_mut() {
+			Some((_, last)) if start <= *last + 1 => *last = end.max(*last),
+			_ => merged.push((start, end))
+		}
+	}
+	
+	Some(merged)
+}
+
+/// Resolves a byte range against a resource of length `len`, returns `None` if it is not satisfiable.
+fn resolve_range(range: &http::Range, len: usize) -> Option<(usize, usize)> {
+	match *range {
+		http::Range { start: Some(start), end } if start < len => {
+			let end = end.map_or(len - 1, |end| end.min(len - 1));
+			Some((start, end)).filter(|_| start <= end)
+		}
+		http::Range { start: None, end: Some(suffix) } if suffix > 0 && len > 0 =>
+			Some((len - suffix.min(len), len - 1)),
+		_ => None
+	}
+}
+
+/// Whether `encoding` is accepted by an `Accept-Encoding` header, either listed or matched by `*`.
+/// An encoding listed with `q=0` is refused, even if it is listed again or matched by `*`.
+fn accepts_encoding(encodings: &[http::Encoding], encoding: &http::Encoding) -> bool {
+	let (mut listed, mut wildcard) = (None::<f32>, None);
+	
+	for (v, q) in encodings.iter().map(http::Encoding::quality) {
+		match v {
+			v if v == *encoding => listed = Some(listed.map_or(q, |v| v.min(q))),
+			http::Encoding::Other(v) if &*v == "*" => wildcard = Some(q),
+			_ => ()
+		}
+	}
+	
+	listed.or(wildcard).map_or(false, |q| q > 0.0)
+}
+
+fn escape_html(s: &str) -> String {
+	s.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
 #[allow(non_camel_case_types)]
 struct __Arc_StorageBackend__(Arc<FsBackend>);
 
@@ -326,14 +875,15 @@ impl StreamHandler<dyn http::traits::AsyncStream> for __Arc_StorageBackend__ {
 			let mut body = Vec::new();
 			stream.read_to_end(&mut body).await?;
 			
-			let (mut method, mut path, mut length, mut range) = (None, None, None, None);
+			let (mut method, mut path, mut length, mut range, mut encodings) = (None, None, None, None, Vec::new());
 			
 			for header in headers {
 				match header {
-					http::Header::Method(v)        => method = Some(v),
-					http::Header::Path(v)          => path   = Some(v),
-					http::Header::ContentLength(v) => length = Some(v),
-					http::Header::Range(v)         => range  = Some(v),
+					http::Header::Method(v)         => method    = Some(v),
+					http::Header::Path(v)           => path      = Some(v),
+					http::Header::ContentLength(v)  => length    = Some(v),
+					http::Header::Range(v)          => range     = Some(v),
+					http::Header::AcceptEncoding(v) => encodings = v,
 					_ => ()
 				}
 			}
@@ -343,73 +893,43 @@ impl StreamHandler<dyn http::traits::AsyncStream> for __Arc_StorageBackend__ {
 				_ => return send_response(stream, http::Status::BadRequest).await
 			};
 			let i = path.find('?').unwrap_or_else(|| path.len());
+			let path = &path[..i];
 			
-			match (self.resources.child(&path[..i]).await, method) {
-				(Ok(node), method @ (http::Method::Get | http::Method::Head)) => {
-					let data = node.data.read().await;
-					let resource = data.as_ref().unwrap();
-					resource.last_read.store(UNIX_EPOCH.elapsed().unwrap().as_millis() as _, Ordering::Relaxed);
-					
-					#[allow(unused_assignments)]
-					let mut data_owned = None;
-					let data = match &resource.state {
-						State::Dir          => return send_response(stream, http::Status::NotFound).await,
-						State::Loaded(data) => &*data,
-						State::NotLoaded    => match smol::fs::read(&*resource.path).await {
-							Ok(v)  => {
-								log::info!("backend `{}` resource `{}`: loaded (on demand)", &self.name, &path);
-								data_owned = Some(v);
-								data_owned.as_ref().unwrap().as_slice()
-							}
-							Err(e) => {
-								log::error!("backend `{}` resource `{}`: failed to load file: {}", &self.name, &path, e);
-								return send_response(stream, http::Status::InternalServerError).await;
-							}
-						}
+			if !self.filter.allows(path) && !(path.ends_with('/') && !self.filter.denies(path)) {
+				return send_response(stream, http::Status::NotFound).await;
+			}
+			
+			match (self.resources.child(path).await, method) {
+				(Ok(node), method @ (http::Method::Head | http::Method::Get)) => {
+					let is_dir = match &*node.data.read().await {
+						Some(resource) => resource.state == State::Dir,
+						None           => return self.redirect_dir(stream, path).await
 					};
 					
-					match range {
-						Some(http::Ranges { unit: http::Unit::Bytes, ranges }) => match ranges.as_slice() {
-							[] => send_response(stream, http::Status::RangeNotSatisfiable).await,
-							[range] => {
-								let (start, end) = match range {
-									http::Range { start: Some(start), end: None }      => (*start, data.len() - 1),
-									http::Range { start: None,        end: Some(end) } => (0, *end),
-									http::Range { start: Some(start), end: Some(end) } => (*start, *end),
-									http::Range { start: None,        end: None }      => (0, 0)
-								};
-								
-								if start > data.len() - 1 || end > data.len() - 1 {
-									send_response(stream, http::Status::RangeNotSatisfiable).await
-								} else {
-									stream.write_headers(&[
-										http::Header::Status(http::Status::PartialContent),
-										http::Header::Server(HEADER_SERVER.to_string()),
-										http::Header::ContentLength(end - start + 1),
-										http::Header::ContentRange(http::ContentRange {
-											unit:  http::Unit::Bytes,
-											range: Some((start, end)),
-											size:  Some(data.len())
-										})
-									]).await?;
-									stream.write_all(if method == http::Method::Head { &[] } else { &data[start..=end] }).await?;
-									Ok(())
-								}
+					if !is_dir {
+						return self.send_file(stream, method, path, node, range, &encodings).await;
+					}
+					
+					for index in &self.http.index {
+						let index_path = format!("{}{}", path, index);
+						
+						if !self.filter.allows(&index_path) {
+							continue;
+						}
+						
+						if let Some(node) = self.resources.get(&index_path).await {
+							if matches!(&*node.data.read().await, Some(v) if v.state != State::Dir) {
+								return self.send_file(stream, method, &index_path, node, range, &encodings).await;
 							}
-							_ranges => send_response(stream, http::Status::NotImplemented).await
-						},
-						Some(_) => send_response(stream, http::Status::RangeNotSatisfiable).await,
-						None => {
-							stream.write_headers(&[
-								http::Header::Status(http::Status::Ok),
-								http::Header::Server(HEADER_SERVER.to_string()),
-								http::Header::ContentLength(data.len())
-							]).await?;
-							stream.write_all(if method == http::Method::Head { &[] } else { data }).await?;
-							Ok(())
 						}
 					}
+					
+					match self.http.autoindex {
+						true  => self.send_index(stream, method, path, node).await,
+						false => send_response(stream, http::Status::NotFound).await
+					}
 				}
+				(Err(_), http::Method::Head | http::Method::Get) => self.redirect_dir(stream, path).await,
 				(Ok(node), http::Method::Put) => {
 					let length = match length {
 						Some(v) => v,
@@ -543,4 +1063,180 @@ impl std::fmt::Debug for State {
 			Self::Loaded(_) => "Loaded"
 		})
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use {super::*, crate::utils::TestHttpStream};
+	
+	fn backend(files: &[(&str, &[u8])], http: ConfigHttp) -> FsBackend {
+		let backend = FsBackend {
+			name:      "test".to_string(),
+			resources: TrieNode::default(),
+			filter:    ConfigFilter::default(),
+			http
+		};
+		
+		smol::block_on(async {
+			for (path, data) in files {
+				backend.resources.insert(path, StorageBackendResource {
+					state:      State::Loaded(data.to_vec().into_boxed_slice()),
+					path:       Path::new(path).into(),
+					dirty:      AtomicBool::new(false),
+					last_read:  AtomicU64::new(0),
+					last_write: AtomicU64::new(0),
+					headers:    Vec::new()
+				}).await;
+			}
+		});
+		
+		backend
+	}
+	
+	fn send(backend: &FsBackend, path: &str, range: Option<&str>, encodings: &str) -> TestHttpStream {
+		let encodings = http::parse_list::<http::Encoding>(encodings).unwrap();
+		let mut stream = TestHttpStream::default();
+		
+		smol::block_on(async {
+			let node = backend.resources.get(path).await.unwrap();
+			let range = range.map(|v| v.parse().unwrap());
+			backend.send_file(&mut stream, http::Method::Get, path, node, range, &encodings).await.unwrap();
+		});
+		
+		stream
+	}
+	
+	fn ranges(s: &str, len: usize) -> Option<Vec<(usize, usize)>> {
+		resolve_ranges(&s.parse::<http::Ranges>().unwrap().ranges, len)
+	}
+	
+	fn accepts(encodings: &str, encoding: http::Encoding) -> bool {
+		accepts_encoding(&http::parse_list::<http::Encoding>(encodings).unwrap(), &encoding)
+	}
+	
+	#[test]
+	fn range() {
+		assert_eq!(ranges("bytes=0-4", 10), Some(vec![(0, 4)]));
+		assert_eq!(ranges("bytes=5-", 10), Some(vec![(5, 9)]));
+		assert_eq!(ranges("bytes=-3", 10), Some(vec![(7, 9)]));
+		assert_eq!(ranges("bytes=-20", 10), Some(vec![(0, 9)]));
+		assert_eq!(ranges("bytes=8-20", 10), Some(vec![(8, 9)]));
+		assert_eq!(ranges("bytes=10-", 10), Some(vec![]));
+		assert_eq!(ranges("bytes=-0", 10), Some(vec![]));
+		assert_eq!(ranges("bytes=0-", 0), Some(vec![]));
+		assert_eq!(ranges("bytes=5-4", 10), Some(vec![]));
+		
+		// overlapping and adjacent ranges are merged, disjoint ones are sorted
+		assert_eq!(ranges("bytes=0-3,2-5", 10), Some(vec![(0, 5)]));
+		assert_eq!(ranges("bytes=4-5,0-3", 10), Some(vec![(0, 5)]));
+		assert_eq!(ranges("bytes=0-0,0-0,0-0", 10), Some(vec![(0, 0)]));
+		assert_eq!(ranges("bytes=-2,0-1", 10), Some(vec![(0, 1), (8, 9)]));
+		assert_eq!(ranges("bytes=6-7,0-1,1-2,20-", 10), Some(vec![(0, 2), (6, 7)]));
+		
+		let many = (0..=MAX_RANGES).map(|i| format!("{0}-{0}", i * 2)).collect::<Vec<_>>().join(",");
+		assert_eq!(ranges(&format!("bytes={}", many), 100), None);
+		let many = (0..MAX_RANGES).map(|i| format!("{0}-{0}", i * 2)).collect::<Vec<_>>().join(",");
+		assert_eq!(ranges(&format!("bytes={}", many), 100).map(|v| v.len()), Some(MAX_RANGES));
+	}
+	
+	#[test]
+	fn range_response() {
+		let backend = backend(&[("/a.txt", b"0123456789")], ConfigHttp::default());
+		
+		let stream = send(&backend, "/a.txt", None, "");
+		assert_eq!(stream.status(), Some(http::Status::Ok));
+		assert_eq!(stream.response_body, b"0123456789");
+		
+		let stream = send(&backend, "/a.txt", Some("bytes=2-3,3-5"), "");
+		assert_eq!(stream.status(), Some(http::Status::PartialContent));
+		assert!(stream.response().contains(&http::Header::ContentRange(http::ContentRange {
+			unit:  http::Unit::Bytes,
+			range: Some((2, 5)),
+			size:  Some(10)
+		})));
+		assert!(stream.response().contains(&http::Header::ContentLength(4)));
+		assert_eq!(stream.response_body, b"2345");
+		
+		let stream = send(&backend, "/a.txt", Some("bytes=10-"), "");
+		assert_eq!(stream.status(), Some(http::Status::RangeNotSatisfiable));
+		assert!(stream.response().contains(&http::Header::ContentLength(0)));
+		assert!(stream.response_body.is_empty());
+		
+		let many = (0..=MAX_RANGES).map(|_| "0-9").collect::<Vec<_>>().join(",");
+		let stream = send(&backend, "/a.txt", Some(&format!("bytes={}", many)), "");
+		assert_eq!(stream.status(), Some(http::Status::Ok));
+		assert_eq!(stream.response_body, b"0123456789");
+		
+		// ranges of other units are ignored
+		let stream = send(&backend, "/a.txt", Some("lines=0-1"), "");
+		assert_eq!(stream.status(), Some(http::Status::Ok));
+	}
+	
+	#[test]
+	fn multipart() {
+		let backend = backend(&[("/a.txt", b"0123456789")], ConfigHttp::default());
+		let stream = send(&backend, "/a.txt", Some("bytes=7-8,0-1,1-2"), "");
+		assert_eq!(stream.status(), Some(http::Status::PartialContent));
+		
+		let content_type = stream.response().iter()
+			.find_map(|v| match v {
+				http::Header::ContentType(v) => Some(v.to_string()),
+				_ => None
+			})
+			.unwrap();
+		let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+		
+		let body = format!(concat!(
+			"\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-2/10\r\n\r\n012",
+			"\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 7-8/10\r\n\r\n78",
+			"\r\n--{0}--\r\n"), boundary);
+		assert_eq!(String::from_utf8(stream.response_body.clone()).unwrap(), body);
+		assert!(stream.response().contains(&http::Header::ContentLength(body.len())));
+	}
+	
+	#[test]
+	fn encodings() {
+		assert!(accepts("gzip, br", http::Encoding::GZip));
+		assert!(accepts("gzip;q=0.5", http::Encoding::GZip));
+		assert!(accepts("*", http::Encoding::Br));
+		assert!(!accepts("", http::Encoding::GZip));
+		assert!(!accepts("br", http::Encoding::GZip));
+		assert!(!accepts("gzip;q=0", http::Encoding::GZip));
+		assert!(!accepts("gzip; q=0.0", http::Encoding::GZip));
+		assert!(!accepts("gzip, gzip;q=0", http::Encoding::GZip));
+		assert!(!accepts("*, gzip;q=0", http::Encoding::GZip));
+		assert!(!accepts("br, *;q=0", http::Encoding::GZip));
+		assert!(accepts("gzip, *;q=0", http::Encoding::GZip));
+		
+		let backend = backend(&[("/a.txt", b"plain"), ("/a.txt.gz", b"gzip"), ("/a.txt.br", b"br")],
+			ConfigHttp { precompressed: true, ..ConfigHttp::default() });
+		
+		let stream = send(&backend, "/a.txt", None, "gzip, br");
+		assert!(stream.response().contains(&http::Header::ContentEncoding(http::Encoding::Br)));
+		assert_eq!(stream.response_body, b"br");
+		
+		let stream = send(&backend, "/a.txt", None, "gzip, br;q=0");
+		assert!(stream.response().contains(&http::Header::ContentEncoding(http::Encoding::GZip)));
+		assert_eq!(stream.response_body, b"gzip");
+		
+		let stream = send(&backend, "/a.txt", None, "gzip;q=0");
+		assert!(!stream.response().iter().any(|v| matches!(v, http::Header::ContentEncoding(_))));
+		assert!(stream.response().contains(&http::Header::Vary(vec!["Accept-Encoding".to_string()])));
+		assert_eq!(stream.response_body, b"plain");
+	}
+	
+	#[test]
+	fn mime_type() {
+		let mut http = ConfigHttp::default();
+		http.mime_types.insert("txt".to_string(), "text/x-custom".to_string());
+		let backend = backend(&[], http);
+		let mime = |path| backend.mime_type(path).to_string();
+		
+		assert_eq!(mime("/index.html"), "text/html; charset=utf-8");
+		assert_eq!(mime("/style.CSS"), "text/css; charset=utf-8");
+		assert_eq!(mime("/a/b.tar.gz"), "application/gzip");
+		assert_eq!(mime("/a.txt"), "text/x-custom");
+		assert_eq!(mime("/file"), DEFAULT_MIME_TYPE);
+		assert_eq!(mime("/dir.d/file"), DEFAULT_MIME_TYPE);
+		assert_eq!(mime("/a.unknown"), DEFAULT_MIME_TYPE);
+	}
+}
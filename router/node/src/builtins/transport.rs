@@ -0,0 +1,549 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Listening sockets and connections over TCP or Unix domain sockets, and UDP sockets.
+
+use {
+	super::*,
+	crate::global::upgrade,
+	std::{
+		io,
+		fmt,
+		future::Future,
+		net::SocketAddr,
+		path::PathBuf,
+		pin::Pin,
+		task::{Context, Poll},
+		os::unix::{fs::{FileTypeExt, PermissionsExt}, io::{AsRawFd, FromRawFd, RawFd}}
+	},
+	nix::sys::socket::{self as sock, sockopt, SockaddrLike},
+	smol::net::{TcpListener, TcpStream, UdpSocket, unix::{UnixListener, UnixStream}}
+};
+
+const LOCALHOST:        &str = "localhost";
+const LISTEN_BACKLOG:   usize = 1024;
+/// The first file descriptor passed by the service manager, see `sd_listen_fds(3)`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A bound listening socket.
+#[derive(Clone)]
+pub(super) enum Listener {
+	Tcp(TcpListener),
+	Unix(UnixListener)
+}
+
+impl Listener {
+	/// Adopts the socket of the module passed by the previous process on an upgrade or by the
+	/// service manager, or binds the Unix domain socket or TCP socket of a config. Returns the
+	/// listener and its address for logs.
+	pub(super) async fn bind(name: &str, cfg: &ConfigSocket, default_port: u16) -> Result<(Self, String)> {
+		let id = crate::component_id(name);
+		let upgraded = crate::get_component::<upgrade::ListenerFd>(id).try_get()
+			.and_then(|v| v.take())
+			.map(Self::from_fd)
+			.transpose()?
+			.filter(|v| v.matches(cfg, default_port));
+		
+		let listener = match (upgraded, cfg) {
+			(Some(listener), _) => listener,
+			(None, ConfigSocket { listen_fd: Some(name), .. }) => Self::inherited(name)?,
+			(None, ConfigSocket { pipe: Some(pipe), tcp: None, .. }) => Self::bind_unix(pipe)?,
+			(None, ConfigSocket { pipe: None, tcp: Some(tcp), .. }) => Self::bind_tcp(tcp, default_port).await?,
+			_ => return Err("expected one of `tcp`, `pipe` or `listen_fd`".into())
+		};
+		
+		// passed to the next process on an upgrade
+		crate::add_component::<upgrade::ListenerFd>(id, upgrade::ListenerFd::new(listener.as_raw_fd())?);
+		let endpoint = listener.to_string();
+		Ok((listener, endpoint))
+	}
+	
+	/// Whether a socket passed by the previous process can be adopted for a config, i.e. its
+	/// address did not change.
+	fn matches(&self, cfg: &ConfigSocket, default_port: u16) -> bool {
+		match (self, cfg) {
+			(_, ConfigSocket { listen_fd: Some(_), .. }) => true,
+			(Self::Unix(v), ConfigSocket { pipe: Some(pipe), tcp: None, .. }) => v.local_addr().ok()
+				.map_or(false, |v| v.as_pathname() == Some(pipe.path.as_path())),
+			(Self::Tcp(v), ConfigSocket { pipe: None, tcp: Some(tcp), .. }) => v.local_addr().ok()
+				.map_or(false, |v| same_addr(v, tcp.host.as_deref(), tcp.port.unwrap_or(default_port))),
+			_ => false
+		}
+	}
+	
+	async fn bind_tcp(cfg: &ConfigSocketTcp, default_port: u16) -> Result<Self> {
+		let host = cfg.host.as_deref().unwrap_or(LOCALHOST);
+		let port = cfg.port.unwrap_or(default_port);
+		
+		if !cfg.reuse_port {
+			return Ok(Self::Tcp(TcpListener::bind((host, port)).await?));
+		}
+		
+		let addr = smol::net::resolve((host, port)).await?
+			.into_iter()
+			.next()
+			.ok_or_else(|| Error::new(format!("`{}` did not resolve to any address", host)))?;
+		let family = match addr {
+			SocketAddr::V4(_) => sock::AddressFamily::Inet,
+			SocketAddr::V6(_) => sock::AddressFamily::Inet6
+		};
+		
+		let fd = sock::socket(family, sock::SockType::Stream, sock::SockFlag::SOCK_CLOEXEC, None)
+			.map_err(io::Error::from)?;
+		// closes the socket if any of the following fails
+		let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+		sock::setsockopt(fd, sockopt::ReuseAddr, &true).map_err(io::Error::from)?;
+		// other processes, like the next version of this one, can bind the same address
+		sock::setsockopt(fd, sockopt::ReusePort, &true).map_err(io::Error::from)?;
+		
+		match addr {
+			SocketAddr::V4(v) => sock::bind(fd, &sock::SockaddrIn::from(v)),
+			SocketAddr::V6(v) => sock::bind(fd, &sock::SockaddrIn6::from(v))
+		}.map_err(io::Error::from)?;
+		sock::listen(fd, LISTEN_BACKLOG).map_err(io::Error::from)?;
+		Ok(Self::Tcp(TcpListener::try_from(listener)?))
+	}
+	
+	fn bind_unix(cfg: &ConfigSocketPipe) -> Result<Self> {
+		// a socket left behind by a previous process is replaced, unless it is still in use
+		if let Ok(meta) = std::fs::symlink_metadata(&cfg.path) {
+			if !meta.file_type().is_socket() {
+				return Err(Error::new(format!("`{}` exists and is not a socket", cfg.path.display())));
+			}
+			
+			if std::os::unix::net::UnixStream::connect(&cfg.path).is_ok() {
+				return Err(Error::new(format!("`{}` is in use", cfg.path.display())));
+			}
+			
+			std::fs::remove_file(&cfg.path)?;
+		}
+		
+		let listener = UnixListener::bind(&cfg.path)?;
+		
+		if let Some(mode) = cfg.mode {
+			std::fs::set_permissions(&cfg.path, std::fs::Permissions::from_mode(mode))?;
+		}
+		
+		if cfg.owner.is_some() || cfg.group.is_some() {
+			let owner = cfg.owner.as_deref().map(user_id).transpose()?;
+			let group = cfg.group.as_deref().map(group_id).transpose()?;
+			nix::unistd::chown(&cfg.path, owner, group)
+				.map_err(|e| Error::new(format!("failed to change owner of `{}`: {}", cfg.path.display(), e)))?;
+		}
+		
+		Ok(Self::Unix(listener))
+	}
+	
+	/// Adopts a socket passed with `LISTEN_FDS`, by its name in `LISTEN_FDNAMES` or its index.
+	/// The socket is duplicated, so it can be adopted again if the config is reloaded.
+	fn inherited(name: &str) -> Result<Self> {
+		let fd = inherited_fd(name, |name| std::env::var(name).ok())?;
+		let fd = nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(LISTEN_FDS_START))
+			.map_err(io::Error::from)?;
+		Self::from_fd(fd)
+	}
+	
+	/// Takes ownership of a listening socket, whose type is determined by its address family.
+	pub(super) fn from_fd(fd: RawFd) -> Result<Self> {
+		let addr = match sock::getsockname::<sock::SockaddrStorage>(fd) {
+			Ok(v) => v,
+			Err(e) => {
+				let _ = nix::unistd::close(fd);
+				return Err(io::Error::from(e).into());
+			}
+		};
+		
+		Ok(match addr.family() {
+			Some(sock::AddressFamily::Unix) => Self::Unix(UnixListener::try_from(
+				unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) })?),
+			Some(sock::AddressFamily::Inet | sock::AddressFamily::Inet6) => Self::Tcp(TcpListener::try_from(
+				unsafe { std::net::TcpListener::from_raw_fd(fd) })?),
+			family => {
+				let _ = nix::unistd::close(fd);
+				return Err(Error::new(format!("unsupported address family {:?}", family)));
+			}
+		})
+	}
+	
+	pub(super) fn as_raw_fd(&self) -> RawFd {
+		match self {
+			Self::Tcp(v)  => v.as_raw_fd(),
+			Self::Unix(v) => v.as_raw_fd()
+		}
+	}
+	
+	async fn accept(self) -> io::Result<Stream> {
+		match self {
+			Self::Tcp(v)  => v.accept().await.map(|(v, _)| Stream::Tcp(v)),
+			Self::Unix(v) => v.accept().await.map(|(v, _)| Stream::Unix(v))
+		}
+	}
+}
+
+impl fmt::Display for Listener {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Tcp(v) => match v.local_addr() {
+				Ok(addr) => write!(f, "{}", addr),
+				Err(_)   => f.write_str("?")
+			},
+			Self::Unix(v) => match v.local_addr().ok().as_ref().and_then(|v| v.as_pathname()) {
+				Some(path) => write!(f, "unix:{}", path.display()),
+				None       => f.write_str("unix:?")
+			}
+		}
+	}
+}
+
+/// Adopts the UDP socket of the module passed by the previous process on an upgrade, or binds
+/// the socket of a config. It is passed on as `<name>/udp`, next to the listener of the module.
+pub(super) async fn bind_udp(name: &str, cfg: &ConfigSocketUdp, default_port: u16) -> Result<(UdpSocket, String)> {
+	let id   = crate::component_id(&format!("{}/udp", name));
+	let port = cfg.port.unwrap_or(default_port);
+	let upgraded = crate::get_component::<upgrade::ListenerFd>(id).try_get()
+		.and_then(|v| v.take())
+		.map(udp_from_fd)
+		.transpose()?
+		.filter(|v| v.local_addr().map_or(false, |v| same_addr(v, cfg.host.as_deref(), port)));
+	
+	let socket = match upgraded {
+		Some(socket) => socket,
+		None => UdpSocket::bind((cfg.host.as_deref().unwrap_or(LOCALHOST), port)).await?
+	};
+	
+	crate::add_component::<upgrade::ListenerFd>(id, upgrade::ListenerFd::new(socket.as_raw_fd())?);
+	let endpoint = format!("udp:{}", socket.local_addr()?);
+	Ok((socket, endpoint))
+}
+
+fn udp_from_fd(fd: RawFd) -> Result<UdpSocket> {
+	match sock::getsockopt(fd, sockopt::SockType) {
+		Ok(sock::SockType::Datagram) => Ok(UdpSocket::try_from(
+			unsafe { std::net::UdpSocket::from_raw_fd(fd) })?),
+		r => {
+			let _ = nix::unistd::close(fd);
+			Err(Error::new(format!("expected a UDP socket, got {:?}", r)))
+		}
+	}
+}
+
+/// The file descriptor of a socket passed with `LISTEN_FDS`, see `sd_listen_fds_with_names(3)`.
+fn inherited_fd(name: &str, var: impl Fn(&str) -> Option<String>) -> Result<RawFd> {
+	// the variables are inherited by child processes, which must not use the sockets
+	if var("LISTEN_PID").and_then(|v| v.parse::<u32>().ok()) != Some(std::process::id()) {
+		return Err("no sockets were passed to this process".into());
+	}
+	
+	let count = var("LISTEN_FDS").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+	let names = var("LISTEN_FDNAMES");
+	let names = names.as_deref().map(|v| v.split(':').collect::<Vec<_>>());
+	
+	if names.as_ref().map_or(false, |v| v.len() != count) {
+		return Err("`LISTEN_FDNAMES` does not name every socket of `LISTEN_FDS`".into());
+	}
+	
+	let index = names.and_then(|v| v.iter().position(|v| *v == name))
+		.or_else(|| name.parse().ok())
+		.filter(|v| *v < count)
+		.ok_or_else(|| Error::new(format!("no socket `{}` was passed to this process", name)))?;
+	
+	Ok(LISTEN_FDS_START + index as RawFd)
+}
+
+/// Whether a socket bound to `addr` listens on the configured address, hosts that are no IP
+/// address match any address.
+fn same_addr(addr: SocketAddr, host: Option<&str>, port: u16) -> bool {
+	addr.port() == port && host
+		.and_then(|host| host.parse::<std::net::IpAddr>().ok())
+		.map_or(true, |ip| ip == addr.ip())
+}
+
+fn user_id(name: &str) -> Result<nix::unistd::Uid> {
+	match name.parse() {
+		Ok(id) => Ok(nix::unistd::Uid::from_raw(id)),
+		Err(_) => nix::unistd::User::from_name(name).map_err(io::Error::from)?
+			.map(|v| v.uid)
+			.ok_or_else(|| Error::new(format!("unknown user `{}`", name)))
+	}
+}
+
+fn group_id(name: &str) -> Result<nix::unistd::Gid> {
+	match name.parse() {
+		Ok(id) => Ok(nix::unistd::Gid::from_raw(id)),
+		Err(_) => nix::unistd::Group::from_name(name).map_err(io::Error::from)?
+			.map(|v| v.gid)
+			.ok_or_else(|| Error::new(format!("unknown group `{}`", name)))
+	}
+}
+
+/// Accepts connections of a listener.
+pub(super) struct Acceptor {
+	listener: Listener,
+	accept:   Option<Pin<Box<dyn Future<Output = io::Result<Stream>> + Send>>>
+}
+
+impl Acceptor {
+	pub(super) fn new(listener: Listener) -> Self {
+		Self { listener, accept: None }
+	}
+}
+
+impl net::utils::AsyncAcceptor for Acceptor {
+	type Connection = Stream;
+	
+	fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Pin<Box<dyn Future<Output = io::Result<Self::Connection>> + Send + 'static>>> {
+		let Self { listener, accept } = self.get_mut();
+		
+		// the next process accepts connections, this one is never woken again
+		if upgrade::is_draining() {
+			*accept = None;
+			return Poll::Pending;
+		}
+		
+		match accept.get_or_insert_with(|| Box::pin(listener.clone().accept())).as_mut().poll(cx) {
+			Poll::Pending  => Poll::Pending,
+			Poll::Ready(r) => {
+				*accept = None;
+				Poll::Ready(Box::pin(async move { r }))
+			}
+		}
+	}
+}
+
+/// The endpoint of a backend.
+#[derive(Clone, Debug)]
+pub(super) enum Target {
+	Tcp(String),
+	Unix(PathBuf)
+}
+
+impl fmt::Display for Target {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Tcp(v)  => f.write_str(v),
+			Self::Unix(v) => write!(f, "unix:{}", v.display())
+		}
+	}
+}
+
+impl net::utils::AsyncConnector for Target {
+	type Connection = Stream;
+	
+	fn connect<'a>(&'a self) -> Pin<Box<dyn Future<Output = io::Result<Self::Connection>> + Send + 'a>> {
+		Box::pin(async move {
+			match self {
+				Self::Tcp(v)  => TcpStream::connect(&**v).await.map(Stream::Tcp),
+				Self::Unix(v) => UnixStream::connect(v).await.map(Stream::Unix)
+			}
+		})
+	}
+}
+
+/// A connection over TCP or a Unix domain socket.
+pub(super) enum Stream {
+	Tcp(TcpStream),
+	Unix(UnixStream)
+}
+
+impl Stream {
+	/// The address of the peer, `None` for Unix domain sockets.
+	pub(super) fn peer_addr(&self) -> io::Result<Option<SocketAddr>> {
+		match self {
+			Self::Tcp(v)  => v.peer_addr().map(Some),
+			Self::Unix(_) => Ok(None)
+		}
+	}
+	
+	/// The local address, `None` for Unix domain sockets.
+	pub(super) fn local_addr(&self) -> io::Result<Option<SocketAddr>> {
+		match self {
+			Self::Tcp(v)  => v.local_addr().map(Some),
+			Self::Unix(_) => Ok(None)
+		}
+	}
+}
+
+impl smol::io::AsyncRead for Stream {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			Self::Tcp(v)  => Pin::new(v).poll_read(cx, buf),
+			Self::Unix(v) => Pin::new(v).poll_read(cx, buf)
+		}
+	}
+	
+	fn poll_read_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [io::IoSliceMut<'_>]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			Self::Tcp(v)  => Pin::new(v).poll_read_vectored(cx, bufs),
+			Self::Unix(v) => Pin::new(v).poll_read_vectored(cx, bufs)
+		}
+	}
+}
+
+impl smol::io::AsyncWrite for Stream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			Self::Tcp(v)  => Pin::new(v).poll_write(cx, buf),
+			Self::Unix(v) => Pin::new(v).poll_write(cx, buf)
+		}
+	}
+	
+	fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			Self::Tcp(v)  => Pin::new(v).poll_write_vectored(cx, bufs),
+			Self::Unix(v) => Pin::new(v).poll_write_vectored(cx, bufs)
+		}
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Self::Tcp(v)  => Pin::new(v).poll_flush(cx),
+			Self::Unix(v) => Pin::new(v).poll_flush(cx)
+		}
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Self::Tcp(v)  => Pin::new(v).poll_close(cx),
+			Self::Unix(v) => Pin::new(v).poll_close(cx)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, crate::utils::stream::test_dir, std::os::unix::{fs::MetadataExt, io::IntoRawFd}};
+	
+	fn env<'a>(vars: &'a [(&str, String)]) -> impl Fn(&str) -> Option<String> + 'a {
+		move |name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.clone())
+	}
+	
+	fn pipe(path: PathBuf, mode: Option<u32>, owner: Option<String>, group: Option<String>) -> ConfigSocketPipe {
+		ConfigSocketPipe { path, mode, owner, group }
+	}
+	
+	#[test]
+	fn inherited_pid_mismatch() {
+		let vars = [("LISTEN_PID", (std::process::id() + 1).to_string()), ("LISTEN_FDS", "1".to_string())];
+		assert!(inherited_fd("0", env(&vars)).is_err());
+		assert!(inherited_fd("0", env(&vars[1..])).is_err());
+	}
+	
+	#[test]
+	fn inherited_count_mismatch() {
+		let pid = std::process::id().to_string();
+		let vars = [("LISTEN_PID", pid.clone()), ("LISTEN_FDS", "2".to_string()), ("LISTEN_FDNAMES", "http".to_string())];
+		assert!(inherited_fd("http", env(&vars)).is_err());
+		
+		let vars = [("LISTEN_PID", pid), ("LISTEN_FDS", "1".to_string()), ("LISTEN_FDNAMES", "http:https".to_string())];
+		assert!(inherited_fd("http", env(&vars)).is_err());
+	}
+	
+	#[test]
+	fn inherited_by_name_or_index() {
+		let vars = [
+			("LISTEN_PID", std::process::id().to_string()),
+			("LISTEN_FDS", "2".to_string()),
+			("LISTEN_FDNAMES", "http:https".to_string())
+		];
+		assert_eq!(inherited_fd("http", env(&vars)).unwrap(), LISTEN_FDS_START);
+		assert_eq!(inherited_fd("https", env(&vars)).unwrap(), LISTEN_FDS_START + 1);
+		assert_eq!(inherited_fd("1", env(&vars)).unwrap(), LISTEN_FDS_START + 1);
+		assert!(inherited_fd("dns", env(&vars)).is_err());
+		assert!(inherited_fd("2", env(&vars)).is_err());
+		
+		let vars = [("LISTEN_PID", std::process::id().to_string()), ("LISTEN_FDS", "1".to_string())];
+		assert_eq!(inherited_fd("0", env(&vars)).unwrap(), LISTEN_FDS_START);
+		assert!(inherited_fd("http", env(&vars)).is_err());
+	}
+	
+	#[test]
+	fn unix_mode_and_owner() {
+		let path = test_dir("transport-mode").join("socket");
+		let uid = nix::unistd::getuid().as_raw();
+		let gid = nix::unistd::getgid().as_raw();
+		let listener = Listener::bind_unix(&pipe(path.clone(), Some(0o600), Some(uid.to_string()), Some(gid.to_string()))).unwrap();
+		assert!(matches!(listener, Listener::Unix(_)));
+		
+		let meta = std::fs::metadata(&path).unwrap();
+		assert!(meta.file_type().is_socket());
+		assert_eq!(meta.mode() & 0o777, 0o600);
+		assert_eq!((meta.uid(), meta.gid()), (uid, gid));
+		
+		assert!(Listener::bind_unix(&pipe(test_dir("transport-owner").join("socket"), None,
+			Some("kranus-no-such-user".to_string()), None)).is_err());
+	}
+	
+	#[test]
+	fn unix_stale_socket() {
+		let dir = test_dir("transport-stale");
+		let path = dir.join("socket");
+		drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+		assert!(path.exists());
+		let listener = Listener::bind_unix(&pipe(path.clone(), None, None, None)).unwrap();
+		
+		// the socket is in use now, so it is not replaced
+		assert!(Listener::bind_unix(&pipe(path.clone(), None, None, None)).is_err());
+		assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+		drop(listener);
+		
+		let file = dir.join("file");
+		std::fs::write(&file, "data").unwrap();
+		assert!(Listener::bind_unix(&pipe(file.clone(), None, None, None)).is_err());
+		assert_eq!(std::fs::read_to_string(&file).unwrap(), "data");
+	}
+	
+	#[test]
+	fn addr() {
+		let addr = "127.0.0.1:8080".parse().unwrap();
+		assert!(same_addr(addr, None, 8080));
+		assert!(same_addr(addr, Some("127.0.0.1"), 8080));
+		assert!(same_addr(addr, Some("localhost"), 8080));
+		assert!(!same_addr(addr, Some("127.0.0.1"), 8081));
+		assert!(!same_addr(addr, Some("127.0.0.2"), 8080));
+		assert!(!same_addr(addr, Some("::1"), 8080));
+		assert!(same_addr("[::1]:53".parse().unwrap(), Some("::1"), 53));
+	}
+	
+	#[test]
+	fn from_fd() {
+		let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = tcp.local_addr().unwrap();
+		let listener = Listener::from_fd(tcp.into_raw_fd()).unwrap();
+		assert!(matches!(listener, Listener::Tcp(_)));
+		assert_eq!(listener.to_string(), addr.to_string());
+		
+		let path = test_dir("transport-fd").join("socket");
+		let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+		let listener = Listener::from_fd(unix.into_raw_fd()).unwrap();
+		assert!(matches!(listener, Listener::Unix(_)));
+		assert_eq!(listener.to_string(), format!("unix:{}", path.display()));
+		
+		// not a socket
+		let file = std::fs::File::open(path.parent().unwrap()).unwrap();
+		assert!(Listener::from_fd(file.into_raw_fd()).is_err());
+		
+		let netlink = sock::socket(sock::AddressFamily::Netlink, sock::SockType::Raw, sock::SockFlag::empty(), None);
+		if let Ok(fd) = netlink {
+			assert!(Listener::from_fd(fd).is_err());
+		}
+	}
+}
@@ -0,0 +1,218 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Validation of the merged config in check mode.
+
+use {
+	super::*,
+	std::collections::BTreeMap,
+	serde::Deserialize,
+	schemars::JsonSchema
+};
+
+/// The shape of a whole config file, used to generate the JSON schema.
+#[allow(dead_code)]
+#[derive(Deserialize, JsonSchema)]
+struct RootConfig {
+	#[serde(default)]
+	include:  Vec<String>,
+	#[serde(default)]
+	merge:    config::MergeStrategies,
+	#[serde(flatten)]
+	global:   global::Config,
+	#[serde(flatten)]
+	builtins: builtins::Config
+}
+
+/// Prints the JSON schema of the config, plugin sections are not included.
+pub fn print_schema() {
+	let schema = schemars::schema_for!(RootConfig);
+	println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+}
+
+/// Remembers which file each key of the merged config came from.
+#[derive(Default)]
+pub struct ConfigOrigins(BTreeMap<String, String>);
+
+impl ConfigOrigins {
+	/// Records `file` as the origin of all keys in `cfg` below `prefix`, including maps, so errors
+	/// about a missing key are found too. Keys defined by later files replace earlier origins the
+	/// same way their values do when merging.
+	pub fn record(&mut self, prefix: &str, cfg: &HashMap<String, serde_dyn_repr::Value>, file: &str) {
+		for (key, value) in cfg {
+			let path = match prefix {
+				"" => key.clone(),
+				_  => format!("{}.{}", prefix, key)
+			};
+
+			if let serde_dyn_repr::Value::Map(map) = value {
+				self.record(&path, map, file);
+			}
+
+			self.0.insert(path, file.to_string());
+		}
+	}
+
+	/// Returns the file that defined the key at `path` or its closest recorded parent.
+	pub fn find(&self, path: &str) -> Option<&str> {
+		let mut path = path;
+
+		loop {
+			if let Some(file) = self.0.get(path) {
+				return Some(file);
+			}
+
+			path = &path[..path.rfind(['.', '['])?];
+		}
+	}
+}
+
+/// Validates the global, builtin and plugin sections of the config and logs every error with the
+/// file it originates from, returns the number of errors.
+pub fn run(
+	cfg:        &HashMap<String, serde_dyn_repr::Value>,
+	origins:    &ConfigOrigins,
+	plugins:    &[(&str, &libloading::Library)],
+	components: &[String]
+) -> usize {
+	let mut errors = Vec::new();
+
+	if let Err(e) = serde_path_to_error::deserialize::<_, global::Config>(serde_dyn_repr::Value::Map(cfg.clone())) {
+		errors.push(ConfigError::from_path("", e));
+	}
+
+	errors.extend(builtins::check(cfg, components));
+
+	for (path, lib) in plugins {
+		match unsafe { kranus_router_node::plugin_check_fn(lib) } {
+			Ok(check_fn) => errors.extend((check_fn)(
+				&mut <dyn erased_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(cfg.clone())))),
+			Err(e) => errors.push(ConfigError::new("", format!("plugin `{}`: {}", path, e.display())))
+		}
+	}
+
+	for error in &errors {
+		match origins.find(&error.path) {
+			Some(file) => log::error!("check: {}: {}", file, error),
+			None       => log::error!("check: {}", error)
+		}
+	}
+
+	errors.len()
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, serde_dyn_repr::Value};
+
+	fn map(entries: &[(&str, Value)]) -> HashMap<String, Value> {
+		entries.iter()
+			.map(|(k, v)| (k.to_string(), v.clone()))
+			.collect()
+	}
+
+	fn string(v: &str) -> Value {
+		Value::String(v.to_string())
+	}
+
+	fn cors(next: &str, extra: &[(&str, Value)]) -> Value {
+		let mut cfg = map(&[
+			("type", string("cors")),
+			("next", string(next)),
+			("origins", Value::Seq(vec![string("*")]))
+		]);
+		cfg.extend(map(extra));
+		Value::Map(cfg)
+	}
+
+	fn check(builtin: &[(&str, Value)], components: &[&str]) -> Vec<(String, String)> {
+		let cfg = map(&[("builtin", Value::Map(map(builtin)))]);
+		let components = components.iter().map(ToString::to_string).collect::<Vec<_>>();
+		let mut errors = builtins::check(&cfg, &components).into_iter()
+			.map(|e| (e.path, e.message))
+			.collect::<Vec<_>>();
+		errors.sort();
+		errors
+	}
+
+	#[test]
+	fn origins() {
+		let mut origins = ConfigOrigins::default();
+		origins.record("", &map(&[
+			("include", Value::Seq(vec![string("b.toml")])),
+			("builtin", Value::Map(map(&[("a", cors("b", &[]))])))
+		]), "a.toml");
+		origins.record("", &map(&[
+			("builtin", Value::Map(map(&[
+				("a", Value::Map(map(&[("next", string("c"))]))),
+				("b", cors("c", &[]))
+			])))
+		]), "b.toml");
+
+		assert_eq!(origins.find("include"), Some("a.toml"));
+		assert_eq!(origins.find("include[0]"), Some("a.toml"));
+		assert_eq!(origins.find("builtin.a.type"), Some("a.toml"));
+		assert_eq!(origins.find("builtin.a.next"), Some("b.toml"));
+		assert_eq!(origins.find("builtin.b.origins[0]"), Some("b.toml"));
+		// maps are recorded too, e.g. for missing keys
+		assert_eq!(origins.find("builtin.a"), Some("b.toml"));
+		assert_eq!(origins.find("builtin.c"), Some("b.toml"));
+		assert_eq!(origins.find("other.key"), None);
+		assert_eq!(origins.find(""), None);
+	}
+
+	#[test]
+	fn bad_key() {
+		let errors = check(&[
+			("a", cors("d", &[("methds", Value::Seq(Vec::new()))])),
+			("b", cors("d", &[("methods", Value::Seq(vec![Value::UInt(1)]))])),
+			("c", Value::Map(map(&[("type", string("unknown"))]))),
+			("d", Value::Map(map(&[("next", string("a"))])))
+		], &[]);
+
+		assert_eq!(errors.len(), 4, "{:?}", errors);
+		assert_eq!(errors[0].0, "builtin.a.methds");
+		assert!(errors[0].1.starts_with("unknown field `methds`"), "{}", errors[0].1);
+		assert_eq!(errors[1].0, "builtin.b.methods[0]");
+		assert_eq!(errors[2], ("builtin.c.type".to_string(), "unknown module type `unknown`".to_string()));
+		assert_eq!(errors[3], ("builtin.d".to_string(), "missing field `type`".to_string()));
+
+		let errors = check(&[("a", Value::Map(map(&[("type", string("cors")), ("next", string("b"))])))], &["b"]);
+		assert_eq!(errors, [("builtin.a".to_string(), "missing field `origins`".to_string())]);
+	}
+
+	#[test]
+	fn unknown_reference() {
+		assert!(check(&[("a", cors("b", &[])), ("b", cors("plugin", &[]))], &["plugin"]).is_empty());
+		assert_eq!(check(&[("a", cors("missing", &[]))], &["plugin"]), [
+			("builtin.a.next".to_string(), "unknown component `missing`".to_string())
+		]);
+	}
+
+	#[test]
+	fn cycle() {
+		let errors = check(&[("a", cors("b", &[])), ("b", cors("a", &[])), ("c", cors("a", &[]))], &[]);
+		assert_eq!(errors.len(), 1, "{:?}", errors);
+		assert_eq!(errors[0].0, "builtin");
+		assert!(errors[0].1.starts_with("dependency cycle: "), "{}", errors[0].1);
+	}
+}
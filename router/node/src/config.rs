@@ -0,0 +1,510 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Preprocessing of config files: substitution of `${...}` expressions and merging of includes.
+//!
+//! Supported expressions in string values:
+//!
+//! | Expression          | Result
+//! |---------------------|-----------------------------------------------------------------
+//! | `${VAR}`            | the value of `VAR`, an empty string if it is not set
+//! | `${VAR:-default}`   | the value of `VAR`, `default` if it is not set or empty
+//! | `${VAR:?message}`   | the value of `VAR`, an error with `message` if it is not set or empty
+//! | `${file:/path}`     | the contents of `/path` without trailing newlines, e.g. for secrets
+//! | `$${`               | a literal `${`
+//!
+//! Sequences are replaced by later files by default, this can be changed per key path:
+//!
+//! ```toml
+//! [merge."builtin.router.filters"]
+//! strategy = "append"
+//!
+//! [merge."builtin.balancer.backends"]
+//! strategy = "merge-by-key"
+//! key      = "name"
+//! ```
+
+use {
+	super::*,
+	serde::Deserialize,
+	schemars::JsonSchema,
+	serde_dyn_repr::Value
+};
+
+pub const CFG_MERGE_KEY: &str = "merge";
+
+/// How a sequence in a later config replaces the same sequence in an earlier config.
+#[derive(Clone, Debug, PartialEq, Deserialize, JsonSchema)]
+#[serde(tag = "strategy", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum MergeStrategy {
+	/// The later sequence replaces the earlier one.
+	Replace,
+	/// The elements of the later sequence are appended to the earlier one.
+	Append,
+	/// Maps with the same value for `key` are merged, all other elements are appended.
+	MergeByKey { key: String }
+}
+
+impl Default for MergeStrategy {
+	fn default() -> Self {
+		Self::Replace
+	}
+}
+
+/// The merge strategies by dot-separated key path.
+pub type MergeStrategies = HashMap<String, MergeStrategy>;
+
+/// Substitutes all expressions in the string values of `val`, `path` is the key path of `val`
+/// and used for error messages. All errors are collected instead of stopping at the first one.
+pub fn substitute(val: &mut Value, path: &str, errors: &mut Vec<ConfigError>) {
+	match val {
+		Value::String(str) => match substitute_str(str, path) {
+			Ok(v)  => *str = v,
+			Err(e) => errors.push(ConfigError::new(path, e))
+		}
+		Value::Seq(seq)    => seq.iter_mut()
+			.enumerate()
+			.for_each(|(i, v)| substitute(v, &format!("{}[{}]", path, i), errors)),
+		Value::Map(map)    => map.iter_mut()
+			.for_each(|(k, v)| substitute(v, &join_path(path, k), errors)),
+		Value::Enum(_, v)  => substitute(v, path, errors),
+		_                  => ()
+	}
+}
+
+fn substitute_str(str: &str, path: &str) -> std::result::Result<String, String> {
+	let mut out  = String::with_capacity(str.len());
+	let mut rest = str;
+
+	while let Some(start) = rest.find('$') {
+		out.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		if let Some(v) = rest.strip_prefix("$${") {
+			out.push_str("${");
+			rest = v;
+			continue;
+		}
+
+		let expr = match rest.strip_prefix("${") {
+			Some(v) => v,
+			None    => {
+				out.push('$');
+				rest = &rest[1..];
+				continue;
+			}
+		};
+
+		let end = expr.find('}')
+			.ok_or_else(|| format!("unterminated `${{` at offset {}", str.len() - rest.len()))?;
+
+		out.push_str(&expand(&expr[..end], path)?);
+		rest = &expr[end + 1..];
+	}
+
+	out.push_str(rest);
+	Ok(out)
+}
+
+fn expand(expr: &str, path: &str) -> std::result::Result<String, String> {
+	if let Some(file) = expr.strip_prefix("file:") {
+		return std::fs::read_to_string(file)
+			.map(|v| v.trim_end_matches(&['\r', '\n'][..]).to_string())
+			.map_err(|e| format!("failed to read `{}`: {}", file, e));
+	}
+
+	let (var, op) = match expr.split_once(':') {
+		Some((var, op)) => (var, Some(op)),
+		None            => (expr, None)
+	};
+
+	let val = match std::env::var(var) {
+		Ok(v)  => Some(v).filter(|v| !v.is_empty() || op.is_none()),
+		Err(std::env::VarError::NotPresent) => None,
+		Err(e) => return Err(format!("environment variable `{}`: {}", var, e))
+	};
+
+	match (val, op) {
+		(Some(v), _) => Ok(v),
+		(None, None) => {
+			log::warn!("init: environment variable `{}` referenced by `{}` is not set, substituting an empty string", var, path);
+			Ok(String::new())
+		}
+		(None, Some(op)) => match op.split_at(op.chars().next().map_or(0, char::len_utf8)) {
+			("-", default) => Ok(default.to_string()),
+			("?", "")      => Err(format!("environment variable `{}` is not set", var)),
+			("?", message) => Err(message.to_string()),
+			_              => Err(format!("invalid expression `${{{}}}`, expected `:-` or `:?` after the variable name", expr))
+		}
+	}
+}
+
+/// Collects the merge strategies declared under the `merge` key of a config.
+pub fn merge_strategies(cfg: &HashMap<String, Value>) -> std::result::Result<MergeStrategies, ConfigError> {
+	match cfg.get(CFG_MERGE_KEY) {
+		Some(v) => serde_path_to_error::deserialize(v.clone())
+			.map_err(|e| ConfigError::from_path(CFG_MERGE_KEY, e)),
+		None    => Ok(MergeStrategies::new())
+	}
+}
+
+/// Merges `cfgs` into `dst` in order, the merge strategies of all configs apply to every merge, so
+/// an include may declare how it is merged into the config that included it.
+pub fn merge_all(
+	dst:  &mut HashMap<String, Value>,
+	cfgs: impl IntoIterator<Item = HashMap<String, Value>>
+) -> std::result::Result<(), ConfigError> {
+	let cfgs = cfgs.into_iter().collect::<Vec<_>>();
+	let mut strategies = merge_strategies(dst)?;
+
+	for cfg in &cfgs {
+		strategies.extend(merge_strategies(cfg)?);
+	}
+
+	cfgs.into_iter().for_each(|cfg| merge_maps(dst, cfg, &strategies, ""));
+	Ok(())
+}
+
+/// Recursively merges `src` into `dst`, values in `src` take precedence.
+pub fn merge_maps(dst: &mut HashMap<String, Value>, src: HashMap<String, Value>, strategies: &MergeStrategies, path: &str) {
+	for (key, val) in src {
+		let path = join_path(path, &key);
+		let mut dst = match dst.entry(key) {
+			std::collections::hash_map::Entry::Occupied(v) => v,
+			std::collections::hash_map::Entry::Vacant(v)   => {
+				v.insert(val);
+				continue;
+			}
+		};
+
+		match (dst.get_mut(), val) {
+			(Value::Map(dst), Value::Map(src)) => merge_maps(dst, src, strategies, &path),
+			(Value::Seq(dst), Value::Seq(src)) => merge_seqs(dst, src, strategies, &path),
+			(dst, src) => *dst = src
+		}
+	}
+}
+
+fn merge_seqs(dst: &mut Vec<Value>, src: Vec<Value>, strategies: &MergeStrategies, path: &str) {
+	match strategies.get(path) {
+		None | Some(MergeStrategy::Replace) => *dst = src,
+		Some(MergeStrategy::Append) => dst.extend(src),
+		Some(MergeStrategy::MergeByKey { key }) => for val in src {
+			let existing = match &val {
+				Value::Map(map) => map.get(key).and_then(|id| dst.iter_mut().find(|v| match v {
+					Value::Map(v) => v.get(key) == Some(id),
+					_ => false
+				})),
+				_ => None
+			};
+
+			match (existing, val) {
+				(Some(Value::Map(dst)), Value::Map(src)) => merge_maps(dst, src, strategies, path),
+				(_, val) => dst.push(val)
+			}
+		}
+	}
+}
+
+/// The files found by [`load`].
+#[derive(Debug, Default)]
+pub struct Sources {
+	/// The parsed configs by path, in the order they were loaded.
+	pub configs: Vec<(String, HashMap<String, Value>)>,
+	/// Shared libraries and WASM modules by path, with the config that referenced them.
+	pub modules: Vec<(String, String)>,
+	/// All files and directories that were read, with the config that referenced them.
+	pub paths:   Vec<(String, String)>,
+	pub errors:  usize
+}
+
+/// Reads the config files and directories in `includes`, with the config that referenced them,
+/// and all configs they include. Variables are substituted, but the configs are not merged.
+pub fn load(mut includes: Vec<(String, String)>) -> Sources {
+	let mut sources = Sources::default();
+
+	while let Some((path, reference)) = includes.pop() {
+		let metadata = match std::fs::metadata(&path) {
+			Ok(v) => v,
+			Err(e) => {
+				log::error!("init: failed to read `{}` (referenced by {}): {}", path, reference, e);
+				sources.errors += 1;
+				continue;
+			}
+		};
+
+		sources.paths.push((path.clone(), reference.clone()));
+
+		if metadata.is_dir() {
+			let dir = match std::fs::read_dir(&path) {
+				Ok(v)  => v,
+				Err(e) => {
+					log::error!("init: failed to read directory `{}` (referenced by {}): {}", path, reference, e);
+					sources.errors += 1;
+					continue;
+				}
+			};
+
+			for entry in dir {
+				let entry = match entry {
+					Ok(v)  => v,
+					Err(e) => {
+						log::error!("init: failed to read directory `{}` (referenced by {}): {}", path, reference, e);
+						sources.errors += 1;
+						continue;
+					}
+				};
+
+				if let Some(path) = entry.path().to_str() {
+					includes.push((path.to_string(), reference.clone()));
+				}
+			}
+
+			continue;
+		}
+
+		let (_, extension) = path.rsplit_once('.').unwrap_or(("", ""));
+
+		if let "so" | "dll" | "wasm" = extension {
+			sources.modules.push((path, reference));
+			continue;
+		}
+
+		let cfg = match std::fs::read_to_string(&path) {
+			Ok(v)  => v,
+			Err(e) => {
+				log::error!("init: failed to read config `{}` (referenced by {}): {}", path, reference, e);
+				sources.errors += 1;
+				continue;
+			}
+		};
+
+		let cfg = match extension {
+			"yml" | "yaml" => serde_yaml::from_str::<HashMap<String, Value>>(&cfg)
+				.map_err(|v| v.to_string()),
+			_ => toml::from_str::<HashMap<String, Value>>(&cfg)
+				.map_err(|v| v.to_string())
+		};
+
+		let cfg = match cfg {
+			Ok(v) => v,
+			Err(e) => {
+				log::error!("init: failed to parse config `{}` (referenced by {}): {}", path, reference, e);
+				sources.errors += 1;
+				continue;
+			}
+		};
+
+		let mut wrapped_cfg = Value::Map(cfg);
+		let mut substitution_errors = Vec::new();
+		substitute(&mut wrapped_cfg, "", &mut substitution_errors);
+
+		if !substitution_errors.is_empty() {
+			for e in substitution_errors {
+				log::error!("init: failed to substitute variables in config `{}` (referenced by {}): {}", path, reference, e);
+			}
+
+			sources.errors += 1;
+			continue;
+		}
+
+		let cfg = match wrapped_cfg {
+			Value::Map(v) => v,
+			_ => unreachable!()
+		};
+
+		if let Some(Value::Seq(seq)) = cfg.get(CFG_INCLUDE_KEY) {
+			for include in seq {
+				match include {
+					Value::String(include) => includes.push((include.clone(), format!("`{}`", path))),
+					_ => {
+						log::error!("init: failed to parse config `{}` (referenced by {}): `include` must be an array of strings", path, reference);
+						sources.errors += 1;
+						continue;
+					}
+				}
+			}
+		}
+
+		log::info!("init: loaded config `{}` (referenced by {})", path, reference);
+		sources.configs.push((path, cfg));
+	}
+
+	sources
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+	match prefix {
+		"" => key.to_string(),
+		_  => format!("{}.{}", prefix, key)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn toml(str: &str) -> HashMap<String, Value> {
+		toml::from_str(str).unwrap()
+	}
+
+	fn yaml(str: &str) -> HashMap<String, Value> {
+		serde_yaml::from_str(str).unwrap()
+	}
+
+	fn merge(cfgs: Vec<HashMap<String, Value>>) -> HashMap<String, Value> {
+		let mut dst = HashMap::new();
+		merge_all(&mut dst, cfgs).unwrap();
+		dst.remove(CFG_MERGE_KEY);
+		dst
+	}
+
+	fn substitute_one(str: &str) -> std::result::Result<String, Vec<ConfigError>> {
+		let mut val = Value::String(str.to_string());
+		let mut errors = Vec::new();
+		substitute(&mut val, "key", &mut errors);
+
+		match val {
+			Value::String(v) if errors.is_empty() => Ok(v),
+			_ => Err(errors)
+		}
+	}
+
+	#[test]
+	fn merge_replace_by_default() {
+		let merged = merge(vec![
+			toml("[a]\nlist = [\"1\", \"2\"]\nkeep = \"x\""),
+			yaml("a:\n  list: [\"3\"]")
+		]);
+
+		assert_eq!(merged, toml("[a]\nlist = [\"3\"]\nkeep = \"x\""));
+	}
+
+	#[test]
+	fn merge_append_declared_in_include() {
+		let merged = merge(vec![
+			toml("[a]\nlist = [\"1\", \"2\"]"),
+			yaml("merge:\n  a.list:\n    strategy: append\na:\n  list: [\"3\"]")
+		]);
+
+		assert_eq!(merged, toml("[a]\nlist = [\"1\", \"2\", \"3\"]"));
+	}
+
+	#[test]
+	fn merge_by_key() {
+		let merged = merge(vec![
+			toml(r#"
+				[merge."builtin.lb.backends"]
+				strategy = "merge-by-key"
+				key      = "name"
+
+				[[builtin.lb.backends]]
+				name   = "a"
+				weight = "1"
+
+				[[builtin.lb.backends]]
+				name   = "b"
+				weight = "1"
+			"#),
+			yaml(r#"
+builtin:
+  lb:
+    backends:
+      - name: b
+        weight: "2"
+      - name: c
+        weight: "3"
+      - weight: "4"
+"#)
+		]);
+
+		assert_eq!(merged, toml(r#"
+			[[builtin.lb.backends]]
+			name   = "a"
+			weight = "1"
+
+			[[builtin.lb.backends]]
+			name   = "b"
+			weight = "2"
+
+			[[builtin.lb.backends]]
+			name   = "c"
+			weight = "3"
+
+			[[builtin.lb.backends]]
+			weight = "4"
+		"#));
+	}
+
+	#[test]
+	fn merge_invalid_strategy() {
+		let mut dst = HashMap::new();
+		let e = merge_all(&mut dst, vec![toml("[merge.\"a.list\"]\nstrategy = \"prepend\"")]).unwrap_err();
+		assert_eq!(e.path, "merge.a.list.strategy");
+	}
+
+	#[test]
+	fn substitute_env() {
+		std::env::set_var("KRANUS_ROUTER_TEST_SUBST_A", "alpha");
+		std::env::set_var("KRANUS_ROUTER_TEST_SUBST_EMPTY", "");
+
+		assert_eq!(substitute_one("x${KRANUS_ROUTER_TEST_SUBST_A}y${KRANUS_ROUTER_TEST_SUBST_A}z").unwrap(), "xalphayalphaz");
+		assert_eq!(substitute_one("${KRANUS_ROUTER_TEST_SUBST_UNSET}").unwrap(), "");
+		assert_eq!(substitute_one("${KRANUS_ROUTER_TEST_SUBST_UNSET:-fallback}").unwrap(), "fallback");
+		assert_eq!(substitute_one("${KRANUS_ROUTER_TEST_SUBST_EMPTY:-fallback}").unwrap(), "fallback");
+		assert_eq!(substitute_one("${KRANUS_ROUTER_TEST_SUBST_A:-fallback}").unwrap(), "alpha");
+		assert_eq!(substitute_one("$${KRANUS_ROUTER_TEST_SUBST_A} costs $5").unwrap(), "${KRANUS_ROUTER_TEST_SUBST_A} costs $5");
+	}
+
+	#[test]
+	fn substitute_errors() {
+		let e = substitute_one("${KRANUS_ROUTER_TEST_SUBST_UNSET:?password required}").unwrap_err();
+		assert_eq!(e[0].path, "key");
+		assert_eq!(e[0].message, "password required");
+		assert!(substitute_one("${KRANUS_ROUTER_TEST_SUBST_UNSET").is_err());
+		assert!(substitute_one("${KRANUS_ROUTER_TEST_SUBST_UNSET:+x}").is_err());
+	}
+
+	#[test]
+	fn substitute_file_and_collect_errors() {
+		let path = std::env::temp_dir().join("kranus-router-test-secret");
+		std::fs::write(&path, "secret\n").unwrap();
+
+		let mut cfg = Value::Map(toml(&format!(r#"
+			password = "${{file:{}}}"
+			list     = ["${{KRANUS_ROUTER_TEST_SUBST_UNSET:?a}}", "${{KRANUS_ROUTER_TEST_SUBST_UNSET:?b}}"]
+		"#, path.display())));
+
+		let mut errors = Vec::new();
+		substitute(&mut cfg, "", &mut errors);
+		std::fs::remove_file(&path).unwrap();
+
+		let mut paths = errors.iter().map(|e| e.path.as_str()).collect::<Vec<_>>();
+		paths.sort_unstable();
+		assert_eq!(paths, ["list[0]", "list[1]"]);
+
+		match cfg {
+			Value::Map(map) => assert_eq!(map["password"], Value::String("secret".to_string())),
+			_ => unreachable!()
+		}
+	}
+}
@@ -21,18 +21,15 @@
 // SOFTWARE.
 
 use {
-	crate::*,
+	crate::{*, interfaces::LifecycleHandler, utils::graph::*},
 	std::{
-		any::Any,
-		sync::{Arc, atomic::AtomicPtr},
-		collections::{BTreeMap, btree_map::Entry},
+		sync::Arc,
+		collections::{BTreeMap, BTreeSet},
 		path::PathBuf
 	},
 	smol::lock::{Mutex, RwLock}
 };
 
-const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
-
 pub enum File {
 	Config(ConfigFile),
 	Plugin(PluginFile),
@@ -67,12 +64,12 @@ pub(crate)  struct ContextImpl {
 	pub(crate) config:       HashMap<String, serde_dyn_repr::Value>,
 	pub(crate) files:        HashMap<PathBuf, Arc<RwLock<File>>>,
 	pub(crate) changes_file: Arc<PathBuf>,
-	pub(crate) components:   BTreeMap<(u128, u64), Arc<AtomicPtr<()>>>
-}
-
-pub enum Component {
-	Present(Arc<dyn Any + Send + Sync>),
-	Waker(Vec<std::task::Waker>)
+	pub(crate) components:   BTreeMap<(u128, u64), Arc<ComponentSlot>>,
+	pub(crate) names:        BTreeMap<u128, String>,
+	/// The config files passed on the command line, loaded again on reload.
+	pub(crate) includes:     Vec<(String, String)>,
+	/// The config passed as arguments, the config files are merged into it.
+	pub(crate) arguments:    HashMap<String, serde_dyn_repr::Value>
 }
 
 pub struct ContextWrapper(pub(crate) Mutex<ContextImpl>);
@@ -81,7 +78,9 @@ impl ContextWrapper {
 	pub fn new(
 		config:       HashMap<String, serde_dyn_repr::Value>,
 		mut files:    HashMap<PathBuf, Arc<RwLock<File>>>,
-		changes_file: PathBuf
+		changes_file: PathBuf,
+		includes:     Vec<(String, String)>,
+		arguments:    HashMap<String, serde_dyn_repr::Value>
 	) -> Self {
 		files.insert(changes_file.clone(), Arc::new(RwLock::new(File::Config(ConfigFile {
 			value:    HashMap::new(),
@@ -92,10 +91,253 @@ impl ContextWrapper {
 			config,
 			files,
 			changes_file: Arc::new(changes_file),
-			components:    Default::default()
+			components:   Default::default(),
+			names:        Default::default(),
+			includes,
+			arguments
 		}))
 	}
 	
+	/// Returns the lifecycle hooks of all registered components, ordered by their dependencies.
+	pub(crate) async fn component_order(&self) -> std::result::Result<Vec<(String, Arc<LifecycleHandler>)>, Vec<GraphError>> {
+		let (ids, present) = {
+			let inner = self.0.lock().await;
+			let interface = get_interface_id::<LifecycleHandler>();
+			let present = inner.components.iter()
+				.filter(|(_, slot)| slot.is_present())
+				.map(|((id, _), _)| *id)
+				.collect::<BTreeSet<_>>();
+			let ids = inner.components.iter()
+				.filter(|((_, i), slot)| *i == interface && slot.is_present())
+				.map(|((id, _), _)| *id)
+				.collect::<Vec<_>>();
+			(ids, present)
+		};
+		
+		let handlers = ids.into_iter()
+			.filter_map(|id| crate::get_component::<LifecycleHandler>(id).try_get()
+				.map(|v| (crate::component_name(id), v)))
+			.collect::<BTreeMap<_, _>>();
+		
+		let graph = handlers.iter()
+			.map(|(name, handler)| (name.clone(), handler.dependencies()))
+			.collect::<BTreeMap<_, _>>();
+		
+		let order = toposort(&graph, |name| present.contains(&self.component_id(name)))?;
+		Ok(order.into_iter()
+			.map(|name| (name.to_string(), handlers[name].clone()))
+			.collect())
+	}
+	
+	/// Returns all present components with an interface, by name.
+	pub(crate) async fn components<T: std::any::Any + Send + Sync>(&self) -> Vec<(String, Arc<T>)> {
+		let ids = {
+			let inner = self.0.lock().await;
+			let interface = get_interface_id::<T>();
+			inner.components.iter()
+				.filter(|((_, i), slot)| *i == interface && slot.is_present())
+				.map(|((id, _), _)| *id)
+				.collect::<Vec<_>>()
+		};
+		
+		ids.into_iter()
+			.filter_map(|id| crate::get_component::<T>(id).try_get()
+				.map(|v| (crate::component_name(id), v)))
+			.collect()
+	}
+	
+	/// Returns the names of all present components.
+	async fn component_names(&self) -> BTreeSet<String> {
+		let ids = self.0.lock().await.components.iter()
+			.filter(|(_, slot)| slot.is_present())
+			.map(|((id, _), _)| *id)
+			.collect::<BTreeSet<_>>();
+		
+		ids.into_iter().map(crate::component_name).collect()
+	}
+	
+	/// Starts all components in dependency order, returns the number of errors.
+	pub(crate) async fn start_components(&self) -> usize {
+		self.start_components_where(|_| true).await
+	}
+	
+	/// Starts the components accepted by `filter` in dependency order, returns the number of errors.
+	async fn start_components_where(&self, filter: impl Fn(&str) -> bool) -> usize {
+		let order = match self.component_order().await {
+			Ok(v) => v,
+			Err(errors) => {
+				errors.iter().for_each(|e| log::error!("init: {}", e));
+				return errors.len();
+			}
+		};
+		
+		let mut errors = 0;
+		for (name, handler) in order.into_iter().filter(|(name, _)| filter(name)) {
+			match handler.start().await {
+				Ok(()) => log::debug!("init: started component `{}`", name),
+				Err(e) => {
+					log::error!("init: failed to start component `{}`: {}", name, e.display());
+					errors += 1;
+				}
+			}
+		}
+		
+		errors
+	}
+	
+	/// Stops all components in reverse dependency order.
+	pub(crate) async fn stop_components(&self) {
+		let order = match self.component_order().await {
+			Ok(v) => v,
+			Err(errors) => {
+				errors.iter().for_each(|e| log::warn!("shutdown: {}", e));
+				return;
+			}
+		};
+		
+		for (name, handler) in order.into_iter().rev() {
+			match handler.stop().await {
+				Ok(()) => log::debug!("shutdown: stopped component `{}`", name),
+				Err(e) => log::error!("shutdown: failed to stop component `{}`: {}", name, e.display())
+			}
+		}
+	}
+	
+	/// Stops a component and unregisters all of its interfaces. The component is dropped once
+	/// all streams that are currently using it are done.
+	pub(crate) async fn remove_component(&self, name: &str) {
+		let id = self.component_id(name);
+		
+		if let Ok(order) = self.component_order().await {
+			for (dependent, handler) in &order {
+				if handler.dependencies().iter().any(|v| v == name) {
+					log::warn!("component `{}`: dependency `{}` is being removed", dependent, name);
+				}
+			}
+		}
+		
+		if let Some(handler) = crate::get_component::<LifecycleHandler>(id).try_get() {
+			if let Err(e) = handler.stop().await {
+				log::error!("component `{}`: failed to stop: {}", name, e.display());
+			}
+		}
+		
+		let slots = self.0.lock().await.components
+			.range((id, 0)..=(id, u64::MAX))
+			.map(|(_, slot)| slot.clone())
+			.collect::<Vec<_>>();
+		
+		slots.into_iter().for_each(|slot| { slot.set(None); });
+		log::info!("component `{}`: removed", name);
+	}
+	
+	/// Passes the new config section of a component to its `Lifecycle::reload` hook.
+	pub(crate) async fn reload_component(&self, name: &str, cfg: serde_dyn_repr::Value) -> Result<()> {
+		match crate::get_component::<LifecycleHandler>(self.component_id(name)).try_get() {
+			Some(handler) => handler.reload(&mut <dyn dyn_serde::Deserializer>::erase(cfg)).await,
+			None => Err(Error::new(format!("component `{}` does not support reloading", name)))
+		}
+	}
+	
+	/// Loads the config files again and applies the changes, see [`Self::apply_config`]. The
+	/// running config is kept if a file contains errors.
+	pub(crate) async fn reload_config(&self) -> Result<()> {
+		let (includes, mut cfg) = {
+			let inner = self.0.lock().await;
+			(inner.includes.clone(), inner.arguments.clone())
+		};
+		
+		let sources = crate::config::load(includes);
+		if sources.errors > 0 {
+			return Err(Error::new(format!("{} errors while loading configs", sources.errors)));
+		}
+		
+		crate::config::merge_all(&mut cfg, sources.configs.into_iter().map(|(_, v)| v))
+			.map_err(|e| Error::new(e.to_string()))?;
+		self.apply_config(cfg).await
+	}
+	
+	/// Applies a changed config to the running components. Builtin modules that were removed
+	/// are removed, changed ones are reloaded or, if they don't support reloading, replaced by a
+	/// new instance, and new ones are initialized and started. Components of plugins are reloaded
+	/// if their section changed. Changes of other sections, e.g. `global`, require a restart.
+	pub(crate) async fn apply_config(&self, cfg: HashMap<String, serde_dyn_repr::Value>) -> Result<()> {
+		let old = self.0.lock().await.config.clone();
+		let (old_modules, new_modules) = (builtin_modules(&old), builtin_modules(&cfg));
+		
+		// builtin modules may reference the components of plugins, but not removed modules
+		let components = self.component_names().await.into_iter()
+			.filter(|name| !old_modules.contains_key(name))
+			.collect::<Vec<_>>();
+		
+		let errors = crate::builtins::check(&cfg, &components);
+		if !errors.is_empty() {
+			return Err(Error::new(errors.iter()
+				.map(ToString::to_string)
+				.collect::<Vec<_>>()
+				.join(", ")));
+		}
+		
+		self.0.lock().await.config = cfg.clone();
+		
+		for name in old_modules.keys().filter(|name| !new_modules.contains_key(*name)) {
+			self.remove_component(name).await;
+		}
+		
+		let mut replaced = HashMap::new();
+		for (name, module) in &new_modules {
+			match old_modules.get(name) {
+				Some(old) if old == module => continue,
+				Some(_) => match self.reload_component(name, module.clone()).await {
+					Ok(()) => {
+						log::info!("component `{}`: reloaded", name);
+						continue;
+					}
+					Err(e) => {
+						log::info!("component `{}`: replacing, {}", name, e.display());
+						self.remove_component(name).await;
+					}
+				}
+				None => ()
+			}
+			
+			replaced.insert(name.clone(), module.clone());
+		}
+		
+		for (name, section) in &cfg {
+			if name == "builtin" || old.get(name) == Some(section) || !components.contains(name) {
+				continue;
+			}
+			
+			match self.reload_component(name, section.clone()).await {
+				Ok(()) => log::info!("component `{}`: reloaded", name),
+				Err(e) => log::warn!("component `{}`: config changed, but it was not reloaded: {}", name, e.display())
+			}
+		}
+		
+		// plugin sections that were removed are reloaded with an empty section, like on init
+		for name in old.keys().filter(|name| !cfg.contains_key(*name) && components.contains(name)) {
+			match self.reload_component(name, serde_dyn_repr::Value::Map(HashMap::new())).await {
+				Ok(()) => log::info!("component `{}`: reloaded", name),
+				Err(e) => log::warn!("component `{}`: config changed, but it was not reloaded: {}", name, e.display())
+			}
+		}
+		
+		if replaced.is_empty() {
+			return Ok(());
+		}
+		
+		let names = replaced.keys().cloned().collect::<BTreeSet<_>>();
+		let mut section = HashMap::new();
+		section.insert("builtin".to_string(), serde_dyn_repr::Value::Map(replaced));
+		crate::builtins::run(&mut <dyn dyn_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(section))).await?;
+		
+		match self.start_components_where(|name| names.contains(name)).await {
+			0 => Ok(()),
+			n => Err(Error::new(format!("failed to start {} components", n)))
+		}
+	}
+	
 	async fn save_cfg(&self) {
 		let (path, buf) = {
 			let inner = self.0.lock().await;
@@ -128,6 +370,14 @@ impl ContextWrapper {
 	}
 }
 
+/// The sections of the builtin modules in a config by name.
+fn builtin_modules(cfg: &HashMap<String, serde_dyn_repr::Value>) -> HashMap<String, serde_dyn_repr::Value> {
+	match cfg.get("builtin") {
+		Some(serde_dyn_repr::Value::Map(v)) => v.clone(),
+		_ => HashMap::new()
+	}
+}
+
 impl Context for ContextWrapper {
 	fn spawn_dyn(&self, f: DynFuture<'static, ()>) {
 		async_executor::spawn_dyn(f)
@@ -145,32 +395,29 @@ impl Context for ContextWrapper {
 		use std::hash::{Hash, Hasher};
 		let mut hasher = std::collections::hash_map::DefaultHasher::new();
 		name.hash(&mut hasher);
-		hasher.finish() as _
-	}
-	
-	fn component_dyn_get(&self, id: u128, interface: u64) -> Arc<AtomicPtr<()>> {
+		let id = hasher.finish() as _;
+		
 		smol::block_on(self.0.lock())
-			.components
-			.entry((id, interface))
-			.or_insert_with(|| Arc::new(AtomicPtr::default()))
-			.clone()
+			.names
+			.entry(id)
+			.or_insert_with(|| name.to_string());
+		
+		id
 	}
 	
-	fn component_dyn_add(&self, id: u128, interface: u64, ptr: *mut ()) {
-		match smol::block_on(self.0.lock())
-			.components
-			.entry((id, interface))
-		{
-			Entry::Occupied(v) => v.get().store(ptr, Ordering::SeqCst),
-			Entry::Vacant(v)   => { v.insert(Arc::new(AtomicPtr::new(ptr))); }
-		}
+	fn component_name(&self, id: u128) -> Option<String> {
+		smol::block_on(self.0.lock())
+			.names
+			.get(&id)
+			.cloned()
 	}
 	
-	fn component_dyn_del(&self, id: u128, interface: u64) -> bool {
+	fn component_dyn_slot(&self, id: u128, interface: u64) -> Arc<ComponentSlot> {
 		smol::block_on(self.0.lock())
 			.components
-			.remove(&(id, interface))
-			.is_none()
+			.entry((id, interface))
+			.or_default()
+			.clone()
 	}
 }
 
@@ -185,4 +432,135 @@ impl std::fmt::Display for ContextWrapper {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		std::fmt::Debug::fmt(self, f)
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::interfaces::{HttpStreamHandler, Lifecycle},
+		std::sync::atomic::{AtomicBool, Ordering},
+		serde::Deserialize
+	};
+	
+	fn ctx() -> &'static ContextWrapper {
+		crate::utils::test_context()
+	}
+	
+	fn cfg(json: &str) -> HashMap<String, serde_dyn_repr::Value> {
+		serde_json::from_str(json).unwrap()
+	}
+	
+	#[derive(Default)]
+	struct Component {
+		reloadable: bool,
+		stopped:    AtomicBool,
+		reloaded:   std::sync::Mutex<Option<serde_dyn_repr::Value>>
+	}
+	
+	impl Component {
+		/// Registers the component as `name`, with a second interface besides its lifecycle hooks.
+		fn register(self, name: &str) -> Arc<Self> {
+			let component = Arc::new(self);
+			let id = ctx().component_id(name);
+			crate::add_component::<LifecycleHandler>(id, Box::new(component.clone()));
+			crate::add_component::<Arc<Self>>(id, component.clone());
+			component
+		}
+	}
+	
+	impl Lifecycle for Component {
+		fn stop(&self) -> DynFuture<'_, Result<()>> {
+			self.stopped.store(true, Ordering::SeqCst);
+			Box::pin(async { Ok(()) })
+		}
+		
+		fn reload<'a>(&'a self, cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)) -> DynFuture<'a, Result<()>> {
+			Box::pin(async move {
+				if !self.reloadable {
+					return Err(Error::new("not reloadable"));
+				}
+				
+				let cfg = serde_dyn_repr::Value::deserialize(cfg).map_err(|e| Error::new(e.to_string()))?;
+				*self.reloaded.lock().unwrap() = Some(cfg);
+				Ok(())
+			})
+		}
+	}
+	
+	#[test]
+	fn remove_component() {
+		let id = ctx().component_id("remove");
+		let component = Arc::downgrade(&Component::default().register("remove"));
+		
+		// a stream that is still using the component
+		let in_use = crate::get_component::<Arc<Component>>(id).try_get().unwrap();
+		smol::block_on(ctx().remove_component("remove"));
+		
+		assert!(in_use.stopped.load(Ordering::SeqCst));
+		assert!(crate::get_component::<LifecycleHandler>(id).try_get().is_none());
+		assert!(crate::get_component::<Arc<Component>>(id).try_get().is_none());
+		assert!(component.upgrade().is_some());
+		
+		std::mem::drop(in_use);
+		assert!(component.upgrade().is_none());
+	}
+	
+	#[test]
+	fn reload_component() {
+		let reloadable = Component { reloadable: true, ..Component::default() }.register("reloadable");
+		let fixed = Component::default().register("fixed");
+		let section = cfg(r#"{ "key": "value" }"#);
+		
+		smol::block_on(ctx().reload_component("reloadable", serde_dyn_repr::Value::Map(section.clone()))).unwrap();
+		assert_eq!(*reloadable.reloaded.lock().unwrap(), Some(serde_dyn_repr::Value::Map(section.clone())));
+		
+		assert!(smol::block_on(ctx().reload_component("fixed", serde_dyn_repr::Value::Map(section.clone()))).is_err());
+		assert!(fixed.reloaded.lock().unwrap().is_none());
+		assert!(smol::block_on(ctx().reload_component("unregistered", serde_dyn_repr::Value::Map(section))).is_err());
+	}
+	
+	#[test]
+	fn apply_config() {
+		let ctx = ctx();
+		let plugin = Component { reloadable: true, ..Component::default() }.register("plugin");
+		let old = cfg(r#"{
+			"plugin":  { "key": "old" },
+			"builtin": {
+				"changed": { "type": "cors", "next": "plugin", "origins": ["https://a.example"] },
+				"removed": { "type": "cors", "next": "plugin", "origins": ["https://c.example"] },
+				"same":    { "type": "cors", "next": "plugin", "origins": ["https://c.example"] }
+			}
+		}"#);
+		
+		let builtin = old.iter().filter(|(k, _)| *k == "builtin").map(|(k, v)| (k.clone(), v.clone())).collect();
+		smol::block_on(crate::builtins::run(&mut <dyn dyn_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(builtin)))).unwrap();
+		smol::block_on(ctx.0.lock()).config = old;
+		
+		let get = |name: &str| crate::get_component::<HttpStreamHandler>(ctx.component_id(name)).try_get();
+		let (changed, same) = (get("changed").unwrap(), get("same").unwrap());
+		
+		// references to unknown components are rejected and the config is kept
+		let invalid = cfg(r#"{ "builtin": { "added": { "type": "cors", "next": "unknown", "origins": ["https://c.example"] } } }"#);
+		assert!(smol::block_on(ctx.apply_config(invalid)).is_err());
+		assert!(get("removed").is_some());
+		assert!(get("added").is_none());
+		
+		let new = cfg(r#"{
+			"plugin":  { "key": "new" },
+			"builtin": {
+				"changed": { "type": "cors", "next": "plugin", "origins": ["https://b.example"] },
+				"same":    { "type": "cors", "next": "plugin", "origins": ["https://c.example"] },
+				"added":   { "type": "cors", "next": "changed", "origins": ["https://c.example"] }
+			}
+		}"#);
+		
+		smol::block_on(ctx.apply_config(new.clone())).unwrap();
+		assert_eq!(smol::block_on(ctx.0.lock()).config, new);
+		assert!(get("removed").is_none());
+		assert!(get("added").is_some());
+		assert!(Arc::ptr_eq(&get("same").unwrap(), &same));
+		// cors does not support reloading, so it was replaced
+		assert!(!Arc::ptr_eq(&get("changed").unwrap(), &changed));
+		assert_eq!(*plugin.reloaded.lock().unwrap(), Some(serde_dyn_repr::Value::Map(cfg(r#"{ "key": "new" }"#))));
+	}
+}
@@ -28,11 +28,12 @@ s, show <key>      show information
 get <key>          show configuration
 set <key> <val>    set configuration
 r, reload          reload configuration
+upgrade            start a new process and hand over the listening sockets
 exit               shut down and exit
 abort              abort the process
 "#;
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Default)]
 #[serde(default, deny_unknown_fields, from = "ConfigEnum<Config>")]
 pub struct Config {
 	pub enabled: bool
@@ -100,12 +101,18 @@ pub fn run(cfg: Config) {
 				}
 				_ => eprintln!("set: expecting 2 parameters")
 			}
-			["reload", ..] =>  {
-
+			["r" | "reload", ..] => if let Err(e) = global::reload::trigger() {
+				log::error!("cli: failed to reload: {}", e.display());
+			}
+			["upgrade", ..] => if let Err(e) = global::upgrade::trigger() {
+				log::error!("cli: failed to upgrade: {}", e.display());
 			}
 			["stop", ..] => {
 				println!("Stopping ...");
-				std::process::exit(0);
+				if let Err(e) = global::upgrade::trigger_shutdown() {
+					log::error!("cli: failed to stop: {}", e.display());
+					std::process::exit(0);
+				}
 			}
 			["abort", ..] => {
 				println!("Aborting ...");
@@ -23,20 +23,23 @@
 use std::io;
 use std::path::PathBuf;
 use serde::Deserialize;
+use schemars::JsonSchema;
 use super::*;
 
 pub mod console;
+pub mod reload;
 pub mod telemetry;
+pub mod upgrade;
 #[cfg(feature = "notify")]
 pub mod watcher;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct Config {
 	#[serde(default)]
 	pub global: Global
 }
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Global {
 	#[serde(default)]
@@ -51,9 +54,11 @@ pub struct Global {
 	pub console:            global::console::Config,
 	#[serde(default)]
 	pub telemetry:          global::telemetry::Config,
+	#[serde(default)]
+	pub upgrade:            global::upgrade::Config,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum GlobalLogLevel { Off, Error, Warn, Info, Debug, Trace }
 
@@ -70,14 +75,16 @@ impl GlobalLogLevel {
 	}
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum ConfigEnum<T> {
 	Bool(bool),
 	Config(T)
 }
 
-pub async fn init<'a>(cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)) -> PathBuf {
+/// Applies the global config, returns the path of the config changes file and the upgrade
+/// config.
+pub async fn init<'a>(cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)) -> (PathBuf, upgrade::Config) {
 	let cfg = match Config::deserialize(cfg) {
 		Ok(v)  => v.global,
 		Err(e) => {
@@ -152,5 +159,5 @@ pub async fn init<'a>(cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Syn
 		}
 	}
 
-	changes_file
+	(changes_file, cfg.upgrade)
 }
\ No newline at end of file
@@ -0,0 +1,71 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Config reloads: on `SIGHUP`, the config files are loaded again and the changes are applied to
+//! the running components, see [`crate::ctx::ContextWrapper::apply_config`].
+
+use {
+	super::*,
+	nix::sys::signal::{self, SigSet, Signal}
+};
+
+const SIGNAL: Signal = Signal::SIGHUP;
+
+/// Blocks the reload signal in the calling thread and all threads it spawns, so it is only
+/// received by [`run`]. Must be called before any other thread is spawned.
+pub fn block_signal() {
+	if let Err(e) = signals().thread_block() {
+		log::error!("reload: failed to block {}: {}", SIGNAL, e);
+	}
+}
+
+fn signals() -> SigSet {
+	let mut set = SigSet::empty();
+	set.add(SIGNAL);
+	set
+}
+
+/// Reloads the config, the same as sending `SIGHUP`.
+pub fn trigger() -> Result<()> {
+	signal::kill(nix::unistd::Pid::this(), SIGNAL)
+		.map_err(|e| Error::new(format!("failed to send {}: {}", SIGNAL, e)))
+}
+
+/// Waits for the reload signal and reloads the config.
+pub fn run(ctx: Arc<crate::ctx::ContextWrapper>) {
+	let set = signals();
+	log::info!("reload: send {} to pid {} to reload the config", SIGNAL, std::process::id());
+
+	loop {
+		if let Err(e) = set.wait() {
+			log::error!("reload: failed to wait for {}: {}", SIGNAL, e);
+			return;
+		}
+
+		log::info!("reload: received {}, reloading config ...", SIGNAL);
+
+		match smol::block_on(ctx.reload_config()) {
+			Ok(()) => log::info!("reload: config reloaded"),
+			Err(e) => log::error!("reload: failed to reload config: {}", e.display())
+		}
+	}
+}
@@ -22,7 +22,7 @@
 
 use super::*;
 
-#[derive(Clone, Debug, Deserialize, Default)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Default)]
 #[serde(default, deny_unknown_fields, from = "ConfigEnum<Config>")]
 pub struct Config {
 	pub enabled:  bool,
@@ -43,7 +43,7 @@ impl From<ConfigEnum<Config>> for Config {
 	}
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum ConfigProtocol {
 	Grpc,
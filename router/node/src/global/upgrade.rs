@@ -0,0 +1,499 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Hot upgrades: on `SIGUSR2`, the binary is started again and takes over the listening sockets,
+//! which are passed over a Unix domain socket. Once the new process is initialized, this one
+//! stops accepting connections, drains the open ones and exits. On `SIGTERM` or `SIGINT`, the
+//! components are stopped and the process exits.
+
+use {
+	super::*,
+	std::{
+		ffi::OsString,
+		io::{IoSlice, IoSliceMut},
+		os::unix::io::RawFd,
+		path::Path,
+		process::{Command, Stdio},
+		sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+		time::Instant
+	},
+	nix::{
+		fcntl::{fcntl, FcntlArg, FdFlag},
+		sys::{signal::{self, SigSet, Signal}, socket::{self as sock, sockopt, ControlMessage, ControlMessageOwned, MsgFlags}, time::{TimeVal, TimeValLike}}
+	}
+};
+
+/// The end of the socket pair a new process receives the listening sockets on.
+pub const ENV_UPGRADE_FD:        &str = "KRANUS_ROUTER_UPGRADE_FD";
+pub const DEFAULT_TIMEOUT:       Duration = Duration::from_secs(60);
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+const SIGNAL:                    Signal = Signal::SIGUSR2;
+const SHUTDOWN_SIGNALS:          [Signal; 2] = [Signal::SIGTERM, Signal::SIGINT];
+/// The maximum number of file descriptors in one message, `SCM_MAX_FD` on Linux.
+const MAX_FDS:                   usize = 253;
+const MAX_NAMES_LEN:             usize = 0x10000;
+const MSG_READY:                 &[u8] = b"ready";
+const DRAIN_POLL_INTERVAL:       Duration = Duration::from_millis(100);
+
+static DRAINING:    AtomicBool  = AtomicBool::new(false);
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	/// The binary of the new process, by default the path this process was started from.
+	pub binary:        Option<PathBuf>,
+	/// How long the new process may take to initialize, before the upgrade is aborted.
+	pub timeout:       Option<Duration>,
+	/// How long open connections are drained, before this process exits.
+	pub drain_timeout: Option<Duration>
+}
+
+/// A duplicate of a listening socket, registered under the name of the module that owns it.
+pub struct ListenerFd(Mutex<Option<RawFd>>);
+
+impl ListenerFd {
+	pub fn new(fd: RawFd) -> io::Result<Self> {
+		let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0)).map_err(io::Error::from)?;
+		Ok(Self(Mutex::new(Some(fd))))
+	}
+
+	/// Takes ownership of the socket, it is no longer passed to new processes.
+	pub fn take(&self) -> Option<RawFd> {
+		self.0.lock().unwrap().take()
+	}
+}
+
+impl Drop for ListenerFd {
+	fn drop(&mut self) {
+		if let Some(fd) = self.take() {
+			let _ = nix::unistd::close(fd);
+		}
+	}
+}
+
+/// Counts a connection, until dropped.
+pub struct Connection(());
+
+impl Drop for Connection {
+	fn drop(&mut self) {
+		CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// Counts a connection, the process waits for it to be closed before exiting after an upgrade.
+pub fn track_connection() -> Connection {
+	CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+	Connection(())
+}
+
+/// Whether the sockets were passed to a new process, no connections should be accepted and
+/// connections should be closed after the current request.
+pub fn is_draining() -> bool {
+	DRAINING.load(Ordering::SeqCst)
+}
+
+/// Blocks the upgrade and shutdown signals in the calling thread and all threads it spawns, so
+/// they are only received by [`run`]. Must be called before any other thread is spawned.
+pub fn block_signal() {
+	if let Err(e) = signals().thread_block() {
+		log::error!("upgrade: failed to block signals: {}", e);
+	}
+}
+
+fn signals() -> SigSet {
+	let mut set = SigSet::empty();
+	set.add(SIGNAL);
+	SHUTDOWN_SIGNALS.iter().for_each(|v| set.add(*v));
+	set
+}
+
+/// Starts an upgrade, the same as sending `SIGUSR2`.
+pub fn trigger() -> Result<()> {
+	signal::kill(nix::unistd::Pid::this(), SIGNAL)
+		.map_err(|e| Error::new(format!("failed to send {}: {}", SIGNAL, e)))
+}
+
+/// Stops the components and exits, the same as sending `SIGTERM`.
+pub fn trigger_shutdown() -> Result<()> {
+	signal::kill(nix::unistd::Pid::this(), Signal::SIGTERM)
+		.map_err(|e| Error::new(format!("failed to send {}: {}", Signal::SIGTERM, e)))
+}
+
+/// Waits for the upgrade signal and performs the upgrade, exits the process if it succeeded.
+/// Stops the components and exits on a shutdown signal.
+pub fn run(ctx: Arc<crate::ctx::ContextWrapper>, cfg: Config, working_dir: PathBuf) {
+	let set = signals();
+	log::info!("upgrade: send {} to pid {} to upgrade", SIGNAL, std::process::id());
+
+	loop {
+		match set.wait() {
+			Ok(signal) if signal == SIGNAL => (),
+			Ok(signal) => shutdown(&ctx, signal),
+			Err(e) => {
+				log::error!("upgrade: failed to wait for signals: {}", e);
+				return;
+			}
+		}
+
+		log::info!("upgrade: received {}, starting new process ...", SIGNAL);
+
+		if let Err(e) = upgrade(&ctx, &cfg, &working_dir) {
+			log::error!("upgrade: aborted: {}", e.display());
+			continue;
+		}
+
+		drain(&ctx, cfg.drain_timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT));
+	}
+}
+
+/// Starts the new process and passes the listening sockets to it, returns once it is
+/// initialized.
+fn upgrade(ctx: &crate::ctx::ContextWrapper, cfg: &Config, working_dir: &Path) -> Result<()> {
+	let binary = match &cfg.binary {
+		Some(v) => v.clone(),
+		None => current_exe()?
+	};
+	let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+
+	// a bad config or binary is found before anything is passed to the new process
+	let status = Command::new(&binary)
+		.args(&args)
+		.arg("--check")
+		.current_dir(working_dir)
+		.env_remove(ENV_UPGRADE_FD)
+		.stdin(Stdio::null())
+		.status()
+		.map_err(|e| Error::new(format!("failed to start `{}`: {}", binary.display(), e)))?;
+
+	if !status.success() {
+		return Err(Error::new(format!("config check of `{}` failed ({})", binary.display(), status)));
+	}
+
+	let (local, remote) = sock::socketpair(sock::AddressFamily::Unix, sock::SockType::SeqPacket, None, sock::SockFlag::SOCK_CLOEXEC)
+		.map_err(io::Error::from)?;
+	let (local, remote) = (FdGuard(local), FdGuard(remote));
+
+	// only the duplicate, which is not closed on exec, is inherited
+	let inherited = FdGuard(nix::unistd::dup(remote.0).map_err(io::Error::from)?);
+	let mut child = Command::new(&binary)
+		.args(&args)
+		.current_dir(working_dir)
+		.env(ENV_UPGRADE_FD, inherited.0.to_string())
+		.stdin(Stdio::null())
+		.spawn()
+		.map_err(|e| Error::new(format!("failed to start `{}`: {}", binary.display(), e)))?;
+	// the new process exiting is only noticed if it holds the last reference to its end
+	std::mem::drop((inherited, remote));
+	log::info!("upgrade: started `{}` (pid {})", binary.display(), child.id());
+
+	let r = handoff(ctx, cfg, local.0);
+
+	if r.is_err() {
+		let _ = child.kill();
+		let _ = child.wait();
+	}
+
+	r
+}
+
+/// Passes the listening sockets to the new process and waits until it is initialized.
+fn handoff(ctx: &crate::ctx::ContextWrapper, cfg: &Config, fd: RawFd) -> Result<()> {
+	let listeners = smol::block_on(ctx.components::<ListenerFd>());
+	let (names, fds) = listeners.iter()
+		.filter_map(|(name, listener)| (*listener.0.lock().unwrap()).map(|fd| (name.as_str(), fd)))
+		.unzip::<_, _, Vec<_>, Vec<_>>();
+
+	send_listeners(fd, &names, &fds)?;
+	log::info!("upgrade: passed {} listening sockets", fds.len());
+
+	let timeout = TimeVal::milliseconds(cfg.timeout.unwrap_or(DEFAULT_TIMEOUT).as_millis() as _);
+	sock::setsockopt(fd, sockopt::ReceiveTimeout, &timeout).map_err(io::Error::from)?;
+
+	let mut buf = [0u8; 16];
+	match nix::unistd::read(fd, &mut buf) {
+		Ok(len) if &buf[..len] == MSG_READY => Ok(()),
+		Ok(0) => Err("new process exited during initialization".into()),
+		Ok(_) => Err("invalid message from new process".into()),
+		Err(nix::errno::Errno::EAGAIN) => Err("new process did not initialize in time".into()),
+		Err(e) => Err(Error::new(format!("failed to wait for new process: {}", e)))
+	}
+}
+
+/// Sends the names of the listening sockets, separated by newlines, and the sockets.
+fn send_listeners(fd: RawFd, names: &[&str], fds: &[RawFd]) -> Result<()> {
+	if fds.len() > MAX_FDS {
+		return Err(Error::new(format!("too many listening sockets ({}, at most {})", fds.len(), MAX_FDS)));
+	}
+
+	let names = names.join("\n");
+	sock::sendmsg::<sock::UnixAddr>(fd, &[IoSlice::new(names.as_bytes())], &[ControlMessage::ScmRights(fds)], MsgFlags::empty(), None)
+		.map(|_| ())
+		.map_err(|e| Error::new(format!("failed to pass listening sockets: {}", e)))
+}
+
+/// Receives the names of the listening sockets and at most `max_fds` sockets, closes them if
+/// they do not match.
+fn recv_listeners(fd: RawFd, max_fds: usize) -> Result<Vec<(String, RawFd)>> {
+	let mut names = vec![0u8; MAX_NAMES_LEN];
+	// `nix::cmsg_space!` only takes a type, and its `c_uint` requires Rust 1.64
+	let mut cmsg = Vec::with_capacity(unsafe {
+		sock::CMSG_SPACE((max_fds * std::mem::size_of::<RawFd>()) as _) } as usize);
+	let msg = sock::recvmsg::<sock::UnixAddr>(fd, &mut [IoSliceMut::new(&mut names)], Some(&mut cmsg), MsgFlags::MSG_CMSG_CLOEXEC)
+		.map_err(|e| Error::new(format!("failed to receive listening sockets: {}", e)))?;
+
+	let fds = msg.cmsgs()
+		.filter_map(|v| match v {
+			ControlMessageOwned::ScmRights(fds) => Some(fds),
+			_ => None
+		})
+		.flatten()
+		.collect::<Vec<_>>();
+	let names = std::str::from_utf8(&names[..msg.bytes])
+		.map_err(|_| Error::from("invalid names of listening sockets"))
+		.map(|v| v.split('\n').filter(|v| !v.is_empty()).collect::<Vec<_>>());
+
+	match names {
+		Ok(v) if !msg.flags.contains(MsgFlags::MSG_CTRUNC) && v.len() == fds.len() =>
+			Ok(v.into_iter().map(str::to_string).zip(fds).collect()),
+		r => {
+			fds.into_iter().for_each(|fd| { let _ = nix::unistd::close(fd); });
+			Err(r.err().unwrap_or_else(|| "listening sockets were truncated".into()))
+		}
+	}
+}
+
+/// Stops accepting connections and exits, once all connections are closed or the timeout
+/// elapsed.
+fn drain(ctx: &crate::ctx::ContextWrapper, timeout: Duration) -> ! {
+	DRAINING.store(true, Ordering::SeqCst);
+	log::info!("upgrade: new process is ready, draining {} connections ...", CONNECTIONS.load(Ordering::SeqCst));
+
+	let t = Instant::now();
+	while CONNECTIONS.load(Ordering::SeqCst) > 0 && t.elapsed() < timeout {
+		std::thread::sleep(DRAIN_POLL_INTERVAL);
+	}
+
+	match CONNECTIONS.load(Ordering::SeqCst) {
+		0 => log::info!("upgrade: drained all connections ({:.3}s), exiting ...", t.elapsed().as_secs_f32()),
+		n => log::warn!("upgrade: drain timeout elapsed, closing {} connections and exiting ...", n)
+	}
+
+	smol::block_on(ctx.stop_components());
+	std::process::exit(0);
+}
+
+/// Stops all components and exits.
+fn shutdown(ctx: &crate::ctx::ContextWrapper, signal: Signal) -> ! {
+	log::info!("shutdown: received {}, stopping components ...", signal);
+	smol::block_on(ctx.stop_components());
+	std::process::exit(0);
+}
+
+/// The listening sockets passed by the previous process.
+pub struct Handoff {
+	fd:        RawFd,
+	listeners: Vec<(u128, Arc<ListenerFd>)>
+}
+
+impl Handoff {
+	/// Returns the socket pair passed by the previous process, if this process was started by
+	/// an upgrade. The variable is removed, so it is not inherited by the next upgrade.
+	pub fn from_env() -> Result<Option<Self>> {
+		let fd = match std::env::var_os(ENV_UPGRADE_FD) {
+			Some(v) => v,
+			None => return Ok(None)
+		};
+
+		std::env::remove_var(ENV_UPGRADE_FD);
+		let fd = fd.to_str()
+			.and_then(|v| v.parse::<RawFd>().ok())
+			.ok_or_else(|| Error::new(format!("invalid value `{}` of env var `{}`", fd.to_string_lossy(), ENV_UPGRADE_FD)))?;
+		fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(io::Error::from)?;
+		Ok(Some(Self { fd, listeners: Vec::new() }))
+	}
+
+	/// Receives the listening sockets and registers them, so they are adopted by the modules of
+	/// the same name instead of binding new ones.
+	pub fn receive(&mut self) -> Result<usize> {
+		for (name, fd) in recv_listeners(self.fd, MAX_FDS)? {
+			let id = crate::component_id(&name);
+			crate::add_component::<ListenerFd>(id, ListenerFd(Mutex::new(Some(fd))));
+
+			if let Some(listener) = crate::get_component::<ListenerFd>(id).try_get() {
+				self.listeners.push((id, listener));
+			}
+		}
+
+		Ok(self.listeners.len())
+	}
+
+	/// Closes the sockets no module adopted and tells the previous process to stop accepting
+	/// connections.
+	pub fn ready(self) -> Result<()> {
+		for (id, listener) in &self.listeners {
+			if listener.0.lock().unwrap().is_some() {
+				log::warn!("upgrade: listening socket of `{}` was not adopted, closing it", crate::component_name(*id));
+				crate::del_component::<ListenerFd>(*id);
+			}
+		}
+
+		let _guard = FdGuard(self.fd);
+		nix::unistd::write(self.fd, MSG_READY)
+			.map(|_| ())
+			.map_err(|e| Error::new(format!("failed to notify previous process: {}", e)))
+	}
+}
+
+struct FdGuard(RawFd);
+
+impl Drop for FdGuard {
+	fn drop(&mut self) {
+		let _ = nix::unistd::close(self.0);
+	}
+}
+
+/// The path of the running binary. If it was replaced, Linux appends ` (deleted)` to the path of
+/// the old one, which is removed to start the new one.
+fn current_exe() -> Result<PathBuf> {
+	Ok(strip_deleted(std::env::current_exe()?.into_os_string()))
+}
+
+fn strip_deleted(path: OsString) -> PathBuf {
+	match path.to_str().and_then(|v| v.strip_suffix(" (deleted)")) {
+		Some(v) => PathBuf::from(v),
+		None => PathBuf::from(path)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, crate::utils::stream::test_dir, std::os::unix::{io::IntoRawFd, net::{UnixListener, UnixStream}}};
+
+	fn pair() -> (FdGuard, FdGuard) {
+		let (a, b) = sock::socketpair(sock::AddressFamily::Unix, sock::SockType::SeqPacket, None, sock::SockFlag::SOCK_CLOEXEC).unwrap();
+		(FdGuard(a), FdGuard(b))
+	}
+
+	/// Binds a listener, whose socket is closed once every duplicate is.
+	fn listener(dir: &Path, name: &str) -> (PathBuf, RawFd) {
+		let path = dir.join(name);
+		(path.clone(), UnixListener::bind(&path).unwrap().into_raw_fd())
+	}
+
+	fn is_open(path: &Path) -> bool {
+		UnixStream::connect(path).is_ok()
+	}
+
+	#[test]
+	fn receive_and_ready() {
+		crate::utils::stream::test_context();
+		let dir = test_dir("upgrade-ready");
+		let (adopted, adopted_fd) = listener(&dir, "adopted");
+		let (unadopted, unadopted_fd) = listener(&dir, "unadopted");
+		let (local, remote) = pair();
+
+		send_listeners(local.0, &["upgrade-test-adopted", "upgrade-test-unadopted"], &[adopted_fd, unadopted_fd]).unwrap();
+		[adopted_fd, unadopted_fd].iter().for_each(|fd| { let _ = nix::unistd::close(*fd); });
+
+		let mut handoff = Handoff { fd: remote.0, listeners: Vec::new() };
+		std::mem::forget(remote);
+		assert_eq!(handoff.receive().unwrap(), 2);
+		assert!(is_open(&adopted) && is_open(&unadopted));
+
+		let fd = crate::get_component::<ListenerFd>(crate::component_id("upgrade-test-adopted"))
+			.try_get().unwrap()
+			.take().unwrap();
+		let fd = FdGuard(fd);
+		handoff.ready().unwrap();
+
+		let mut buf = [0u8; 16];
+		let len = nix::unistd::read(local.0, &mut buf).unwrap();
+		assert_eq!(&buf[..len], MSG_READY);
+		assert!(crate::get_component::<ListenerFd>(crate::component_id("upgrade-test-unadopted")).try_get().is_none());
+		assert!(!is_open(&unadopted));
+		assert!(is_open(&adopted));
+		drop(fd);
+		assert!(!is_open(&adopted));
+	}
+
+	#[test]
+	fn receive_count_mismatch() {
+		let dir = test_dir("upgrade-mismatch");
+		let (path, fd) = listener(&dir, "socket");
+		let (local, remote) = pair();
+
+		send_listeners(local.0, &["a", "b"], &[fd]).unwrap();
+		let _ = nix::unistd::close(fd);
+		assert!(recv_listeners(remote.0, MAX_FDS).is_err());
+		assert!(!is_open(&path));
+	}
+
+	#[test]
+	fn receive_truncated() {
+		let dir = test_dir("upgrade-truncated");
+		let (a, a_fd) = listener(&dir, "a");
+		let (b, b_fd) = listener(&dir, "b");
+		let (c, c_fd) = listener(&dir, "c");
+		let (local, remote) = pair();
+
+		// the control message buffer is aligned, so it fits two sockets
+		send_listeners(local.0, &["a", "b", "c"], &[a_fd, b_fd, c_fd]).unwrap();
+		[a_fd, b_fd, c_fd].iter().for_each(|fd| { let _ = nix::unistd::close(*fd); });
+		assert!(recv_listeners(remote.0, 1).is_err());
+		assert!(!is_open(&a) && !is_open(&b) && !is_open(&c));
+
+		let (d, d_fd) = listener(&dir, "d");
+		send_listeners(local.0, &["d"], &[d_fd]).unwrap();
+		let _ = nix::unistd::close(d_fd);
+		let received = recv_listeners(remote.0, 1).unwrap();
+		assert_eq!(received.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["d"]);
+		assert!(is_open(&d));
+		drop(FdGuard(received[0].1));
+		assert!(!is_open(&d));
+	}
+
+	#[test]
+	fn from_env() {
+		std::env::remove_var(ENV_UPGRADE_FD);
+		assert!(Handoff::from_env().unwrap().is_none());
+
+		std::env::set_var(ENV_UPGRADE_FD, "socket");
+		assert!(Handoff::from_env().is_err());
+		assert!(std::env::var_os(ENV_UPGRADE_FD).is_none());
+
+		let (_local, remote) = pair();
+		let fd = FdGuard(nix::unistd::dup(remote.0).unwrap());
+		std::env::set_var(ENV_UPGRADE_FD, fd.0.to_string());
+		let handoff = Handoff::from_env().unwrap().unwrap();
+		assert_eq!(handoff.fd, fd.0);
+		assert!(std::env::var_os(ENV_UPGRADE_FD).is_none());
+		assert!(FdFlag::from_bits_truncate(fcntl(fd.0, FcntlArg::F_GETFD).unwrap()).contains(FdFlag::FD_CLOEXEC));
+	}
+
+	#[test]
+	fn deleted_exe() {
+		assert_eq!(strip_deleted("/usr/bin/kranus (deleted)".into()), Path::new("/usr/bin/kranus"));
+		assert_eq!(strip_deleted("/usr/bin/kranus".into()), Path::new("/usr/bin/kranus"));
+		assert_eq!(strip_deleted("/usr/bin/kranus (deleted) (deleted)".into()), Path::new("/usr/bin/kranus (deleted)"));
+	}
+}
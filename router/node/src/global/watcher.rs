@@ -21,8 +21,9 @@
 // SOFTWARE.
 
 use {
-	std::{sync::{Arc, mpsc}, time::Duration},
-	notify::{DebouncedEvent, RecommendedWatcher}
+	std::{path::Path, sync::{Arc, mpsc}, time::Duration},
+	notify::{DebouncedEvent, RecommendedWatcher},
+	serde_dyn_repr::Value
 };
 
 pub const DEFAULT_WATCHER_DELAY: Duration = Duration::from_secs(1);
@@ -34,12 +35,7 @@ pub async fn run(ctx: Arc<crate::ctx::ContextWrapper>, tx: mpsc::Receiver<Deboun
 		match event {
 			DebouncedEvent::NoticeWrite(_)  => (),
 			DebouncedEvent::NoticeRemove(_) => (),
-			DebouncedEvent::Create(path) => {
-			
-			}
-			DebouncedEvent::Write(path) => {
-			
-			}
+			DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => reload(&ctx, &path).await,
 			DebouncedEvent::Chmod(path) => {
 			
 			}
@@ -49,6 +45,9 @@ pub async fn run(ctx: Arc<crate::ctx::ContextWrapper>, tx: mpsc::Receiver<Deboun
 					Some(_) => log::info!("watcher: removed `{}`", path.display()),
 					None    => log::warn!("watcher: received remove event for `{}`, but this path is not registered", path.display())
 				}
+				
+				std::mem::drop(inner);
+				reload(&ctx, &path).await;
 			}
 			DebouncedEvent::Rename(path, new) => {
 				let mut inner = ctx.0.lock().await;
@@ -61,7 +60,9 @@ pub async fn run(ctx: Arc<crate::ctx::ContextWrapper>, tx: mpsc::Receiver<Deboun
 				};
 				
 				log::info!("watcher: renamed `{}` to `{}`", path.display(), new.display());
-				inner.files.insert(new, v);
+				inner.files.insert(new.clone(), v);
+				std::mem::drop(inner);
+				reload(&ctx, &new).await;
 			}
 			DebouncedEvent::Rescan => {
 				log::info!("watcher: rescan triggered");
@@ -72,4 +73,23 @@ pub async fn run(ctx: Arc<crate::ctx::ContextWrapper>, tx: mpsc::Receiver<Deboun
 			}
 		}
 	}
-}
\ No newline at end of file
+}
+
+/// Reloads the config after a config file changed, unless `global.disable_hot_reload` is set.
+async fn reload(ctx: &crate::ctx::ContextWrapper, path: &Path) {
+	let disabled = match ctx.0.lock().await.config.get("global") {
+		Some(Value::Map(v)) => v.get("disable_hot_reload") == Some(&Value::Bool(true)),
+		_ => false
+	};
+	
+	if disabled {
+		return;
+	}
+	
+	log::info!("watcher: `{}` changed, reloading config ...", path.display());
+	
+	match ctx.reload_config().await {
+		Ok(()) => log::info!("watcher: config reloaded"),
+		Err(e) => log::error!("watcher: failed to reload config: {}", e.display())
+	}
+}
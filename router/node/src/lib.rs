@@ -20,21 +20,21 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-//! Library for net-services plugins, each module must have an entry point that looks like this:
+//! Library for net-services plugins, each plugin must declare its components with the [`plugin!`]
+//! macro, which exports the [`PluginInfo`] and the entry point the router looks for:
 //!
-//! ```
-//! #[no_mangle]
-//! pub extern "Rust" fn main<'a>(
-//!     cfg: &'a mut (dyn net_services::dyn_serde::Deserializer + Send + Sync)
-//! ) -> net_services::DynFuture<'a, net_services::Result<()>> {
-//!     Box::pin(async move {
-//!         // ...
-//!         Ok(())
-//!     })
+//! ```ignore
+//! use kranus_router_node::{*, interfaces::*};
+//!
+//! plugin! {
+//!     hello: Hello as HttpStreamHandler
 //! }
 //! ```
+//!
+//! Every component type must implement [`PluginComponent`] and the traits behind the interfaces it
+//! is registered as.
 
-use std::{future::Future, pin::Pin, sync::Arc, any::Any};
+use std::{future::Future, pin::Pin, sync::Arc, any::Any, collections::HashMap, ffi::CStr, os::raw::c_char};
 
 pub use {
 	log,
@@ -44,37 +44,280 @@ pub use {
 	kranus_protocols as net
 };
 
+/// The version of the plugin ABI. Must be incremented whenever [`PluginInfo`], [`PluginInitFn`],
+/// [`Context`] or any of the [`interfaces`] change in an incompatible way.
+pub const ABI_VERSION: u32 = 3;
+
+/// The version of the compiler this crate was built with. Rust has no stable ABI, so plugins must
+/// be built with the exact same compiler as the router.
+pub const RUSTC_VERSION: &str = env!("KRANUS_ROUTER_RUSTC_VERSION");
+
+/// The name of the [`PluginInfo`] static exported by every plugin.
+pub const PLUGIN_INFO_SYMBOL: &str = "NET_SERVICES_PLUGIN_INFO\0";
+
+/// The name of the [`PluginInitFn`] exported by every plugin.
+pub const PLUGIN_INIT_SYMBOL: &str = "net_services_plugin_init\0";
+
+/// The name of the [`PluginCheckFn`] exported by every plugin.
+pub const PLUGIN_CHECK_SYMBOL: &str = "net_services_plugin_check\0";
+
+pub type PluginInitFn = for<'a> extern "Rust" fn(
+	log: &'static dyn log::Log,
+	lvl: log::LevelFilter,
+	ctx: Arc<dyn Context>,
+	trt: otel::Runtime,
+	cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)
+) -> DynFuture<'a, Result<()>>;
+
+/// Deserializes the configs of all components of a plugin, without initializing them.
+pub type PluginCheckFn = for<'a> extern "Rust" fn(
+	cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)
+) -> Vec<ConfigError>;
+
+/// A component of a plugin, see [`plugin!`].
+pub trait PluginComponent: Sized + Send + Sync + 'static {
+	/// The config of the component, deserialized from the top-level section with the name of the
+	/// component. If the section is missing, the config is deserialized from an empty map.
+	type Config: serde::de::DeserializeOwned;
+
+	fn new(name: &str, cfg: Self::Config) -> DynFuture<'_, Result<Self>>;
+}
+
+/// Declares the components of a plugin and exports its [`PluginInfo`] and entry point.
+///
+/// ```ignore
+/// plugin! {
+///     <component name>: <component struct> as <interface1> + <interface2> ...,
+///     ...
+/// }
+/// ```
+#[macro_export]
 macro_rules! plugin {
-    ( ( $ident:ident: $ty:ty as $( $interface:ty )+ ),* ) => {
+	( $( $ident:ident: $ty:ident as $interface:ident $( + $interfaces:ident )* ),* $(,)? ) => {
 		#[no_mangle]
-		pub extern "Rust" fn main<'a>(cfg: &'a mut (dyn $crate::dyn_serde::Deserializer + Send + Sync)) -> $crate::DynFuture<'a, $crate::Result<()>> {
-			Box::pin(async move {
-				(
-					{
-						let component = $ty::new(&mut*cfg).await?;
-						let id = net_services::component_id(stringify!($ident));
+		pub static NET_SERVICES_PLUGIN_INFO: $crate::PluginInfo = $crate::PluginInfo {
+			abi_version:   $crate::ABI_VERSION,
+			rustc_version: $crate::__plugin_cstr!($crate::RUSTC_VERSION_CSTR),
+			name:          $crate::__plugin_cstr!(concat!(env!("CARGO_PKG_NAME"), "\0")),
+			version:       $crate::__plugin_cstr!(concat!(env!("CARGO_PKG_VERSION"), "\0")),
+			authors:       $crate::__plugin_cstr!(concat!(env!("CARGO_PKG_AUTHORS"), "\0")),
+			components:    $crate::__plugin_cstr!(concat!($( stringify!($ident), ",", )* "\0"))
+		};
 
-						$(
-							$crate::add_component(id, Arc::new(Box::new(component) as $interface)).await;
-						)*
-					}
-				)*
+		#[no_mangle]
+		pub extern "Rust" fn net_services_plugin_init<'a>(
+			log: &'static dyn $crate::log::Log,
+			lvl: $crate::log::LevelFilter,
+			ctx: ::std::sync::Arc<dyn $crate::Context>,
+			trt: $crate::otel::Runtime,
+			cfg: &'a mut (dyn $crate::dyn_serde::Deserializer<'a> + Send + Sync)
+		) -> $crate::DynFuture<'a, $crate::Result<()>> {
+			$crate::init_plugin(log, lvl, ctx, trt, env!("CARGO_PKG_NAME"));
+
+			::std::boxed::Box::pin(async move {
+				let mut sections = $crate::plugin_config_sections(cfg)?;
+
+				$({
+					let cfg = $crate::plugin_config::<<$ty as $crate::PluginComponent>::Config>(
+						&mut sections, stringify!($ident))?;
+					let component = ::std::sync::Arc::new(
+						<$ty as $crate::PluginComponent>::new(stringify!($ident), cfg).await?);
+					let id = $crate::component_id(stringify!($ident));
+					let _ = $crate::add_component::<$interface>(id, ::std::boxed::Box::new(component.clone()));
+					$( let _ = $crate::add_component::<$interfaces>(id, ::std::boxed::Box::new(component.clone())); )*
+				})*
 
 				Ok(())
 			})
 		}
+
+		#[no_mangle]
+		pub extern "Rust" fn net_services_plugin_check<'a>(
+			cfg: &'a mut (dyn $crate::dyn_serde::Deserializer<'a> + Send + Sync)
+		) -> ::std::vec::Vec<$crate::ConfigError> {
+			let mut sections = match $crate::plugin_config_sections(cfg) {
+				Ok(v)  => v,
+				Err(e) => return vec![e]
+			};
+
+			let mut errors = ::std::vec::Vec::new();
+
+			$(
+				if let Err(e) = $crate::plugin_config::<<$ty as $crate::PluginComponent>::Config>(
+					&mut sections, stringify!($ident)) {
+					errors.push(e);
+				}
+			)*
+
+			errors
+		}
 	};
 }
 
-//#![feature(const_type_id)]
-//#[no_mangle]
-//pub static CHECK_ID: std::any::TypeId = std::any::TypeId::of::<()>();
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plugin_cstr {
+	( $s:expr ) => { $crate::PluginStr($s.as_ptr() as *const ::std::os::raw::c_char) };
+}
+
+#[doc(hidden)]
+pub const RUSTC_VERSION_CSTR: &str = concat!(env!("KRANUS_ROUTER_RUSTC_VERSION"), "\0");
+
+/// Information about a plugin, exported by the [`plugin!`] macro. The first two fields must never
+/// change, as they are read before the ABI compatibility of a plugin is known.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PluginInfo {
+	pub abi_version:   u32,
+	pub rustc_version: PluginStr,
+	pub name:          PluginStr,
+	pub version:       PluginStr,
+	pub authors:       PluginStr,
+	/// A comma separated list of the components the plugin registers.
+	pub components:    PluginStr
+}
+
+impl PluginInfo {
+	pub fn components(&self) -> impl Iterator<Item = &'static str> {
+		self.components.as_str().split(',').filter(|v| !v.is_empty())
+	}
+
+	/// Checks if the plugin is compatible with this build of the router.
+	pub fn check(&self) -> std::result::Result<(), AbiMismatch> {
+		if self.abi_version != ABI_VERSION || self.rustc_version.as_str() != RUSTC_VERSION {
+			return Err(AbiMismatch {
+				plugin_abi_version:   self.abi_version,
+				plugin_rustc_version: self.rustc_version.as_str().to_string(),
+			});
+		}
+
+		Ok(())
+	}
+}
+
+impl std::fmt::Display for PluginInfo {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} v{} by {} (ABI v{}, {})", self.name.as_str(), self.version.as_str(),
+			self.authors.as_str(), self.abi_version, self.rustc_version.as_str())
+	}
+}
+
+/// A nul-terminated static string, which can be read regardless of the compiler that created it.
+#[repr(transparent)]
+#[derive(Copy, Clone)]
+pub struct PluginStr(pub *const c_char);
+
+// SAFE: points to a static string
+unsafe impl Send for PluginStr {}
+unsafe impl Sync for PluginStr {}
+
+impl PluginStr {
+	pub fn as_str(&self) -> &'static str {
+		if self.0.is_null() {
+			return "";
+		}
+
+		unsafe { CStr::from_ptr(self.0) }.to_str().unwrap_or("<invalid UTF-8>")
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct AbiMismatch {
+	pub plugin_abi_version:   u32,
+	pub plugin_rustc_version: String
+}
+
+impl std::fmt::Display for AbiMismatch {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "plugin was built for ABI v{} with `{}`, but the router requires ABI v{} with `{}`",
+			self.plugin_abi_version, self.plugin_rustc_version, ABI_VERSION, RUSTC_VERSION)
+	}
+}
+
+/// Reads the [`PluginInfo`] of a loaded plugin and checks if it is compatible with this router.
+///
+/// # Safety
+///
+/// The library must stay loaded as long as the returned reference is used.
+pub unsafe fn plugin_info(lib: &libloading::Library) -> Result<&PluginInfo> {
+	let info = *lib.get::<*const PluginInfo>(PLUGIN_INFO_SYMBOL.as_bytes())
+		.with_msg("not a plugin, `NET_SERVICES_PLUGIN_INFO` not found")?;
+	let info = &*info;
+	info.check()?;
+	Ok(info)
+}
+
+/// Resolves the config check function of a plugin, the plugin must have been checked with [`plugin_info`].
+///
+/// # Safety
+///
+/// The library must stay loaded as long as the returned function is used.
+pub unsafe fn plugin_check_fn(lib: &libloading::Library) -> Result<PluginCheckFn> {
+	Ok(*lib.get::<PluginCheckFn>(PLUGIN_CHECK_SYMBOL.as_bytes())
+		.with_msg("not a plugin, `net_services_plugin_check` not found")?)
+}
 
-#[no_mangle]
-pub static NET_SERVICES_PLUGIN_INFO_STR: &str = concat!(env!("CARGO_PKG_NAME"), " v", env!("CARGO_PKG_VERSION"), " by ", env!("CARGO_PKG_AUTHORS"));
+/// An error in the config, `path` is the dot-separated path of the key that caused it.
+#[derive(Clone, Debug)]
+pub struct ConfigError {
+	pub path:    String,
+	pub message: String
+}
 
-#[no_mangle]
-pub static NET_SERVICES_PLUGIN_INFO: &&str = &NET_SERVICES_PLUGIN_INFO_STR;
+impl ConfigError {
+	pub fn new(path: impl ToString, message: impl ToString) -> Self {
+		Self { path: path.to_string(), message: message.to_string() }
+	}
+
+	/// Creates an error from a deserialization error, the path of the error is appended to `prefix`.
+	pub fn from_path<E: std::fmt::Display>(prefix: &str, e: serde_path_to_error::Error<E>) -> Self {
+		let path = match e.path().to_string() {
+			v if v == "." => prefix.to_string(),
+			v if prefix.is_empty() => v,
+			v if v.starts_with('[') => format!("{}{}", prefix, v),
+			v => format!("{}.{}", prefix, v)
+		};
+
+		Self::new(path, e.inner())
+	}
+}
+
+impl std::fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "`{}`: {}", self.path, self.message)
+	}
+}
+
+#[doc(hidden)]
+pub fn plugin_config_sections<'a>(
+	cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)
+) -> std::result::Result<HashMap<String, serde_dyn_repr::Value>, ConfigError> {
+	<HashMap<String, serde_dyn_repr::Value> as serde::Deserialize>::deserialize(cfg)
+		.map_err(|e| ConfigError::new("", e))
+}
+
+#[doc(hidden)]
+pub fn plugin_config<T: serde::de::DeserializeOwned>(
+	sections: &mut HashMap<String, serde_dyn_repr::Value>,
+	name:     &str
+) -> std::result::Result<T, ConfigError> {
+	let section = sections.get(name)
+		.cloned()
+		.unwrap_or_else(|| serde_dyn_repr::Value::Map(HashMap::new()));
+
+	serde_path_to_error::deserialize(section)
+		.map_err(|e| ConfigError::from_path(name, e))
+}
+
+/// Resolves the entry point of a plugin, the plugin must have been checked with [`plugin_info`].
+///
+/// # Safety
+///
+/// The library must stay loaded as long as the returned function is used.
+pub unsafe fn plugin_init_fn(lib: &libloading::Library) -> Result<PluginInitFn> {
+	Ok(*lib.get::<PluginInitFn>(PLUGIN_INIT_SYMBOL.as_bytes())
+		.with_msg("not a plugin, `net_services_plugin_init` not found")?)
+}
 
 //#![feature(once_cell)]
 //static CONTEXT: std::lazy::SyncOnceCell<Arc<dyn Context>> = std::lazy::SyncOnceCell::new();
@@ -83,28 +326,124 @@ static CONTEXT_VTABLE: std::sync::atomic::AtomicPtr<()> = std::sync::atomic::Ato
 
 pub type DynFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
-pub struct ComponentRef<T>(Arc<std::sync::atomic::AtomicPtr<T>>);
+/// The time [`ComponentRef::get`] waits for a component to be registered.
+pub const COMPONENT_WAIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The state of a component slot, either the registered component or the wakers of all tasks
+/// that are waiting for the component to be registered.
+pub enum Component {
+	Present(Arc<dyn Any + Send + Sync>),
+	Waker(Vec<std::task::Waker>)
+}
 
-impl<T> std::ops::Deref for ComponentRef<T> {
-	type Target = T;
+/// A registry entry for a component and one of its interfaces. Slots are created on first use
+/// and are never removed, so references to components can be created before the component
+/// itself is registered.
+pub struct ComponentSlot(std::sync::Mutex<Component>);
 
-	fn deref(&self) -> &Self::Target {
-		let ptr = self.0.load(std::sync::atomic::Ordering::SeqCst);
+impl ComponentSlot {
+	pub fn new() -> Self {
+		Self(std::sync::Mutex::new(Component::Waker(Vec::new())))
+	}
 
-		if ptr.is_null() {
-			panic!("component not present");
+	pub fn get(&self) -> Option<Arc<dyn Any + Send + Sync>> {
+		match &*self.lock() {
+			Component::Present(v) => Some(v.clone()),
+			Component::Waker(_)   => None
 		}
+	}
+
+	pub fn poll_get(&self, cx: &mut std::task::Context<'_>) -> std::task::Poll<Arc<dyn Any + Send + Sync>> {
+		match &mut*self.lock() {
+			Component::Present(v) => std::task::Poll::Ready(v.clone()),
+			Component::Waker(wakers) => {
+				if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+					wakers.push(cx.waker().clone());
+				}
 
-		unsafe { &*ptr }
+				std::task::Poll::Pending
+			}
+		}
+	}
+
+	/// Replaces the component, returns the previous one. Tasks waiting for the component are woken.
+	pub fn set(&self, component: Option<Arc<dyn Any + Send + Sync>>) -> Option<Arc<dyn Any + Send + Sync>> {
+		let new = match component {
+			Some(v) => Component::Present(v),
+			None    => Component::Waker(Vec::new())
+		};
+
+		match std::mem::replace(&mut*self.lock(), new) {
+			Component::Present(v) => Some(v),
+			Component::Waker(wakers) => {
+				wakers.into_iter().for_each(std::task::Waker::wake);
+				None
+			}
+		}
+	}
+
+	pub fn is_present(&self) -> bool {
+		matches!(&*self.lock(), Component::Present(_))
+	}
+
+	fn lock(&self) -> std::sync::MutexGuard<'_, Component> {
+		self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+	}
+}
+
+impl Default for ComponentSlot {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// A typed reference to a component, that may not be registered yet.
+pub struct ComponentRef<T> {
+	id:      u128,
+	slot:    Arc<ComponentSlot>,
+	_marker: std::marker::PhantomData<fn() -> T>
+}
+
+impl<T: Any + Send + Sync> ComponentRef<T> {
+	pub fn id(&self) -> u128 {
+		self.id
+	}
+
+	/// Returns the component, if it is registered.
+	pub fn try_get(&self) -> Option<Arc<T>> {
+		self.slot.get().map(downcast_component)
+	}
+
+	/// Returns the component, waits at most [`COMPONENT_WAIT_TIMEOUT`] for it to be registered.
+	pub async fn get(&self) -> Result<Arc<T>> {
+		if let Some(v) = self.try_get() {
+			return Ok(v);
+		}
+
+		let slot = &self.slot;
+		smol::future::or(
+			async { Ok(downcast_component(smol::future::poll_fn(|cx| slot.poll_get(cx)).await)) },
+			async {
+				smol::Timer::after(COMPONENT_WAIT_TIMEOUT).await;
+				Err(Error::new(format!("component `{}` not present after {}s",
+					component_name(self.id), COMPONENT_WAIT_TIMEOUT.as_secs())))
+			}
+		).await
 	}
 }
 
 impl<T> Clone for ComponentRef<T> {
 	fn clone(&self) -> Self {
-		Self(self.0.clone())
+		Self { id: self.id, slot: self.slot.clone(), _marker: std::marker::PhantomData }
 	}
 }
 
+/// SAFE: slots are keyed by the interface id of `T`, so the component behind a slot is always a `T`.
+fn downcast_component<T: Any + Send + Sync>(v: Arc<dyn Any + Send + Sync>) -> Arc<T> {
+	// `Arc::downcast` can't be used, type ids are not guaranteed to match across shared libraries
+	unsafe { Arc::from_raw(Arc::into_raw(v) as *const T) }
+}
+
 // can't use the std version because it's unstable
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -113,25 +452,22 @@ pub struct TraitObject {
 	pub vtable: *mut (),
 }
 
-extern "Rust" {
-	fn main<'a>(cfg: &'a (dyn erased_serde::Deserializer + Send + Sync)) -> DynFuture<'a, Result<()>>;
-}
-
-#[no_mangle]
-pub extern "Rust" fn net_services_plugin_init<'a>(
-	log: &'static dyn log::Log,
-	lvl: log::LevelFilter,
-	ctx: Arc<dyn Context>,
-	trt: otel::Runtime,
-	cfg: &'a (dyn dyn_serde::Deserializer + Send + Sync)
-) -> DynFuture<'a, Result<()>> {
-	log::set_logger(log).unwrap();
+/// Initializes the logger, context and telemetry runtime of a plugin, called by the entry point
+/// generated by [`plugin!`].
+#[doc(hidden)]
+pub fn init_plugin(
+	log:  &'static dyn log::Log,
+	lvl:  log::LevelFilter,
+	ctx:  Arc<dyn Context>,
+	trt:  otel::Runtime,
+	name: &str
+) {
+	let _ = log::set_logger(log);
 	log::set_max_level(lvl);
 	//CONTEXT.set(ctx);
 	set_context(ctx);
 	unsafe { otel_mrt::set_global(trt) };
-	log::debug!("init: plugin `{}` context initialized", env!("CARGO_PKG_NAME"));
-	unsafe { main(cfg) }
+	log::debug!("init: plugin `{}` context initialized", name);
 }
 
 pub fn set_context(ctx: Arc<dyn Context>) {
@@ -158,21 +494,36 @@ pub fn component_id(name: &str) -> u128 {
 	context().component_id(name)
 }
 
+/// Returns the name a component id was created from, or the id itself, if the name is unknown.
+pub fn component_name(id: u128) -> String {
+	context().component_name(id).unwrap_or_else(|| format!("#{:032x}", id))
+}
+
 pub fn get_component<T: Any + Send + Sync>(id: u128) -> ComponentRef<T> {
-	let ptr = context().component_dyn_get(id, get_interface_id::<T>());
-	ComponentRef(unsafe { std::mem::transmute(ptr) })
+	ComponentRef {
+		id,
+		slot:    context().component_dyn_slot(id, get_interface_id::<T>()),
+		_marker: std::marker::PhantomData
+	}
 }
 
-pub fn add_component<T: Any + Send + Sync>(id: u128, interface: T) {
-	let v = Box::leak(Box::new(interface));
-	context().component_dyn_add(id, get_interface_id::<T>(), v as *mut T as _);
+/// Registers a component, returns the component that was previously registered for this id
+/// and interface. Tasks waiting for the component are woken.
+pub fn add_component<T: Any + Send + Sync>(id: u128, interface: T) -> Option<Arc<T>> {
+	context().component_dyn_slot(id, get_interface_id::<T>())
+		.set(Some(Arc::new(interface)))
+		.map(downcast_component)
 }
 
-pub fn del_component<T: Any + Send + Sync>(id: u128) -> bool {
-	context().component_dyn_del(id, get_interface_id::<T>())
+/// Unregisters a component. The component is dropped once the last reference obtained from a
+/// [`ComponentRef`] is dropped.
+pub fn del_component<T: Any + Send + Sync>(id: u128) -> Option<Arc<T>> {
+	context().component_dyn_slot(id, get_interface_id::<T>())
+		.set(None)
+		.map(downcast_component)
 }
 
-fn get_interface_id<T: ?Sized>() -> u64 {
+pub fn get_interface_id<T: ?Sized>() -> u64 {
 	use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}};
 	let mut hasher = DefaultHasher::new();
 	std::any::type_name::<T>().hash(&mut hasher);
@@ -184,11 +535,9 @@ pub trait Context: Send + Sync + 'static + std::fmt::Debug + std::fmt::Display {
 
 	fn component_id(&self, name: &str) -> u128;
 
-	fn component_dyn_get(&self, id: u128, interface: u64) -> Arc<std::sync::atomic::AtomicPtr<()>>;
-
-	fn component_dyn_add(&self, id: u128, interface: u64, ptr: *mut ());
+	fn component_name(&self, id: u128) -> Option<String>;
 
-	fn component_dyn_del(&self, id: u128, interface: u64) -> bool;
+	fn component_dyn_slot(&self, id: u128, interface: u64) -> Arc<ComponentSlot>;
 }
 
 pub struct ErrorWithMessage<E: std::fmt::Display>(&'static str, E);
@@ -227,6 +576,283 @@ impl<T> WithMessage<T, String> for std::option::Option<T> {
 pub mod interfaces {
 	use {super::*, net::http, std::any::TypeId};
 
+	/// The identity of a client that presented a verified TLS certificate.
+	///
+	/// Sockets attach it to every request as headers, after removing any headers with the same
+	/// names sent by the client, so handlers can trust them.
+	#[derive(Clone, Debug, Default, Eq, PartialEq)]
+	pub struct ClientIdentity {
+		/// The subject distinguished name, e.g. `CN=client, O=example`.
+		pub subject:     String,
+		/// The subject alternative names, prefixed with their type, e.g. `DNS:example.com`,
+		/// `URI:spiffe://example.com/client`, `IP:127.0.0.1` or `email:client@example.com`.
+		pub sans:        Vec<String>,
+		/// The lowercase hex encoded SHA-256 digest of the DER encoded certificate.
+		pub fingerprint: String
+	}
+
+	impl ClientIdentity {
+		pub const HEADER_SUBJECT:     &'static str = "x-kranus-client-subject";
+		pub const HEADER_SAN:         &'static str = "x-kranus-client-san";
+		pub const HEADER_FINGERPRINT: &'static str = "x-kranus-client-fingerprint";
+
+		/// Whether `name` is one of the headers reserved for the client identity.
+		pub fn is_header(name: &str) -> bool {
+			[Self::HEADER_SUBJECT, Self::HEADER_SAN, Self::HEADER_FINGERPRINT].iter()
+				.any(|v| v.eq_ignore_ascii_case(name))
+		}
+
+		pub fn from_headers<'a>(headers: impl IntoIterator<Item = &'a http::Header>) -> Option<Self> {
+			let mut identity = Self::default();
+			let mut present = false;
+
+			for header in headers {
+				match header {
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_SUBJECT) => {
+						identity.subject = v.clone();
+						present = true;
+					}
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_SAN) =>
+						identity.sans.push(v.clone()),
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_FINGERPRINT) =>
+						identity.fingerprint = v.clone(),
+					_ => ()
+				}
+			}
+
+			Some(identity).filter(|_| present)
+		}
+
+		pub fn headers(&self) -> impl Iterator<Item = http::Header> + '_ {
+			std::iter::once(http::Header::Custom(Self::HEADER_SUBJECT.to_string(), self.subject.clone()))
+				.chain(self.sans.iter().map(|v| http::Header::Custom(Self::HEADER_SAN.to_string(), v.clone())))
+				.chain(std::iter::once(http::Header::Custom(Self::HEADER_FINGERPRINT.to_string(), self.fingerprint.clone())))
+		}
+
+		/// Formats the identity like the `X-Forwarded-Client-Cert` header, for backends.
+		pub fn to_forwarded(&self) -> String {
+			let mut buf = format!("Hash={};Subject=\"{}\"", self.fingerprint, self.subject.replace('"', "\\\""));
+
+			for san in &self.sans {
+				match san.split_once(':') {
+					Some(("DNS", v)) => buf.push_str(&format!(";DNS={}", v)),
+					Some(("URI", v)) => buf.push_str(&format!(";URI={}", v)),
+					_ => ()
+				}
+			}
+
+			buf
+		}
+	}
+
+	/// What a socket knows about the connection a request was received on.
+	///
+	/// Like [`ClientIdentity`], sockets attach it to every request as headers.
+	#[derive(Clone, Debug, Default, Eq, PartialEq)]
+	pub struct ConnectionInfo {
+		/// The address of the peer, or of the client if it was sent by a trusted proxy.
+		pub addr:        Option<std::net::SocketAddr>,
+		/// The address the connection was accepted on.
+		pub local_addr:  Option<std::net::SocketAddr>,
+		/// The negotiated TLS version, e.g. `TLSv1_3`.
+		pub tls_version: Option<String>,
+		/// The negotiated TLS cipher suite, e.g. `TLS13_AES_128_GCM_SHA256`.
+		pub tls_cipher:  Option<String>,
+		/// The server name sent by the client.
+		pub tls_sni:     Option<String>,
+		/// The HTTP version of the request, e.g. `HTTP/1.1`.
+		pub http_version: Option<String>
+	}
+
+	impl ConnectionInfo {
+		pub const HEADER_ADDR:        &'static str = "x-kranus-client-addr";
+		pub const HEADER_LOCAL_ADDR:  &'static str = "x-kranus-local-addr";
+		pub const HEADER_TLS_VERSION: &'static str = "x-kranus-tls-version";
+		pub const HEADER_TLS_CIPHER:  &'static str = "x-kranus-tls-cipher";
+		pub const HEADER_TLS_SNI:     &'static str = "x-kranus-tls-sni";
+		pub const HEADER_HTTP_VERSION: &'static str = "x-kranus-http-version";
+
+		/// Whether `name` is one of the headers reserved for the connection info.
+		pub fn is_header(name: &str) -> bool {
+			[Self::HEADER_ADDR, Self::HEADER_LOCAL_ADDR, Self::HEADER_TLS_VERSION, Self::HEADER_TLS_CIPHER, Self::HEADER_TLS_SNI,
+				Self::HEADER_HTTP_VERSION].iter()
+				.any(|v| v.eq_ignore_ascii_case(name))
+		}
+
+		pub fn from_headers<'a>(headers: impl IntoIterator<Item = &'a http::Header>) -> Self {
+			let mut info = Self::default();
+
+			for header in headers {
+				match header {
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_ADDR) =>
+						info.addr = v.parse().ok(),
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_LOCAL_ADDR) =>
+						info.local_addr = v.parse().ok(),
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_TLS_VERSION) =>
+						info.tls_version = Some(v.clone()),
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_TLS_CIPHER) =>
+						info.tls_cipher = Some(v.clone()),
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_TLS_SNI) =>
+						info.tls_sni = Some(v.clone()),
+					http::Header::Custom(k, v) if k.eq_ignore_ascii_case(Self::HEADER_HTTP_VERSION) =>
+						info.http_version = Some(v.clone()),
+					_ => ()
+				}
+			}
+
+			info
+		}
+
+		pub fn headers(&self) -> impl Iterator<Item = http::Header> + '_ {
+			[
+				(Self::HEADER_ADDR, self.addr.map(|v| v.to_string())),
+				(Self::HEADER_LOCAL_ADDR, self.local_addr.map(|v| v.to_string())),
+				(Self::HEADER_TLS_VERSION, self.tls_version.clone()),
+				(Self::HEADER_TLS_CIPHER, self.tls_cipher.clone()),
+				(Self::HEADER_TLS_SNI, self.tls_sni.clone()),
+				(Self::HEADER_HTTP_VERSION, self.http_version.clone())
+			].into_iter()
+				.filter_map(|(k, v)| Some(http::Header::Custom(k.to_string(), v?)))
+		}
+	}
+
+	/// Timeouts of a route, attached to requests by routers as headers. They override the
+	/// timeouts of the backend the request is relayed to.
+	#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+	pub struct RequestTimeouts {
+		/// The time until the first byte of the response is received.
+		pub first_byte: Option<std::time::Duration>,
+		/// The time until the response is complete.
+		pub total:      Option<std::time::Duration>,
+		/// The time without progress while transmitting a body.
+		pub idle:       Option<std::time::Duration>
+	}
+
+	impl RequestTimeouts {
+		pub const HEADER_FIRST_BYTE: &'static str = "x-kranus-timeout-first-byte";
+		pub const HEADER_TOTAL:      &'static str = "x-kranus-timeout-total";
+		pub const HEADER_IDLE:       &'static str = "x-kranus-timeout-idle";
+
+		/// Whether `name` is one of the headers reserved for request timeouts.
+		pub fn is_header(name: &str) -> bool {
+			[Self::HEADER_FIRST_BYTE, Self::HEADER_TOTAL, Self::HEADER_IDLE].iter()
+				.any(|v| v.eq_ignore_ascii_case(name))
+		}
+
+		/// Parses timeouts in milliseconds.
+		pub fn from_headers<'a>(headers: impl IntoIterator<Item = &'a http::Header>) -> Self {
+			let mut timeouts = Self::default();
+
+			for header in headers {
+				let (k, v) = match header {
+					http::Header::Custom(k, v) => (k, v.parse().ok().map(std::time::Duration::from_millis)),
+					_ => continue
+				};
+
+				if k.eq_ignore_ascii_case(Self::HEADER_FIRST_BYTE) {
+					timeouts.first_byte = v;
+				} else if k.eq_ignore_ascii_case(Self::HEADER_TOTAL) {
+					timeouts.total = v;
+				} else if k.eq_ignore_ascii_case(Self::HEADER_IDLE) {
+					timeouts.idle = v;
+				}
+			}
+
+			timeouts
+		}
+
+		pub fn headers(&self) -> impl Iterator<Item = http::Header> {
+			[
+				(Self::HEADER_FIRST_BYTE, self.first_byte),
+				(Self::HEADER_TOTAL, self.total),
+				(Self::HEADER_IDLE, self.idle)
+			].into_iter()
+				.filter_map(|(k, v)| Some(http::Header::Custom(k.to_string(), v?.as_millis().to_string())))
+		}
+
+		/// Timeouts of `self`, or of `other` where `self` has none.
+		pub fn or(self, other: Self) -> Self {
+			Self {
+				first_byte: self.first_byte.or(other.first_byte),
+				total:      self.total.or(other.total),
+				idle:       self.idle.or(other.idle)
+			}
+		}
+	}
+
+	/// A response header naming the backend a request was relayed to. It is removed by sockets
+	/// before the response is sent to the client.
+	pub const HEADER_UPSTREAM: &str = "x-kranus-upstream";
+	
+	/// A request header with the base64url encoded claims of a JWT verified by an auth module, for
+	/// router filters. It is removed from client requests by sockets and not sent to backends.
+	pub const HEADER_JWT_CLAIMS: &str = "x-kranus-jwt-claims";
+
+	/// A DNS query received by a socket. Handlers answer it by setting the response, queries
+	/// without a response are answered with `SERVFAIL`.
+	#[derive(Clone, Debug, Default)]
+	pub struct DnsExchange {
+		pub query:      net::dns::Message,
+		pub response:   Option<net::dns::Message>,
+		pub connection: ConnectionInfo
+	}
+
+	/// A mail transaction received by a socket. Handlers deliver or reject it by setting the
+	/// reply, transactions without a reply are rejected with a temporary failure.
+	#[derive(Clone, Debug, Default)]
+	pub struct MailTransaction {
+		/// The reverse path of `MAIL FROM` without angle brackets, empty for bounces.
+		pub sender:     String,
+		/// The forward paths of `RCPT TO` without angle brackets.
+		pub recipients: Vec<String>,
+		/// The message with its headers, CRLF line endings and without dot-stuffing.
+		pub message:    Vec<u8>,
+		/// The user authenticated with `AUTH`.
+		pub user:       Option<String>,
+		pub connection: ConnectionInfo,
+		/// The SMTP reply code and text, e.g. `(250, "OK")`.
+		pub reply:      Option<(u16, String)>
+	}
+
+	impl MailTransaction {
+		/// The length of the header section of the message, including the empty line.
+		pub fn headers_len(&self) -> usize {
+			self.message.windows(4).position(|v| v == b"\r\n\r\n").map_or(self.message.len(), |i| i + 4)
+		}
+
+		/// The unfolded headers of the message, as names and values.
+		pub fn headers(&self) -> impl Iterator<Item = (&str, String)> {
+			let headers = std::str::from_utf8(&self.message[..self.headers_len()]).unwrap_or("");
+			let mut lines = headers.split("\r\n").peekable();
+
+			std::iter::from_fn(move || loop {
+				let line = lines.next()?;
+				let (name, value) = match line.split_once(':') {
+					Some(v) => v,
+					None    => continue
+				};
+
+				let mut value = value.trim().to_string();
+				while let Some(next) = lines.next_if(|v| v.starts_with([' ', '\t'])) {
+					value.push(' ');
+					value.push_str(next.trim());
+				}
+
+				return Some((name.trim(), value));
+			})
+		}
+	}
+
+	/// Credentials received by a socket, e.g. with SMTP `AUTH`. Handlers set `valid` if they
+	/// verified them.
+	#[derive(Clone, Debug, Default)]
+	pub struct Credentials {
+		pub user:       String,
+		pub password:   String,
+		pub connection: ConnectionInfo,
+		pub valid:      bool
+	}
+
 	pub trait AsyncByteStream: smol::io::AsyncRead + smol::io::AsyncWrite + Send {}
 
 	impl<T: smol::io::AsyncRead + smol::io::AsyncWrite + Send> AsyncByteStream for T {}
@@ -270,11 +896,793 @@ pub mod interfaces {
 		fn accept<'a>(&'a self, stream: &'static mut T) -> DynFuture<'a, Result<()>>;
 	}
 
+	impl<T: ?Sized, H: StreamHandler<T> + ?Sized> StreamHandler<T> for Arc<H> {
+		fn accept<'a>(&'a self, stream: &'static mut T) -> DynFuture<'a, Result<()>> {
+			(**self).accept(stream)
+		}
+	}
+
 	pub trait StreamFilter<T: ?Sized>: Send + Sync {
 		fn filter<'a>(&'a self, stream: &'static mut T) -> DynFuture<'a, Result<bool>>;
 	}
 
+	/// Hooks invoked by the router over the lifetime of a component. Components that depend on other
+	/// components or need to be notified of lifecycle events register this as an additional interface.
+	pub trait Lifecycle: Send + Sync {
+		/// The names of the components this component depends on. Dependencies are started before
+		/// and stopped after this component. Components are stopped on `SIGTERM`, `SIGINT` and
+		/// before the process exits after an upgrade.
+		fn dependencies(&self) -> Vec<String> {
+			Vec::new()
+		}
+
+		fn start(&self) -> DynFuture<'_, Result<()>> {
+			Box::pin(async { Ok(()) })
+		}
+
+		fn stop(&self) -> DynFuture<'_, Result<()>> {
+			Box::pin(async { Ok(()) })
+		}
+
+		/// Applies a changed config section of the component. Builtin modules that don't support
+		/// this are replaced by a new instance, components of plugins keep their old config.
+		fn reload<'a>(&'a self, _cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)) -> DynFuture<'a, Result<()>> {
+			Box::pin(async { Err(Error::new("component does not support reloading")) })
+		}
+	}
+
+	impl<H: Lifecycle + ?Sized> Lifecycle for Arc<H> {
+		fn dependencies(&self) -> Vec<String> {
+			(**self).dependencies()
+		}
+
+		fn start(&self) -> DynFuture<'_, Result<()>> {
+			(**self).start()
+		}
+
+		fn stop(&self) -> DynFuture<'_, Result<()>> {
+			(**self).stop()
+		}
+
+		fn reload<'a>(&'a self, cfg: &'a mut (dyn dyn_serde::Deserializer<'a> + Send + Sync)) -> DynFuture<'a, Result<()>> {
+			(**self).reload(cfg)
+		}
+	}
+
+	pub type LifecycleHandler = Box<dyn Lifecycle>;
 	pub type GenericStreamHandler = Box<dyn StreamHandler<dyn GenericStream>>;
 	pub type HttpStreamHandler = Box<dyn StreamHandler<dyn http::traits::AsyncStream>>;
 	pub type ByteStreamHandler = Box<dyn StreamHandler<dyn AsyncByteStream>>;
-}
\ No newline at end of file
+	pub type DnsHandler = Box<dyn StreamHandler<DnsExchange>>;
+	pub type MailHandler = Box<dyn StreamHandler<MailTransaction>>;
+	pub type CredentialsHandler = Box<dyn StreamHandler<Credentials>>;
+}
+/// Types used by handlers generated with the `controller` macro of `kranus-router-api-controller`.
+pub mod controller {
+	use {
+		super::*,
+		net::http,
+		std::{borrow::Cow, fmt, str::FromStr},
+		serde::{Serialize, de::DeserializeOwned},
+		schemars::{JsonSchema, gen::SchemaGenerator, schema::Schema}
+	};
+
+	pub use {serde_json, schemars};
+
+	/// An error that is answered with its status, if no `#[error]` handler matches it.
+	#[derive(Clone, Debug)]
+	pub struct HttpError {
+		pub status:  http::Status,
+		pub message: Cow<'static, str>
+	}
+
+	impl HttpError {
+		pub fn new(status: http::Status, message: impl Into<Cow<'static, str>>) -> Self {
+			Self { status, message: message.into() }
+		}
+
+		pub fn bad_request(message: impl Into<Cow<'static, str>>) -> Self {
+			Self::new(http::Status::BadRequest, message)
+		}
+	}
+
+	impl fmt::Display for HttpError {
+		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			write!(f, "{}: {}", self.status, self.message)
+		}
+	}
+
+	/// A response returned by a handler.
+	#[derive(Clone, Debug)]
+	pub struct Response {
+		pub status:  http::Status,
+		pub headers: Vec<http::Header>,
+		pub body:    Vec<u8>
+	}
+
+	impl Response {
+		pub fn new(status: http::Status) -> Self {
+			Self { status, headers: Vec::new(), body: Vec::new() }
+		}
+
+		pub fn header(mut self, header: http::Header) -> Self {
+			self.headers.push(header);
+			self
+		}
+
+		pub fn body(mut self, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+			match content_type.parse() {
+				Ok(v)  => self.headers.push(http::Header::ContentType(Box::new(v))),
+				Err(_) => self.headers.push(http::Header::Custom("content-type".to_string(), content_type.to_string()))
+			}
+
+			self.body = body.into();
+			self
+		}
+
+		pub async fn send(self, stream: &mut dyn http::traits::AsyncStream) -> Result<()> {
+			self.send_inner(stream, false).await
+		}
+
+		/// Sends the response to a `HEAD` request, i.e. without the body but with its length.
+		pub async fn send_head(self, stream: &mut dyn http::traits::AsyncStream) -> Result<()> {
+			self.send_inner(stream, true).await
+		}
+
+		async fn send_inner(self, stream: &mut dyn http::traits::AsyncStream, head: bool) -> Result<()> {
+			let mut headers = Vec::with_capacity(self.headers.len() + 2);
+			headers.push(http::Header::Status(self.status));
+			headers.extend(self.headers);
+			headers.push(http::Header::ContentLength(self.body.len()));
+
+			http::MessageBuilder::from(headers)
+				.body(if head { Vec::new() } else { self.body })
+				.send_async(stream)
+				.await.map_err(Into::into)
+		}
+	}
+
+	/// A value that can be sent as response.
+	pub trait IntoResponse {
+		fn into_response(self) -> Result<Response>;
+	}
+
+	impl IntoResponse for Response {
+		fn into_response(self) -> Result<Response> {
+			Ok(self)
+		}
+	}
+
+	impl IntoResponse for http::Status {
+		fn into_response(self) -> Result<Response> {
+			Ok(Response::new(self))
+		}
+	}
+
+	impl IntoResponse for String {
+		fn into_response(self) -> Result<Response> {
+			Ok(Response::new(http::Status::Ok).body("text/plain; charset=utf-8", self))
+		}
+	}
+
+	impl IntoResponse for &'static str {
+		fn into_response(self) -> Result<Response> {
+			self.to_string().into_response()
+		}
+	}
+
+	impl IntoResponse for Vec<u8> {
+		fn into_response(self) -> Result<Response> {
+			Ok(Response::new(http::Status::Ok).body("application/octet-stream", self))
+		}
+	}
+
+	impl<T: IntoResponse> IntoResponse for (http::Status, T) {
+		fn into_response(self) -> Result<Response> {
+			let mut response = self.1.into_response()?;
+			response.status = self.0;
+			Ok(response)
+		}
+	}
+
+	impl<T: IntoResponse> IntoResponse for (http::Status, Vec<http::Header>, T) {
+		fn into_response(self) -> Result<Response> {
+			let mut response = self.2.into_response()?;
+			response.status = self.0;
+			response.headers.extend(self.1);
+			Ok(response)
+		}
+	}
+
+	/// A value that is sent as JSON.
+	#[derive(Copy, Clone, Debug, Default)]
+	pub struct Json<T>(pub T);
+
+	impl<T: Serialize> IntoResponse for Json<T> {
+		fn into_response(self) -> Result<Response> {
+			let body = serde_json::to_vec(&self.0).map_err(|e| Error::new(e.to_string()))?;
+			Ok(Response::new(http::Status::Ok).body("application/json", body))
+		}
+	}
+
+	/// The value returned by a handler or an `#[error]` handler. With `()`, the handler sent the
+	/// response itself. Errors are passed to the `#[error]` handlers.
+	pub trait HandlerOutput {
+		fn into_output(self) -> Result<Option<Response>>;
+	}
+
+	impl HandlerOutput for () {
+		fn into_output(self) -> Result<Option<Response>> {
+			Ok(None)
+		}
+	}
+
+	impl<T: IntoResponse> HandlerOutput for T {
+		fn into_output(self) -> Result<Option<Response>> {
+			self.into_response().map(Some)
+		}
+	}
+
+	impl<E: Into<Error>> HandlerOutput for std::result::Result<(), E> {
+		fn into_output(self) -> Result<Option<Response>> {
+			self.map(|_| None).map_err(Into::into)
+		}
+	}
+
+	impl<T: IntoResponse, E: Into<Error>> HandlerOutput for std::result::Result<T, E> {
+		fn into_output(self) -> Result<Option<Response>> {
+			self.map_err(Into::into)?.into_response().map(Some)
+		}
+	}
+
+	/// Sends the output of a handler, without the body if `head` is set. An unhandled [`HttpError`]
+	/// is answered with its status, other errors are returned.
+	pub async fn send_output(stream: &mut dyn http::traits::AsyncStream, output: Result<Option<Response>>, head: bool) -> Result<()> {
+		let response = match output {
+			Ok(Some(response)) => response,
+			Ok(None) => return Ok(()),
+			Err(e) => match e.downcast_ref::<HttpError>() {
+				Some(e) => Response::new(e.status)
+					.body("text/plain; charset=utf-8", e.message.as_bytes()),
+				None => return Err(e)
+			}
+		};
+
+		response.send_inner(stream, head).await
+	}
+
+	/// The stream passed to handlers of `HEAD` requests. Headers are written as is, but the body
+	/// is discarded, so handlers that send the response themselves can treat it like a `GET`.
+	pub struct HeadStream<'a> {
+		inner: &'a mut dyn http::traits::AsyncStream
+	}
+
+	impl<'a> HeadStream<'a> {
+		pub fn new(inner: &'a mut dyn http::traits::AsyncStream) -> Self {
+			Self { inner }
+		}
+	}
+
+	impl<'a> smol::io::AsyncRead for HeadStream<'a> {
+		fn poll_read(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut [u8]) -> std::task::Poll<std::io::Result<usize>> {
+			unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read(cx, buf)
+		}
+	}
+
+	impl<'a> smol::io::AsyncWrite for HeadStream<'a> {
+		fn poll_write(self: Pin<&mut Self>, _cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+			std::task::Poll::Ready(Ok(buf.len()))
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+			unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_flush(cx)
+		}
+
+		fn poll_close(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+			unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_close(cx)
+		}
+	}
+
+	impl<'a> http::traits::AsyncStream for HeadStream<'a> {
+		fn poll_read_headers(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<Vec<http::Header>>> {
+			unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read_headers(cx)
+		}
+
+		fn poll_write_headers(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, headers: &[http::Header]) -> std::task::Poll<std::io::Result<()>> {
+			unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write_headers(cx, headers)
+		}
+	}
+
+	/// The routes of a controller in a `static`, built on first use.
+	pub struct LazyRoutes {
+		once:   std::sync::Once,
+		routes: std::sync::atomic::AtomicPtr<Routes>
+	}
+
+	impl LazyRoutes {
+		pub const fn new() -> Self {
+			Self { once: std::sync::Once::new(), routes: std::sync::atomic::AtomicPtr::new(std::ptr::null_mut()) }
+		}
+
+		pub fn get_or_init(&self, init: impl FnOnce() -> Routes) -> &Routes {
+			self.once.call_once(|| self.routes.store(Box::into_raw(Box::new(init())), std::sync::atomic::Ordering::Release));
+			// the routes are never dropped, the `static` lives until the process exits
+			unsafe { &*self.routes.load(std::sync::atomic::Ordering::Acquire) }
+		}
+	}
+
+	impl Default for LazyRoutes {
+		fn default() -> Self {
+			Self::new()
+		}
+	}
+
+	/// A segment trie of the routes of a controller. Literal segments take precedence over
+	/// parameters and parameters over wildcards, routes without a method match any method.
+	#[derive(Clone, Debug, Default)]
+	pub struct Routes {
+		literals: HashMap<String, Routes>,
+		param:    Option<Box<Routes>>,
+		wildcard: Vec<(Option<http::Method>, usize)>,
+		methods:  Vec<(Option<http::Method>, usize)>
+	}
+
+	impl Routes {
+		/// Adds a route with a path like `/items/{id}/{rest...}`, `None` matches any path.
+		pub fn insert(&mut self, method: Option<http::Method>, path: Option<&str>, route: usize) {
+			let mut node = self;
+
+			for s in path.into_iter().flat_map(|v| v.split('/')) {
+				match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+					Some(s) if s.ends_with("...") => break,
+					Some(_) => node = node.param.get_or_insert_with(Default::default),
+					None    => node = node.literals.entry(s.to_string()).or_default()
+				}
+			}
+
+			match path {
+				Some(v) if !v.ends_with("...}") => node.methods.push((method, route)),
+				_ => node.wildcard.push((method, route))
+			}
+		}
+
+		/// Finds the route of a request, returns its index, the path parameters and the segments
+		/// matched by a wildcard. `HEAD` falls back to `GET` routes. Otherwise returns the response,
+		/// `404`, `405` or the allowed methods for `OPTIONS`.
+		#[allow(clippy::type_complexity)]
+		pub fn find<'a>(&self, method: &http::Method, path: &'a [&'a str]) -> std::result::Result<(usize, Vec<&'a str>, &'a [&'a str]), Response> {
+			let (mut params, mut allowed) = (Vec::new(), Vec::new());
+
+			if let Some((route, rest)) = self.find_inner(method, path, &mut params, &mut allowed) {
+				return Ok((route, params, rest));
+			}
+
+			if allowed.is_empty() {
+				return Err(Response::new(http::Status::NotFound));
+			}
+
+			if allowed.contains(&http::Method::Get) {
+				allowed.push(http::Method::Head);
+			}
+
+			allowed.push(http::Method::Options);
+			allowed.sort();
+			allowed.dedup();
+
+			Err(match method {
+				http::Method::Options => Response::new(http::Status::NoContent),
+				_ => Response::new(http::Status::MethodNotAllowed)
+			}.header(http::Header::Allow(allowed)))
+		}
+
+		fn find_inner<'a>(
+			&self,
+			method:  &http::Method,
+			path:    &'a [&'a str],
+			params:  &mut Vec<&'a str>,
+			allowed: &mut Vec<http::Method>
+		) -> Option<(usize, &'a [&'a str])> {
+			match path.split_first() {
+				Some((segment, path)) => {
+					if let Some(v) = self.literals.get(*segment)
+						.and_then(|node| node.find_inner(method, path, params, allowed))
+					{
+						return Some(v);
+					}
+
+					if let Some(node) = &self.param {
+						params.push(*segment);
+
+						match node.find_inner(method, path, params, allowed) {
+							Some(v) => return Some(v),
+							None => { params.pop(); }
+						}
+					}
+				}
+				None => if let Some(route) = Self::select(&self.methods, method, allowed) {
+					return Some((route, path));
+				}
+			}
+
+			Self::select(&self.wildcard, method, allowed).map(|route| (route, path))
+		}
+
+		fn select(routes: &[(Option<http::Method>, usize)], method: &http::Method, allowed: &mut Vec<http::Method>) -> Option<usize> {
+			let find = |method: &http::Method| routes.iter()
+				.find(|(v, _)| v.is_none() || v.as_ref() == Some(method))
+				.map(|(_, route)| *route);
+
+			let route = find(method).or_else(|| match method {
+				http::Method::Head => find(&http::Method::Get),
+				_ => None
+			});
+
+			if route.is_none() {
+				allowed.extend(routes.iter().filter_map(|(v, _)| v.clone()));
+			}
+
+			route
+		}
+	}
+
+	/// Decodes `%XX` escapes and, if `plus_as_space` is set, `+` as space. Returns `None` if the
+	/// result is not valid UTF-8 or an escape is incomplete or not hex.
+	pub fn percent_decode(v: &str, plus_as_space: bool) -> Option<Cow<'_, str>> {
+		if !(v.contains('%') || plus_as_space && v.contains('+')) {
+			return Some(Cow::Borrowed(v));
+		}
+
+		let mut buf = Vec::with_capacity(v.len());
+		let mut bytes = v.bytes();
+
+		while let Some(b) = bytes.next() {
+			buf.push(match b {
+				b'%' => {
+					let (hi, lo) = (bytes.next()?, bytes.next()?);
+					(hex_digit(hi)? << 4) | hex_digit(lo)?
+				}
+				b'+' if plus_as_space => b' ',
+				b => b
+			});
+		}
+
+		String::from_utf8(buf).ok().map(Cow::Owned)
+	}
+
+	fn hex_digit(b: u8) -> Option<u8> {
+		(b as char).to_digit(16).map(|v| v as u8)
+	}
+
+	/// Splits and decodes a query string, e.g. `a=1&b=x%20y`.
+	pub fn parse_query(query: &str) -> Result<HashMap<Cow<'_, str>, Cow<'_, str>>> {
+		query.split('&')
+			.filter(|v| !v.is_empty())
+			.map(|v| {
+				let (key, value) = v.split_once('=').unwrap_or((v, ""));
+				percent_decode(key, true).zip(percent_decode(value, true))
+					.ok_or_else(|| HttpError::bad_request("invalid query string").into())
+			})
+			.collect()
+	}
+
+	/// Parses a path or query parameter.
+	pub fn parse_param<T: FromStr>(v: &str, name: &str) -> Result<T> {
+		v.parse().map_err(|_| HttpError::bad_request(format!("invalid value of parameter `{}`", name)).into())
+	}
+
+	/// Reads the body of a request.
+	pub async fn read_body(stream: &mut dyn http::traits::AsyncStream) -> Result<Vec<u8>> {
+		let mut buf = Vec::with_capacity(0x1000);
+		smol::io::AsyncReadExt::read_to_end(stream, &mut buf).await?;
+		Ok(buf)
+	}
+
+	/// Reads and deserializes a JSON body.
+	pub async fn read_json<T: DeserializeOwned>(stream: &mut dyn http::traits::AsyncStream) -> Result<T> {
+		serde_json::from_slice(&read_body(stream).await?)
+			.map_err(|e| HttpError::bad_request(format!("invalid JSON body: {}", e)).into())
+	}
+
+	/// Reads and deserializes a `application/x-www-form-urlencoded` body.
+	pub async fn read_form<T: DeserializeOwned>(stream: &mut dyn http::traits::AsyncStream) -> Result<T> {
+		serde_urlencoded::from_bytes(&read_body(stream).await?)
+			.map_err(|e| HttpError::bad_request(format!("invalid form body: {}", e)).into())
+	}
+
+	/// The responses of a handler in an OpenAPI document, see [`OpenApi`].
+	pub trait DescribeResponse {
+		/// The status of the response in the document, `default` if it is only known at runtime.
+		fn status() -> &'static str {
+			"200"
+		}
+
+		/// The media type and schema of the body.
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)>;
+	}
+
+	impl DescribeResponse for () {
+		fn status() -> &'static str {
+			"default"
+		}
+
+		fn content(_gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			None
+		}
+	}
+
+	impl DescribeResponse for Response {
+		fn status() -> &'static str {
+			"default"
+		}
+
+		fn content(_gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			None
+		}
+	}
+
+	impl DescribeResponse for http::Status {
+		fn status() -> &'static str {
+			"default"
+		}
+
+		fn content(_gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			None
+		}
+	}
+
+	impl DescribeResponse for String {
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			Some(("text/plain", gen.subschema_for::<String>()))
+		}
+	}
+
+	impl DescribeResponse for &'static str {
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			String::content(gen)
+		}
+	}
+
+	impl DescribeResponse for Vec<u8> {
+		fn content(_gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			Some(("application/octet-stream", Schema::Bool(true)))
+		}
+	}
+
+	impl<T: JsonSchema> DescribeResponse for Json<T> {
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			Some(("application/json", gen.subschema_for::<T>()))
+		}
+	}
+
+	impl<T: DescribeResponse> DescribeResponse for (http::Status, T) {
+		fn status() -> &'static str {
+			"default"
+		}
+
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			T::content(gen)
+		}
+	}
+
+	impl<T: DescribeResponse> DescribeResponse for (http::Status, Vec<http::Header>, T) {
+		fn status() -> &'static str {
+			"default"
+		}
+
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			T::content(gen)
+		}
+	}
+
+	impl<T: DescribeResponse, E> DescribeResponse for std::result::Result<T, E> {
+		fn status() -> &'static str {
+			T::status()
+		}
+
+		fn content(gen: &mut SchemaGenerator) -> Option<(&'static str, Schema)> {
+			T::content(gen)
+		}
+	}
+
+	/// An OpenAPI 3.1 document, built by the `openapi()` function generated by the `controller`
+	/// macro.
+	pub struct OpenApi {
+		gen:   SchemaGenerator,
+		paths: serde_json::Map<String, serde_json::Value>
+	}
+
+	impl OpenApi {
+		pub fn new() -> Self {
+			let gen = schemars::gen::SchemaSettings::draft2019_09()
+				.with(|settings| {
+					settings.definitions_path = "#/components/schemas/".to_string();
+					settings.meta_schema = None;
+				})
+				.into_generator();
+
+			Self { gen, paths: serde_json::Map::new() }
+		}
+
+		/// A parameter `location` is one of `path`, `query` or `header`.
+		pub fn parameter<T: JsonSchema>(&mut self, name: &str, location: &str, required: bool) -> serde_json::Value {
+			serde_json::json!({
+				"name":     name,
+				"in":       location,
+				"required": required,
+				"schema":   self.gen.subschema_for::<T>()
+			})
+		}
+
+		pub fn request_body<T: JsonSchema>(&mut self, media_type: &str) -> serde_json::Value {
+			serde_json::json!({
+				"required": true,
+				"content":  { media_type: { "schema": self.gen.subschema_for::<T>() } }
+			})
+		}
+
+		pub fn responses<T: DescribeResponse>(&mut self) -> serde_json::Value {
+			let mut response = serde_json::json!({ "description": "" });
+
+			if let Some((media_type, schema)) = T::content(&mut self.gen) {
+				response["content"] = serde_json::json!({ media_type: { "schema": schema } });
+			}
+
+			serde_json::json!({ T::status(): response })
+		}
+
+		/// Adds an operation, e.g. `get` on `/items/{id}`.
+		pub fn operation(&mut self, method: &str, path: &str, operation: serde_json::Value) {
+			match self.paths.entry(path).or_insert_with(|| serde_json::json!({})) {
+				serde_json::Value::Object(v) => { v.insert(method.to_string(), operation); }
+				_ => unreachable!()
+			}
+		}
+
+		pub fn finish(self, title: &str, version: &str) -> serde_json::Value {
+			serde_json::json!({
+				"openapi":    "3.1.0",
+				"info":       { "title": title, "version": version },
+				"paths":      self.paths,
+				"components": { "schemas": self.gen.definitions() }
+			})
+		}
+	}
+
+	impl Default for OpenApi {
+		fn default() -> Self {
+			Self::new()
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+
+		type Match = (usize, Vec<String>, Vec<String>);
+
+		fn routes() -> Routes {
+			let mut routes = Routes::default();
+			routes.insert(Some(http::Method::Get), Some("/items"), 0);
+			routes.insert(Some(http::Method::Post), Some("/items"), 1);
+			routes.insert(Some(http::Method::Get), Some("/items/{id}"), 2);
+			routes.insert(Some(http::Method::Get), Some("/items/new"), 3);
+			routes.insert(Some(http::Method::Delete), Some("/items/{id}"), 4);
+			routes.insert(Some(http::Method::Get), Some("/items/{id}/{rest...}"), 5);
+			routes.insert(None, Some("/files/{rest...}"), 6);
+			routes.insert(None, Some("/any"), 7);
+			routes
+		}
+
+		fn find(routes: &Routes, method: http::Method, path: &str) -> std::result::Result<Match, Response> {
+			let path = path.split('/').collect::<Vec<_>>();
+			let to_vec = |v: &[&str]| v.iter().map(ToString::to_string).collect::<Vec<_>>();
+			routes.find(&method, &path).map(|(route, params, rest)| (route, to_vec(&params), to_vec(rest)))
+		}
+
+		fn matched(route: usize, params: &[&str], rest: &[&str]) -> Match {
+			(route, params.iter().map(ToString::to_string).collect(), rest.iter().map(ToString::to_string).collect())
+		}
+
+		#[test]
+		fn precedence() {
+			let routes = routes();
+			assert_eq!(find(&routes, http::Method::Get, "/items/new").unwrap(), matched(3, &[], &[]));
+			assert_eq!(find(&routes, http::Method::Get, "/items/42").unwrap(), matched(2, &["42"], &[]));
+			assert_eq!(find(&routes, http::Method::Delete, "/items/new").unwrap(), matched(4, &["new"], &[]));
+			assert_eq!(find(&routes, http::Method::Get, "/items/42/a/b").unwrap(), matched(5, &["42"], &["a", "b"]));
+			assert_eq!(find(&routes, http::Method::Put, "/files/a/b").unwrap(), matched(6, &[], &["a", "b"]));
+			assert_eq!(find(&routes, http::Method::Get, "/files").unwrap(), matched(6, &[], &[]));
+			assert_eq!(find(&routes, http::Method::Patch, "/any").unwrap(), matched(7, &[], &[]));
+
+			let mut fallback = Routes::default();
+			fallback.insert(Some(http::Method::Get), Some("/items"), 0);
+			fallback.insert(None, None, 1);
+			assert_eq!(find(&fallback, http::Method::Get, "/items").unwrap(), matched(0, &[], &[]));
+			assert_eq!(find(&fallback, http::Method::Get, "/other/x").unwrap(), matched(1, &[], &["", "other", "x"]));
+		}
+
+		#[test]
+		fn not_found() {
+			let routes = routes();
+
+			for path in ["/", "/other", "/any/x"] {
+				let response = find(&routes, http::Method::Get, path).unwrap_err();
+				assert_eq!(response.status, http::Status::NotFound, "{}", path);
+				assert!(response.headers.is_empty());
+			}
+		}
+
+		#[test]
+		fn method_not_allowed() {
+			let routes = routes();
+
+			let response = find(&routes, http::Method::Put, "/items").unwrap_err();
+			assert_eq!(response.status, http::Status::MethodNotAllowed);
+			assert_eq!(response.headers, vec![http::Header::Allow(vec![
+				http::Method::Options, http::Method::Get, http::Method::Head, http::Method::Post
+			])]);
+
+			// the literal route only allows `GET`, the parameter route also `DELETE`
+			let response = find(&routes, http::Method::Put, "/items/new").unwrap_err();
+			assert_eq!(response.status, http::Status::MethodNotAllowed);
+			assert_eq!(response.headers, vec![http::Header::Allow(vec![
+				http::Method::Options, http::Method::Get, http::Method::Head, http::Method::Delete
+			])]);
+		}
+
+		#[test]
+		fn head_and_options() {
+			let routes = routes();
+			assert_eq!(find(&routes, http::Method::Head, "/items").unwrap(), matched(0, &[], &[]));
+			assert_eq!(find(&routes, http::Method::Head, "/items/42").unwrap(), matched(2, &["42"], &[]));
+
+			let response = find(&routes, http::Method::Options, "/items").unwrap_err();
+			assert_eq!(response.status, http::Status::NoContent);
+			assert_eq!(response.headers, vec![http::Header::Allow(vec![
+				http::Method::Options, http::Method::Get, http::Method::Head, http::Method::Post
+			])]);
+
+			// `HEAD` is only allowed with a `GET` route
+			let response = find(&routes, http::Method::Head, "/items/42/x").unwrap();
+			assert_eq!(response, matched(5, &["42"], &["x"]));
+
+			let mut post_only = Routes::default();
+			post_only.insert(Some(http::Method::Post), Some("/items"), 0);
+			let response = find(&post_only, http::Method::Head, "/items").unwrap_err();
+			assert_eq!(response.status, http::Status::MethodNotAllowed);
+			assert_eq!(response.headers, vec![http::Header::Allow(vec![http::Method::Options, http::Method::Post])]);
+		}
+
+		#[test]
+		fn decode() {
+			assert_eq!(percent_decode("a%20b%2Fc", false).as_deref(), Some("a b/c"));
+			assert_eq!(percent_decode("%e2%82%AC", false).as_deref(), Some("\u{20AC}"));
+			assert_eq!(percent_decode("a+b%2B", true).as_deref(), Some("a b+"));
+			assert_eq!(percent_decode("a+b", false).as_deref(), Some("a+b"));
+			assert!(matches!(percent_decode("plain", true), Some(Cow::Borrowed("plain"))));
+
+			// incomplete escapes and signs or other characters that are not hex digits
+			for v in ["%", "a%2", "%+1", "%-1", "%1+", "%zz", "% 1"] {
+				assert_eq!(percent_decode(v, false), None, "{}", v);
+			}
+
+			// not valid UTF-8
+			assert_eq!(percent_decode("%FF", false), None);
+			assert_eq!(percent_decode("%C3%28", true), None);
+		}
+
+		#[test]
+		fn query() {
+			let query = parse_query("a=1&b=x%20y&c=1+2&&d").unwrap();
+			assert_eq!(query.len(), 4);
+			assert_eq!(query["a"], "1");
+			assert_eq!(query["b"], "x y");
+			assert_eq!(query["c"], "1 2");
+			assert_eq!(query["d"], "");
+
+			let e = parse_query("a=%+1").unwrap_err();
+			assert_eq!(e.downcast_ref::<HttpError>().map(|e| e.status), Some(http::Status::BadRequest));
+			assert!(parse_query("a%FF=1").is_err());
+		}
+	}
+}
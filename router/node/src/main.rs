@@ -34,26 +34,19 @@ use global::watcher;
 
 pub use kranus_router_node::*;
 
+mod check;
+mod config;
 mod ctx;
 mod global;
 mod builtins;
 mod utils;
 
-type ModuleInitFn = for<'a> extern "Rust" fn(
-	log: &'static dyn log::Log,
-	lvl: log::LevelFilter,
-	ctx: Arc<dyn Context>,
-	trt: otel_mrt::Runtime,
-	cfg: &'a mut (dyn erased_serde::Deserializer + Send + Sync)
-) -> DynFuture<'a, Result<()>>;
-
 const ENV_CONFIG:          &str = "KRANUS_ROUTER_CONFIG";
 const ENV_WORKING_DIR:     &str = "KRANUS_ROUTER_WORKING_DIR";
 const ENV_LOG_LEVEL:       &str = "KRANUS_ROUTER_LOG_LEVEL";
+const ENV_WORKER_THREADS: &str = "LOCALFIX";
 const DEFAULT_CONFIG_PATH: &str = "./config.toml";
 const CFG_INCLUDE_KEY:     &str = "include";
-const MODULE_INFO_CONST:   &str = "NET_SERVICES_PLUGIN_INFO\0";
-const MODULE_INIT_FN:      &str = "net_services_plugin_init\0";
 const HEADER_SERVER:       &str = "net-services";
 const BANNER:              &str = r#"
              _                              _
@@ -74,6 +67,7 @@ Options:
  -c, --config, --include <config file|module file|dir>
  -a, --abort
  -d, --dry-run, --check
+ --print-schema
 
 Environment Varaibles:
  KRANUS_ROUTER_WORKER_THREADS
@@ -113,12 +107,16 @@ fn main() {
 	let threads = std::env::var(ENV_WORKER_THREADS).ok().and_then(|v| v.parse().ok());
 	log::set_max_level(log::LevelFilter::Debug);
 	log::set_logger(stdout_log::get()).unwrap();
+	global::upgrade::block_signal();
+	global::reload::block_signal();
 	async_executor::run(threads, run);
 }
 
 async fn run() {
 	log::info!("init: commencing initialization sequence ...");
 	let t = std::time::Instant::now();
+	// new processes started by an upgrade resolve the same relative paths
+	let initial_dir = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
 
     // apply ENV_WORKING_DIR
 
@@ -165,9 +163,12 @@ async fn run() {
 	let mut errors_total = 0;
 	let mut includes     = vec![root_cfg];
 	let mut merged_cfg   = HashMap::new();
+	let mut loaded_cfgs  = Vec::new();
 	let mut module_paths = Vec::new();
 	let mut modules      = HashMap::new();
+	let mut components   = Vec::new();
 	let mut files        = HashMap::new();
+	let mut origins      = check::ConfigOrigins::default();
 	let (tx, rx)         = mpsc::channel();
 	#[cfg(feature = "hot-reload")]
 	let mut watcher = match notify::watcher(tx, watcher::DEFAULT_WATCHER_DELAY) {
@@ -208,6 +209,10 @@ async fn run() {
 				dry_run = true;
 				continue;
 			}
+			Some("-print-schema") => {
+				check::print_schema();
+				std::process::exit(0);
+			}
 			Some(v) => {
 				log::warn!("global: invalid argument: `{}`", v);
 				continue;
@@ -260,121 +265,39 @@ async fn run() {
 		}
 	}
 
+	origins.record("", &merged_cfg, "argument");
+
     // parse config
 
-	while let Some((path, reference)) = includes.pop() {
-		let metadata = match std::fs::metadata(&path) {
-			Ok(v) => v,
-			Err(e) => {
-				log::error!("init: failed to read `{}` (referenced by {}): {}", path, reference, e);
-				errors += 1;
-				continue;
-			}
-		};
+	let sources = config::load(includes.clone());
+	errors += sources.errors;
 
-		#[cfg(feature = "hot-reload")]
-		if let Err(e) = watcher.watch(&path, RecursiveMode::Recursive) {
+	#[cfg(feature = "hot-reload")]
+	for (path, reference) in &sources.paths {
+		if let Err(e) = watcher.watch(path, RecursiveMode::Recursive) {
 			log::error!("init: failed to watch `{}` (referenced by {}): {}", path, reference, e);
 			errors += 1;
-			continue;
 		}
+	}
 
-		if metadata.is_dir() {
-			let dir = match std::fs::read_dir(&path) {
-				Ok(v)  => v,
-				Err(e) => {
-					log::error!("init: failed to read directory `{}` (referenced by {}): {}", path, reference, e);
-					errors += 1;
-					continue;
-				}
-			};
-
-			for entry in dir {
-				let entry = match entry {
-					Ok(v)  => v,
-					Err(e) => {
-						log::error!("init: failed to read directory `{}` (referenced by {}): {}", path, reference, e);
-						errors += 1;
-						continue;
-					}
-				};
-
-				if let Some(path) = entry.path().to_str() {
-					includes.push((path.to_string(), reference.clone()));
-				}
-			}
-
-			continue;
-		}
-
-		let (_, extension) = path.rsplit_once('.').unwrap_or(("", ""));
-
-		match extension {
-			"so" | "dll" => {
-				module_paths.push((PluginPath::SharedLib(path), reference));
-				continue;
-			}
-			"wasm" => {
-				module_paths.push((PluginPath::Wasm(path), reference));
-				continue;
-			}
-			_ => ()
-		}
-
-		let cfg = match std::fs::read_to_string(&path) {
-			Ok(v)  => v,
-			Err(e) => {
-				log::error!("init: failed to read config `{}` (referenced by {}): {}", path, reference, e);
-				errors += 1;
-				continue;
-			}
-		};
-
-		let cfg = match extension {
-			"yml" | "yaml" => serde_yaml::from_str::<HashMap<String, serde_dyn_repr::Value>>(&cfg)
-				.map_err(|v| v.to_string()),
-			_ => toml::from_str::<HashMap<String, serde_dyn_repr::Value>>(&cfg)
-				.map_err(|v| v.to_string())
-		};
-
-		let cfg = match cfg {
-			Ok(v) => v,
-			Err(e) => {
-				log::error!("init: failed to parse config `{}` (referenced by {}): {}", path, reference, e);
-				errors += 1;
-				continue;
-			}
-		};
-
-		let mut wrapped_cfg = serde_dyn_repr::Value::Map(cfg);
-
-		if let Err(e) = substitute_env_vars(&mut wrapped_cfg) {
-			log::error!("init: failed to substitute environment variables in config `{}` (referenced by {}): {}", path, reference, e);
-			errors += 1;
-			continue;
-		}
+	for (path, reference) in sources.modules {
+		module_paths.push((match path.ends_with(".wasm") {
+			true  => PluginPath::Wasm(path),
+			false => PluginPath::SharedLib(path)
+		}, reference));
+	}
 
-		let cfg = match wrapped_cfg {
-			serde_dyn_repr::Value::Map(v) => v,
-			_ => unreachable!()
-		};
+	for (path, cfg) in sources.configs {
+		loaded += 1;
+		origins.record("", &cfg, &path);
+		loaded_cfgs.push(cfg);
+	}
 
-		if let Some(serde_dyn_repr::Value::Seq(seq)) = cfg.get(CFG_INCLUDE_KEY) {
-			for include in seq {
-				match include {
-					serde_dyn_repr::Value::String(include) => includes.push((include.clone(), format!("`{}`", path))),
-					_ => {
-						log::error!("init: failed to parse config `{}` (referenced by {}): `include` must be an array of strings", path, reference);
-						errors += 1;
-						continue;
-					}
-				}
-			}
-		}
+	let arguments = merged_cfg.clone();
 
-		loaded += 1;
-		merge_maps(&mut merged_cfg, cfg);
-		log::info!("init: loaded config `{}` (referenced by {})", path, reference);
+	if let Err(e) = config::merge_all(&mut merged_cfg, loaded_cfgs) {
+		log::error!("init: failed to merge configs: {}", e);
+		errors += 1;
 	}
 
 	let cfg = merged_cfg;
@@ -398,8 +321,20 @@ async fn run() {
 					}
 				};
 
+				let info = match unsafe { kranus_router_node::plugin_info(&lib) } {
+					Ok(v)  => {
+						components.extend(v.components().map(str::to_string));
+						v.to_string()
+					}
+					Err(e) => {
+						log::error!("init: refusing to load module `{}` (referenced by {}): {}", path, reference, e.display());
+						errors += 1;
+						continue;
+					}
+				};
+
 				loaded += 1;
-				log::info!("init: loaded module `{}` (referenced by {})", path, reference);
+				log::info!("init: loaded module `{}` (referenced by {}): {}", path, reference, info);
 				modules.insert(path, (reference, Arc::new(Plugin::SharedLib(lib))));
 			}
 			#[cfg(feature = "wasm-runtime")]
@@ -436,44 +371,69 @@ async fn run() {
 
 	log::info!("init: loaded {} plugins (skipped {} due to errors)", loaded, errors);
 
-	let changes_file = global::init(&mut <dyn erased_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(cfg.clone()))).await;
-	let ctx = Arc::new(ctx::ContextWrapper::new(cfg.clone(), files, changes_file));
-	log::info!("init: initialized global context");
+	errors_total += errors;
 
 	if dry_run {
+		let plugins = modules.iter()
+			.filter_map(|(path, (_, module))| match module.as_ref() {
+				Plugin::SharedLib(lib) => Some((path.as_str(), lib)),
+				#[cfg(feature = "wasm-runtime")]
+				Plugin::Wasm(_) => None
+			})
+			.collect::<Vec<_>>();
+
+		errors_total += check::run(&cfg, &origins, &plugins, &components);
+
+		if errors_total > 0 {
+			log::error!("init: dry run failed with {} errors", errors_total);
+			std::process::exit(1);
+		}
+
 		log::info!("init: dry run flag set, exiting ...");
 		std::process::exit(0);
 	}
 
-	errors_total += errors;
+	let mut handoff = match global::upgrade::Handoff::from_env() {
+		Ok(v) => v,
+		Err(e) => {
+			log::error!("init: failed to take over from previous process: {}", e.display());
+			std::process::exit(1);
+		}
+	};
+
+	let (changes_file, upgrade_cfg) = global::init(&mut <dyn erased_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(cfg.clone()))).await;
+	let ctx = Arc::new(ctx::ContextWrapper::new(cfg.clone(), files, changes_file, includes, arguments));
+	log::info!("init: initialized global context");
+
 	let (loaded, errors) = (AtomicUsize::new(0), AtomicUsize::new(0));
 	kranus_router_node::set_context(ctx.clone());
 
+	if let Some(handoff) = &mut handoff {
+		match handoff.receive() {
+			Ok(n) => log::info!("init: received {} listening sockets from previous process", n),
+			Err(e) => {
+				log::error!("init: failed to take over from previous process: {}", e.display());
+				std::process::exit(1);
+			}
+		}
+	}
+
 	smol::stream::StreamExt::for_each(net::utils::zip(modules.into_iter()
-		.map(|(path, (reference, module))| Box::pin(async {
+		.map(|(path, (reference, module))| -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + '_>> { Box::pin(async {
 			let (path, reference, module) = (path, reference, module);
 			(match module.as_ref() {
 				Plugin::SharedLib(lib) => {
-					let info = match unsafe { lib.get::<&'static &'static str>(MODULE_INFO_CONST.as_bytes()) } {
-						Ok(v)  => *v,
-						Err(e) => {
-							errors.fetch_add(1, Ordering::SeqCst);
-							log::error!("init: failed to initialize plugin `{}` (referenced by {}): {}", path, reference, e);
-							return;
-						}
-					};
-
-					let init_fn = match unsafe { lib.get::<ModuleInitFn>(MODULE_INIT_FN.as_bytes()) } {
+					let init_fn = match unsafe { kranus_router_node::plugin_init_fn(lib) } {
 						Ok(v)  => v,
 						Err(e) => {
 							errors.fetch_add(1, Ordering::SeqCst);
-							log::error!("init: failed to initialize plugin `{}` (referenced by {}): {}", path, reference, e);
+							log::error!("init: failed to initialize plugin `{}` (referenced by {}): {}", path, reference, e.display());
 							return;
 						}
 					};
 
 					// TODO if e.is::<Vec<Error>>(), count errors
-					if let Err(e) = (init_fn)(log::logger(), log::max_level(), ctx.clone(), otel_rt.clone(),
+					if let Err(e) = (init_fn)(log::logger(), log::max_level(), ctx.clone(), otel_mrt::runtime(),
 											  &mut <dyn erased_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(cfg.clone()))).await {
 						errors.fetch_add(1, Ordering::SeqCst);
 						log::error!("init: failed to initialize plugin `{}` (referenced by {}): {}", path, reference, e.display());
@@ -488,9 +448,9 @@ async fn run() {
 					//instance.call(MODULE_INIT_FN, &[]);
 					todo!()
 				}
-			}) as std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>
-		}))
-		.chain(std::iter::once_with(|| Box::pin(async {
+			})
+		})})
+		.chain(std::iter::once_with(|| -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + '_>> { Box::pin(async {
 			if let Err(e) = builtins::run(&mut <dyn erased_serde::Deserializer>::erase(serde_dyn_repr::Value::Map(cfg.clone()))).await {
 				log::error!("init: failed to initialize plugin `builtins`: {:?}", e);
 				errors.fetch_add(1, Ordering::SeqCst);
@@ -498,11 +458,13 @@ async fn run() {
 				log::info!("init: initialized plugin `builtins`");
 				loaded.fetch_add(1, Ordering::SeqCst);
 			}
-		})))), |_| ()).await;
+		})}))), |_| ()).await;
 
 	errors_total += errors.load(Ordering::SeqCst);
 	log::info!("init: initialized {} plugins (skipped {} due to errors)", loaded.load(Ordering::SeqCst), errors.load(Ordering::SeqCst));
 
+	errors_total += ctx.start_components().await;
+
 	if abort && errors_total > 0 {
 		log::error!("init: {} errors during initialization, shutting down", errors_total);
 		std::process::exit(1);
@@ -510,8 +472,32 @@ async fn run() {
 
 	log::info!("init: initialization completed with {} errors, server online ({:.3}s)", errors_total, t.elapsed().as_secs_f32());
 
+	if let Some(handoff) = handoff {
+		match handoff.ready() {
+			Ok(()) => log::info!("init: took over from previous process"),
+			Err(e) => {
+				log::error!("init: failed to take over from previous process: {}", e.display());
+				std::process::exit(1);
+			}
+		}
+	}
+
 	std::mem::drop(args);
 
+	let upgrade_ctx = ctx.clone();
+	if let Err(e) = std::thread::Builder::new()
+		.name("upgrade".to_string())
+		.spawn(move || global::upgrade::run(upgrade_ctx, upgrade_cfg, initial_dir)) {
+		log::error!("upgrade: failed to spawn thread: {}", e);
+	}
+
+	let reload_ctx = ctx.clone();
+	if let Err(e) = std::thread::Builder::new()
+		.name("reload".to_string())
+		.spawn(move || global::reload::run(reload_ctx)) {
+		log::error!("reload: failed to spawn thread: {}", e);
+	}
+
 	#[cfg(feature = "hot-reload")]
 	if let Err(e) = std::thread::Builder::new()
 		.name("watcher-main".to_string())
@@ -530,54 +516,3 @@ enum Plugin {
 	#[cfg(feature = "wasm-runtime")]
 	Wasm(wasmer_runtime::Instance)
 }
-
-fn substitute_env_vars(val: &mut serde_dyn_repr::Value) -> std::result::Result<(), std::env::VarError> {
-	use serde_dyn_repr::Value::*;
-
-	match val {
-		String(str) => {
-			let mut i = 0;
-			while let Some(start) = str[i..].find("${") {
-
-				let end = match str[start + 2..].find('}') {
-					Some(v) => v,
-					None => break
-				};
-
-				let env_var = std::env::var(&str[start + 2..end])?;
-
-				str.drain(start..=end);
-				str.insert_str(start, &env_var);
-				i = start + env_var.len();
-			}
-
-			Ok(())
-		}
-		Seq(seq)   => seq.iter_mut().try_for_each(substitute_env_vars),
-		Map(map)   => map.iter_mut().try_for_each(|(_, v)| substitute_env_vars(v)),
-		Enum(_, ref mut v) => substitute_env_vars(v),
-		_          => Ok(())
-	}
-}
-
-fn merge_maps(dst: &mut HashMap<String, serde_dyn_repr::Value>, src: HashMap<String, serde_dyn_repr::Value>) {
-	for (key, val) in src {
-		let mut dst = match dst.entry(key) {
-			std::collections::hash_map::Entry::Occupied(v) => v,
-			std::collections::hash_map::Entry::Vacant(v)   => {
-				v.insert(val);
-				continue;
-			}
-		};
-
-		match (dst.get_mut(), val) {
-			(serde_dyn_repr::Value::Map(dst), serde_dyn_repr::Value::Map(src)) => merge_maps(dst, src),
-			(serde_dyn_repr::Value::Seq(dst), serde_dyn_repr::Value::Seq(src)) => merge_seqs(dst, src),
-			(dst, src) => *dst = src
-		}
-	}
-}
-
-fn merge_seqs(dst: &mut Vec<serde_dyn_repr::Value>, src: Vec<serde_dyn_repr::Value>) {
-	*dst = src; // TODO
-}
\ No newline at end of file
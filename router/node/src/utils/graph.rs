@@ -0,0 +1,146 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GraphError {
+	Unknown { node: String, dependency: String },
+	Cycle(Vec<String>)
+}
+
+impl std::fmt::Display for GraphError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Unknown { node, dependency } => write!(f, "component `{}` references unknown component `{}`", node, dependency),
+			Self::Cycle(nodes) => write!(f, "dependency cycle: `{}`", nodes.join("` -> `"))
+		}
+	}
+}
+
+/// Sorts the nodes of a dependency graph, so that every node comes after all of its dependencies.
+/// Dependencies that are not a node of the graph are only accepted if `known` returns true.
+pub fn toposort(
+	graph: &BTreeMap<String, Vec<String>>,
+	known: impl Fn(&str) -> bool
+) -> Result<Vec<&str>, Vec<GraphError>> {
+	#[derive(Copy, Clone, Eq, PartialEq)]
+	enum State { Visiting, Done }
+
+	fn visit<'a>(
+		graph:  &'a BTreeMap<String, Vec<String>>,
+		known:  &impl Fn(&str) -> bool,
+		node:   &'a str,
+		states: &mut BTreeMap<&'a str, State>,
+		stack:  &mut Vec<&'a str>,
+		order:  &mut Vec<&'a str>,
+		errors: &mut Vec<GraphError>
+	) {
+		match states.get(node) {
+			Some(State::Done) => return,
+			Some(State::Visiting) => {
+				let i = stack.iter().position(|v| *v == node).unwrap_or(0);
+				errors.push(GraphError::Cycle(stack[i..].iter()
+					.chain(std::iter::once(&node))
+					.map(|v| v.to_string())
+					.collect()));
+				return;
+			}
+			None => ()
+		}
+
+		states.insert(node, State::Visiting);
+		stack.push(node);
+
+		for dependency in &graph[node] {
+			match graph.get_key_value(dependency) {
+				Some((dependency, _)) => visit(graph, known, dependency, states, stack, order, errors),
+				None if known(dependency) => (),
+				None => errors.push(GraphError::Unknown { node: node.to_string(), dependency: dependency.clone() })
+			}
+		}
+
+		stack.pop();
+		states.insert(node, State::Done);
+		order.push(node);
+	}
+
+	let (mut states, mut stack, mut order, mut errors) = (BTreeMap::new(), Vec::new(), Vec::new(), Vec::new());
+
+	for node in graph.keys() {
+		visit(graph, &known, node, &mut states, &mut stack, &mut order, &mut errors);
+	}
+
+	if errors.is_empty() {
+		Ok(order)
+	} else {
+		Err(errors)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn graph(nodes: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+		nodes.iter()
+			.map(|(node, deps)| (node.to_string(), deps.iter().map(|v| v.to_string()).collect()))
+			.collect()
+	}
+	
+	#[test]
+	fn order() {
+		let g = graph(&[("a", &["b", "c"]), ("b", &["c"]), ("c", &[]), ("d", &[])]);
+		assert_eq!(toposort(&g, |_| false), Ok(vec!["c", "b", "a", "d"]));
+		
+		let g = graph(&[("a", &[]), ("b", &["a"]), ("c", &["b", "a"])]);
+		assert_eq!(toposort(&g, |_| false), Ok(vec!["a", "b", "c"]));
+		
+		assert_eq!(toposort(&BTreeMap::new(), |_| false), Ok(Vec::new()));
+	}
+	
+	#[test]
+	fn cycle() {
+		let g = graph(&[("a", &["b"]), ("b", &["a"])]);
+		assert_eq!(toposort(&g, |_| true), Err(vec![GraphError::Cycle(vec![
+			"a".to_string(), "b".to_string(), "a".to_string()])]));
+		
+		let g = graph(&[("a", &["a"])]);
+		assert_eq!(toposort(&g, |_| true), Err(vec![GraphError::Cycle(vec![
+			"a".to_string(), "a".to_string()])]));
+		
+		// the cycle is reported once, without the node leading into it
+		let g = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["b"])]);
+		assert_eq!(toposort(&g, |_| true), Err(vec![GraphError::Cycle(vec![
+			"b".to_string(), "c".to_string(), "b".to_string()])]));
+	}
+	
+	#[test]
+	fn unknown() {
+		let g = graph(&[("a", &["b", "plugin"]), ("b", &["missing"])]);
+		assert_eq!(toposort(&g, |v| v == "plugin"), Err(vec![
+			GraphError::Unknown { node: "b".to_string(), dependency: "missing".to_string() }
+		]));
+		assert_eq!(toposort(&g, |_| true), Ok(vec!["b", "a"]));
+		assert_eq!(toposort(&g, |_| false).unwrap_err().len(), 2);
+	}
+}
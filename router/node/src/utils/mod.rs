@@ -20,12 +20,14 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use {crate::HEADER_SERVER, net::http};
+use {crate::HEADER_SERVER, net::http, smol::io::AsyncReadExt};
 
+pub mod graph;
 pub mod trie;
 pub mod serde;
+pub mod stream;
 
-pub use {trie::*, self::serde::*};
+pub use {graph::*, trie::*, self::serde::*, stream::*};
 
 pub async fn send_response(stream: &mut dyn http::traits::AsyncStream, code: http::Status) -> dyn_error::Result<()> {
 	http::MessageBuilder::new()
@@ -34,4 +36,31 @@ pub async fn send_response(stream: &mut dyn http::traits::AsyncStream, code: htt
 		.content_length(0)
 		.send_async(stream)
 		.await.map_err(Into::into)
-}
\ No newline at end of file
+}
+
+pub async fn discard_body(stream: &mut dyn http::traits::AsyncStream) -> std::io::Result<()> {
+	let mut buf = Vec::new();
+	stream.read_to_end(&mut buf).await?;
+	Ok(())
+}
+
+/// Starts a span that continues the trace propagated in the request `headers`.
+pub fn start_span(headers: &[http::Header], params: otel_mrt::SpanParams) -> otel_mrt::tracing::Span {
+	let parent = otel_mrt::SpanContext::extract(headers);
+	otel_mrt::runtime().span(params.parent(parent.as_ref()))
+}
+
+/// Fails with [`std::io::ErrorKind::TimedOut`] if `f` does not complete within `duration`, if any.
+pub async fn timeout<T, E: From<std::io::Error>>(duration: Option<std::time::Duration>, f: impl std::future::Future<Output = Result<T, E>>) -> Result<T, E> {
+	match duration {
+		Some(duration) => smol::future::or(f, async {
+			smol::Timer::after(duration).await;
+			Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+		}).await,
+		None => f.await
+	}
+}
+
+pub fn is_timeout(e: &dyn_error::Error) -> bool {
+	e.downcast_ref::<std::io::Error>().map_or(false, |e| e.kind() == std::io::ErrorKind::TimedOut)
+}
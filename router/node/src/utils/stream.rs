@@ -0,0 +1,365 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use {
+	net::http,
+	std::{io, pin::Pin, task::{Context, Poll}}
+};
+
+/// A stream that returns already read request headers, e.g. after they have been inspected or
+/// modified by a module, before passing the stream on to the next module.
+pub struct HeadersHttpStream<'a> {
+	inner:   &'a mut dyn http::traits::AsyncStream,
+	headers: Option<Vec<http::Header>>
+}
+
+impl<'a> HeadersHttpStream<'a> {
+	pub fn new(inner: &'a mut dyn http::traits::AsyncStream, headers: Vec<http::Header>) -> Self {
+		Self { inner, headers: Some(headers) }
+	}
+}
+
+impl<'a> smol::io::AsyncRead for HeadersHttpStream<'a> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read(cx, buf)
+	}
+	
+	fn poll_read_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &mut [io::IoSliceMut<'_>]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_read_vectored(cx, bufs)
+	}
+}
+
+impl<'a> smol::io::AsyncWrite for HeadersHttpStream<'a> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write(cx, buf)
+	}
+	
+	fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write_vectored(cx, bufs)
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_flush(cx)
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_close(cx)
+	}
+}
+
+impl<'a> http::traits::AsyncStream for HeadersHttpStream<'a> {
+	fn poll_read_headers(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		let Self { inner, headers } = unsafe { Pin::into_inner_unchecked(self) };
+		
+		match headers.take() {
+			Some(headers) => Poll::Ready(Ok(headers)),
+			None          => unsafe { Pin::new_unchecked(&mut **inner) }.poll_read_headers(cx)
+		}
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		unsafe { Pin::map_unchecked_mut(self, |v| v.inner) }.poll_write_headers(cx, headers)
+	}
+}
+
+/// A stream that reads a request from memory and records the response, for tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct TestHttpStream {
+	pub request_headers:  Option<Vec<http::Header>>,
+	pub request_body:     io::Cursor<Vec<u8>>,
+	pub response_headers: Vec<Vec<http::Header>>,
+	pub response_body:    Vec<u8>
+}
+
+#[cfg(test)]
+impl TestHttpStream {
+	pub fn new(headers: Vec<http::Header>, body: impl Into<Vec<u8>>) -> Self {
+		Self { request_headers: Some(headers), request_body: io::Cursor::new(body.into()), ..Self::default() }
+	}
+	
+	/// The headers of the final response.
+	pub fn response(&self) -> &[http::Header] {
+		self.response_headers.last().map_or(&[], Vec::as_slice)
+	}
+	
+	pub fn status(&self) -> Option<http::Status> {
+		self.response().iter().find_map(http::Header::as_status).copied()
+	}
+	
+	/// Passes the stream to `handler` and returns it, once the handler completed.
+	pub fn accept(mut self, handler: &dyn crate::interfaces::StreamHandler<dyn http::traits::AsyncStream>) -> (dyn_error::Result<()>, Self) {
+		// this is unsafe, but that's ok, see HttpStreamHandler::accept
+		let stream = unsafe { std::mem::transmute::<&mut TestHttpStream, &'static mut TestHttpStream>(&mut self) };
+		let result = smol::block_on(handler.accept(stream));
+		(result, self)
+	}
+}
+
+/// One end of an in-memory connection, for tests. Reads return EOF once the other end was closed
+/// or dropped.
+#[cfg(test)]
+pub struct TestDuplex {
+	read:  std::sync::Arc<std::sync::Mutex<TestPipe>>,
+	write: std::sync::Arc<std::sync::Mutex<TestPipe>>
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct TestPipe {
+	buf:    std::collections::VecDeque<u8>,
+	closed: bool,
+	waker:  Option<std::task::Waker>
+}
+
+#[cfg(test)]
+impl TestPipe {
+	fn close(&mut self) {
+		self.closed = true;
+		self.wake();
+	}
+	
+	fn wake(&mut self) {
+		if let Some(waker) = self.waker.take() {
+			waker.wake();
+		}
+	}
+}
+
+#[cfg(test)]
+impl TestDuplex {
+	pub fn pair() -> (Self, Self) {
+		let a = std::sync::Arc::new(std::sync::Mutex::new(TestPipe::default()));
+		let b = std::sync::Arc::new(std::sync::Mutex::new(TestPipe::default()));
+		(Self { read: a.clone(), write: b.clone() }, Self { read: b, write: a })
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestDuplex {
+	fn drop(&mut self) {
+		self.read.lock().unwrap().close();
+		self.write.lock().unwrap().close();
+	}
+}
+
+#[cfg(test)]
+impl smol::io::AsyncRead for TestDuplex {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let mut pipe = self.read.lock().unwrap();
+		
+		if pipe.buf.is_empty() && !pipe.closed {
+			pipe.waker = Some(cx.waker().clone());
+			return Poll::Pending;
+		}
+		
+		let len = buf.len().min(pipe.buf.len());
+		buf.iter_mut().zip(pipe.buf.drain(..len)).for_each(|(dst, src)| *dst = src);
+		Poll::Ready(Ok(len))
+	}
+}
+
+#[cfg(test)]
+impl smol::io::AsyncWrite for TestDuplex {
+	fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let mut pipe = self.write.lock().unwrap();
+		
+		if pipe.closed {
+			return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+		}
+		
+		pipe.buf.extend(buf);
+		pipe.wake();
+		Poll::Ready(Ok(buf.len()))
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		self.write.lock().unwrap().close();
+		Poll::Ready(Ok(()))
+	}
+}
+
+/// Reads up to and including the empty line after the headers, for tests.
+#[cfg(test)]
+pub async fn read_head(stream: &mut (impl smol::io::AsyncRead + Unpin)) -> String {
+	use smol::io::AsyncReadExt;
+	
+	let mut head = Vec::new();
+	let mut byte = [0u8];
+	
+	while !head.ends_with(b"\r\n\r\n") {
+		match stream.read(&mut byte).await {
+			Ok(1) => head.push(byte[0]),
+			_     => break
+		}
+	}
+	
+	String::from_utf8(head).unwrap()
+}
+
+/// Initializes the global telemetry runtime, which handlers record spans and metrics with.
+#[cfg(test)]
+pub fn test_telemetry() {
+	static INIT: std::sync::Once = std::sync::Once::new();
+	// the exporter is never run, nothing is sent anywhere
+	INIT.call_once(|| otel_mrt::init_global(otel_mrt::Config::disabled(), Some(Box::new(|_| ()))));
+}
+
+/// Passes the request on the server end of `stream` to `handler`, for tests.
+#[cfg(test)]
+pub async fn serve_http(handler: &dyn crate::interfaces::StreamHandler<dyn http::traits::AsyncStream>, stream: TestDuplex) -> dyn_error::Result<()> {
+	use {smol::io::AsyncWriteExt, http::traits::AsyncSharedConnectionExt};
+	
+	test_telemetry();
+	let conn = net::http::v1::AsyncSharedConnection::new(
+		net::http::v1::AsyncConnection::new(
+			net::buffered::AsyncBufStream::new(stream)));
+	let id = conn.opened().await?.ok_or("connection closed")?;
+	let mut stream = http::AsyncStream::new(&conn, id);
+	// this is unsafe, but that's ok, see HttpStreamHandler::accept
+	let stream_static = unsafe { std::mem::transmute::<
+		&'_      mut (dyn http::traits::AsyncStream + '_),
+		&'static mut (dyn http::traits::AsyncStream + 'static)
+	>(&mut stream as &mut dyn http::traits::AsyncStream) };
+	handler.accept(stream_static).await?;
+	// closes the stream like the socket does, which flushes the response
+	stream.close().await.map_err(Into::into)
+}
+
+/// Fails a test instead of waiting forever if `f` does not finish.
+#[cfg(test)]
+pub async fn deadline<T>(f: impl std::future::Future<Output = T>) -> T {
+	smol::future::or(f, async {
+		smol::Timer::after(std::time::Duration::from_secs(5)).await;
+		panic!("deadline exceeded")
+	}).await
+}
+
+/// A fresh temporary directory for the files of a test, `name` must be unique among all tests.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> std::path::PathBuf {
+	let dir = std::env::temp_dir().join(format!("kranus-{}-{}", name, std::process::id()));
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+/// The context of all tests, components are registered in the global context.
+#[cfg(test)]
+pub fn test_context() -> &'static crate::ctx::ContextWrapper {
+	use std::sync::{Arc, Once, atomic::{AtomicPtr, Ordering}};
+	
+	static INIT: Once = Once::new();
+	static CTX: AtomicPtr<crate::ctx::ContextWrapper> = AtomicPtr::new(std::ptr::null_mut());
+	
+	INIT.call_once(|| {
+		let ctx = Arc::new(crate::ctx::ContextWrapper::new(Default::default(), Default::default(),
+			std::env::temp_dir().join("kranus-router-test-changes.yml"), Vec::new(), Default::default()));
+		// the context is never dropped, see `set_context`
+		CTX.store(Arc::as_ptr(&ctx) as *mut _, Ordering::SeqCst);
+		crate::set_context(ctx);
+	});
+	
+	unsafe { &*CTX.load(Ordering::SeqCst) }
+}
+
+/// Registers a component under a generated name, e.g. the next module of a tested module.
+#[cfg(test)]
+pub fn test_component<T: std::any::Any + Send + Sync>(component: T) -> crate::ComponentRef<T> {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	
+	static NEXT: AtomicUsize = AtomicUsize::new(0);
+	test_context();
+	let id = crate::component_id(&format!("test-component-{}", NEXT.fetch_add(1, Ordering::Relaxed)));
+	crate::add_component(id, component);
+	crate::get_component(id)
+}
+
+/// Requests recorded by a [`TestHttpHandler`], their headers and bodies.
+#[cfg(test)]
+pub type TestHttpRequests = std::sync::Arc<std::sync::Mutex<Vec<(Vec<http::Header>, Vec<u8>)>>>;
+
+/// A handler that records requests and answers them with `response` and `body`, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct TestHttpHandler {
+	pub response: Vec<http::Header>,
+	pub body:     Vec<u8>,
+	pub requests: TestHttpRequests
+}
+
+#[cfg(test)]
+impl crate::interfaces::StreamHandler<dyn http::traits::AsyncStream> for TestHttpHandler {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> crate::DynFuture<'a, dyn_error::Result<()>> {
+		use {smol::io::{AsyncReadExt, AsyncWriteExt}, http::traits::AsyncStreamExt};
+		
+		Box::pin(async move {
+			let headers = stream.read_headers().await?;
+			let mut body = Vec::new();
+			stream.read_to_end(&mut body).await?;
+			self.requests.lock().unwrap().push((headers, body));
+			stream.write_headers(&self.response).await?;
+			stream.write_all(&self.body).await?;
+			stream.flush().await?;
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+impl smol::io::AsyncRead for TestHttpStream {
+	fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Poll::Ready(io::Read::read(&mut self.get_mut().request_body, buf))
+	}
+}
+
+#[cfg(test)]
+impl smol::io::AsyncWrite for TestHttpStream {
+	fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		self.get_mut().response_body.extend_from_slice(buf);
+		Poll::Ready(Ok(buf.len()))
+	}
+	
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+	
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+#[cfg(test)]
+impl http::traits::AsyncStream for TestHttpStream {
+	fn poll_read_headers(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		Poll::Ready(self.get_mut().request_headers.take().ok_or_else(|| io::ErrorKind::UnexpectedEof.into()))
+	}
+	
+	fn poll_write_headers(self: Pin<&mut Self>, _cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		self.get_mut().response_headers.push(headers.to_vec());
+		Poll::Ready(Ok(()))
+	}
+}
@@ -0,0 +1,247 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Applies the `controller` macro and sends requests through the generated handler.
+
+// the macro expands to paths relative to these crates
+extern crate kranus_router_node as net_services;
+extern crate kranus_protocols as net;
+
+use {
+	kranus_router_api_controller::controller,
+	net_services::{Error, controller::Json, interfaces::StreamHandler},
+	net::http::{self, traits::AsyncStream},
+	serde::{Serialize, Deserialize},
+	std::{fmt, io, pin::Pin, sync::Arc, task::{Context, Poll}}
+};
+
+#[derive(Debug)]
+struct Items;
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+struct NewItem {
+	name:  String,
+	count: u32
+}
+
+#[derive(Debug)]
+struct Missing(u32);
+
+impl fmt::Display for Missing {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "item {} not found", self.0)
+	}
+}
+
+#[controller(ItemsController, openapi = "/openapi.json")]
+impl Items {
+	#[route(method = "GET", path = "/items/{id}")]
+	async fn get(&self, #[path] id: u32, #[query("verbose")] verbose: Option<bool>) -> Result<String, Missing> {
+		match id {
+			0 => Err(Missing(id)),
+			_ => Ok(format!("item {}, verbose: {}", id, verbose.unwrap_or(false)))
+		}
+	}
+
+	#[route(method = "POST", path = "/items")]
+	async fn create(&self, #[json] item: NewItem) -> (http::Status, Json<NewItem>) {
+		(http::Status::Created, Json(item))
+	}
+
+	#[route(method = "GET", path = "/files/{rest...}")]
+	async fn files(&self, #[path] rest: &[&str]) -> String {
+		rest.join("/")
+	}
+
+	#[route(method = "GET", path = "/raw")]
+	async fn raw(&self, #[stream] stream: &mut dyn AsyncStream) -> io::Result<()> {
+		http::MessageBuilder::from(vec![http::Header::Status(http::Status::Ok), http::Header::ContentLength(3)])
+			.body(b"raw".to_vec())
+			.send_async(stream)
+			.await
+	}
+
+	#[error(Missing)]
+	async fn missing(&self, _headers: &[http::Header], _stream: &mut dyn AsyncStream, e: Error) -> (http::Status, String) {
+		(http::Status::NotFound, e.display().to_string())
+	}
+}
+
+/// A stream that reads a request from memory and records the response.
+#[derive(Debug, Default)]
+struct TestStream {
+	request_headers:  Option<Vec<http::Header>>,
+	request_body:     io::Cursor<Vec<u8>>,
+	response_headers: Vec<http::Header>,
+	response_body:    Vec<u8>
+}
+
+impl TestStream {
+	fn status(&self) -> Option<http::Status> {
+		self.response_headers.iter().find_map(http::Header::as_status).copied()
+	}
+
+	fn content_length(&self) -> Option<usize> {
+		self.response_headers.iter().find_map(|v| match v {
+			http::Header::ContentLength(v) => Some(*v),
+			_ => None
+		})
+	}
+}
+
+impl smol::io::AsyncRead for TestStream {
+	fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		Poll::Ready(io::Read::read(&mut self.request_body, buf))
+	}
+}
+
+impl smol::io::AsyncWrite for TestStream {
+	fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		self.response_body.extend_from_slice(buf);
+		Poll::Ready(Ok(buf.len()))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl AsyncStream for TestStream {
+	fn poll_read_headers(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<Vec<http::Header>>> {
+		Poll::Ready(self.request_headers.take().ok_or_else(|| io::ErrorKind::UnexpectedEof.into()))
+	}
+
+	fn poll_write_headers(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, headers: &[http::Header]) -> Poll<io::Result<()>> {
+		self.response_headers = headers.to_vec();
+		Poll::Ready(Ok(()))
+	}
+}
+
+fn request(method: http::Method, path: &str, body: &str) -> TestStream {
+	let mut stream = TestStream {
+		request_headers: Some(vec![http::Header::Method(method), http::Header::Path(path.to_string())]),
+		request_body:    io::Cursor::new(body.as_bytes().to_vec()),
+		..TestStream::default()
+	};
+
+	let handler = ItemsController(Arc::new(Items));
+	// this is unsafe, but the stream is not used until the handler completed
+	let stream_ref = unsafe { std::mem::transmute::<&mut TestStream, &'static mut TestStream>(&mut stream) };
+
+	if let Err(e) = smol::block_on(handler.accept(stream_ref)) {
+		panic!("{}", e.display());
+	}
+
+	stream
+}
+
+#[test]
+fn path_and_query() {
+	let response = request(http::Method::Get, "/items/42?verbose=true", "");
+	assert_eq!(response.status(), Some(http::Status::Ok));
+	assert_eq!(response.response_body, b"item 42, verbose: true");
+
+	let response = request(http::Method::Get, "/items/42", "");
+	assert_eq!(response.response_body, b"item 42, verbose: false");
+
+	let response = request(http::Method::Get, "/other", "");
+	assert_eq!(response.status(), Some(http::Status::NotFound));
+}
+
+#[test]
+fn wildcard() {
+	let response = request(http::Method::Get, "/files/a/b%20c", "");
+	assert_eq!(response.status(), Some(http::Status::Ok));
+	assert_eq!(response.response_body, b"a/b c");
+}
+
+#[test]
+fn json_body() {
+	let response = request(http::Method::Post, "/items", r#"{ "name": "a", "count": 2 }"#);
+	assert_eq!(response.status(), Some(http::Status::Created));
+	assert_eq!(
+		serde_json::from_slice::<serde_json::Value>(&response.response_body).unwrap(),
+		serde_json::json!({ "name": "a", "count": 2 })
+	);
+}
+
+#[test]
+fn errors() {
+	let response = request(http::Method::Get, "/items/0", "");
+	assert_eq!(response.status(), Some(http::Status::NotFound));
+	assert_eq!(response.response_body, b"item 0 not found");
+
+	// unhandled `HttpError`s are answered with their status
+	let response = request(http::Method::Get, "/items/x", "");
+	assert_eq!(response.status(), Some(http::Status::BadRequest));
+
+	let response = request(http::Method::Post, "/items", "{");
+	assert_eq!(response.status(), Some(http::Status::BadRequest));
+}
+
+#[test]
+fn head() {
+	let response = request(http::Method::Head, "/items/42", "");
+	assert_eq!(response.status(), Some(http::Status::Ok));
+	assert_eq!(response.content_length(), Some(b"item 42, verbose: false".len()));
+	assert!(response.response_body.is_empty());
+
+	// the handler sends the response itself
+	let response = request(http::Method::Head, "/raw", "");
+	assert_eq!(response.status(), Some(http::Status::Ok));
+	assert_eq!(response.content_length(), Some(3));
+	assert!(response.response_body.is_empty());
+
+	let response = request(http::Method::Get, "/raw", "");
+	assert_eq!(response.response_body, b"raw");
+}
+
+#[test]
+fn openapi() {
+	let doc = ItemsController::openapi();
+	let paths = doc["paths"].as_object().unwrap();
+	assert_eq!(paths.keys().map(String::as_str).collect::<Vec<_>>(), ["/files/{rest}", "/items", "/items/{id}", "/raw"]);
+
+	assert_eq!(doc["paths"]["/items/{id}"]["get"]["parameters"], serde_json::json!([
+		{ "name": "id", "in": "path", "required": true, "schema": { "type": "integer", "format": "uint32", "minimum": 0.0 } },
+		{ "name": "verbose", "in": "query", "required": false, "schema": { "type": "boolean" } }
+	]));
+	assert_eq!(doc["paths"]["/files/{rest}"]["get"]["parameters"][0]["name"], "rest");
+	assert_eq!(doc["paths"]["/items/{id}"]["get"]["responses"]["200"]["content"]["text/plain"]["schema"]["type"], "string");
+
+	let create = &doc["paths"]["/items"]["post"];
+	assert_eq!(create["operationId"], "create");
+	assert_eq!(create["requestBody"], serde_json::json!({
+		"required": true,
+		"content":  { "application/json": { "schema": { "$ref": "#/components/schemas/NewItem" } } }
+	}));
+	assert_eq!(doc["components"]["schemas"]["NewItem"]["required"], serde_json::json!(["count", "name"]));
+
+	// the document is served on the path of the `openapi` attribute
+	let response = request(http::Method::Get, "/openapi.json", "");
+	assert_eq!(response.status(), Some(http::Status::Ok));
+	assert_eq!(serde_json::from_slice::<serde_json::Value>(&response.response_body).unwrap(), doc);
+}
@@ -0,0 +1,167 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Builds the example plugin and loads it like the router does.
+
+use {
+	kranus_router_node::{*, interfaces::HttpStreamHandler},
+	std::{
+		collections::HashMap,
+		fmt,
+		hash::{Hash, Hasher},
+		os::raw::c_char,
+		path::{Path, PathBuf},
+		process::Command,
+		sync::{Arc, Mutex}
+	}
+};
+
+const EXAMPLE_PLUGIN: &str = "kranus-router-plugin-example";
+
+/// Builds the plugin with the profile and into the target directory of the test executable,
+/// which is located in `<target dir>/<profile>/deps`.
+fn build_example_plugin() -> PathBuf {
+	let exe = std::env::current_exe().expect("failed to locate the test executable");
+	let profile_dir = exe.parent().and_then(Path::parent).expect("invalid test executable path");
+	let target_dir = profile_dir.parent().expect("invalid test executable path");
+	let profile = match profile_dir.file_name().and_then(|v| v.to_str()) {
+		Some("debug") => "dev",
+		Some(v)       => v,
+		None          => panic!("invalid test executable path")
+	};
+
+	let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+		.args(["build", "--package", EXAMPLE_PLUGIN, "--profile", profile, "--target-dir"])
+		.arg(target_dir)
+		.status()
+		.expect("failed to run cargo");
+	assert!(status.success(), "failed to build `{}`", EXAMPLE_PLUGIN);
+
+	profile_dir.join(libloading::library_filename(EXAMPLE_PLUGIN.replace('-', "_")))
+}
+
+/// A context, that only keeps track of the registered components.
+#[derive(Default)]
+struct TestContext {
+	slots: Mutex<HashMap<(u128, u64), Arc<ComponentSlot>>>
+}
+
+impl fmt::Debug for TestContext {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("TestContext").finish_non_exhaustive()
+	}
+}
+
+impl fmt::Display for TestContext {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("test context")
+	}
+}
+
+impl Context for TestContext {
+	fn spawn_dyn(&self, f: DynFuture<'static, ()>) {
+		smol::spawn(f).detach()
+	}
+
+	fn component_id(&self, name: &str) -> u128 {
+		let mut hasher = std::collections::hash_map::DefaultHasher::new();
+		name.hash(&mut hasher);
+		hasher.finish() as _
+	}
+
+	fn component_name(&self, _id: u128) -> Option<String> {
+		None
+	}
+
+	fn component_dyn_slot(&self, id: u128, interface: u64) -> Arc<ComponentSlot> {
+		self.slots.lock().unwrap()
+			.entry((id, interface))
+			.or_default()
+			.clone()
+	}
+}
+
+fn init(init_fn: PluginInitFn, ctx: &Arc<TestContext>, cfg: &str) -> Result<()> {
+	let cfg = serde_json::from_str::<serde_dyn_repr::Value>(cfg).unwrap();
+	let mut cfg = <dyn dyn_serde::Deserializer>::erase(cfg);
+	let rt = otel::Runtime::new(otel::Config::disabled(), Some(Box::new(|_| ())));
+	smol::block_on((init_fn)(log::logger(), log::LevelFilter::Off, ctx.clone(), rt, &mut cfg))
+}
+
+#[test]
+fn load_example_plugin() {
+	let path = build_example_plugin();
+	let lib = unsafe { libloading::Library::new(&path) }
+		.unwrap_or_else(|e| panic!("failed to load `{}`: {}", path.display(), e));
+
+	let info = match unsafe { plugin_info(&lib) } {
+		Ok(v)  => v,
+		Err(e) => panic!("{}", e.display())
+	};
+
+	assert_eq!(info.abi_version, ABI_VERSION);
+	assert_eq!(info.rustc_version.as_str(), RUSTC_VERSION);
+	assert_eq!(info.name.as_str(), EXAMPLE_PLUGIN);
+	assert_eq!(info.components().collect::<Vec<_>>(), ["hello"]);
+
+	let init_fn = match unsafe { plugin_init_fn(&lib) } {
+		Ok(v)  => v,
+		Err(e) => panic!("{}", e.display())
+	};
+
+	let ctx = Arc::new(TestContext::default());
+	let slot = ctx.component_dyn_slot(ctx.component_id("hello"), get_interface_id::<HttpStreamHandler>());
+
+	// the config of the component is checked before it is registered
+	assert!(init(init_fn, &ctx, r#"{ "hello": { "unknown": true } }"#).is_err());
+	assert!(!slot.is_present());
+
+	if let Err(e) = init(init_fn, &ctx, r#"{ "hello": { "message": "Hello, Test!" } }"#) {
+		panic!("{}", e.display());
+	}
+
+	assert!(slot.is_present());
+}
+
+#[test]
+fn reject_abi_mismatch() {
+	let info = PluginInfo {
+		abi_version:   ABI_VERSION + 1,
+		rustc_version: PluginStr(RUSTC_VERSION_CSTR.as_ptr() as *const c_char),
+		name:          PluginStr(std::ptr::null()),
+		version:       PluginStr(std::ptr::null()),
+		authors:       PluginStr(std::ptr::null()),
+		components:    PluginStr(std::ptr::null())
+	};
+
+	let e = info.check().unwrap_err();
+	assert_eq!(e.plugin_abi_version, ABI_VERSION + 1);
+	assert_eq!(e.plugin_rustc_version, RUSTC_VERSION);
+
+	let info = PluginInfo {
+		abi_version:   ABI_VERSION,
+		rustc_version: PluginStr("rustc 0.0.0\0".as_ptr() as *const c_char),
+		..info
+	};
+
+	assert!(info.check().is_err());
+}
@@ -0,0 +1,80 @@
+// MIT License
+//
+// Copyright (c) 2019-2023 Tobias Pfeiffer
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A minimal plugin, that replies to every HTTP request with a configurable message.
+//!
+//! ```toml
+//! include = ["./libkranus_router_plugin_example.so"]
+//!
+//! [hello]
+//! message = "Hello, World!"
+//! ```
+
+#![warn(clippy::all)]
+
+use {
+	kranus_router_node::{*, interfaces::*, net::http},
+	serde::Deserialize,
+	smol::io::AsyncReadExt
+};
+
+plugin! {
+	hello: Hello as HttpStreamHandler
+}
+
+const DEFAULT_MESSAGE: &str = "Hello, World!";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigHello {
+	message: Option<String>
+}
+
+struct Hello {
+	message: String
+}
+
+impl PluginComponent for Hello {
+	type Config = ConfigHello;
+
+	fn new(_name: &str, cfg: Self::Config) -> DynFuture<'_, Result<Self>> {
+		Box::pin(async move {
+			Ok(Self { message: cfg.message.unwrap_or_else(|| DEFAULT_MESSAGE.to_string()) })
+		})
+	}
+}
+
+impl StreamHandler<dyn http::traits::AsyncStream> for Hello {
+	fn accept<'a>(&'a self, stream: &'static mut dyn http::traits::AsyncStream) -> DynFuture<'a, Result<()>> {
+		Box::pin(async move {
+			let _ = http::traits::AsyncStreamExt::read_headers(stream).await?;
+			stream.read_to_end(&mut Vec::new()).await?;
+
+			http::MessageBuilder::new()
+				.status(http::Status::Ok)
+				.content_length(self.message.len())
+				.body(self.message.as_bytes())
+				.send_async(stream)
+				.await.map_err(Into::into)
+		})
+	}
+}